@@ -0,0 +1,83 @@
+use std::path::PathBuf;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use termie::terminal_emulator::{TermIo, TermIoErr, TerminalEmulator};
+
+/// Output is fed in directly, so there is nothing to do with input
+struct BenchIo;
+
+impl TermIo for BenchIo {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, TermIoErr> {
+        Ok(buf.len())
+    }
+
+    fn set_win_size(&mut self, _width: usize, _height: usize) -> Result<(), TermIoErr> {
+        Ok(())
+    }
+}
+
+/// Something shaped like the output of cargo build, color on every line
+fn colorized_build_log(num_crates: usize) -> Vec<u8> {
+    let mut ret = Vec::new();
+    for i in 0..num_crates {
+        ret.extend(
+            format!("\x1b[1m\x1b[32m   Compiling\x1b[0m crate-{i} v0.{i}.0 (/src/crate-{i})\r\n")
+                .as_bytes(),
+        );
+        if i % 4 == 0 {
+            ret.extend(
+                format!(
+                    "\x1b[1m\x1b[33mwarning\x1b[0m\x1b[1m: unused variable: `value_{i}`\x1b[0m\r\n\
+                     \x1b[1m\x1b[34m  --> \x1b[0msrc/lib.rs:{i}:9\r\n\
+                     \x1b[1m\x1b[34m   |\x1b[0m\r\n\
+                     \x1b[1m\x1b[34m{i:<3}|\x1b[0m     let value_{i} = compute();\r\n\
+                     \x1b[1m\x1b[34m   |\x1b[0m         \x1b[1m\x1b[33m^^^^^^^^\x1b[0m \
+                     \x1b[1m\x1b[33mhelp: prefix it with an underscore\x1b[0m\r\n"
+                )
+                .as_bytes(),
+            );
+        }
+    }
+    ret.extend(b"\x1b[1m\x1b[32m    Finished\x1b[0m `dev` profile [unoptimized + debuginfo]\r\n");
+    ret
+}
+
+fn new_emulator() -> TerminalEmulator<BenchIo> {
+    let mut emulator = TerminalEmulator::new_with_io(BenchIo, PathBuf::from("recordings"));
+    emulator
+        .set_win_size(120, 40)
+        .expect("failed to set win size");
+    emulator
+}
+
+/// Feeds output in pty sized chunks
+fn process_output(emulator: &mut TerminalEmulator<BenchIo>, output: &[u8]) {
+    for chunk in output.chunks(4096) {
+        emulator.process_output(chunk);
+    }
+}
+
+fn bench_colorized_output(c: &mut Criterion) {
+    termie::log::init();
+    let output = colorized_build_log(2000);
+
+    c.bench_function("read colorized build log", |b| {
+        b.iter_batched(
+            new_emulator,
+            |mut emulator| {
+                process_output(&mut emulator, &output);
+                emulator
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    let mut emulator = new_emulator();
+    process_output(&mut emulator, &output);
+    c.bench_function("format colorized build log", |b| {
+        b.iter(|| emulator.format_data())
+    });
+}
+
+criterion_group!(benches, bench_colorized_output);
+criterion_main!(benches);
@@ -0,0 +1,379 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+use crate::config::{default_config_path, ConfigOverrides};
+
+pub const USAGE_EXIT_CODE: i32 = 2;
+pub const FAILURE_EXIT_CODE: i32 = 1;
+
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum UsageError {
+    #[error("missing value for {0}")]
+    MissingValue(String),
+    #[error("invalid value for {arg}: {val}")]
+    InvalidValue { arg: String, val: String },
+    #[error("unexpected argument {0}")]
+    UnexpectedArg(String),
+    #[error("missing {0}")]
+    MissingPositional(&'static str),
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct RunArgs {
+    /// Config file to use instead of the default one
+    pub config_path: Option<PathBuf>,
+    pub overrides: ConfigOverrides,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConvertFormat {
+    /// Output bytes as the child wrote them
+    Raw,
+    /// asciicast v2, as used by asciinema
+    Asciicast,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run(RunArgs),
+    Replay {
+        recording: PathBuf,
+    },
+    Dump {
+        recording: PathBuf,
+        scrollback: bool,
+    },
+    Convert {
+        input: PathBuf,
+        output: PathBuf,
+        format: ConvertFormat,
+    },
+    Help,
+    Version,
+}
+
+/// Parse everything after the program name
+pub fn parse<It: Iterator<Item = String>>(it: It) -> Result<Command, UsageError> {
+    let args: Vec<String> = it.collect();
+
+    // Anything after -e belongs to the program being run
+    for arg in args.iter().take_while(|arg| *arg != "-e") {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            _ => (),
+        }
+    }
+
+    let mut it = args.into_iter().peekable();
+    let subcommand =
+        it.next_if(|arg| matches!(arg.as_str(), "run" | "replay" | "dump" | "convert" | "help"));
+
+    match subcommand.as_deref() {
+        None | Some("run") => parse_run(it),
+        Some("replay") => parse_replay(it),
+        Some("dump") => parse_dump(it),
+        Some("convert") => parse_convert(it),
+        _ => Ok(Command::Help),
+    }
+}
+
+fn next_value<It: Iterator<Item = String>>(arg: &str, it: &mut It) -> Result<String, UsageError> {
+    it.next()
+        .ok_or_else(|| UsageError::MissingValue(arg.to_string()))
+}
+
+fn invalid_value(arg: &str, val: String) -> UsageError {
+    UsageError::InvalidValue {
+        arg: arg.to_string(),
+        val,
+    }
+}
+
+/// "unlimited" or a number
+fn parse_limit<It: Iterator<Item = String>>(
+    arg: &str,
+    it: &mut It,
+) -> Result<Option<usize>, UsageError> {
+    let val = next_value(arg, it)?;
+    if val == "unlimited" {
+        return Ok(None);
+    }
+
+    val.parse().map(Some).map_err(|_| invalid_value(arg, val))
+}
+
+/// WIDTHxHEIGHT in characters
+fn parse_size<It: Iterator<Item = String>>(
+    arg: &str,
+    it: &mut It,
+) -> Result<(usize, usize), UsageError> {
+    let val = next_value(arg, it)?;
+    let size = val
+        .split_once('x')
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+        .filter(|&(width, height)| width > 0 && height > 0);
+    size.ok_or_else(|| invalid_value(arg, val))
+}
+
+fn parse_font_size<It: Iterator<Item = String>>(arg: &str, it: &mut It) -> Result<f32, UsageError> {
+    let val = next_value(arg, it)?;
+    match val.parse::<f32>() {
+        Ok(v) if (1.0..=100.0).contains(&v) => Ok(v),
+        _ => Err(invalid_value(arg, val)),
+    }
+}
+
+fn parse_run<It: Iterator<Item = String>>(mut it: It) -> Result<Command, UsageError> {
+    let mut args = RunArgs::default();
+    let overrides = &mut args.overrides;
+
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "--config" => args.config_path = Some(next_value(&arg, &mut it)?.into()),
+            "--recording-path" => {
+                overrides.recording_path = Some(next_value(&arg, &mut it)?.into())
+            }
+            "--scrollback-lines" => overrides.scrollback_lines = Some(parse_limit(&arg, &mut it)?),
+            "--scrollback-bytes" => overrides.scrollback_bytes = Some(parse_limit(&arg, &mut it)?),
+            "--close-on-exit" => overrides.close_on_exit = true,
+            "--size" => overrides.size = Some(parse_size(&arg, &mut it)?),
+            "--font-size" => overrides.font_size = Some(parse_font_size(&arg, &mut it)?),
+            "--login" => overrides.login = true,
+            "--cwd" => overrides.cwd = Some(next_value(&arg, &mut it)?.into()),
+            "--shell" => overrides.program = Some(next_value(&arg, &mut it)?.into()),
+            "-e" => {
+                let program = next_value(&arg, &mut it)?;
+                // Everything after the program belongs to it
+                overrides.program = Some(program.into());
+                overrides.args = it.by_ref().map(Into::into).collect();
+            }
+            _ => Err(UsageError::UnexpectedArg(arg))?,
+        }
+    }
+
+    Ok(Command::Run(args))
+}
+
+/// Positional arguments of a subcommand, flags are consumed by handle_flag
+fn parse_positionals<It: Iterator<Item = String>>(
+    it: It,
+    names: &[&'static str],
+    mut handle_flag: impl FnMut(&str) -> bool,
+) -> Result<Vec<PathBuf>, UsageError> {
+    let mut ret = Vec::new();
+    for arg in it {
+        if handle_flag(&arg) {
+            continue;
+        }
+
+        if arg.starts_with('-') || ret.len() >= names.len() {
+            return Err(UsageError::UnexpectedArg(arg));
+        }
+
+        ret.push(arg.into());
+    }
+
+    if let Some(missing) = names.get(ret.len()) {
+        Err(UsageError::MissingPositional(missing))?
+    }
+
+    Ok(ret)
+}
+
+fn parse_replay<It: Iterator<Item = String>>(it: It) -> Result<Command, UsageError> {
+    let mut positionals = parse_positionals(it, &["recording"], |_| false)?;
+    Ok(Command::Replay {
+        recording: positionals.remove(0),
+    })
+}
+
+fn parse_dump<It: Iterator<Item = String>>(it: It) -> Result<Command, UsageError> {
+    let mut scrollback = false;
+    let mut positionals = parse_positionals(it, &["recording"], |arg| {
+        let handled = arg == "--scrollback";
+        scrollback |= handled;
+        handled
+    })?;
+
+    Ok(Command::Dump {
+        recording: positionals.remove(0),
+        scrollback,
+    })
+}
+
+fn parse_convert<It: Iterator<Item = String>>(mut it: It) -> Result<Command, UsageError> {
+    let mut format = None;
+    let mut rest = Vec::new();
+
+    while let Some(arg) = it.next() {
+        if arg == "--format" {
+            let val = next_value(&arg, &mut it)?;
+            format = Some(match val.as_str() {
+                "raw" => ConvertFormat::Raw,
+                "asciicast" => ConvertFormat::Asciicast,
+                _ => Err(invalid_value(&arg, val))?,
+            });
+        } else {
+            rest.push(arg);
+        }
+    }
+
+    let positionals = parse_positionals(rest.into_iter(), &["input", "output"], |_| false)?;
+    let [input, output]: [PathBuf; 2] = positionals
+        .try_into()
+        .expect("parse_positionals returns exactly the requested number of args");
+
+    let format = format.unwrap_or_else(|| {
+        if output.extension().is_some_and(|ext| ext == "cast") {
+            ConvertFormat::Asciicast
+        } else {
+            ConvertFormat::Raw
+        }
+    });
+
+    Ok(Command::Convert {
+        input,
+        output,
+        format,
+    })
+}
+
+pub fn help(program_name: &str) -> String {
+    let config_path = default_config_path()
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|| "$XDG_CONFIG_HOME/termie/config.json".to_string());
+    format!(
+        "\
+Usage:
+  {program_name} [run] [OPTIONS] [-e PROGRAM [ARGS...]]
+  {program_name} replay RECORDING
+  {program_name} dump [--scrollback] RECORDING
+  {program_name} convert [--format raw|asciicast] INPUT OUTPUT
+
+Commands:
+  run      Open a terminal window (default)
+  replay   Step through a recording in a window
+  dump     Print the screen at the end of a recording as text
+  convert  Convert a recording to raw output bytes, or asciicast if OUTPUT ends in .cast
+
+Run options override the config file, {config_path} by default:
+  --config PATH             Config file to use
+  --recording-path DIR      Where to output recordings to (default recordings)
+  --scrollback-lines N      Lines of scrollback to keep or \"unlimited\" (default 10000)
+  --scrollback-bytes N      Bytes of scrollback to keep or \"unlimited\" (default)
+  --close-on-exit           Close the window when the shell exits successfully
+  --size WIDTHxHEIGHT       Initial size in characters
+  --font-size SIZE          Initial font size (default 12)
+  --shell PROGRAM           Shell to run instead of $SHELL
+  --login                   Start the shell as a login shell
+  --cwd DIR                 Working directory of the shell
+  -e PROGRAM [ARGS...]      Run PROGRAM instead of the shell, must come last
+
+Options:
+  -h, --help                Print this help
+  -V, --version             Print the version
+"
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_strs(args: &[&str]) -> Result<Command, UsageError> {
+        parse(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn test_parse_run() {
+        assert_eq!(parse_strs(&[]), Ok(Command::Run(RunArgs::default())));
+
+        let Ok(Command::Run(RunArgs {
+            config_path,
+            overrides,
+        })) = parse_strs(&[
+            "run",
+            "--config",
+            "termie.json",
+            "--size",
+            "80x24",
+            "--scrollback-lines",
+            "unlimited",
+            "--font-size",
+            "14",
+            "-e",
+            "vim",
+            "--size",
+            "-e",
+        ])
+        else {
+            panic!("failed to parse run args");
+        };
+        assert_eq!(config_path, Some("termie.json".into()));
+        assert_eq!(overrides.size, Some((80, 24)));
+        assert_eq!(overrides.scrollback_lines, Some(None));
+        assert_eq!(overrides.scrollback_bytes, None);
+        assert_eq!(overrides.font_size, Some(14.0));
+        assert_eq!(overrides.program, Some("vim".into()));
+        assert_eq!(overrides.args, ["--size", "-e"]);
+
+        assert_eq!(
+            parse_strs(&["--size", "80"]),
+            Err(UsageError::InvalidValue {
+                arg: "--size".into(),
+                val: "80".into()
+            })
+        );
+        assert_eq!(
+            parse_strs(&["--cwd"]),
+            Err(UsageError::MissingValue("--cwd".into()))
+        );
+        assert_eq!(
+            parse_strs(&["--bogus"]),
+            Err(UsageError::UnexpectedArg("--bogus".into()))
+        );
+    }
+
+    #[test]
+    fn test_parse_subcommands() {
+        assert_eq!(
+            parse_strs(&["dump", "--scrollback", "0.json"]),
+            Ok(Command::Dump {
+                recording: "0.json".into(),
+                scrollback: true
+            })
+        );
+        assert_eq!(
+            parse_strs(&["replay"]),
+            Err(UsageError::MissingPositional("recording"))
+        );
+        assert_eq!(
+            parse_strs(&["replay", "a.json", "b.json"]),
+            Err(UsageError::UnexpectedArg("b.json".into()))
+        );
+        assert_eq!(
+            parse_strs(&["convert", "0.json", "out.cast"]),
+            Ok(Command::Convert {
+                input: "0.json".into(),
+                output: "out.cast".into(),
+                format: ConvertFormat::Asciicast,
+            })
+        );
+        assert_eq!(
+            parse_strs(&["convert", "0.json", "--format", "raw", "out.cast"]),
+            Ok(Command::Convert {
+                input: "0.json".into(),
+                output: "out.cast".into(),
+                format: ConvertFormat::Raw,
+            })
+        );
+        assert_eq!(parse_strs(&["dump", "--help"]), Ok(Command::Help));
+        assert_eq!(parse_strs(&["help"]), Ok(Command::Help));
+        assert_eq!(parse_strs(&["-V"]), Ok(Command::Version));
+        assert!(matches!(
+            parse_strs(&["-e", "ls", "--help"]),
+            Ok(Command::Run(_))
+        ));
+    }
+}
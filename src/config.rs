@@ -0,0 +1,739 @@
+use crate::gui::{default_keybindings, Action, KeyBinding, DEFAULT_FONT_SIZE};
+use eframe::egui::{Key, KeyboardShortcut, Modifiers};
+use termie::terminal_emulator::{
+    theme_names, CreatePtyIoError, LaunchSpec, Palette, PtyIo, Rgb, ScrollbackLimit,
+    TerminalEmulator,
+};
+use thiserror::Error;
+use tinyjson::JsonValue;
+
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+    time::{Duration, SystemTime},
+};
+
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Names of the palette entries, in SGR order
+const NAMED_COLORS: [&str; 16] = [
+    "black",
+    "red",
+    "green",
+    "yellow",
+    "blue",
+    "magenta",
+    "cyan",
+    "white",
+    "bright_black",
+    "bright_red",
+    "bright_green",
+    "bright_yellow",
+    "bright_blue",
+    "bright_magenta",
+    "bright_cyan",
+    "bright_white",
+];
+
+const ACTIONS: [(&str, Action); 4] = [
+    ("increase_font_size", Action::IncreaseFontSize),
+    ("decrease_font_size", Action::DecreaseFontSize),
+    ("reset_font_size", Action::ResetFontSize),
+    ("toggle_recording", Action::ToggleRecording),
+];
+
+/// Problem with a single value in the config
+#[derive(Debug, Error, PartialEq)]
+pub enum ConfigProblem {
+    #[error("unknown field")]
+    UnknownField,
+    #[error("expected {0}")]
+    WrongType(&'static str),
+    #[error("must be between {min} and {max}")]
+    OutOfRange { min: f64, max: f64 },
+    #[error("invalid color {0:?}, expected #rrggbb")]
+    Color(String),
+    #[error("invalid key {0:?}")]
+    Key(String),
+    #[error("invalid modifier {0:?}, expected ctrl, shift, alt or command")]
+    Modifier(String),
+    #[error("unknown action {0:?}, expected one of {1}")]
+    Action(String, String),
+    #[error("unknown theme {0:?}, expected one of {1}")]
+    Theme(String, String),
+}
+
+#[derive(Debug, Error, PartialEq)]
+#[error("{field}: {problem}")]
+pub struct InvalidConfig {
+    field: String,
+    problem: ConfigProblem,
+}
+
+#[derive(Debug, Error)]
+enum LoadConfigErrorKind {
+    #[error("failed to read {0}")]
+    Read(String, #[source] std::io::Error),
+    #[error("{0} is not valid json")]
+    Parse(String, #[source] tinyjson::JsonParseError),
+    #[error("invalid config in {0}")]
+    Invalid(String, #[source] InvalidConfig),
+}
+
+#[derive(Debug, Error)]
+#[error(transparent)]
+pub struct LoadConfigError(#[from] LoadConfigErrorKind);
+
+/// A value in the config along with where it was found
+struct Field {
+    name: String,
+    value: JsonValue,
+}
+
+impl Field {
+    fn invalid(&self, problem: ConfigProblem) -> InvalidConfig {
+        InvalidConfig {
+            field: self.name.clone(),
+            problem,
+        }
+    }
+
+    fn into_string(self) -> Result<String, InvalidConfig> {
+        match self.value {
+            JsonValue::String(v) => Ok(v),
+            _ => Err(self.invalid(ConfigProblem::WrongType("a string"))),
+        }
+    }
+
+    fn into_bool(self) -> Result<bool, InvalidConfig> {
+        match self.value {
+            JsonValue::Boolean(v) => Ok(v),
+            _ => Err(self.invalid(ConfigProblem::WrongType("true or false"))),
+        }
+    }
+
+    fn into_number(self, min: f64, max: f64) -> Result<f64, InvalidConfig> {
+        let JsonValue::Number(v) = self.value else {
+            return Err(self.invalid(ConfigProblem::WrongType("a number")));
+        };
+
+        if !(min..=max).contains(&v) {
+            return Err(self.invalid(ConfigProblem::OutOfRange { min, max }));
+        }
+
+        Ok(v)
+    }
+
+    fn into_usize(self, min: usize) -> Result<usize, InvalidConfig> {
+        match self.value {
+            JsonValue::Number(v) if v.fract() == 0.0 && v >= min as f64 => Ok(v as usize),
+            _ => Err(self.invalid(ConfigProblem::WrongType(if min == 0 {
+                "a non-negative integer"
+            } else {
+                "a positive integer"
+            }))),
+        }
+    }
+
+    /// "unlimited" or a number
+    fn into_limit(self) -> Result<Option<usize>, InvalidConfig> {
+        match &self.value {
+            JsonValue::String(v) if v == "unlimited" => Ok(None),
+            JsonValue::Number(_) => self.into_usize(0).map(Some),
+            _ => Err(self.invalid(ConfigProblem::WrongType("a number or \"unlimited\""))),
+        }
+    }
+
+    fn into_color(self) -> Result<Rgb, InvalidConfig> {
+        let name = self.name.clone();
+        let s = self.into_string()?;
+        parse_color(&s).ok_or(InvalidConfig {
+            field: name,
+            problem: ConfigProblem::Color(s),
+        })
+    }
+
+    fn into_array(self) -> Result<Vec<Field>, InvalidConfig> {
+        let JsonValue::Array(items) = self.value else {
+            return Err(InvalidConfig {
+                field: self.name,
+                problem: ConfigProblem::WrongType("an array"),
+            });
+        };
+
+        Ok(items
+            .into_iter()
+            .enumerate()
+            .map(|(i, value)| Field {
+                name: format!("{}[{i}]", self.name),
+                value,
+            })
+            .collect())
+    }
+
+    fn into_object(self) -> Result<Object, InvalidConfig> {
+        let JsonValue::Object(fields) = self.value else {
+            return Err(InvalidConfig {
+                field: self.name,
+                problem: ConfigProblem::WrongType("an object"),
+            });
+        };
+
+        Ok(Object {
+            name: self.name,
+            fields,
+        })
+    }
+}
+
+struct Object {
+    name: String,
+    fields: HashMap<String, JsonValue>,
+}
+
+impl Object {
+    fn field_name(&self, key: &str) -> String {
+        if self.name.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{key}", self.name)
+        }
+    }
+
+    fn take(&mut self, key: &str) -> Option<Field> {
+        let value = self.fields.remove(key)?;
+        Some(Field {
+            name: self.field_name(key),
+            value,
+        })
+    }
+
+    /// All remaining fields, in a stable order
+    fn drain(&mut self) -> Vec<(String, Field)> {
+        let mut fields = std::mem::take(&mut self.fields)
+            .into_iter()
+            .map(|(key, value)| {
+                let name = self.field_name(&key);
+                (key, Field { name, value })
+            })
+            .collect::<Vec<_>>();
+        fields.sort_by(|a, b| a.0.cmp(&b.0));
+        fields
+    }
+
+    /// Fails on fields that were not taken, they are most likely typos
+    fn finish(self) -> Result<(), InvalidConfig> {
+        match self.fields.keys().min() {
+            Some(key) => Err(InvalidConfig {
+                field: self.field_name(key),
+                problem: ConfigProblem::UnknownField,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+fn parse_color(s: &str) -> Option<Rgb> {
+    let hex = s.strip_prefix('#')?;
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some(Rgb::new(channel(0)?, channel(2)?, channel(4)?))
+}
+
+/// e.g. ctrl+shift+c
+fn parse_shortcut(s: &str) -> Result<KeyboardShortcut, ConfigProblem> {
+    let mut parts = s.split('+').map(str::trim).collect::<Vec<_>>();
+    let key_name = parts.pop().unwrap_or_default();
+    let key = Key::from_name(key_name)
+        .or_else(|| {
+            Key::ALL
+                .iter()
+                .copied()
+                .find(|key| key.name().eq_ignore_ascii_case(key_name))
+        })
+        .ok_or_else(|| ConfigProblem::Key(key_name.to_string()))?;
+
+    let mut modifiers = Modifiers::NONE;
+    for part in parts {
+        modifiers |= match part.to_ascii_lowercase().as_str() {
+            "ctrl" | "control" => Modifiers::CTRL,
+            "shift" => Modifiers::SHIFT,
+            "alt" => Modifiers::ALT,
+            "cmd" | "command" => Modifiers::COMMAND,
+            _ => return Err(ConfigProblem::Modifier(part.to_string())),
+        };
+    }
+
+    Ok(KeyboardShortcut::new(modifiers, key))
+}
+
+fn parse_action(s: &str) -> Result<Option<Action>, ConfigProblem> {
+    if s == "none" {
+        return Ok(None);
+    }
+
+    ACTIONS
+        .iter()
+        .find(|(name, _)| *name == s)
+        .map(|(_, action)| Some(*action))
+        .ok_or_else(|| {
+            let mut names = ACTIONS.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+            names.push("none");
+            ConfigProblem::Action(s.to_string(), names.join(", "))
+        })
+}
+
+/// Settings given on the command line, these win over the config file
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConfigOverrides {
+    pub recording_path: Option<PathBuf>,
+    /// Some(None) is unlimited
+    pub scrollback_lines: Option<Option<usize>>,
+    /// Some(None) is unlimited
+    pub scrollback_bytes: Option<Option<usize>>,
+    pub close_on_exit: bool,
+    pub size: Option<(usize, usize)>,
+    pub font_size: Option<f32>,
+    /// Replaces the configured shell along with its args
+    pub program: Option<OsString>,
+    pub args: Vec<OsString>,
+    pub login: bool,
+    pub cwd: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub font_size: f32,
+    /// Initial (width, height) in characters
+    pub size: Option<(usize, usize)>,
+    pub palette: Palette,
+    pub launch_spec: LaunchSpec,
+    pub scrollback_limit: ScrollbackLimit,
+    pub keybindings: Vec<KeyBinding>,
+    pub recording_path: PathBuf,
+    pub close_on_exit: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            font_size: DEFAULT_FONT_SIZE,
+            size: None,
+            palette: Palette::default(),
+            launch_spec: LaunchSpec::shell(),
+            scrollback_limit: ScrollbackLimit::default(),
+            keybindings: default_keybindings(),
+            recording_path: "recordings".into(),
+            close_on_exit: false,
+        }
+    }
+}
+
+impl Config {
+    fn parse_named(name: &str, content: &str) -> Result<Config, LoadConfigError> {
+        let json: JsonValue = content
+            .parse()
+            .map_err(|e| LoadConfigErrorKind::Parse(name.to_string(), e))?;
+        let root = Field {
+            name: String::new(),
+            value: json,
+        };
+        Ok(Self::from_json(root).map_err(|e| LoadConfigErrorKind::Invalid(name.to_string(), e))?)
+    }
+
+    fn from_json(root: Field) -> Result<Config, InvalidConfig> {
+        let mut config = Config::default();
+        let mut root = root.into_object()?;
+
+        if let Some(font) = root.take("font") {
+            let mut font = font.into_object()?;
+            if let Some(size) = font.take("size") {
+                config.font_size = size.into_number(1.0, 100.0)? as f32;
+            }
+            font.finish()?;
+        }
+
+        if let Some(size) = root.take("size") {
+            let mut size = size.into_object()?;
+            let mut dimension = |name| -> Result<usize, InvalidConfig> {
+                match size.take(name) {
+                    Some(v) => v.into_usize(1),
+                    None => Err(InvalidConfig {
+                        field: size.field_name(name),
+                        problem: ConfigProblem::WrongType("a positive integer"),
+                    }),
+                }
+            };
+            config.size = Some((dimension("width")?, dimension("height")?));
+            size.finish()?;
+        }
+
+        if let Some(palette) = root.take("palette") {
+            let mut palette = palette.into_object()?;
+            // Individual colors override the theme wherever they appear in the file
+            if let Some(theme) = palette.take("theme") {
+                let name = theme.name.clone();
+                let theme = theme.into_string()?;
+                config.palette = Palette::theme(&theme).ok_or_else(|| InvalidConfig {
+                    field: name,
+                    problem: ConfigProblem::Theme(
+                        theme,
+                        theme_names().collect::<Vec<_>>().join(", "),
+                    ),
+                })?;
+            }
+            if let Some(v) = palette.take("foreground") {
+                config.palette.foreground = v.into_color()?;
+            }
+            if let Some(v) = palette.take("background") {
+                config.palette.background = v.into_color()?;
+            }
+            if let Some(v) = palette.take("cursor") {
+                config.palette.cursor = v.into_color()?;
+            }
+            if let Some(v) = palette.take("selection") {
+                config.palette.selection = v.into_color()?;
+            }
+            for (i, name) in NAMED_COLORS.iter().enumerate() {
+                if let Some(v) = palette.take(name) {
+                    config.palette.indexed[i] = v.into_color()?;
+                }
+            }
+            palette.finish()?;
+        }
+
+        if let Some(shell) = root.take("shell") {
+            Self::parse_shell(&mut config.launch_spec, shell.into_object()?)?;
+        }
+
+        if let Some(scrollback) = root.take("scrollback") {
+            let mut scrollback = scrollback.into_object()?;
+            if let Some(v) = scrollback.take("lines") {
+                config.scrollback_limit.max_lines = v.into_limit()?;
+            }
+            if let Some(v) = scrollback.take("bytes") {
+                config.scrollback_limit.max_bytes = v.into_limit()?;
+            }
+            scrollback.finish()?;
+        }
+
+        if let Some(keybindings) = root.take("keybindings") {
+            let mut keybindings = keybindings.into_object()?;
+            for (key, field) in keybindings.drain() {
+                let shortcut = parse_shortcut(&key).map_err(|problem| field.invalid(problem))?;
+                let name = field.name.clone();
+                let action =
+                    parse_action(&field.into_string()?).map_err(|problem| InvalidConfig {
+                        field: name,
+                        problem,
+                    })?;
+
+                // Bindings from the file replace the defaults for the same keys
+                config
+                    .keybindings
+                    .retain(|binding| binding.shortcut != shortcut);
+                if let Some(action) = action {
+                    config.keybindings.push(KeyBinding { shortcut, action });
+                }
+            }
+        }
+
+        if let Some(recording) = root.take("recording") {
+            let mut recording = recording.into_object()?;
+            if let Some(v) = recording.take("path") {
+                config.recording_path = v.into_string()?.into();
+            }
+            recording.finish()?;
+        }
+
+        if let Some(v) = root.take("close_on_exit") {
+            config.close_on_exit = v.into_bool()?;
+        }
+
+        root.finish()?;
+        Ok(config)
+    }
+
+    fn parse_shell(spec: &mut LaunchSpec, mut shell: Object) -> Result<(), InvalidConfig> {
+        if let Some(v) = shell.take("program") {
+            spec.program = v.into_string()?.into();
+        }
+        if let Some(v) = shell.take("args") {
+            spec.args = v
+                .into_array()?
+                .into_iter()
+                .map(|arg| arg.into_string().map(Into::into))
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(v) = shell.take("env") {
+            spec.env = v
+                .into_object()?
+                .drain()
+                .into_iter()
+                .map(|(key, val)| Ok((key.into(), val.into_string()?.into())))
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(v) = shell.take("env_remove") {
+            spec.env_remove = v
+                .into_array()?
+                .into_iter()
+                .map(|key| key.into_string().map(Into::into))
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(v) = shell.take("cwd") {
+            spec.cwd = Some(v.into_string()?.into());
+        }
+        if let Some(v) = shell.take("login") {
+            spec.login = v.into_bool()?;
+        }
+        shell.finish()
+    }
+
+    fn apply_overrides(&mut self, overrides: &ConfigOverrides) {
+        if let Some(v) = &overrides.recording_path {
+            self.recording_path = v.clone();
+        }
+        if let Some(v) = overrides.scrollback_lines {
+            self.scrollback_limit.max_lines = v;
+        }
+        if let Some(v) = overrides.scrollback_bytes {
+            self.scrollback_limit.max_bytes = v;
+        }
+        self.close_on_exit |= overrides.close_on_exit;
+        if let Some(v) = overrides.size {
+            self.size = Some(v);
+        }
+        if let Some(v) = overrides.font_size {
+            self.font_size = v;
+        }
+        if let Some(v) = &overrides.program {
+            self.launch_spec.program = v.clone();
+            self.launch_spec.args = overrides.args.clone();
+        }
+        self.launch_spec.login |= overrides.login;
+        if let Some(v) = &overrides.cwd {
+            self.launch_spec.cwd = Some(v.clone());
+        }
+    }
+
+    pub fn new_terminal_emulator(&self) -> Result<TerminalEmulator<PtyIo>, CreatePtyIoError> {
+        let mut ret = TerminalEmulator::new(&self.launch_spec, self.recording_path.clone())?;
+        ret.set_scrollback_limit(self.scrollback_limit);
+        ret.set_palette(self.palette.clone());
+        if let Some((width, height)) = self.size {
+            if let Err(e) = ret.set_win_size(width, height) {
+                error!(
+                    "Failed to set initial size: {}",
+                    termie::error::backtraced_err(&*e)
+                );
+            }
+        }
+        Ok(ret)
+    }
+}
+
+/// $XDG_CONFIG_HOME/termie/config.json, falling back to ~/.config
+pub fn default_config_path() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| Some(PathBuf::from(std::env::var_os("HOME")?).join(".config")))?;
+    Some(config_dir.join("termie").join("config.json"))
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Where the config comes from, and the command line settings applied on top of it
+#[derive(Clone, Debug)]
+pub struct ConfigSource {
+    pub path: Option<PathBuf>,
+    pub overrides: ConfigOverrides,
+}
+
+impl ConfigSource {
+    /// A missing config file is not an error, the defaults are used instead
+    pub fn load(&self) -> Result<Config, LoadConfigError> {
+        let mut config = match &self.path {
+            Some(path) => match std::fs::read_to_string(path) {
+                Ok(content) => Config::parse_named(&path.display().to_string(), &content)?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Config::default(),
+                Err(e) => Err(LoadConfigErrorKind::Read(path.display().to_string(), e))?,
+            },
+            None => Config::default(),
+        };
+
+        config.apply_overrides(&self.overrides);
+        Ok(config)
+    }
+
+    /// Polls the config file, sending the reloaded config whenever it changes. The thread exits
+    /// once the receiver is gone
+    pub fn watch(
+        &self,
+        on_change: impl Fn() + Send + 'static,
+    ) -> Result<Option<Receiver<Result<Config, LoadConfigError>>>, std::io::Error> {
+        let Some(path) = self.path.clone() else {
+            return Ok(None);
+        };
+
+        let source = self.clone();
+        let (tx, rx) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("termie-config".to_string())
+            .spawn(move || {
+                let mut last_modified = modified_time(&path);
+                loop {
+                    std::thread::sleep(CONFIG_POLL_INTERVAL);
+
+                    let modified = modified_time(&path);
+                    if modified == last_modified {
+                        continue;
+                    }
+                    last_modified = modified;
+
+                    info!("Reloading config from {}", path.display());
+                    if tx.send(source.load()).is_err() {
+                        break;
+                    }
+                    on_change();
+                }
+            })?;
+
+        Ok(Some(rx))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn invalid(content: &str) -> String {
+        let err = Config::parse_named("config", content).expect_err("config should be invalid");
+        let source = std::error::Error::source(&err).expect("error should have a source");
+        source.to_string()
+    }
+
+    #[test]
+    fn test_parse_config() {
+        assert_eq!(
+            Config::parse_named("config", "{}").expect("empty config"),
+            Config::default()
+        );
+
+        let config = Config::parse_named(
+            "config",
+            r##"{
+                "font": {"size": 16},
+                "size": {"width": 80, "height": 24},
+                "palette": {
+                    "bright_red": "#ff0000",
+                    "theme": "solarized-light",
+                    "background": "#101010"
+                },
+                "shell": {
+                    "program": "/bin/zsh",
+                    "args": ["-i"],
+                    "env": {"EDITOR": "vi"},
+                    "env_remove": ["PROMPT_COMMAND"],
+                    "login": true
+                },
+                "scrollback": {"lines": "unlimited", "bytes": 1000},
+                "keybindings": {"ctrl+shift+r": "toggle_recording", "ctrl+0": "none"},
+                "recording": {"path": "/tmp/recordings"},
+                "close_on_exit": true
+            }"##,
+        )
+        .expect("valid config");
+
+        assert_eq!(config.font_size, 16.0);
+        assert_eq!(config.size, Some((80, 24)));
+        let solarized_light = Palette::theme("solarized-light").expect("bundled theme");
+        assert_eq!(config.palette.background, Rgb::new(0x10, 0x10, 0x10));
+        assert_eq!(config.palette.indexed[9], Rgb::new(255, 0, 0));
+        assert_eq!(config.palette.indexed[0], solarized_light.indexed[0]);
+        assert_eq!(config.palette.foreground, solarized_light.foreground);
+        assert_eq!(config.launch_spec.program, "/bin/zsh");
+        assert_eq!(config.launch_spec.args, ["-i"]);
+        assert_eq!(config.launch_spec.env, [("EDITOR".into(), "vi".into())]);
+        assert_eq!(config.launch_spec.env_remove, ["PROMPT_COMMAND"]);
+        assert!(config.launch_spec.login);
+        assert_eq!(
+            config.scrollback_limit,
+            ScrollbackLimit {
+                max_lines: None,
+                max_bytes: Some(1000)
+            }
+        );
+        assert!(config.keybindings.contains(&KeyBinding {
+            shortcut: KeyboardShortcut::new(Modifiers::CTRL | Modifiers::SHIFT, Key::R),
+            action: Action::ToggleRecording,
+        }));
+        assert!(!config
+            .keybindings
+            .iter()
+            .any(|binding| binding.shortcut.logical_key == Key::Num0));
+        assert_eq!(config.recording_path, PathBuf::from("/tmp/recordings"));
+        assert!(config.close_on_exit);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert_eq!(
+            invalid(r#"{"palete": {}}"#),
+            "palete: unknown field".to_string()
+        );
+        assert_eq!(
+            invalid(r#"{"palette": {"red": "red"}}"#),
+            "palette.red: invalid color \"red\", expected #rrggbb"
+        );
+        assert!(invalid(r#"{"palette": {"theme": "neon"}}"#)
+            .starts_with("palette.theme: unknown theme \"neon\", expected one of termie-dark"));
+        assert_eq!(
+            invalid(r#"{"font": {"size": 0}}"#),
+            "font.size: must be between 1 and 100"
+        );
+        assert_eq!(
+            invalid(r#"{"shell": {"args": ["-l", 1]}}"#),
+            "shell.args[1]: expected a string"
+        );
+        assert_eq!(
+            invalid(r#"{"size": {"width": 80}}"#),
+            "size.height: expected a positive integer"
+        );
+        assert_eq!(
+            invalid(r#"{"keybindings": {"hyper+a": "reset_font_size"}}"#),
+            "keybindings.hyper+a: invalid modifier \"hyper\", expected ctrl, shift, alt or command"
+        );
+        assert!(invalid(r#"{"keybindings": {"ctrl+a": "explode"}}"#)
+            .starts_with("keybindings.ctrl+a: unknown action \"explode\""));
+        assert!(Config::parse_named("config", "{").is_err());
+    }
+
+    #[test]
+    fn test_overrides() {
+        let source = ConfigSource {
+            path: None,
+            overrides: ConfigOverrides {
+                scrollback_lines: Some(None),
+                program: Some("vim".into()),
+                args: vec!["notes.txt".into()],
+                font_size: Some(20.0),
+                ..Default::default()
+            },
+        };
+
+        let config = source.load().expect("no config file");
+        assert_eq!(config.scrollback_limit.max_lines, None);
+        assert_eq!(config.launch_spec.program, "vim");
+        assert_eq!(config.launch_spec.args, ["notes.txt"]);
+        assert_eq!(config.font_size, 20.0);
+        assert_eq!(config.recording_path, PathBuf::from("recordings"));
+    }
+}
@@ -1,8 +1,10 @@
 use crate::{
+    config::{Config, ConfigSource, LoadConfigError},
     error::backtraced_err,
     terminal_emulator::{
-        ControlAction, LoadRecordingError, LoadSnapshotError, PtyIo, Recording, RecordingAction,
-        RecordingHandle, ReplayControl, ReplayIo, TerminalEmulator,
+        spawn_io_thread, CreatePtyIoError, LoadRecordingError, LoadSnapshotError, PtyIo, Recording,
+        RecordingAction, RecordingHandle, ReplayControl, ReplayIo, TermIo, TermReader,
+        TerminalEmulator,
     },
 };
 use eframe::{
@@ -10,9 +12,13 @@ use eframe::{
     epaint::Color32,
 };
 use terminal::TerminalWidget;
+pub use terminal::{default_keybindings, Action, KeyBinding, DEFAULT_FONT_SIZE};
 use thiserror::Error;
 
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    sync::{mpsc::Receiver, Arc, Mutex, MutexGuard},
+};
 
 mod terminal;
 
@@ -22,6 +28,44 @@ fn set_egui_options(ctx: &egui::Context) {
     });
 }
 
+/// Output is handled on the io thread, which asks for a repaint whenever it changes the screen
+fn start_io_thread<Io: TermIo + Send + 'static>(
+    ctx: &egui::Context,
+    terminal_emulator: TerminalEmulator<Io>,
+    reader: impl TermReader + Send + 'static,
+) -> Result<Arc<Mutex<TerminalEmulator<Io>>>, std::io::Error> {
+    let terminal_emulator = Arc::new(Mutex::new(terminal_emulator));
+    let ctx = ctx.clone();
+    spawn_io_thread(Arc::clone(&terminal_emulator), reader, move || {
+        ctx.request_repaint()
+    })?;
+    Ok(terminal_emulator)
+}
+
+fn lock_terminal_emulator<Io: TermIo>(
+    terminal_emulator: &Mutex<TerminalEmulator<Io>>,
+) -> MutexGuard<'_, TerminalEmulator<Io>> {
+    terminal_emulator
+        .lock()
+        .expect("terminal emulator lock poisoned")
+}
+
+/// Central panel filled with the terminal background, so it also covers the area around the grid
+fn terminal_panel<Io: TermIo>(
+    ctx: &egui::Context,
+    terminal_emulator: &Mutex<TerminalEmulator<Io>>,
+) -> CentralPanel {
+    let background = lock_terminal_emulator(terminal_emulator)
+        .palette()
+        .background;
+    let frame = egui::Frame::central_panel(&ctx.style()).fill(Color32::from_rgb(
+        background.r,
+        background.g,
+        background.b,
+    ));
+    CentralPanel::default().frame(frame)
+}
+
 fn calc_row_offset_px(current: usize, desired: usize, row_height: f32) -> f32 {
     let offset = desired as i64 - current as i64;
     offset as f32 * row_height
@@ -83,7 +127,7 @@ fn render_actions(ui: &mut Ui, replay_control: &mut ReplayControl, position_chan
 }
 
 struct LoadReplayResponse {
-    terminal_emulator: TerminalEmulator<ReplayIo>,
+    terminal_emulator: Arc<Mutex<TerminalEmulator<ReplayIo>>>,
     replay_control: ReplayControl,
 }
 
@@ -93,15 +137,20 @@ enum LoadReplayError {
     Recording(LoadRecordingError),
     #[error("failed to construct terminal emulator")]
     CreateTerminalEmulator(LoadSnapshotError),
+    #[error("failed to start io thread")]
+    StartIoThread(#[source] std::io::Error),
 }
 
-fn load_replay(path: &Path) -> Result<LoadReplayResponse, LoadReplayError> {
+fn load_replay(ctx: &egui::Context, path: &Path) -> Result<LoadReplayResponse, LoadReplayError> {
     let recording = Recording::load(path).map_err(LoadReplayError::Recording)?;
     let mut replay_control = ReplayControl::new(recording);
     let io_handle = replay_control.io_handle();
     let snapshot = replay_control.initial_state();
     let terminal_emulator = TerminalEmulator::from_snapshot(snapshot, io_handle)
         .map_err(LoadReplayError::CreateTerminalEmulator)?;
+    // The previous replay's io thread exits once its replay control is dropped
+    let terminal_emulator = start_io_thread(ctx, terminal_emulator, replay_control.reader())
+        .map_err(LoadReplayError::StartIoThread)?;
     Ok(LoadReplayResponse {
         terminal_emulator,
         replay_control,
@@ -109,7 +158,7 @@ fn load_replay(path: &Path) -> Result<LoadReplayResponse, LoadReplayError> {
 }
 
 struct ReplayTermieGui {
-    terminal_emulator: TerminalEmulator<ReplayIo>,
+    terminal_emulator: Arc<Mutex<TerminalEmulator<ReplayIo>>>,
     terminal_widget: TerminalWidget,
     replay_path: PathBuf,
     replay_control: ReplayControl,
@@ -120,14 +169,14 @@ impl ReplayTermieGui {
     fn new(
         cc: &eframe::CreationContext<'_>,
         replay_path: PathBuf,
-        terminal_emulator: TerminalEmulator<ReplayIo>,
+        terminal_emulator: Arc<Mutex<TerminalEmulator<ReplayIo>>>,
         replay_control: ReplayControl,
     ) -> Self {
         set_egui_options(&cc.egui_ctx);
 
         ReplayTermieGui {
             terminal_emulator,
-            terminal_widget: TerminalWidget::new(&cc.egui_ctx),
+            terminal_widget: TerminalWidget::new(&cc.egui_ctx, DEFAULT_FONT_SIZE),
             replay_path,
             replay_control,
             slider_pos: 0,
@@ -135,19 +184,11 @@ impl ReplayTermieGui {
     }
 
     fn step_replay(&mut self) {
-        let action = self.replay_control.next();
-        match action {
-            ControlAction::Resize { width, height } => {
-                if let Err(e) = self.terminal_emulator.set_win_size(width, height) {
-                    error!("failed to set window size: {}", backtraced_err(&*e));
-                }
-            }
-            ControlAction::None => (),
-        }
+        self.replay_control.next();
     }
 
-    fn reload_replay(&mut self) {
-        match load_replay(&self.replay_path) {
+    fn reload_replay(&mut self, ctx: &egui::Context) {
+        match load_replay(ctx, &self.replay_path) {
             Ok(response) => {
                 self.terminal_emulator = response.terminal_emulator;
                 self.replay_control = response.replay_control;
@@ -158,7 +199,12 @@ impl ReplayTermieGui {
         }
     }
 
-    fn update_replay_pos(&mut self, slider_response: &Response, next_response: &Response) -> bool {
+    fn update_replay_pos(
+        &mut self,
+        ctx: &egui::Context,
+        slider_response: &Response,
+        next_response: &Response,
+    ) -> bool {
         if !next_response.clicked() && !slider_response.changed() {
             return false;
         }
@@ -166,7 +212,7 @@ impl ReplayTermieGui {
         // Slider has requested that we move backwards, this requires a reload as we can only move
         // forwards
         if self.replay_control.current_pos() > self.slider_pos {
-            self.reload_replay();
+            self.reload_replay(ctx);
         }
 
         // Now we can move to where the slider wants us to be
@@ -201,13 +247,14 @@ impl eframe::App for ReplayTermieGui {
             ui.add(slider)
         });
 
-        let position_changed = self.update_replay_pos(&slider_response.inner, &next_response.inner);
+        let position_changed =
+            self.update_replay_pos(ctx, &slider_response.inner, &next_response.inner);
 
         egui::SidePanel::left("actions").show(ctx, |ui| {
             render_actions(ui, &mut self.replay_control, position_changed);
         });
-        let panel_response = CentralPanel::default().show(ctx, |ui| {
-            self.terminal_widget.show(ui, &mut self.terminal_emulator);
+        let panel_response = terminal_panel(ctx, &self.terminal_emulator).show(ctx, |ui| {
+            self.terminal_widget.show(ui, &self.terminal_emulator);
         });
 
         panel_response.response.context_menu(|ui| {
@@ -216,55 +263,256 @@ impl eframe::App for ReplayTermieGui {
     }
 }
 
-struct TermieGui {
+const DEFAULT_WINDOW_TITLE: &str = "Termie";
+
+#[derive(Debug, Error)]
+enum StartTerminalError {
+    #[error("failed to create terminal emulator")]
+    CreateTerminalEmulator(#[source] CreatePtyIoError),
+    #[error("failed to create pty reader")]
+    CreateReader(#[source] std::io::Error),
+    #[error("failed to start io thread")]
+    StartIoThread(#[source] std::io::Error),
+    #[error("failed to watch config")]
+    WatchConfig(#[source] std::io::Error),
+}
+
+fn start_terminal(
+    ctx: &egui::Context,
     terminal_emulator: TerminalEmulator<PtyIo>,
+) -> Result<Arc<Mutex<TerminalEmulator<PtyIo>>>, StartTerminalError> {
+    let reader = terminal_emulator
+        .reader()
+        .map_err(StartTerminalError::CreateReader)?;
+    start_io_thread(ctx, terminal_emulator, reader).map_err(StartTerminalError::StartIoThread)
+}
+
+struct TermieGui {
+    terminal_emulator: Arc<Mutex<TerminalEmulator<PtyIo>>>,
+    config: Config,
+    config_rx: Option<Receiver<Result<Config, LoadConfigError>>>,
+    // Shown until dismissed, the previous config stays in use
+    config_error: Option<String>,
+    // Requested size in characters, applied once fonts are available
+    pending_size: Option<(usize, usize)>,
     terminal_widget: TerminalWidget,
     recording_handle: Option<RecordingHandle>,
+    // Last title pushed to the viewport, used to avoid sending a command every frame
+    window_title: String,
 }
 
 impl TermieGui {
-    fn new(cc: &eframe::CreationContext<'_>, terminal_emulator: TerminalEmulator<PtyIo>) -> Self {
+    fn new(
+        cc: &eframe::CreationContext<'_>,
+        terminal_emulator: TerminalEmulator<PtyIo>,
+        config: Config,
+        config_source: ConfigSource,
+    ) -> Result<Self, StartTerminalError> {
         set_egui_options(&cc.egui_ctx);
 
-        TermieGui {
-            terminal_emulator,
-            terminal_widget: TerminalWidget::new(&cc.egui_ctx),
+        let ctx = cc.egui_ctx.clone();
+        let config_rx = config_source
+            .watch(move || ctx.request_repaint())
+            .map_err(StartTerminalError::WatchConfig)?;
+
+        let mut ret = TermieGui {
+            terminal_emulator: start_terminal(&cc.egui_ctx, terminal_emulator)?,
+            config_rx,
+            config_error: None,
+            pending_size: config.size,
+            terminal_widget: TerminalWidget::new(&cc.egui_ctx, config.font_size),
+            config,
             recording_handle: None,
+            window_title: DEFAULT_WINDOW_TITLE.to_string(),
+        };
+        ret.apply_config();
+        Ok(ret)
+    }
+
+    /// Settings that take effect immediately. The shell, recording path and size are used the
+    /// next time the terminal is restarted
+    fn apply_config(&mut self) {
+        self.terminal_widget.set_font_size(self.config.font_size);
+        self.terminal_widget
+            .set_keybindings(self.config.keybindings.clone());
+        let mut terminal_emulator = lock_terminal_emulator(&self.terminal_emulator);
+        terminal_emulator.set_scrollback_limit(self.config.scrollback_limit);
+        terminal_emulator.set_palette(self.config.palette.clone());
+    }
+
+    fn receive_config(&mut self) {
+        let Some(config_rx) = &self.config_rx else {
+            return;
+        };
+
+        // Only the latest version of the file matters
+        let Some(res) = config_rx.try_iter().last() else {
+            return;
+        };
+
+        match res {
+            Ok(config) => {
+                self.config = config;
+                self.config_error = None;
+                self.apply_config();
+            }
+            Err(e) => {
+                let message = backtraced_err(&e).to_string();
+                error!("failed to reload config: {message}");
+                self.config_error = Some(message);
+            }
+        }
+    }
+
+    fn show_config_error(&mut self, ctx: &egui::Context) {
+        let Some(config_error) = &self.config_error else {
+            return;
+        };
+
+        let dismiss_response = egui::TopBottomPanel::top("config_error").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.colored_label(ui.visuals().error_fg_color, config_error);
+                ui.button("Dismiss")
+            })
+            .inner
+        });
+
+        if dismiss_response.inner.clicked() {
+            self.config_error = None;
+        }
+    }
+
+    fn restart(&mut self, ctx: &egui::Context) {
+        let terminal_emulator = self
+            .config
+            .new_terminal_emulator()
+            .map_err(StartTerminalError::CreateTerminalEmulator)
+            .and_then(|terminal_emulator| start_terminal(ctx, terminal_emulator));
+        match terminal_emulator {
+            Ok(v) => {
+                self.terminal_emulator = v;
+                // Recordings belong to the previous child
+                self.recording_handle = None;
+            }
+            Err(e) => {
+                error!("failed to restart terminal: {}", backtraced_err(&e));
+            }
+        }
+    }
+
+    /// Banner with a restart button once the child has exited
+    fn show_exit_banner(&mut self, ctx: &egui::Context) {
+        let Some(exit_status) = lock_terminal_emulator(&self.terminal_emulator).exit_status()
+        else {
+            return;
+        };
+
+        if self.config.close_on_exit && exit_status.success() {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            return;
+        }
+
+        let restart_response = egui::TopBottomPanel::bottom("exit_banner").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label(format!("Process exited ({exit_status})"));
+                ui.button("Restart")
+            })
+            .inner
+        });
+
+        if restart_response.inner.clicked() {
+            self.restart(ctx);
+        }
+    }
+
+    fn apply_pending_size(&mut self, ctx: &egui::Context) {
+        let Some((width_chars, height_chars)) = self.pending_size.take() else {
+            return;
+        };
+
+        let margin = egui::Frame::central_panel(&ctx.style())
+            .total_margin()
+            .sum();
+        let size = self
+            .terminal_widget
+            .size_for(ctx, width_chars, height_chars)
+            + margin;
+        ctx.send_viewport_cmd(egui::ViewportCommand::InnerSize(size));
+    }
+
+    fn update_window_title(&mut self, ctx: &egui::Context) {
+        let terminal_emulator = lock_terminal_emulator(&self.terminal_emulator);
+        let title = terminal_emulator
+            .title()
+            .filter(|title| !title.is_empty())
+            .unwrap_or(DEFAULT_WINDOW_TITLE);
+
+        if title != self.window_title {
+            self.window_title = title.to_string();
+            ctx.send_viewport_cmd(egui::ViewportCommand::Title(self.window_title.clone()));
+        }
+    }
+
+    fn toggle_recording(&mut self) {
+        if self.recording_handle.take().is_some() {
+            return;
+        }
+
+        match lock_terminal_emulator(&self.terminal_emulator).start_recording() {
+            Ok(v) => {
+                self.recording_handle = Some(v);
+            }
+            Err(e) => {
+                error!("failed to start recording: {}", backtraced_err(&e));
+            }
+        }
+    }
+
+    fn handle_action(&mut self, action: Action) {
+        let font_size = self.terminal_widget.font_size();
+        match action {
+            Action::IncreaseFontSize => self.terminal_widget.set_font_size(font_size + 1.0),
+            Action::DecreaseFontSize => self.terminal_widget.set_font_size(font_size - 1.0),
+            Action::ResetFontSize => self.terminal_widget.set_font_size(self.config.font_size),
+            Action::ToggleRecording => self.toggle_recording(),
         }
     }
 }
 
 impl eframe::App for TermieGui {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        let panel_response = CentralPanel::default().show(ctx, |ui| {
+        self.receive_config();
+        self.apply_pending_size(ctx);
+        self.show_config_error(ctx);
+        self.show_exit_banner(ctx);
+
+        let panel_response = terminal_panel(ctx, &self.terminal_emulator).show(ctx, |ui| {
             let (width_chars, height_chars) = self.terminal_widget.calculate_available_size(ui);
 
-            if let Err(e) = self
-                .terminal_emulator
+            if let Err(e) = lock_terminal_emulator(&self.terminal_emulator)
                 .set_win_size(width_chars, height_chars)
             {
                 error!("failed to set window size {}", backtraced_err(&*e));
             }
 
-            self.terminal_widget.show(ui, &mut self.terminal_emulator);
+            self.terminal_widget.show(ui, &self.terminal_emulator)
         });
 
+        for action in panel_response.inner {
+            self.handle_action(action);
+        }
+
+        self.update_window_title(ctx);
+
         panel_response.response.context_menu(|ui| {
             self.terminal_widget.show_options(ui);
 
-            if self.recording_handle.is_some() {
-                if ui.button("Stop recording").clicked() {
-                    self.recording_handle = None;
-                }
-            } else if ui.button("Start recording").clicked() {
-                match self.terminal_emulator.start_recording() {
-                    Ok(v) => {
-                        self.recording_handle = Some(v);
-                    }
-                    Err(e) => {
-                        error!("failed to start recording: {}", backtraced_err(&e));
-                    }
-                }
+            let label = match self.recording_handle {
+                Some(_) => "Stop recording",
+                None => "Start recording",
+            };
+            if ui.button(label).clicked() {
+                self.toggle_recording();
             }
         });
     }
@@ -273,15 +521,14 @@ impl eframe::App for TermieGui {
 pub fn run_replay(replay_path: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
     let native_options = eframe::NativeOptions::default();
 
-    let LoadReplayResponse {
-        terminal_emulator,
-        replay_control,
-    } = load_replay(&replay_path)?;
-
     eframe::run_native(
         "Termie",
         native_options,
         Box::new(move |cc| {
+            let LoadReplayResponse {
+                terminal_emulator,
+                replay_control,
+            } = load_replay(&cc.egui_ctx, &replay_path)?;
             Ok(Box::new(ReplayTermieGui::new(
                 cc,
                 replay_path,
@@ -294,12 +541,23 @@ pub fn run_replay(replay_path: PathBuf) -> Result<(), Box<dyn std::error::Error>
     Ok(())
 }
 
-pub fn run(terminal_emulator: TerminalEmulator<PtyIo>) -> Result<(), Box<dyn std::error::Error>> {
+pub fn run(
+    terminal_emulator: TerminalEmulator<PtyIo>,
+    config: Config,
+    config_source: ConfigSource,
+) -> Result<(), Box<dyn std::error::Error>> {
     let native_options = eframe::NativeOptions::default();
     eframe::run_native(
-        "Termie",
+        DEFAULT_WINDOW_TITLE,
         native_options,
-        Box::new(move |cc| Ok(Box::new(TermieGui::new(cc, terminal_emulator)))),
+        Box::new(move |cc| {
+            Ok(Box::new(TermieGui::new(
+                cc,
+                terminal_emulator,
+                config,
+                config_source,
+            )?))
+        }),
     )?;
     Ok(())
 }
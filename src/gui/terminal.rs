@@ -1,124 +1,237 @@
+use super::lock_terminal_emulator;
 use crate::error::backtraced_err;
 use crate::terminal_emulator::{
-    CursorPos, FormatTagSerialized, TermIo, TerminalColor, TerminalEmulator, TerminalInput,
+    char_width, BufPos, CursorPos, CursorShape, CursorStyle, FormatTagSerialized, InputModifiers,
+    MouseButton, MouseEvent, MouseEventKind, MouseTracking, Palette, RenderData, Rgb,
+    SelectionMode, TermIo, TerminalColor, TerminalEmulator, TerminalInput,
 };
 use eframe::egui::{
-    self, text::LayoutJob, Color32, Context, DragValue, Event, FontData, FontDefinitions,
-    FontFamily, FontId, InputState, Key, Modifiers, Rect, TextFormat, TextStyle, Ui,
+    self,
+    text::{LayoutJob, LayoutSection},
+    Color32, Context, DragValue, Event, FontData, FontDefinitions, FontFamily, FontId, Id,
+    InputState, Key, KeyboardShortcut, Modal, Modifiers, MouseWheelUnit, PointerButton, Pos2, Rect,
+    Response, Sense, Stroke, StrokeKind, TextFormat, TextStyle, Ui,
 };
+use eframe::epaint::text::Fonts;
 
-use std::ops::RangeInclusive;
-use std::{borrow::Cow, sync::Arc};
+use std::ops::{Range, RangeInclusive};
+use std::{
+    borrow::Cow,
+    sync::{Arc, Mutex},
+};
 
 const REGULAR_FONT_NAME: &str = "jetbrains-mono";
 const BOLD_FONT_NAME: &str = "jetbrains-mono-bold";
+const ITALIC_FONT_NAME: &str = "jetbrains-mono-italic";
+const BOLD_ITALIC_FONT_NAME: &str = "jetbrains-mono-bold-italic";
+pub const DEFAULT_FONT_SIZE: f32 = 12.0;
+pub const MIN_FONT_SIZE: f32 = 1.0;
+pub const MAX_FONT_SIZE: f32 = 100.0;
+// Seconds the cursor spends shown, and then hidden, while blinking
+const CURSOR_BLINK_INTERVAL: f64 = 0.5;
+
+/// Things a key binding can do instead of sending the key to the terminal
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    IncreaseFontSize,
+    DecreaseFontSize,
+    ResetFontSize,
+    ToggleRecording,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyBinding {
+    pub shortcut: KeyboardShortcut,
+    pub action: Action,
+}
+
+pub fn default_keybindings() -> Vec<KeyBinding> {
+    let ctrl = |key| KeyboardShortcut::new(Modifiers::CTRL, key);
+    [
+        (ctrl(Key::Equals), Action::IncreaseFontSize),
+        (ctrl(Key::Plus), Action::IncreaseFontSize),
+        (ctrl(Key::Minus), Action::DecreaseFontSize),
+        (ctrl(Key::Num0), Action::ResetFontSize),
+    ]
+    .into_iter()
+    .map(|(shortcut, action)| KeyBinding { shortcut, action })
+    .collect()
+}
+
+fn find_binding(keybindings: &[KeyBinding], event: &Event) -> Option<Action> {
+    let Event::Key {
+        key,
+        pressed: true,
+        modifiers,
+        ..
+    } = event
+    else {
+        return None;
+    };
+
+    keybindings
+        .iter()
+        .find(|binding| {
+            binding.shortcut.logical_key == *key
+                && modifiers.matches_exact(binding.shortcut.modifiers)
+        })
+        .map(|binding| binding.action)
+}
+
+/// What input did other than being written to the terminal
+#[derive(Default)]
+struct InputResponse {
+    /// Actions of any key bindings that were pressed
+    actions: Vec<Action>,
+    /// Text to put on the clipboard
+    copied_text: Option<String>,
+    /// Paste that was held back until the user confirms it
+    unconfirmed_paste: Option<String>,
+    /// Whether anything was written to the child
+    wrote_input: bool,
+}
+
+fn input_modifiers(modifiers: &Modifiers) -> InputModifiers {
+    InputModifiers {
+        shift: modifiers.shift,
+        alt: modifiers.alt,
+        ctrl: modifiers.ctrl,
+    }
+}
 
+/// Keys that do not type text. egui does not tell keypad keys apart from the rest of the keyboard,
+/// so they are sent as the keys they match even in application keypad mode
+fn special_key_input(key: Key) -> Option<TerminalInput> {
+    let input = match key {
+        Key::Enter => TerminalInput::Enter,
+        Key::Tab => TerminalInput::Tab,
+        Key::Escape => TerminalInput::Escape,
+        Key::Backspace => TerminalInput::Backspace,
+        Key::ArrowUp => TerminalInput::ArrowUp,
+        Key::ArrowDown => TerminalInput::ArrowDown,
+        Key::ArrowLeft => TerminalInput::ArrowLeft,
+        Key::ArrowRight => TerminalInput::ArrowRight,
+        Key::Home => TerminalInput::Home,
+        Key::End => TerminalInput::End,
+        Key::Delete => TerminalInput::Delete,
+        Key::Insert => TerminalInput::Insert,
+        Key::PageUp => TerminalInput::PageUp,
+        Key::PageDown => TerminalInput::PageDown,
+        Key::F1 => TerminalInput::Function(1),
+        Key::F2 => TerminalInput::Function(2),
+        Key::F3 => TerminalInput::Function(3),
+        Key::F4 => TerminalInput::Function(4),
+        Key::F5 => TerminalInput::Function(5),
+        Key::F6 => TerminalInput::Function(6),
+        Key::F7 => TerminalInput::Function(7),
+        Key::F8 => TerminalInput::Function(8),
+        Key::F9 => TerminalInput::Function(9),
+        Key::F10 => TerminalInput::Function(10),
+        Key::F11 => TerminalInput::Function(11),
+        Key::F12 => TerminalInput::Function(12),
+        _ => return None,
+    };
+    Some(input)
+}
+
+/// Character that gives the control code xterm sends for ctrl+key
+fn ctrl_key_char(key: Key) -> Option<u8> {
+    if key >= Key::A && key <= Key::Z {
+        let name = key.name();
+        assert!(name.len() == 1);
+        return Some(name.as_bytes()[0]);
+    }
+
+    let c = match key {
+        Key::Space | Key::Num2 => b'@',
+        Key::OpenBracket | Key::Num3 => b'[',
+        Key::Backslash | Key::Num4 => b'\\',
+        Key::CloseBracket | Key::Num5 => b']',
+        Key::Num6 => b'^',
+        Key::Slash | Key::Num7 => b'_',
+        Key::Num8 => b'?',
+        _ => return None,
+    };
+    Some(c)
+}
+
+/// A shell that has not enabled bracketed paste runs every pasted line as it arrives
+fn paste_needs_confirmation(text: &str, bracketed_paste_mode: bool) -> bool {
+    !bracketed_paste_mode && text.contains(['\n', '\r'])
+}
+
+/// Writes input to the terminal. Ctrl+C copies and clears the selection if there is one, and is
+/// sent to the terminal otherwise. Ctrl+Shift+C only ever copies. Multi-line pastes are returned
+/// instead of written if the terminal would run them
 fn write_input_to_terminal<Io: TermIo>(
     input: &InputState,
     terminal_emulator: &mut TerminalEmulator<Io>,
-) {
+    keybindings: &[KeyBinding],
+) -> InputResponse {
+    let mut response = InputResponse::default();
+
     for event in &input.raw.events {
+        if let Some(action) = find_binding(keybindings, event) {
+            response.actions.push(action);
+            continue;
+        }
+
         let inputs: Cow<'static, [TerminalInput]> = match event {
-            Event::Text(text) => text
-                .as_bytes()
-                .iter()
-                .map(|c| TerminalInput::Ascii(*c))
+            // Alt sends ESC before the text, like xterm's metaSendsEscape
+            Event::Text(text) => input
+                .modifiers
+                .alt
+                .then_some(TerminalInput::Escape)
+                .into_iter()
+                .chain(text.as_bytes().iter().map(|c| TerminalInput::Ascii(*c)))
                 .collect::<Vec<_>>()
                 .into(),
-            Event::Key {
-                key: Key::Enter,
-                pressed: true,
-                ..
-            } => [TerminalInput::Enter].as_ref().into(),
             // https://github.com/emilk/egui/issues/3653
             Event::Copy => {
+                // Only Ctrl+Shift+C copies, Ctrl+C has to keep interrupting the child
+                if input.modifiers.shift {
+                    response.copied_text = terminal_emulator.selected_text();
+                    continue;
+                }
+
                 // NOTE: Technically not correct if we were on a mac, but also we are using linux
                 // syscalls so we'd have to solve that before this is a problem
                 [TerminalInput::Ctrl(b'c')].as_ref().into()
             }
+            Event::Paste(text) => {
+                if paste_needs_confirmation(text, terminal_emulator.bracketed_paste_mode()) {
+                    response.unconfirmed_paste = Some(text.clone());
+                    continue;
+                }
+
+                vec![TerminalInput::Paste(text.clone())].into()
+            }
             Event::Key {
                 key,
                 pressed: true,
-                modifiers: Modifiers { ctrl: true, .. },
+                modifiers,
                 ..
             } => {
-                if *key >= Key::A && *key <= Key::Z {
-                    let name = key.name();
-                    assert!(name.len() == 1);
-                    let name_c = name.as_bytes()[0];
-                    vec![TerminalInput::Ctrl(name_c)].into()
-                } else if *key == Key::OpenBracket {
-                    [TerminalInput::Ctrl(b'[')].as_ref().into()
-                } else if *key == Key::CloseBracket {
-                    [TerminalInput::Ctrl(b']')].as_ref().into()
-                } else if *key == Key::Backslash {
-                    [TerminalInput::Ctrl(b'\\')].as_ref().into()
+                let input = if let Some(input) = special_key_input(*key) {
+                    input
+                } else if modifiers.ctrl {
+                    let Some(c) = ctrl_key_char(*key) else {
+                        info!("Unexpected ctrl key: {}", key.name());
+                        continue;
+                    };
+                    TerminalInput::Ctrl(c)
                 } else {
-                    info!("Unexpected ctrl key: {}", key.name());
                     continue;
-                }
+                };
+                vec![input.with_modifiers(input_modifiers(modifiers))].into()
             }
-            Event::Key {
-                key: Key::Backspace,
-                pressed: true,
-                ..
-            } => [TerminalInput::Backspace].as_ref().into(),
-            Event::Key {
-                key: Key::ArrowUp,
-                pressed: true,
-                ..
-            } => [TerminalInput::ArrowUp].as_ref().into(),
-            Event::Key {
-                key: Key::ArrowDown,
-                pressed: true,
-                ..
-            } => [TerminalInput::ArrowDown].as_ref().into(),
-            Event::Key {
-                key: Key::ArrowLeft,
-                pressed: true,
-                ..
-            } => [TerminalInput::ArrowLeft].as_ref().into(),
-            Event::Key {
-                key: Key::ArrowRight,
-                pressed: true,
-                ..
-            } => [TerminalInput::ArrowRight].as_ref().into(),
-            Event::Key {
-                key: Key::Home,
-                pressed: true,
-                ..
-            } => [TerminalInput::Home].as_ref().into(),
-            Event::Key {
-                key: Key::End,
-                pressed: true,
-                ..
-            } => [TerminalInput::End].as_ref().into(),
-            Event::Key {
-                key: Key::Delete,
-                pressed: true,
-                ..
-            } => [TerminalInput::Delete].as_ref().into(),
-            Event::Key {
-                key: Key::Insert,
-                pressed: true,
-                ..
-            } => [TerminalInput::Insert].as_ref().into(),
-            Event::Key {
-                key: Key::PageUp,
-                pressed: true,
-                ..
-            } => [TerminalInput::PageUp].as_ref().into(),
-            Event::Key {
-                key: Key::PageDown,
-                pressed: true,
-                ..
-            } => [TerminalInput::PageDown].as_ref().into(),
             _ => {
                 continue;
             }
         };
 
         for input in inputs.as_ref() {
+            response.wrote_input = true;
             if let Err(e) = terminal_emulator.write(input.clone()) {
                 error!(
                     "Failed to write input to terminal emulator: {}",
@@ -127,6 +240,8 @@ fn write_input_to_terminal<Io: TermIo>(
             }
         }
     }
+
+    response
 }
 
 fn get_char_size(ctx: &egui::Context, font_size: f32) -> (f32, f32) {
@@ -171,10 +286,23 @@ fn get_char_size(ctx: &egui::Context, font_size: f32) -> (f32, f32) {
     })
 }
 
+/// Whether a blinking cursor is in its shown half, a repaint is requested for when that changes
+fn cursor_blink_on(ctx: &Context, blink_start: f64) -> bool {
+    let elapsed = ctx.input(|input| input.time) - blink_start;
+    let phase = (elapsed / CURSOR_BLINK_INTERVAL).max(0.0);
+    let until_change = (1.0 - phase.fract()) * CURSOR_BLINK_INTERVAL;
+    ctx.request_repaint_after(std::time::Duration::from_secs_f64(until_change));
+    (phase as u64).is_multiple_of(2)
+}
+
+/// Draws the cursor in style, or as an outlined block if the window does not have focus
 fn paint_cursor(
     label_rect: Rect,
     character_size: &(f32, f32),
     cursor_pos: &CursorPos,
+    style: CursorStyle,
+    focused: bool,
+    color: Color32,
     ui: &mut Ui,
 ) {
     let painter = ui.painter();
@@ -183,14 +311,25 @@ fn paint_cursor(
     let left = label_rect.left();
     let y_offset = cursor_pos.y as f32 * character_size.1;
     let x_offset = cursor_pos.x as f32 * character_size.0;
-    painter.rect_filled(
-        Rect::from_min_size(
-            egui::pos2(left + x_offset, top + y_offset),
-            egui::vec2(character_size.0, character_size.1),
-        ),
-        0.0,
-        Color32::GRAY,
+    let cell = Rect::from_min_size(
+        egui::pos2(left + x_offset, top + y_offset),
+        egui::vec2(character_size.0, character_size.1),
     );
+
+    if !focused {
+        painter.rect_stroke(cell, 0.0, Stroke::new(1.0, color), StrokeKind::Inside);
+        return;
+    }
+
+    let thickness = (character_size.1 / 8.0).max(1.0);
+    let rect = match style.shape {
+        CursorShape::Block => cell,
+        CursorShape::Underline => {
+            Rect::from_min_max(egui::pos2(cell.left(), cell.bottom() - thickness), cell.max)
+        }
+        CursorShape::Bar => Rect::from_min_size(cell.min, egui::vec2(thickness, cell.height())),
+    };
+    painter.rect_filled(rect, 0.0, color);
 }
 
 fn setup_fonts(ctx: &egui::Context) {
@@ -210,20 +349,41 @@ fn setup_fonts(ctx: &egui::Context) {
         ))),
     );
 
-    fonts
+    fonts.font_data.insert(
+        ITALIC_FONT_NAME.to_owned(),
+        Arc::new(FontData::from_static(include_bytes!(
+            "../../res/JetBrainsMonoNerdFont-Italic.ttf"
+        ))),
+    );
+
+    fonts.font_data.insert(
+        BOLD_ITALIC_FONT_NAME.to_owned(),
+        Arc::new(FontData::from_static(include_bytes!(
+            "../../res/JetBrainsMonoNerdFont-BoldItalic.ttf"
+        ))),
+    );
+
+    let monospace = fonts
         .families
         .get_mut(&FontFamily::Monospace)
-        .expect("egui should provide a monospace font")
-        .insert(0, REGULAR_FONT_NAME.to_owned());
+        .expect("egui should provide a monospace font");
 
-    fonts.families.insert(
-        FontFamily::Name(REGULAR_FONT_NAME.to_string().into()),
-        vec![REGULAR_FONT_NAME.to_string()],
-    );
-    fonts.families.insert(
-        FontFamily::Name(BOLD_FONT_NAME.to_string().into()),
-        vec![BOLD_FONT_NAME.to_string()],
-    );
+    // egui's own fonts cover glyphs (CJK, emoji) that ours do not
+    let fallback_fonts = monospace.clone();
+    monospace.insert(0, REGULAR_FONT_NAME.to_owned());
+
+    for name in [
+        REGULAR_FONT_NAME,
+        BOLD_FONT_NAME,
+        ITALIC_FONT_NAME,
+        BOLD_ITALIC_FONT_NAME,
+    ] {
+        let mut family = vec![name.to_string()];
+        family.extend(fallback_fonts.iter().cloned());
+        fonts
+            .families
+            .insert(FontFamily::Name(name.to_string().into()), family);
+    }
 
     ctx.set_fonts(fonts);
 }
@@ -231,37 +391,131 @@ fn setup_fonts(ctx: &egui::Context) {
 struct TerminalFonts {
     regular: FontFamily,
     bold: FontFamily,
+    italic: FontFamily,
+    bold_italic: FontFamily,
 }
 
 impl TerminalFonts {
     fn new() -> TerminalFonts {
         let bold = FontFamily::Name(BOLD_FONT_NAME.to_string().into());
         let regular = FontFamily::Name(REGULAR_FONT_NAME.to_string().into());
+        let italic = FontFamily::Name(ITALIC_FONT_NAME.to_string().into());
+        let bold_italic = FontFamily::Name(BOLD_ITALIC_FONT_NAME.to_string().into());
 
-        TerminalFonts { regular, bold }
+        TerminalFonts {
+            regular,
+            bold,
+            italic,
+            bold_italic,
+        }
     }
 
-    fn get_family(&self, is_bold: bool) -> FontFamily {
-        if is_bold {
-            self.bold.clone()
-        } else {
-            self.regular.clone()
+    fn get_family(&self, is_bold: bool, is_italic: bool) -> FontFamily {
+        match (is_bold, is_italic) {
+            (false, false) => self.regular.clone(),
+            (true, false) => self.bold.clone(),
+            (false, true) => self.italic.clone(),
+            (true, true) => self.bold_italic.clone(),
         }
     }
 }
 
-fn terminal_color_to_egui(default_color: &Color32, color: &TerminalColor) -> Color32 {
-    match color {
-        TerminalColor::Default => *default_color,
-        TerminalColor::Black => Color32::BLACK,
-        TerminalColor::Red => Color32::RED,
-        TerminalColor::Green => Color32::GREEN,
-        TerminalColor::Yellow => Color32::YELLOW,
-        TerminalColor::Blue => Color32::BLUE,
-        TerminalColor::Magenta => Color32::from_rgb(255, 0, 255),
-        TerminalColor::Cyan => Color32::from_rgb(0, 255, 255),
-        TerminalColor::White => Color32::WHITE,
+fn rgb_to_egui(color: Rgb) -> Color32 {
+    Color32::from_rgb(color.r, color.g, color.b)
+}
+
+fn terminal_color_to_egui(
+    palette: &Palette,
+    default_color: &Color32,
+    color: &TerminalColor,
+) -> Color32 {
+    palette
+        .get(color)
+        .map(rgb_to_egui)
+        .unwrap_or(*default_color)
+}
+
+/// Applies the attributes of a format tag to the text format used for rendering it
+fn apply_format_tag(
+    textformat: &mut TextFormat,
+    tag: &FormatTagSerialized,
+    terminal_fonts: &TerminalFonts,
+    font_size: f32,
+    palette: &Palette,
+    default_color: &Color32,
+    default_background: &Color32,
+) {
+    textformat.font_id.family = terminal_fonts.get_family(tag.bold, tag.italic);
+    textformat.font_id.size = font_size;
+
+    let mut color = terminal_color_to_egui(palette, default_color, &tag.color);
+    let mut background = match tag.background_color {
+        TerminalColor::Default => Color32::TRANSPARENT,
+        _ => terminal_color_to_egui(palette, default_background, &tag.background_color),
+    };
+
+    if tag.reverse {
+        let reversed_foreground = match background {
+            Color32::TRANSPARENT => *default_background,
+            _ => background,
+        };
+        background = color;
+        color = reversed_foreground;
+    }
+
+    if tag.dim {
+        color = color.gamma_multiply(0.5);
     }
+
+    textformat.color = color;
+    textformat.background = background;
+    textformat.underline = match tag.underline {
+        true => Stroke::new(1.0, color),
+        false => Stroke::NONE,
+    };
+    textformat.strikethrough = match tag.strikethrough {
+        true => Stroke::new(1.0, color),
+        false => Stroke::NONE,
+    };
+}
+
+/// Gives the bytes in selection a background of color, splitting sections where needed
+fn highlight_selection(job: &mut LayoutJob, selection: &Range<usize>, color: Color32) {
+    let mut sections = Vec::with_capacity(job.sections.len() + 2);
+    for section in std::mem::take(&mut job.sections) {
+        let range = section.byte_range.clone();
+        let start = selection.start.clamp(range.start, range.end);
+        let end = selection.end.clamp(range.start, range.end);
+        if start == end {
+            sections.push(section);
+            continue;
+        }
+
+        let parts = [
+            (range.start..start, false),
+            (start..end, true),
+            (end..range.end, false),
+        ];
+        for (byte_range, selected) in parts {
+            if byte_range.is_empty() {
+                continue;
+            }
+
+            let mut format = section.format.clone();
+            if selected {
+                format.background = color;
+            }
+            sections.push(LayoutSection {
+                leading_space: match byte_range.start == range.start {
+                    true => section.leading_space,
+                    false => 0.0,
+                },
+                byte_range,
+                format,
+            });
+        }
+    }
+    job.sections = sections;
 }
 
 fn create_terminal_output_layout_job(
@@ -284,22 +538,85 @@ fn create_terminal_output_layout_job(
     Ok((job, textformat))
 }
 
+/// Splits sections so that every glyph starts on the cell grid. A glyph whose advance does not
+/// match the cells it occupies (wide chars, combining chars, glyphs from fallback fonts) has the
+/// difference applied as leading space of the section following it
+fn snap_sections_to_cell_grid(job: &mut LayoutJob, fonts: &Fonts, cell_width: f32) {
+    let mut sections = Vec::with_capacity(job.sections.len());
+    let mut pending_space = 0.0;
+
+    for section in std::mem::take(&mut job.sections) {
+        let range = section.byte_range.clone();
+        let mut start = range.start;
+        let mut leading_space = section.leading_space + pending_space;
+        pending_space = 0.0;
+
+        for (i, c) in job.text[range.clone()].char_indices() {
+            if c == '\n' {
+                continue;
+            }
+
+            let expected_width = char_width(c) as f32 * cell_width;
+            let advance = fonts.glyph_width(&section.format.font_id, c);
+            // Sub pixel differences are lost to pixel rounding anyways
+            if (expected_width - advance).abs() < 0.5 {
+                continue;
+            }
+
+            let end = range.start + i + c.len_utf8();
+            sections.push(LayoutSection {
+                leading_space,
+                byte_range: start..end,
+                format: section.format.clone(),
+            });
+            start = end;
+            leading_space = 0.0;
+            pending_space = expected_width - advance;
+        }
+
+        if start < range.end {
+            sections.push(LayoutSection {
+                leading_space: leading_space + pending_space,
+                byte_range: start..range.end,
+                format: section.format,
+            });
+            pending_space = 0.0;
+        }
+    }
+
+    job.sections = sections;
+}
+
+/// Settings shared by the scrollback and screen areas
+struct OutputStyle<'a> {
+    font_size: f32,
+    palette: &'a Palette,
+    cell_width: f32,
+    render_newlines: bool,
+}
+
 fn add_terminal_data_to_ui(
     ui: &mut Ui,
     data: &[u8],
     format_data: &[FormatTagSerialized],
-    font_size: f32,
-    render_newlines: bool,
+    selection: Option<Range<usize>>,
+    style: &OutputStyle,
 ) -> Result<egui::Response, std::str::Utf8Error> {
+    let OutputStyle {
+        font_size,
+        palette,
+        cell_width,
+        render_newlines,
+    } = *style;
     let (mut job, mut textformat) =
         create_terminal_output_layout_job(ui.style(), ui.available_width(), data)?;
 
-    let default_color = textformat.color;
+    let default_color = rgb_to_egui(palette.foreground);
+    let default_background = rgb_to_egui(palette.background);
     let terminal_fonts = TerminalFonts::new();
 
     for tag in format_data {
         let mut range = tag.start..tag.end;
-        let color = tag.color;
 
         if range.end == usize::MAX {
             range.end = data.len()
@@ -321,9 +638,15 @@ fn add_terminal_data_to_ui(
             range.end = data.len();
         }
 
-        textformat.font_id.family = terminal_fonts.get_family(tag.bold);
-        textformat.font_id.size = font_size;
-        textformat.color = terminal_color_to_egui(&default_color, &color);
+        apply_format_tag(
+            &mut textformat,
+            tag,
+            &terminal_fonts,
+            font_size,
+            palette,
+            &default_color,
+            &default_background,
+        );
 
         job.sections.push(egui::text::LayoutSection {
             leading_space: 0.0f32,
@@ -332,13 +655,21 @@ fn add_terminal_data_to_ui(
         });
     }
 
-    let galley = ui.fonts(move |fonts| fonts.layout_job(job));
-    let label_response = ui.label(Arc::clone(&galley));
+    if let Some(selection) = selection {
+        highlight_selection(&mut job, &selection, rgb_to_egui(palette.selection));
+    }
+
+    let galley = ui.fonts(move |fonts| {
+        snap_sections_to_cell_grid(&mut job, fonts, cell_width);
+        fonts.layout_job(job)
+    });
+    // Selection is handled by the terminal so that it can span scrollback and the screen
+    let label_response = ui.add(egui::Label::new(Arc::clone(&galley)).selectable(false));
     if render_newlines {
         let painter = ui.painter();
         let font = FontId {
             size: font_size,
-            family: terminal_fonts.get_family(false),
+            family: terminal_fonts.get_family(false, false),
         };
 
         for row in &galley.rows {
@@ -368,20 +699,33 @@ fn add_terminal_data_to_ui(
 struct TerminalOutputRenderResponse {
     scrollback_area: Rect,
     canvas_area: Rect,
+    /// Clicks and drags on the output, used for selection
+    response: Response,
 }
 
-fn render_terminal_output<Io: TermIo>(
+fn render_terminal_output(
     ui: &mut egui::Ui,
-    // FIXME: no mut
-    terminal_emulator: &mut TerminalEmulator<Io>,
+    render_data: RenderData,
+    palette: &Palette,
     font_size: f32,
     show_newlines: bool,
+    // Off while the wheel is reported to the child instead
+    scrollback_scrolling: bool,
 ) -> TerminalOutputRenderResponse {
-    let terminal_data = terminal_emulator.data();
+    let RenderData {
+        text: terminal_data,
+        formats: mut format_data,
+        selection: selection_data,
+    } = render_data;
     let mut scrollback_data: &[u8] = &terminal_data.scrollback;
     let mut canvas_data: &[u8] = &terminal_data.visible;
     debug!("{:?}", std::str::from_utf8(canvas_data));
-    let mut format_data = terminal_emulator.format_data();
+    let style = OutputStyle {
+        font_size,
+        palette,
+        cell_width: get_char_size(ui.ctx(), font_size).0,
+        render_newlines: show_newlines,
+    };
 
     // Arguably incorrect. Scrollback does end with a newline, and that newline causes a blank
     // space between widgets. Should we strip it here, or in the terminal emulator output?
@@ -399,6 +743,9 @@ fn render_terminal_output<Io: TermIo>(
     let response = egui::ScrollArea::new([false, true])
         .auto_shrink([false, false])
         .stick_to_bottom(true)
+        // Dragging selects text instead
+        .drag_to_scroll(false)
+        .enable_scrolling(scrollback_scrolling)
         .show(ui, |ui| {
             let error_logged_rect =
                 |response: Result<egui::Response, std::str::Utf8Error>| match response {
@@ -412,25 +759,218 @@ fn render_terminal_output<Io: TermIo>(
                 ui,
                 scrollback_data,
                 &format_data.scrollback,
-                font_size,
-                show_newlines,
+                selection_data.scrollback,
+                &style,
             ));
             let canvas_area = error_logged_rect(add_terminal_data_to_ui(
                 ui,
                 canvas_data,
                 &format_data.visible,
-                font_size,
-                show_newlines,
+                selection_data.visible,
+                &style,
             ));
+            let response = ui.interact(
+                scrollback_area.union(canvas_area),
+                ui.id().with("terminal_output"),
+                Sense::click_and_drag(),
+            );
             TerminalOutputRenderResponse {
                 scrollback_area,
                 canvas_area,
+                response,
             }
         });
 
     response.inner
 }
 
+/// Cell under pos. When round is set the nearest cell boundary is used instead, so that dragging
+/// over half of a cell is enough to select it
+fn buf_pos_at<Io: TermIo>(
+    terminal_emulator: &TerminalEmulator<Io>,
+    output: &TerminalOutputRenderResponse,
+    character_size: &(f32, f32),
+    pos: Pos2,
+    round: bool,
+) -> BufPos {
+    let to_cells = |offset: f32, size: f32| {
+        let cells = offset.max(0.0) / size;
+        match round {
+            true => cells.round() as usize,
+            false => cells.floor() as usize,
+        }
+    };
+
+    let x = to_cells(pos.x - output.canvas_area.left(), character_size.0);
+    if pos.y >= output.canvas_area.top() {
+        let y = ((pos.y - output.canvas_area.top()) / character_size.1) as usize;
+        terminal_emulator.buf_pos_on_screen(&CursorPos { x, y })
+    } else {
+        let row = (pos.y - output.scrollback_area.top()).max(0.0) / character_size.1;
+        terminal_emulator.buf_pos_in_scrollback(x, row as usize)
+    }
+}
+
+/// Click and drag to select, double click for words and triple click for lines
+fn update_selection<Io: TermIo>(
+    ui: &Ui,
+    terminal_emulator: &mut TerminalEmulator<Io>,
+    output: &TerminalOutputRenderResponse,
+    character_size: &(f32, f32),
+) {
+    let response = &output.response;
+    let Some(pointer_pos) = response.interact_pointer_pos() else {
+        return;
+    };
+    let buf_pos_at = |terminal_emulator: &TerminalEmulator<Io>, pos, round| {
+        buf_pos_at(terminal_emulator, output, character_size, pos, round)
+    };
+
+    if response.triple_clicked() {
+        let pos = buf_pos_at(terminal_emulator, pointer_pos, false);
+        terminal_emulator.start_selection(pos, SelectionMode::Line);
+    } else if response.double_clicked() {
+        let pos = buf_pos_at(terminal_emulator, pointer_pos, false);
+        terminal_emulator.start_selection(pos, SelectionMode::Word);
+    } else if response.drag_started_by(PointerButton::Primary) {
+        let origin = ui
+            .input(|input| input.pointer.press_origin())
+            .unwrap_or(pointer_pos);
+        let pos = buf_pos_at(terminal_emulator, origin, true);
+        terminal_emulator.start_selection(pos, SelectionMode::Cell);
+        let pos = buf_pos_at(terminal_emulator, pointer_pos, true);
+        terminal_emulator.extend_selection(pos);
+    } else if response.dragged_by(PointerButton::Primary) {
+        let pos = buf_pos_at(terminal_emulator, pointer_pos, true);
+        terminal_emulator.extend_selection(pos);
+    } else if response.clicked() {
+        terminal_emulator.clear_selection();
+    }
+}
+
+/// Screen cell under pos, clamped to the screen so drags past the edge still report
+fn screen_pos_at<Io: TermIo>(
+    terminal_emulator: &TerminalEmulator<Io>,
+    output: &TerminalOutputRenderResponse,
+    character_size: &(f32, f32),
+    pos: Pos2,
+) -> CursorPos {
+    let (width, height) = terminal_emulator.get_win_size();
+    let to_cell = |offset: f32, size: f32, cells: usize| {
+        ((offset.max(0.0) / size) as usize).min(cells.saturating_sub(1))
+    };
+    CursorPos {
+        x: to_cell(pos.x - output.canvas_area.left(), character_size.0, width),
+        y: to_cell(pos.y - output.canvas_area.top(), character_size.1, height),
+    }
+}
+
+fn mouse_button(button: PointerButton) -> Option<MouseButton> {
+    match button {
+        PointerButton::Primary => Some(MouseButton::Left),
+        PointerButton::Middle => Some(MouseButton::Middle),
+        PointerButton::Secondary => Some(MouseButton::Right),
+        PointerButton::Extra1 | PointerButton::Extra2 => None,
+    }
+}
+
+/// Converts pointer events over the screen into mouse reports for the child. Presses and the
+/// wheel only count over the screen, motion only when it moves to another cell
+fn report_mouse_events<Io: TermIo>(
+    input: &InputState,
+    terminal_emulator: &mut TerminalEmulator<Io>,
+    output: &TerminalOutputRenderResponse,
+    character_size: &(f32, f32),
+    last_mouse_pos: &mut Option<CursorPos>,
+) {
+    let held_button = [
+        PointerButton::Primary,
+        PointerButton::Middle,
+        PointerButton::Secondary,
+    ]
+    .into_iter()
+    .find(|button| input.pointer.button_down(*button))
+    .and_then(mouse_button);
+
+    let mut events = Vec::new();
+    for event in &input.raw.events {
+        match event {
+            Event::PointerButton {
+                pos,
+                button,
+                pressed,
+                modifiers,
+            } => {
+                let Some(button) = mouse_button(*button) else {
+                    continue;
+                };
+                if *pressed && !output.canvas_area.contains(*pos) {
+                    continue;
+                }
+                let kind = match pressed {
+                    true => MouseEventKind::Press(button),
+                    false => MouseEventKind::Release(button),
+                };
+                events.push((kind, *pos, input_modifiers(modifiers)));
+            }
+            Event::PointerMoved(pos) => {
+                if held_button.is_none() && !output.canvas_area.contains(*pos) {
+                    continue;
+                }
+                let kind = MouseEventKind::Motion(held_button);
+                events.push((kind, *pos, input_modifiers(&input.modifiers)));
+            }
+            Event::MouseWheel {
+                unit,
+                delta,
+                modifiers,
+            } => {
+                let Some(pos) = input.pointer.hover_pos() else {
+                    continue;
+                };
+                if delta.y == 0.0 || !output.canvas_area.contains(pos) {
+                    continue;
+                }
+                let button = match delta.y > 0.0 {
+                    true => MouseButton::WheelUp,
+                    false => MouseButton::WheelDown,
+                };
+                let lines = match unit {
+                    MouseWheelUnit::Line => (delta.y.abs().round() as usize).max(1),
+                    MouseWheelUnit::Point | MouseWheelUnit::Page => 1,
+                };
+                for _ in 0..lines {
+                    events.push((
+                        MouseEventKind::Press(button),
+                        pos,
+                        input_modifiers(modifiers),
+                    ));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    for (kind, pos, modifiers) in events {
+        let pos = screen_pos_at(terminal_emulator, output, character_size, pos);
+        if let MouseEventKind::Motion(_) = kind {
+            if last_mouse_pos.as_ref() == Some(&pos) {
+                continue;
+            }
+        }
+        *last_mouse_pos = Some(pos.clone());
+
+        let event = MouseEvent {
+            kind,
+            pos,
+            modifiers,
+        };
+        if let Err(e) = terminal_emulator.report_mouse(&event) {
+            error!("Failed to report mouse event: {}", backtraced_err(&*e));
+        }
+    }
+}
+
 struct DebugRenderer {
     enable: bool,
 }
@@ -452,21 +992,45 @@ impl DebugRenderer {
 
 pub struct TerminalWidget {
     font_size: f32,
+    keybindings: Vec<KeyBinding>,
     debug_renderer: DebugRenderer,
     show_newlines: bool,
+    /// Multi-line paste waiting on the user to confirm it
+    pending_paste: Option<String>,
+    /// Last cell reported to the child, motion within a cell is not reported
+    last_mouse_pos: Option<CursorPos>,
+    /// Time the cursor last started blinking, typing restarts it so the cursor shows while typing
+    cursor_blink_start: f64,
 }
 
 impl TerminalWidget {
-    pub fn new(ctx: &Context) -> TerminalWidget {
+    pub fn new(ctx: &Context, font_size: f32) -> TerminalWidget {
         setup_fonts(ctx);
 
         TerminalWidget {
-            font_size: 12.0,
+            font_size,
+            keybindings: Vec::new(),
             debug_renderer: DebugRenderer::new(),
             show_newlines: false,
+            pending_paste: None,
+            last_mouse_pos: None,
+            cursor_blink_start: 0.0,
         }
     }
 
+    pub fn font_size(&self) -> f32 {
+        self.font_size
+    }
+
+    pub fn set_font_size(&mut self, font_size: f32) {
+        self.font_size = font_size.clamp(MIN_FONT_SIZE, MAX_FONT_SIZE);
+    }
+
+    /// Keys bound here are not sent to the terminal, their actions are returned from show instead
+    pub fn set_keybindings(&mut self, keybindings: Vec<KeyBinding>) {
+        self.keybindings = keybindings;
+    }
+
     #[allow(unused)]
     pub fn calculate_available_size(&self, ui: &mut Ui) -> (usize, usize) {
         let character_size = get_char_size(ui.ctx(), self.font_size);
@@ -475,48 +1039,169 @@ impl TerminalWidget {
         (width_chars, height_chars)
     }
 
-    pub fn show<Io: TermIo>(&mut self, ui: &mut Ui, terminal_emulator: &mut TerminalEmulator<Io>) {
-        let character_size = get_char_size(ui.ctx(), self.font_size);
+    /// Space the widget takes up when showing width_chars x height_chars
+    pub fn size_for(&self, ctx: &Context, width_chars: usize, height_chars: usize) -> egui::Vec2 {
+        let character_size = get_char_size(ctx, self.font_size);
+        egui::vec2(
+            (width_chars as f32 + 0.5) * character_size.0,
+            (height_chars as f32 + 0.5) * character_size.1,
+        )
+    }
 
-        terminal_emulator.read();
+    /// The emulator is only locked while input is written and output is taken, not during layout
+    pub fn show<Io: TermIo>(
+        &mut self,
+        ui: &mut Ui,
+        terminal_emulator: &Mutex<TerminalEmulator<Io>>,
+    ) -> Vec<Action> {
+        let character_size = get_char_size(ui.ctx(), self.font_size);
 
         let frame_response = egui::Frame::new().show(ui, |ui| {
-            let (width_chars, height_chars) = terminal_emulator.get_win_size();
+            let mut locked_emulator = lock_terminal_emulator(terminal_emulator);
+            let (width_chars, height_chars) = locked_emulator.get_win_size();
             let width_chars = width_chars as f32;
             let height_chars = height_chars as f32;
 
             ui.set_width((width_chars + 0.5) * character_size.0);
             ui.set_height((height_chars + 0.5) * character_size.1);
 
-            ui.input(|input_state| {
-                write_input_to_terminal(input_state, terminal_emulator);
-            });
+            // Keys pressed while confirming a paste are for the prompt
+            let input_response = match self.pending_paste {
+                Some(_) => InputResponse::default(),
+                None => ui.input(|input_state| {
+                    write_input_to_terminal(input_state, &mut locked_emulator, &self.keybindings)
+                }),
+            };
+            if let Some(text) = input_response.copied_text {
+                ui.ctx().copy_text(text);
+            }
+            if input_response.unconfirmed_paste.is_some() {
+                self.pending_paste = input_response.unconfirmed_paste;
+            }
+            if input_response.wrote_input {
+                self.cursor_blink_start = ui.input(|input| input.time);
+            }
+
+            // Shift bypasses mouse reporting so that text can still be selected
+            let report_mouse = locked_emulator.mouse_tracking() != MouseTracking::Off
+                && self.pending_paste.is_none()
+                && !ui.input(|input| input.modifiers.shift);
+
+            let render_data = locked_emulator.render_data();
+            let palette = locked_emulator.palette().clone();
+            let cursor_pos = locked_emulator.cursor_pos();
+            let cursor_style = locked_emulator.cursor_style();
+            let cursor_visible = locked_emulator.cursor_visible();
+            drop(locked_emulator);
+
+            let output_response = render_terminal_output(
+                ui,
+                render_data,
+                &palette,
+                self.font_size,
+                self.show_newlines,
+                !report_mouse,
+            );
+
+            let mut locked_emulator = lock_terminal_emulator(terminal_emulator);
+            if report_mouse {
+                ui.input(|input| {
+                    report_mouse_events(
+                        input,
+                        &mut locked_emulator,
+                        &output_response,
+                        &character_size,
+                        &mut self.last_mouse_pos,
+                    )
+                });
+            } else {
+                update_selection(ui, &mut locked_emulator, &output_response, &character_size);
+            }
+            drop(locked_emulator);
 
-            let output_response =
-                render_terminal_output(ui, terminal_emulator, self.font_size, self.show_newlines);
             self.debug_renderer
                 .render(ui, output_response.canvas_area, Color32::BLUE);
 
             self.debug_renderer
                 .render(ui, output_response.scrollback_area, Color32::YELLOW);
 
-            paint_cursor(
-                output_response.canvas_area,
-                &character_size,
-                &terminal_emulator.cursor_pos(),
-                ui,
-            );
+            // An unfocused cursor is drawn steady so that it is easy to find
+            let focused = ui.input(|input| input.focused);
+            let show_cursor = cursor_visible
+                && (!cursor_style.blinking
+                    || !focused
+                    || cursor_blink_on(ui.ctx(), self.cursor_blink_start));
+            if show_cursor {
+                paint_cursor(
+                    output_response.canvas_area,
+                    &character_size,
+                    &cursor_pos,
+                    cursor_style,
+                    focused,
+                    rgb_to_egui(palette.cursor),
+                    ui,
+                );
+            }
+
+            input_response.actions
         });
 
         self.debug_renderer
             .render(ui, frame_response.response.rect, Color32::RED);
+
+        self.show_paste_confirmation(ui.ctx(), terminal_emulator);
+
+        frame_response.inner
+    }
+
+    fn show_paste_confirmation<Io: TermIo>(
+        &mut self,
+        ctx: &Context,
+        terminal_emulator: &Mutex<TerminalEmulator<Io>>,
+    ) {
+        let Some(text) = &self.pending_paste else {
+            return;
+        };
+
+        let modal_response = Modal::new(Id::new("paste_confirmation")).show(ctx, |ui| {
+            ui.heading("Paste multiple lines?");
+            ui.label(
+                "The program has not enabled bracketed paste, so each line may run as a command.",
+            );
+            egui::ScrollArea::vertical()
+                .max_height(200.0)
+                .show(ui, |ui| ui.monospace(text));
+
+            ui.horizontal(|ui| {
+                let paste = ui.button("Paste").clicked();
+                let cancel = ui.button("Cancel").clicked();
+                (paste, cancel)
+            })
+            .inner
+        });
+
+        let (paste, cancel) = modal_response.inner;
+        if paste {
+            if let Some(text) = self.pending_paste.take() {
+                let mut terminal_emulator = lock_terminal_emulator(terminal_emulator);
+                if let Err(e) = terminal_emulator.write(TerminalInput::Paste(text)) {
+                    error!(
+                        "Failed to write paste to terminal emulator: {}",
+                        backtraced_err(&*e)
+                    );
+                }
+            }
+        } else if cancel || modal_response.should_close() {
+            self.pending_paste = None;
+        }
     }
 
     pub fn show_options(&mut self, ui: &mut Ui) {
         ui.horizontal(|ui| {
             ui.label("Font size:");
             ui.add(
-                DragValue::new(&mut self.font_size).range(RangeInclusive::new(1.0, 100.0)), // .clamp_range(1.0..=100.0)
+                DragValue::new(&mut self.font_size)
+                    .range(RangeInclusive::new(MIN_FONT_SIZE, MAX_FONT_SIZE)),
             );
         });
         ui.checkbox(&mut self.debug_renderer.enable, "Debug render");
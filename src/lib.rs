@@ -0,0 +1,4 @@
+#[macro_use]
+pub mod log;
+pub mod error;
+pub mod terminal_emulator;
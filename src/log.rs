@@ -1,5 +1,6 @@
 use std::{cell::UnsafeCell, collections::HashMap, fmt::Arguments, mem::MaybeUninit, str::FromStr};
 
+#[macro_export]
 macro_rules! log {
     ($level:expr, $($arg:tt)+) => {
         if $level >= $crate::log::level(module_path!()) {
@@ -12,27 +13,31 @@ macro_rules! log {
     }
 }
 
+#[macro_export]
 macro_rules! debug {
     ($($arg:tt)+) => {
-        log!($crate::log::Level::Debug, $($arg)+)
+        $crate::log!($crate::log::Level::Debug, $($arg)+)
     }
 }
 
+#[macro_export]
 macro_rules! info {
     ($($arg:tt)+) => {
-        log!($crate::log::Level::Info, $($arg)+)
+        $crate::log!($crate::log::Level::Info, $($arg)+)
     }
 }
 
+#[macro_export]
 macro_rules! warn {
     ($($arg:tt)+) => {
-        log!($crate::log::Level::Warn, $($arg)+)
+        $crate::log!($crate::log::Level::Warn, $($arg)+)
     }
 }
 
+#[macro_export]
 macro_rules! error {
     ($($arg:tt)+) => {
-        log!($crate::log::Level::Error, $($arg)+)
+        $crate::log!($crate::log::Level::Error, $($arg)+)
     }
 }
 
@@ -1,85 +1,77 @@
-use std::path::PathBuf;
-use terminal_emulator::TerminalEmulator;
+use cli::{Command, ConvertFormat, RunArgs, FAILURE_EXIT_CODE, USAGE_EXIT_CODE};
+use config::ConfigSource;
+use std::path::Path;
+use termie::{error, log, terminal_emulator};
 
 #[macro_use]
-mod log;
-mod error;
+extern crate termie;
+
+mod cli;
+mod config;
 mod gui;
-mod terminal_emulator;
+mod recording_tools;
 
-struct Args {
-    recording_path: PathBuf,
-    replay: Option<PathBuf>,
+fn run(args: RunArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let config_source = ConfigSource {
+        path: args.config_path.or_else(config::default_config_path),
+        overrides: args.overrides,
+    };
+    let config = config_source.load()?;
+    let terminal_emulator = config.new_terminal_emulator()?;
+    gui::run(terminal_emulator, config, config_source)
 }
 
-impl Args {
-    fn parse<It: Iterator<Item = String>>(mut it: It) -> Args {
-        let program_name = it.next();
+/// Converted in memory first, creating output would truncate input if both are the same file
+fn convert(
+    input: &Path,
+    output: &Path,
+    format: ConvertFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut converted = Vec::new();
+    recording_tools::convert(input, format, &mut converted)?;
+    std::fs::write(output, converted)?;
+    Ok(())
+}
 
-        // Default value
-        let mut recording_path = "recordings".into();
-        let mut replay = None;
+fn main() {
+    log::init();
 
-        while let Some(arg) = it.next() {
-            match arg.as_str() {
-                "--recording-path" => {
-                    recording_path = match it.next() {
-                        Some(p) => p.into(),
-                        None => {
-                            println!("Missing argument for --recording-path");
-                            Self::help(program_name.as_deref());
-                        }
-                    };
-                }
-                "--replay" => replay = it.next().map(PathBuf::from),
-                _ => {
-                    println!("Invalid argument {arg}");
-                    Self::help(program_name.as_deref())
-                }
-            }
+    let mut args = std::env::args();
+    let program_name = args.next().unwrap_or_else(|| "termie".to_string());
+    let command = match cli::parse(args) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{program_name}: {e}");
+            eprintln!("Try '{program_name} --help' for more information");
+            std::process::exit(USAGE_EXIT_CODE);
         }
+    };
 
-        Args {
-            recording_path,
-            replay,
+    let res = match command {
+        Command::Help => {
+            print!("{}", cli::help(&program_name));
+            Ok(())
         }
-    }
-
-    fn help(program_name: Option<&str>) -> ! {
-        let program_name = program_name.unwrap_or("termie");
-        println!(
-            "\
-                 Usage:\n\
-                 {program_name} [ARGS]\n\
-                 \n\
-                 Args:\n\
-                 --recording-path: Optional, where to output recordings to
-                 --replay: Replay a recording
-                 "
-        );
-        std::process::exit(1);
-    }
-}
-
-fn main() {
-    log::init();
-    let args = Args::parse(std::env::args());
-    let res = if let Some(replay) = args.replay {
-        gui::run_replay(replay)
-    } else {
-        match TerminalEmulator::new(args.recording_path) {
-            Ok(v) => gui::run(v),
-            Err(e) => {
-                error!(
-                    "Failed to create terminal emulator: {}",
-                    error::backtraced_err(&e)
-                );
-                return;
-            }
+        Command::Version => {
+            println!("termie {}", env!("CARGO_PKG_VERSION"));
+            Ok(())
         }
+        Command::Run(args) => run(args),
+        Command::Replay { recording } => gui::run_replay(recording),
+        Command::Dump {
+            recording,
+            scrollback,
+        } => recording_tools::dump(&recording, scrollback, &mut std::io::stdout().lock())
+            .map_err(Into::into),
+        Command::Convert {
+            input,
+            output,
+            format,
+        } => convert(&input, &output, format),
     };
 
     if let Err(e) = res {
-        error!("Failed to run gui: {}", error::backtraced_err(&*e));
+        eprintln!("{program_name}: {}", error::backtraced_err(&*e));
+        std::process::exit(FAILURE_EXIT_CODE);
     }
 }
@@ -0,0 +1,238 @@
+use std::{io::Write, path::Path};
+use termie::terminal_emulator::{
+    LoadRecordingError, LoadSnapshotError, Recording, RecordingItem, ReplayIo, TermIoErr,
+    TerminalEmulator,
+};
+use thiserror::Error;
+use tinyjson::JsonValue;
+
+use crate::cli::ConvertFormat;
+
+#[derive(Debug, Error)]
+pub enum RecordingToolError {
+    #[error("failed to load recording")]
+    LoadRecording(#[source] LoadRecordingError),
+    #[error("failed to load initial state of recording")]
+    LoadSnapshot(#[source] LoadSnapshotError),
+    #[error("failed to resize terminal: {0}")]
+    Resize(TermIoErr),
+    #[error("failed to encode asciicast event")]
+    EncodeJson(#[source] tinyjson::JsonGenerateError),
+    #[error("failed to write output")]
+    WriteOutput(#[source] std::io::Error),
+}
+
+fn load_emulator(recording: &Recording) -> Result<TerminalEmulator<ReplayIo>, RecordingToolError> {
+    TerminalEmulator::from_snapshot(recording.initial_state(), ReplayIo)
+        .map_err(RecordingToolError::LoadSnapshot)
+}
+
+/// Write the text on screen once the whole recording has been replayed
+pub fn dump(
+    recording_path: &Path,
+    scrollback: bool,
+    out: &mut dyn Write,
+) -> Result<(), RecordingToolError> {
+    let recording = Recording::load(recording_path).map_err(RecordingToolError::LoadRecording)?;
+    let mut terminal_emulator = load_emulator(&recording)?;
+
+    for item in recording.items() {
+        match item {
+            RecordingItem::Write { data } => terminal_emulator.process_output(data),
+            RecordingItem::SetWinSize { width, height } => terminal_emulator
+                .set_win_size(*width, *height)
+                .map_err(RecordingToolError::Resize)?,
+            RecordingItem::Reply { .. } => (),
+        }
+    }
+
+    let data = terminal_emulator.data();
+    if scrollback {
+        out.write_all(&data.scrollback)
+            .map_err(RecordingToolError::WriteOutput)?;
+    }
+    out.write_all(&data.visible)
+        .map_err(RecordingToolError::WriteOutput)
+}
+
+/// Recordings carry no timing, so every asciicast event happens at time 0
+fn write_asciicast(recording: &Recording, out: &mut dyn Write) -> Result<(), RecordingToolError> {
+    let (width, height) = load_emulator(recording)?.get_win_size();
+
+    let mut write_json = |value: JsonValue| -> Result<(), RecordingToolError> {
+        let line = value.stringify().map_err(RecordingToolError::EncodeJson)?;
+        writeln!(out, "{line}").map_err(RecordingToolError::WriteOutput)
+    };
+
+    write_json(JsonValue::Object(
+        [
+            ("version".to_string(), JsonValue::Number(2.0)),
+            ("width".to_string(), JsonValue::Number(width as f64)),
+            ("height".to_string(), JsonValue::Number(height as f64)),
+        ]
+        .into(),
+    ))?;
+
+    let event = |code: &str, data: String| {
+        JsonValue::Array(vec![
+            JsonValue::Number(0.0),
+            JsonValue::String(code.to_string()),
+            JsonValue::String(data),
+        ])
+    };
+
+    // Output split in the middle of a character is held until the rest of it arrives
+    let mut pending = Vec::new();
+    for item in recording.items() {
+        match item {
+            RecordingItem::Write { data } => {
+                pending.extend_from_slice(data);
+                let len = complete_utf8_len(&pending);
+                if len == 0 {
+                    continue;
+                }
+                let text = String::from_utf8_lossy(&pending[..len]).into_owned();
+                pending.drain(..len);
+                write_json(event("o", text))?;
+            }
+            RecordingItem::SetWinSize { width, height } => {
+                write_json(event("r", format!("{width}x{height}")))?
+            }
+            RecordingItem::Reply { .. } => (),
+        }
+    }
+
+    if !pending.is_empty() {
+        write_json(event("o", String::from_utf8_lossy(&pending).into_owned()))?;
+    }
+
+    Ok(())
+}
+
+/// Length of data without a utf8 sequence that is cut off at the end
+fn complete_utf8_len(data: &[u8]) -> usize {
+    let mut start = 0;
+    loop {
+        match std::str::from_utf8(&data[start..]) {
+            Ok(_) => return data.len(),
+            Err(e) => match e.error_len() {
+                // Invalid rather than incomplete, lossy conversion replaces it
+                Some(len) => start += e.valid_up_to() + len,
+                None => return start + e.valid_up_to(),
+            },
+        }
+    }
+}
+
+pub fn convert(
+    input: &Path,
+    format: ConvertFormat,
+    out: &mut dyn Write,
+) -> Result<(), RecordingToolError> {
+    let recording = Recording::load(input).map_err(RecordingToolError::LoadRecording)?;
+
+    match format {
+        ConvertFormat::Raw => {
+            for item in recording.items() {
+                if let RecordingItem::Write { data } = item {
+                    out.write_all(data)
+                        .map_err(RecordingToolError::WriteOutput)?;
+                }
+            }
+            Ok(())
+        }
+        ConvertFormat::Asciicast => write_asciicast(&recording, out),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dump_and_convert() {
+        let temp_dir = tempfile::TempDir::new().expect("failed to create tmp dir");
+        let mut terminal_emulator = TerminalEmulator::new_with_io(ReplayIo, temp_dir.path().into());
+        let recording_handle = terminal_emulator
+            .start_recording()
+            .expect("failed to start recording");
+        terminal_emulator.process_output(b"hello\r\n\x1b[31mworld");
+        terminal_emulator
+            .set_win_size(20, 5)
+            .expect("failed to resize");
+        terminal_emulator.process_output("\r\nüber".as_bytes());
+        // Saves the recording
+        drop(recording_handle);
+
+        let recording_path = temp_dir.path().join("0.json");
+
+        let mut out = Vec::new();
+        dump(&recording_path, false, &mut out).expect("failed to dump");
+        assert_eq!(out, "hello\nworld\nüber\n".as_bytes());
+
+        let mut out = Vec::new();
+        convert(&recording_path, ConvertFormat::Raw, &mut out).expect("failed to convert");
+        assert_eq!(out, "hello\r\n\x1b[31mworld\r\nüber".as_bytes());
+
+        let mut out = Vec::new();
+        convert(&recording_path, ConvertFormat::Asciicast, &mut out).expect("failed to convert");
+        let lines = String::from_utf8(out).expect("asciicast should be utf8");
+        let lines = lines
+            .lines()
+            .map(|line| line.parse::<JsonValue>().expect("invalid json"))
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0]["version"], JsonValue::Number(2.0));
+        assert_eq!(lines[0]["width"], JsonValue::Number(50.0));
+        assert_eq!(lines[0]["height"], JsonValue::Number(16.0));
+        assert_eq!(
+            lines[1][2],
+            JsonValue::String("hello\r\n\x1b[31mworld".into())
+        );
+        assert_eq!(lines[2][1], JsonValue::String("r".into()));
+        assert_eq!(lines[2][2], JsonValue::String("20x5".into()));
+        assert_eq!(lines[3][2], JsonValue::String("\r\nüber".into()));
+    }
+
+    #[test]
+    fn test_asciicast_split_char() {
+        let temp_dir = tempfile::TempDir::new().expect("failed to create tmp dir");
+        let mut terminal_emulator = TerminalEmulator::new_with_io(ReplayIo, temp_dir.path().into());
+        let recording_handle = terminal_emulator
+            .start_recording()
+            .expect("failed to start recording");
+        // Resizing in between keeps the writes from being merged
+        for (i, data) in [&b"a\xc3"[..], b"\xbc\xe2\x82", b"\xac\xff\xc3"]
+            .into_iter()
+            .enumerate()
+        {
+            terminal_emulator.process_output(data);
+            terminal_emulator
+                .set_win_size(20 + i, 5)
+                .expect("failed to resize");
+        }
+        drop(recording_handle);
+
+        let mut out = Vec::new();
+        convert(
+            &temp_dir.path().join("0.json"),
+            ConvertFormat::Asciicast,
+            &mut out,
+        )
+        .expect("failed to convert");
+        let lines = String::from_utf8(out).expect("asciicast should be utf8");
+        let output = lines
+            .lines()
+            .skip(1)
+            .map(|line| line.parse::<JsonValue>().expect("invalid json"))
+            .filter(|event| event[1] == JsonValue::String("o".into()))
+            .map(|event| {
+                event[2]
+                    .get::<String>()
+                    .expect("data is not a string")
+                    .clone()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(output, ["a", "ü", "€\u{fffd}", "\u{fffd}"]);
+    }
+}
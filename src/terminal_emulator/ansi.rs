@@ -1,6 +1,8 @@
 use super::{
+    charset::{Charset, CharsetSlot},
+    palette::{PaletteEntry, Rgb},
     recording::{NotIntOfType, NotMap},
-    Mode,
+    CursorStyle, Mode,
 };
 use crate::terminal_emulator::recording::SnapshotItem;
 use thiserror::Error;
@@ -10,6 +12,16 @@ pub enum SelectGraphicRendition {
     // NOTE: Non-exhaustive list
     Reset,
     Bold,
+    Dim,
+    Italic,
+    Underline,
+    Reverse,
+    Strikethrough,
+    NormalIntensity,
+    NotItalic,
+    NotUnderlined,
+    NotReversed,
+    NotStrikethrough,
     ForegroundBlack,
     ForegroundRed,
     ForegroundGreen,
@@ -26,6 +38,28 @@ pub enum SelectGraphicRendition {
     ForegroundBrightMagenta,
     ForegroundBrightCyan,
     ForegroundBrightWhite,
+    ForegroundIndexed(u8),
+    ForegroundRgb(u8, u8, u8),
+    DefaultForeground,
+    BackgroundBlack,
+    BackgroundRed,
+    BackgroundGreen,
+    BackgroundYellow,
+    BackgroundBlue,
+    BackgroundMagenta,
+    BackgroundCyan,
+    BackgroundWhite,
+    BackgroundBrightBlack,
+    BackgroundBrightRed,
+    BackgroundBrightGreen,
+    BackgroundBrightYellow,
+    BackgroundBrightBlue,
+    BackgroundBrightMagenta,
+    BackgroundBrightCyan,
+    BackgroundBrightWhite,
+    BackgroundIndexed(u8),
+    BackgroundRgb(u8, u8, u8),
+    DefaultBackground,
     Unknown(usize),
 }
 
@@ -34,6 +68,18 @@ impl SelectGraphicRendition {
         match val {
             0 => SelectGraphicRendition::Reset,
             1 => SelectGraphicRendition::Bold,
+            2 => SelectGraphicRendition::Dim,
+            3 => SelectGraphicRendition::Italic,
+            4 => SelectGraphicRendition::Underline,
+            7 => SelectGraphicRendition::Reverse,
+            9 => SelectGraphicRendition::Strikethrough,
+            // Doubly underlined, we only have one kind of underline
+            21 => SelectGraphicRendition::Underline,
+            22 => SelectGraphicRendition::NormalIntensity,
+            23 => SelectGraphicRendition::NotItalic,
+            24 => SelectGraphicRendition::NotUnderlined,
+            27 => SelectGraphicRendition::NotReversed,
+            29 => SelectGraphicRendition::NotStrikethrough,
             30 => SelectGraphicRendition::ForegroundBlack,
             31 => SelectGraphicRendition::ForegroundRed,
             32 => SelectGraphicRendition::ForegroundGreen,
@@ -42,6 +88,16 @@ impl SelectGraphicRendition {
             35 => SelectGraphicRendition::ForegroundMagenta,
             36 => SelectGraphicRendition::ForegroundCyan,
             37 => SelectGraphicRendition::ForegroundWhite,
+            39 => SelectGraphicRendition::DefaultForeground,
+            40 => SelectGraphicRendition::BackgroundBlack,
+            41 => SelectGraphicRendition::BackgroundRed,
+            42 => SelectGraphicRendition::BackgroundGreen,
+            43 => SelectGraphicRendition::BackgroundYellow,
+            44 => SelectGraphicRendition::BackgroundBlue,
+            45 => SelectGraphicRendition::BackgroundMagenta,
+            46 => SelectGraphicRendition::BackgroundCyan,
+            47 => SelectGraphicRendition::BackgroundWhite,
+            49 => SelectGraphicRendition::DefaultBackground,
             90 => SelectGraphicRendition::ForegroundBrightBlack,
             91 => SelectGraphicRendition::ForegroundBrightRed,
             92 => SelectGraphicRendition::ForegroundBrightGreen,
@@ -50,22 +106,161 @@ impl SelectGraphicRendition {
             95 => SelectGraphicRendition::ForegroundBrightMagenta,
             96 => SelectGraphicRendition::ForegroundBrightCyan,
             97 => SelectGraphicRendition::ForegroundBrightWhite,
+            100 => SelectGraphicRendition::BackgroundBrightBlack,
+            101 => SelectGraphicRendition::BackgroundBrightRed,
+            102 => SelectGraphicRendition::BackgroundBrightGreen,
+            103 => SelectGraphicRendition::BackgroundBrightYellow,
+            104 => SelectGraphicRendition::BackgroundBrightBlue,
+            105 => SelectGraphicRendition::BackgroundBrightMagenta,
+            106 => SelectGraphicRendition::BackgroundBrightCyan,
+            107 => SelectGraphicRendition::BackgroundBrightWhite,
             _ => Self::Unknown(val),
         }
     }
+
+    /// Builds an extended color from the parameters following a 38 or 48, e.g. the `5;n` in
+    /// `38;5;n` or the `2;r;g;b` in `38;2;r;g;b`
+    ///
+    /// Returns the parsed color, or None if it is truncated or out of range, and the number of
+    /// parameters consumed. A broken color still consumes its parameters so that the rest of the
+    /// SGR can be applied
+    fn extended_color(
+        is_background: bool,
+        params: &[Option<usize>],
+    ) -> (Option<SelectGraphicRendition>, usize) {
+        let to_u8 = |idx: usize| -> Option<u8> {
+            let val = params.get(idx)?.unwrap_or(0);
+            val.try_into().ok()
+        };
+
+        let (sgr, consumed) = match params.first().copied().flatten() {
+            Some(5) => {
+                let sgr = to_u8(1).map(|idx| match is_background {
+                    true => SelectGraphicRendition::BackgroundIndexed(idx),
+                    false => SelectGraphicRendition::ForegroundIndexed(idx),
+                });
+                (sgr, 2)
+            }
+            Some(2) => {
+                let sgr = match (to_u8(1), to_u8(2), to_u8(3)) {
+                    (Some(r), Some(g), Some(b)) => Some(match is_background {
+                        true => SelectGraphicRendition::BackgroundRgb(r, g, b),
+                        false => SelectGraphicRendition::ForegroundRgb(r, g, b),
+                    }),
+                    _ => None,
+                };
+                (sgr, 4)
+            }
+            // Unknown color space, only the id is known to belong to the color
+            _ => (None, 1),
+        };
+        (sgr, consumed.min(params.len()))
+    }
+
+    /// Handles a single parameter that used colon separated sub-parameters (ITU T.416), e.g.
+    /// `38:2::r:g:b` or `4:3`
+    fn from_sub_params(sub_params: &[Option<usize>]) -> Option<SelectGraphicRendition> {
+        let code = sub_params.first().copied().flatten().unwrap_or(0);
+        match code {
+            38 | 48 => {
+                let is_background = code == 48;
+                let mut color_params = sub_params[1..].to_vec();
+                // The truecolor form has a (usually empty) color space id before r:g:b, but some
+                // applications leave it out entirely
+                if color_params.first() == Some(&Some(2)) && color_params.len() >= 5 {
+                    color_params.remove(1);
+                }
+                Self::extended_color(is_background, &color_params).0
+            }
+            // Underline style, anything other than "no underline" is drawn as a plain underline
+            4 => match sub_params.get(1).copied().flatten() {
+                Some(0) => Some(SelectGraphicRendition::NotUnderlined),
+                _ => Some(SelectGraphicRendition::Underline),
+            },
+            _ => Some(Self::from_usize(code)),
+        }
+    }
 }
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum TerminalOutput {
-    SetCursorPos { x: Option<usize>, y: Option<usize> },
-    SetCursorPosRel { x: Option<i32>, y: Option<i32> },
+    SetCursorPos {
+        x: Option<usize>,
+        y: Option<usize>,
+    },
+    SetCursorPosRel {
+        x: Option<i32>,
+        y: Option<i32>,
+    },
     ClearForwards,
+    // ed 1 (8.3.39 of ecma-48)
+    ClearBackwards,
     ClearAll,
+    ClearScrollback,
     CarriageReturn,
     ClearLineForwards,
+    // el 1 (8.3.41 of ecma-48)
+    ClearLineBackwards,
+    // el 2
+    ClearLine,
+    // ech (8.3.38 of ecma-48)
+    EraseChars(usize),
     Newline,
     Backspace,
     InsertLines(usize),
+    // dl (8.3.32 of ecma-48)
+    DeleteLines(usize),
+    // su (8.3.147 of ecma-48)
+    ScrollUp(usize),
+    // sd (8.3.113 of ecma-48)
+    ScrollDown(usize),
+    // DECSTBM, 1 indexed and inclusive. None means the edge of the screen
+    SetScrollRegion {
+        top: Option<usize>,
+        bottom: Option<usize>,
+    },
+    // ESC D
+    Index,
+    // ESC E
+    NextLine,
+    // ESC M
+    ReverseIndex,
+    // HT, or CHT (8.3.10 of ecma-48) with a count
+    ForwardTab(usize),
+    // CBT (8.3.7 of ecma-48)
+    BackwardTab(usize),
+    // HTS (ESC H)
+    SetTabStop,
+    // DECSC (ESC 7) or SCOSC (CSI s)
+    SaveCursor,
+    // DECRC (ESC 8) or SCORC (CSI u)
+    RestoreCursor,
+    // TBC 0 (8.3.154 of ecma-48)
+    ClearTabStop,
+    // TBC 3
+    ClearAllTabStops,
+    // SCS (ESC ( or ESC ) followed by the charset)
+    DesignateCharset(CharsetSlot, Charset),
+    // SO, use G1
+    ShiftOut,
+    // SI, use G0
+    ShiftIn,
+    // DECKPAM (ESC =)
+    ApplicationKeypad,
+    // DECKPNM (ESC >)
+    NormalKeypad,
+    // DSR 5 (8.3.35 of ecma-48)
+    RequestStatusReport,
+    // DSR 6
+    RequestCursorPosition,
+    // DA1 (8.3.24 of ecma-48)
+    RequestPrimaryDeviceAttributes,
+    // DA2
+    RequestSecondaryDeviceAttributes,
+    // XTVERSION
+    RequestTerminalVersion,
+    // DECSCUSR (CSI Ps SP q)
+    SetCursorStyle(CursorStyle),
     Delete(usize),
     Sgr(SelectGraphicRendition),
     Data(Vec<u8>),
@@ -73,6 +268,19 @@ pub enum TerminalOutput {
     ResetMode(Mode),
     // ich (8.3.64 of ecma-48)
     InsertSpaces(usize),
+    // OSC 0/1
+    SetIconName(String),
+    // OSC 0/2
+    SetWindowTitle(String),
+    // OSC 4/10/11/12 with a color
+    SetPaletteEntry(PaletteEntry, Rgb),
+    // OSC 4/10/11/12 with ?. Answered with the same string terminator as the query
+    QueryPaletteEntry {
+        entry: PaletteEntry,
+        bel_terminated: bool,
+    },
+    // OSC 104/110/111/112. None resets all indexed colors
+    ResetPaletteEntry(Option<PaletteEntry>),
     Invalid,
 }
 
@@ -120,6 +328,53 @@ fn split_params_into_semicolon_delimited_usize(params: &[u8]) -> Result<Vec<Opti
     params
 }
 
+fn parse_sgr_params(params: &[u8]) -> Result<Vec<SelectGraphicRendition>, ()> {
+    let groups = params
+        .split(|b| *b == b';')
+        .map(|group| {
+            group
+                .split(|b| *b == b':')
+                .map(parse_param_as::<usize>)
+                .collect::<Result<Vec<Option<usize>>, ()>>()
+        })
+        .collect::<Result<Vec<_>, ()>>()?;
+
+    let mut ret = Vec::new();
+    let mut idx = 0;
+    while idx < groups.len() {
+        let group = &groups[idx];
+        idx += 1;
+
+        if group.len() > 1 {
+            match SelectGraphicRendition::from_sub_params(group) {
+                Some(sgr) => ret.push(sgr),
+                None => warn!("Invalid SGR color {group:?}"),
+            }
+            continue;
+        }
+
+        // An omitted parameter is equivalent to 0
+        let code = group[0].unwrap_or(0);
+        if code == 38 || code == 48 {
+            let remaining = groups[idx..]
+                .iter()
+                .map(|group| group[0])
+                .collect::<Vec<_>>();
+            let (sgr, consumed) = SelectGraphicRendition::extended_color(code == 48, &remaining);
+            match sgr {
+                Some(sgr) => ret.push(sgr),
+                None => warn!("Invalid SGR color {:?}", &remaining[..consumed]),
+            }
+            idx += consumed;
+            continue;
+        }
+
+        ret.push(SelectGraphicRendition::from_usize(code));
+    }
+
+    Ok(ret)
+}
+
 fn parse_param_as<T: std::str::FromStr>(param_bytes: &[u8]) -> Result<Option<T>, ()> {
     let param_str =
         std::str::from_utf8(param_bytes).expect("parameter should always be valid utf8");
@@ -130,16 +385,79 @@ fn parse_param_as<T: std::str::FromStr>(param_bytes: &[u8]) -> Result<Option<T>,
     Ok(Some(param))
 }
 
+/// Pushes accumulated data as a Data output. Invalid or incomplete utf8 is replaced with
+/// U+FFFD so that consumers can rely on data being valid utf8
 fn push_data_if_non_empty(data: &mut Vec<u8>, output: &mut Vec<TerminalOutput>) {
-    if !data.is_empty() {
-        output.push(TerminalOutput::Data(std::mem::take(data)));
+    if data.is_empty() {
+        return;
     }
+
+    let data = std::mem::take(data);
+    let data = match String::from_utf8(data) {
+        Ok(s) => s.into_bytes(),
+        Err(e) => String::from_utf8_lossy(e.as_bytes())
+            .into_owned()
+            .into_bytes(),
+    };
+    output.push(TerminalOutput::Data(data));
+}
+
+/// Splits off a utf8 sequence at the end of data that has not been fully received yet
+fn split_incomplete_utf8(data: &mut Vec<u8>) -> Vec<u8> {
+    // Sequences are at most 4 bytes, so an incomplete one starts in the last 3
+    let search_start = data.len().saturating_sub(3);
+    for i in (search_start..data.len()).rev() {
+        let sequence_len = match data[i] {
+            // Continuation byte, keep looking for the start
+            0x80..=0xbf => continue,
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => return Vec::new(),
+        };
+
+        if data.len() - i < sequence_len {
+            return data.split_off(i);
+        }
+        return Vec::new();
+    }
+
+    Vec::new()
+}
+
+/// Modes of a SM/RM sequence, several may be set at once with `;`. A leading `?` applies to all
+/// of them
+fn modes_from_params(params: &[u8]) -> Vec<Mode> {
+    let (prefix, params) = match params.strip_prefix(b"?") {
+        Some(params) => (b"?".as_slice(), params),
+        None => (b"".as_slice(), params),
+    };
+    params
+        .split(|b| *b == b';')
+        .map(|param| mode_from_params(&[prefix, param].concat()))
+        .collect()
 }
 
 fn mode_from_params(params: &[u8]) -> Mode {
     match params {
         // https://vt100.net/docs/vt510-rm/DECCKM.html
         b"?1" => Mode::Decckm,
+        // https://vt100.net/docs/vt510-rm/DECOM.html
+        b"?6" => Mode::Decom,
+        // https://vt100.net/docs/vt510-rm/DECAWM.html
+        b"?7" => Mode::Decawm,
+        // https://vt100.net/docs/vt510-rm/DECTCEM.html
+        b"?25" => Mode::Dectcem,
+        // https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h2-The-Alternate-Screen-Buffer
+        b"?47" | b"?1047" => Mode::AlternateScreen,
+        b"?1049" => Mode::AlternateScreenSaveCursor,
+        // https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h2-Mouse-Tracking
+        b"?1000" => Mode::MouseClickTracking,
+        b"?1002" => Mode::MouseDragTracking,
+        b"?1003" => Mode::MouseMotionTracking,
+        b"?1006" => Mode::SgrMouse,
+        // https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h2-Bracketed-Paste-Mode
+        b"?2004" => Mode::BracketedPaste,
         _ => Mode::Unknown(params.to_vec()),
     }
 }
@@ -330,6 +648,241 @@ impl CsiParser {
     }
 }
 
+#[derive(Eq, PartialEq, Debug)]
+enum OscParserState {
+    Data,
+    // Saw an ESC, waiting to see if it is the start of ST (ESC \)
+    Escape,
+    Finished,
+    Invalid,
+}
+
+mod osc_parser_state_keys {
+    pub const DATA: &str = "data";
+    pub const ESCAPE: &str = "escape";
+    pub const FINISHED: &str = "finished";
+    pub const INVALID: &str = "invalid";
+}
+
+/// Longest OSC string we are willing to buffer. Anything past this is dropped, but we still wait
+/// for the terminator so that the rest of the string does not leak into the terminal
+const MAX_OSC_LEN: usize = 4096;
+
+#[derive(Debug, Error)]
+enum LoadOscParserSnapshotError {
+    #[error(transparent)]
+    RootItemNotMap(NotMap),
+    #[error("could not find item {0}")]
+    MissingItem(&'static str),
+    #[error("data is not an array")]
+    DataNotVec,
+    #[error("data element is not a u8")]
+    DataNotU8(#[source] NotIntOfType),
+    #[error("state is not a string")]
+    StateNotString,
+    #[error("{0} is not a valid state")]
+    InvalidState(String),
+}
+
+// https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h3-Operating-System-Commands
+#[derive(Eq, PartialEq, Debug)]
+struct OscParser {
+    state: OscParserState,
+    data: Vec<u8>,
+}
+
+mod osc_parser_keys {
+    pub const STATE: &str = "state";
+    pub const DATA: &str = "data";
+}
+
+impl OscParser {
+    fn new() -> OscParser {
+        OscParser {
+            state: OscParserState::Data,
+            data: Vec::new(),
+        }
+    }
+
+    fn snapshot(&self) -> SnapshotItem {
+        let state = match self.state {
+            OscParserState::Data => osc_parser_state_keys::DATA,
+            OscParserState::Escape => osc_parser_state_keys::ESCAPE,
+            OscParserState::Finished => osc_parser_state_keys::FINISHED,
+            OscParserState::Invalid => osc_parser_state_keys::INVALID,
+        };
+
+        SnapshotItem::Map(
+            [
+                (osc_parser_keys::STATE.to_string(), state.into()),
+                (
+                    osc_parser_keys::DATA.to_string(),
+                    self.data.iter().collect(),
+                ),
+            ]
+            .into(),
+        )
+    }
+
+    fn from_snapshot(snapshot: SnapshotItem) -> Result<OscParser, LoadOscParserSnapshotError> {
+        use LoadOscParserSnapshotError::*;
+        let mut items = snapshot.into_map().map_err(RootItemNotMap)?;
+
+        let data = items
+            .remove(osc_parser_keys::DATA)
+            .ok_or(MissingItem(osc_parser_keys::DATA))?;
+        let data = data
+            .into_vec()
+            .map_err(|_| DataNotVec)?
+            .into_iter()
+            .map(|item| item.into_num::<u8>().map_err(DataNotU8))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let state = items
+            .remove(osc_parser_keys::STATE)
+            .ok_or(MissingItem(osc_parser_keys::STATE))?;
+        let state = state.into_string().map_err(|_| StateNotString)?;
+        let state = match state.as_str() {
+            osc_parser_state_keys::DATA => OscParserState::Data,
+            osc_parser_state_keys::ESCAPE => OscParserState::Escape,
+            osc_parser_state_keys::FINISHED => OscParserState::Finished,
+            osc_parser_state_keys::INVALID => OscParserState::Invalid,
+            _ => Err(InvalidState(state))?,
+        };
+
+        Ok(OscParser { state, data })
+    }
+
+    fn push(&mut self, b: u8) {
+        match &self.state {
+            OscParserState::Data => {
+                // BEL is not technically a string terminator, but xterm accepts it and most
+                // applications use it
+                if b == 0x07 {
+                    self.state = OscParserState::Finished;
+                } else if b == 0x1b {
+                    self.state = OscParserState::Escape;
+                } else if self.data.len() < MAX_OSC_LEN {
+                    self.data.push(b);
+                }
+            }
+            OscParserState::Escape => {
+                if b == b'\\' {
+                    self.state = OscParserState::Finished;
+                } else {
+                    self.state = OscParserState::Invalid;
+                }
+            }
+            OscParserState::Finished | OscParserState::Invalid => {
+                panic!("OscParser should not be pushed to once finished");
+            }
+        }
+    }
+
+    fn finish(&self, output: &mut Vec<TerminalOutput>, bel_terminated: bool) {
+        let (command, arg) = match self.data.iter().position(|b| *b == b';') {
+            Some(pos) => (&self.data[..pos], &self.data[pos + 1..]),
+            None => (self.data.as_slice(), [].as_slice()),
+        };
+
+        // Unlike CSI params, nothing has checked that this is utf8
+        let Some(command) = std::str::from_utf8(command)
+            .ok()
+            .and_then(|s| s.parse::<usize>().ok())
+        else {
+            warn!(
+                "Invalid osc command: {:?}",
+                String::from_utf8_lossy(command)
+            );
+            output.push(TerminalOutput::Invalid);
+            return;
+        };
+
+        let arg = String::from_utf8_lossy(arg).into_owned();
+        match command {
+            0 => {
+                output.push(TerminalOutput::SetIconName(arg.clone()));
+                output.push(TerminalOutput::SetWindowTitle(arg));
+            }
+            1 => output.push(TerminalOutput::SetIconName(arg)),
+            2 => output.push(TerminalOutput::SetWindowTitle(arg)),
+            // index;spec pairs
+            4 => {
+                let mut it = arg.split(';');
+                while let Some(idx) = it.next() {
+                    let (Ok(idx), Some(spec)) = (idx.parse::<u8>(), it.next()) else {
+                        warn!("Invalid osc 4 argument: {arg:?}");
+                        output.push(TerminalOutput::Invalid);
+                        return;
+                    };
+                    push_palette_spec(output, PaletteEntry::Indexed(idx), spec, bel_terminated);
+                }
+            }
+            // Each additional spec applies to the next dynamic color, e.g. OSC 10;fg;bg
+            10..=12 => {
+                let entries = [
+                    PaletteEntry::Foreground,
+                    PaletteEntry::Background,
+                    PaletteEntry::Cursor,
+                ];
+                for (entry, spec) in entries[command - 10..].iter().zip(arg.split(';')) {
+                    push_palette_spec(output, *entry, spec, bel_terminated);
+                }
+            }
+            104 if arg.is_empty() => output.push(TerminalOutput::ResetPaletteEntry(None)),
+            104 => {
+                for idx in arg.split(';') {
+                    match idx.parse::<u8>() {
+                        Ok(idx) => output.push(TerminalOutput::ResetPaletteEntry(Some(
+                            PaletteEntry::Indexed(idx),
+                        ))),
+                        Err(_) => {
+                            warn!("Invalid osc 104 argument: {arg:?}");
+                            output.push(TerminalOutput::Invalid);
+                        }
+                    }
+                }
+            }
+            110 => output.push(TerminalOutput::ResetPaletteEntry(Some(
+                PaletteEntry::Foreground,
+            ))),
+            111 => output.push(TerminalOutput::ResetPaletteEntry(Some(
+                PaletteEntry::Background,
+            ))),
+            112 => output.push(TerminalOutput::ResetPaletteEntry(Some(
+                PaletteEntry::Cursor,
+            ))),
+            _ => {
+                warn!("Unhandled osc command: {command}");
+                output.push(TerminalOutput::Invalid);
+            }
+        }
+    }
+}
+
+fn push_palette_spec(
+    output: &mut Vec<TerminalOutput>,
+    entry: PaletteEntry,
+    spec: &str,
+    bel_terminated: bool,
+) {
+    if spec == "?" {
+        output.push(TerminalOutput::QueryPaletteEntry {
+            entry,
+            bel_terminated,
+        });
+        return;
+    }
+
+    match Rgb::parse_xcolor(spec) {
+        Some(color) => output.push(TerminalOutput::SetPaletteEntry(entry, color)),
+        None => {
+            warn!("Unsupported color spec: {spec:?}");
+            output.push(TerminalOutput::Invalid);
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 enum LoadSnapshotErrorKind {
     #[error("{0} is not a {1}")]
@@ -340,6 +893,8 @@ enum LoadSnapshotErrorKind {
     UnknownElem(&'static str, String),
     #[error("failed to load csi parser snapshot")]
     Csi(#[from] LoadCsiParserSnapshotError),
+    #[error("failed to load osc parser snapshot")]
+    Osc(#[from] LoadOscParserSnapshotError),
 }
 
 #[derive(Debug, Error)]
@@ -350,25 +905,34 @@ pub struct LoadSnapshotError(#[from] LoadSnapshotErrorKind);
 enum AnsiParserInner {
     Empty,
     Escape,
+    // Waiting for the charset of an SCS sequence
+    Designate(CharsetSlot),
     Csi(CsiParser),
+    Osc(OscParser),
 }
 
 mod ansi_parser_keys {
     pub const EMPTY: &str = "empty";
     pub const ESCAPE: &str = "escape";
+    pub const DESIGNATE: &str = "designate";
     pub const CSI: &str = "csi";
+    pub const OSC: &str = "osc";
     pub const TYPE: &str = "type";
     pub const VAL: &str = "val";
+    pub const PARTIAL_UTF8: &str = "partial_utf8";
 }
 
 pub struct AnsiParser {
     inner: AnsiParserInner,
+    // Start of a utf8 sequence that was split across push() calls
+    partial_utf8: Vec<u8>,
 }
 
 impl AnsiParser {
     pub fn new() -> AnsiParser {
         AnsiParser {
             inner: AnsiParserInner::Empty,
+            partial_utf8: Vec::new(),
         }
     }
 
@@ -384,6 +948,17 @@ impl AnsiParser {
         let inner = match typ.as_str() {
             ansi_parser_keys::EMPTY => AnsiParserInner::Empty,
             ansi_parser_keys::ESCAPE => AnsiParserInner::Escape,
+            ansi_parser_keys::DESIGNATE => {
+                let slot = root
+                    .remove(ansi_parser_keys::VAL)
+                    .ok_or(MissingElem("root", ansi_parser_keys::VAL))?
+                    .into_string()
+                    .map_err(|_| WrongType(ansi_parser_keys::VAL, "string"))?;
+                let Some(slot) = CharsetSlot::from_str(&slot) else {
+                    Err(UnknownElem(ansi_parser_keys::VAL, slot))?
+                };
+                AnsiParserInner::Designate(slot)
+            }
             ansi_parser_keys::CSI => {
                 let item = root
                     .remove(ansi_parser_keys::VAL)
@@ -392,43 +967,137 @@ impl AnsiParser {
                     CsiParser::from_snapshot(item).map_err(LoadSnapshotErrorKind::Csi)?,
                 )
             }
+            ansi_parser_keys::OSC => {
+                let item = root
+                    .remove(ansi_parser_keys::VAL)
+                    .ok_or(MissingElem("root", ansi_parser_keys::VAL))?;
+                AnsiParserInner::Osc(
+                    OscParser::from_snapshot(item).map_err(LoadSnapshotErrorKind::Osc)?,
+                )
+            }
             _ => Err(UnknownElem("type", typ))?,
         };
-        Ok(AnsiParser { inner })
+
+        let partial_utf8 = root
+            .remove(ansi_parser_keys::PARTIAL_UTF8)
+            .ok_or(MissingElem("root", ansi_parser_keys::PARTIAL_UTF8))?
+            .into_vec()
+            .map_err(|_| WrongType(ansi_parser_keys::PARTIAL_UTF8, "array"))?
+            .into_iter()
+            .map(|item| item.into_num::<u8>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| WrongType(ansi_parser_keys::PARTIAL_UTF8, "u8 array"))?;
+
+        Ok(AnsiParser {
+            inner,
+            partial_utf8,
+        })
     }
 
     pub fn snapshot(&self) -> SnapshotItem {
-        match &self.inner {
-            AnsiParserInner::Empty => SnapshotItem::Map(
-                [(
+        let mut ret: std::collections::HashMap<String, SnapshotItem> = match &self.inner {
+            AnsiParserInner::Empty => [(
+                ansi_parser_keys::TYPE.to_string(),
+                ansi_parser_keys::EMPTY.into(),
+            )]
+            .into(),
+            AnsiParserInner::Escape => [(
+                ansi_parser_keys::TYPE.to_string(),
+                ansi_parser_keys::ESCAPE.into(),
+            )]
+            .into(),
+            AnsiParserInner::Designate(slot) => [
+                (
                     ansi_parser_keys::TYPE.to_string(),
-                    ansi_parser_keys::EMPTY.into(),
-                )]
-                .into(),
-            ),
-            AnsiParserInner::Escape => SnapshotItem::Map(
-                [(
+                    ansi_parser_keys::DESIGNATE.into(),
+                ),
+                (ansi_parser_keys::VAL.to_string(), slot.as_str().into()),
+            ]
+            .into(),
+            AnsiParserInner::Csi(v) => [
+                (
                     ansi_parser_keys::TYPE.to_string(),
-                    ansi_parser_keys::ESCAPE.into(),
-                )]
-                .into(),
-            ),
-            AnsiParserInner::Csi(v) => SnapshotItem::Map(
-                [
-                    (
-                        ansi_parser_keys::TYPE.to_string(),
-                        ansi_parser_keys::CSI.into(),
-                    ),
-                    (ansi_parser_keys::VAL.to_string(), v.snapshot()),
-                ]
-                .into(),
-            ),
+                    ansi_parser_keys::CSI.into(),
+                ),
+                (ansi_parser_keys::VAL.to_string(), v.snapshot()),
+            ]
+            .into(),
+            AnsiParserInner::Osc(v) => [
+                (
+                    ansi_parser_keys::TYPE.to_string(),
+                    ansi_parser_keys::OSC.into(),
+                ),
+                (ansi_parser_keys::VAL.to_string(), v.snapshot()),
+            ]
+            .into(),
+        };
+
+        ret.insert(
+            ansi_parser_keys::PARTIAL_UTF8.to_string(),
+            self.partial_utf8.iter().collect(),
+        );
+        SnapshotItem::Map(ret)
+    }
+
+    /// Handles b following an ESC
+    fn push_escape(&mut self, b: u8, output: &mut Vec<TerminalOutput>) {
+        match b {
+            b'[' => {
+                self.inner = AnsiParserInner::Csi(CsiParser::new());
+            }
+            b']' => {
+                self.inner = AnsiParserInner::Osc(OscParser::new());
+            }
+            b'D' => {
+                output.push(TerminalOutput::Index);
+                self.inner = AnsiParserInner::Empty;
+            }
+            b'E' => {
+                output.push(TerminalOutput::NextLine);
+                self.inner = AnsiParserInner::Empty;
+            }
+            b'M' => {
+                output.push(TerminalOutput::ReverseIndex);
+                self.inner = AnsiParserInner::Empty;
+            }
+            b'H' => {
+                output.push(TerminalOutput::SetTabStop);
+                self.inner = AnsiParserInner::Empty;
+            }
+            b'7' => {
+                output.push(TerminalOutput::SaveCursor);
+                self.inner = AnsiParserInner::Empty;
+            }
+            b'8' => {
+                output.push(TerminalOutput::RestoreCursor);
+                self.inner = AnsiParserInner::Empty;
+            }
+            b'(' => {
+                self.inner = AnsiParserInner::Designate(CharsetSlot::G0);
+            }
+            b')' => {
+                self.inner = AnsiParserInner::Designate(CharsetSlot::G1);
+            }
+            b'=' => {
+                output.push(TerminalOutput::ApplicationKeypad);
+                self.inner = AnsiParserInner::Empty;
+            }
+            b'>' => {
+                output.push(TerminalOutput::NormalKeypad);
+                self.inner = AnsiParserInner::Empty;
+            }
+            _ => {
+                let b_utf8 = std::char::from_u32(b as u32);
+                warn!("Unhandled escape sequence {b_utf8:?} {b:x}");
+                self.inner = AnsiParserInner::Empty;
+            }
         }
     }
 
     pub fn push(&mut self, incoming: &[u8]) -> Vec<TerminalOutput> {
         let mut output = Vec::new();
-        let mut data_output = Vec::new();
+        // Partial sequences are only held while in the empty state, so they continue here
+        let mut data_output = std::mem::take(&mut self.partial_utf8);
         for b in incoming {
             match &mut self.inner {
                 AnsiParserInner::Empty => {
@@ -455,21 +1124,47 @@ impl AnsiParser {
                         continue;
                     }
 
+                    if *b == b'\t' {
+                        push_data_if_non_empty(&mut data_output, &mut output);
+                        output.push(TerminalOutput::ForwardTab(1));
+                        continue;
+                    }
+
+                    if *b == 0x0e {
+                        push_data_if_non_empty(&mut data_output, &mut output);
+                        output.push(TerminalOutput::ShiftOut);
+                        continue;
+                    }
+
+                    if *b == 0x0f {
+                        push_data_if_non_empty(&mut data_output, &mut output);
+                        output.push(TerminalOutput::ShiftIn);
+                        continue;
+                    }
+
                     data_output.push(*b);
                 }
                 AnsiParserInner::Escape => {
                     push_data_if_non_empty(&mut data_output, &mut output);
-
-                    match b {
-                        b'[' => {
-                            self.inner = AnsiParserInner::Csi(CsiParser::new());
+                    self.push_escape(*b, &mut output);
+                }
+                AnsiParserInner::Designate(slot) => {
+                    let charset = match b {
+                        b'B' => Some(Charset::Ascii),
+                        b'0' => Some(Charset::DecSpecialGraphics),
+                        _ => None,
+                    };
+                    match charset {
+                        Some(charset) => {
+                            output.push(TerminalOutput::DesignateCharset(*slot, charset))
                         }
-                        _ => {
+                        None => {
                             let b_utf8 = std::char::from_u32(*b as u32);
-                            warn!("Unhandled escape sequence {b_utf8:?} {b:x}");
-                            self.inner = AnsiParserInner::Empty;
+                            warn!("Unsupported charset {b_utf8:?} {b:x}");
+                            output.push(TerminalOutput::Invalid);
                         }
                     }
+                    self.inner = AnsiParserInner::Empty;
                 }
                 AnsiParserInner::Csi(parser) => {
                     parser.push(*b);
@@ -573,7 +1268,9 @@ impl AnsiParser {
 
                             let ret = match param.unwrap_or(0) {
                                 0 => TerminalOutput::ClearForwards,
-                                2 | 3 => TerminalOutput::ClearAll,
+                                1 => TerminalOutput::ClearBackwards,
+                                2 => TerminalOutput::ClearAll,
+                                3 => TerminalOutput::ClearScrollback,
                                 _ => TerminalOutput::Invalid,
                             };
                             output.push(ret);
@@ -590,6 +1287,8 @@ impl AnsiParser {
                             // ECMA-48 8.3.39
                             match param.unwrap_or(0) {
                                 0 => output.push(TerminalOutput::ClearLineForwards),
+                                1 => output.push(TerminalOutput::ClearLineBackwards),
+                                2 => output.push(TerminalOutput::ClearLine),
                                 v => {
                                     warn!("Unsupported erase in line command ({v})");
                                     output.push(TerminalOutput::Invalid);
@@ -610,104 +1309,278 @@ impl AnsiParser {
 
                             self.inner = AnsiParserInner::Empty;
                         }
-                        CsiParserState::Finished(b'P') => {
+                        CsiParserState::Finished(b'M') => {
                             let Ok(param) = parse_param_as::<usize>(&parser.params) else {
-                                warn!("Invalid del command");
+                                warn!("Invalid dl command");
                                 output.push(TerminalOutput::Invalid);
                                 self.inner = AnsiParserInner::Empty;
                                 continue;
                             };
 
-                            output.push(TerminalOutput::Delete(param.unwrap_or(1)));
+                            output.push(TerminalOutput::DeleteLines(param.unwrap_or(1)));
 
                             self.inner = AnsiParserInner::Empty;
                         }
-                        CsiParserState::Finished(b'm') => {
-                            let params =
-                                split_params_into_semicolon_delimited_usize(&parser.params);
-
-                            let Ok(mut params) = params else {
-                                warn!("Invalid SGR sequence");
+                        CsiParserState::Finished(b'S') => {
+                            let Ok(param) = parse_param_as::<usize>(&parser.params) else {
+                                warn!("Invalid su command");
                                 output.push(TerminalOutput::Invalid);
                                 self.inner = AnsiParserInner::Empty;
                                 continue;
                             };
 
-                            if params.is_empty() {
-                                params.push(Some(0));
-                            }
-
-                            if params.len() == 1 && params[0].is_none() {
-                                params[0] = Some(0);
-                            }
-
-                            for param in params {
-                                let Some(param) = param else {
-                                    continue;
-                                };
-                                output.push(TerminalOutput::Sgr(
-                                    SelectGraphicRendition::from_usize(param),
-                                ));
-                            }
+                            output.push(TerminalOutput::ScrollUp(param.unwrap_or(1)));
 
                             self.inner = AnsiParserInner::Empty;
                         }
-                        CsiParserState::Finished(b'h') => {
-                            output.push(TerminalOutput::SetMode(mode_from_params(&parser.params)));
+                        CsiParserState::Finished(b'T') => {
+                            let Ok(param) = parse_param_as::<usize>(&parser.params) else {
+                                warn!("Invalid sd command");
+                                output.push(TerminalOutput::Invalid);
+                                self.inner = AnsiParserInner::Empty;
+                                continue;
+                            };
+
+                            output.push(TerminalOutput::ScrollDown(param.unwrap_or(1)));
+
                             self.inner = AnsiParserInner::Empty;
                         }
-                        CsiParserState::Finished(b'l') => {
-                            output
-                                .push(TerminalOutput::ResetMode(mode_from_params(&parser.params)));
+                        CsiParserState::Finished(b'I') => {
+                            let Ok(param) = parse_param_as::<usize>(&parser.params) else {
+                                warn!("Invalid cht command");
+                                output.push(TerminalOutput::Invalid);
+                                self.inner = AnsiParserInner::Empty;
+                                continue;
+                            };
+
+                            output.push(TerminalOutput::ForwardTab(param.unwrap_or(1)));
                             self.inner = AnsiParserInner::Empty;
                         }
-                        CsiParserState::Finished(b'@') => {
+                        CsiParserState::Finished(b'Z') => {
                             let Ok(param) = parse_param_as::<usize>(&parser.params) else {
-                                warn!("Invalid ich command");
+                                warn!("Invalid cbt command");
                                 output.push(TerminalOutput::Invalid);
                                 self.inner = AnsiParserInner::Empty;
                                 continue;
                             };
 
-                            // ecma-48 8.3.64
-                            output.push(TerminalOutput::InsertSpaces(param.unwrap_or(1)));
+                            output.push(TerminalOutput::BackwardTab(param.unwrap_or(1)));
                             self.inner = AnsiParserInner::Empty;
                         }
-                        CsiParserState::Finished(esc) => {
-                            warn!(
-                                "Unhandled csi code: {:?} {esc:x} {}/{}",
-                                std::char::from_u32(esc as u32),
-                                esc >> 4,
-                                esc & 0xf,
-                            );
-                            output.push(TerminalOutput::Invalid);
+                        CsiParserState::Finished(b'g') => {
+                            let ret = match parse_param_as::<usize>(&parser.params) {
+                                Ok(None | Some(0)) => TerminalOutput::ClearTabStop,
+                                Ok(Some(3)) => TerminalOutput::ClearAllTabStops,
+                                _ => {
+                                    warn!("Unhandled tbc command {:?}", parser.params);
+                                    TerminalOutput::Invalid
+                                }
+                            };
+                            output.push(ret);
                             self.inner = AnsiParserInner::Empty;
                         }
-                        CsiParserState::Invalid => {
-                            warn!("Invalid CSI sequence");
-                            output.push(TerminalOutput::Invalid);
+                        // With parameters these set left and right margins, which are not
+                        // supported
+                        CsiParserState::Finished(b's')
+                            if parser.params.is_empty() && parser.intermediates.is_empty() =>
+                        {
+                            output.push(TerminalOutput::SaveCursor);
                             self.inner = AnsiParserInner::Empty;
                         }
-                        _ => {}
-                    }
-                }
-            }
-        }
-
-        if !data_output.is_empty() {
-            output.push(TerminalOutput::Data(data_output));
-        }
-
-        output
-    }
-}
+                        CsiParserState::Finished(b'u')
+                            if parser.params.is_empty() && parser.intermediates.is_empty() =>
+                        {
+                            output.push(TerminalOutput::RestoreCursor);
+                            self.inner = AnsiParserInner::Empty;
+                        }
+                        CsiParserState::Finished(b'r') => {
+                            let params =
+                                split_params_into_semicolon_delimited_usize(&parser.params);
 
-#[cfg(test)]
-mod test {
-    use super::*;
+                            let Ok(params) = params else {
+                                warn!("Invalid set scroll region sequence");
+                                output.push(TerminalOutput::Invalid);
+                                self.inner = AnsiParserInner::Empty;
+                                continue;
+                            };
 
-    #[test]
-    fn test_set_cursor_position() {
+                            // https://vt100.net/docs/vt510-rm/DECSTBM.html
+                            output.push(TerminalOutput::SetScrollRegion {
+                                top: extract_param(0, &params),
+                                bottom: extract_param(1, &params),
+                            });
+                            self.inner = AnsiParserInner::Empty;
+                        }
+                        CsiParserState::Finished(b'n') if parser.intermediates.is_empty() => {
+                            let ret = match parser.params.as_slice() {
+                                b"5" => TerminalOutput::RequestStatusReport,
+                                b"6" => TerminalOutput::RequestCursorPosition,
+                                params => {
+                                    warn!("Unhandled device status report {params:?}");
+                                    TerminalOutput::Invalid
+                                }
+                            };
+                            output.push(ret);
+                            self.inner = AnsiParserInner::Empty;
+                        }
+                        CsiParserState::Finished(b'c') if parser.intermediates.is_empty() => {
+                            let ret = match parser.params.as_slice() {
+                                b"" | b"0" => TerminalOutput::RequestPrimaryDeviceAttributes,
+                                b">" | b">0" => TerminalOutput::RequestSecondaryDeviceAttributes,
+                                params => {
+                                    warn!("Unhandled device attributes request {params:?}");
+                                    TerminalOutput::Invalid
+                                }
+                            };
+                            output.push(ret);
+                            self.inner = AnsiParserInner::Empty;
+                        }
+                        // https://invisible-island.net/xterm/ctlseqs/ctlseqs.html (XTVERSION)
+                        CsiParserState::Finished(b'q')
+                            if parser.intermediates.is_empty()
+                                && matches!(parser.params.as_slice(), b">" | b">0") =>
+                        {
+                            output.push(TerminalOutput::RequestTerminalVersion);
+                            self.inner = AnsiParserInner::Empty;
+                        }
+                        // https://vt100.net/docs/vt510-rm/DECSCUSR.html
+                        CsiParserState::Finished(b'q') if parser.intermediates == b" " => {
+                            let style = parse_param_as::<usize>(&parser.params)
+                                .ok()
+                                .and_then(|param| CursorStyle::from_decscusr(param.unwrap_or(0)));
+                            match style {
+                                Some(style) => output.push(TerminalOutput::SetCursorStyle(style)),
+                                None => {
+                                    warn!("Invalid DECSCUSR sequence");
+                                    output.push(TerminalOutput::Invalid);
+                                }
+                            }
+                            self.inner = AnsiParserInner::Empty;
+                        }
+                        CsiParserState::Finished(b'P') => {
+                            let Ok(param) = parse_param_as::<usize>(&parser.params) else {
+                                warn!("Invalid del command");
+                                output.push(TerminalOutput::Invalid);
+                                self.inner = AnsiParserInner::Empty;
+                                continue;
+                            };
+
+                            output.push(TerminalOutput::Delete(param.unwrap_or(1)));
+
+                            self.inner = AnsiParserInner::Empty;
+                        }
+                        CsiParserState::Finished(b'X') => {
+                            let Ok(param) = parse_param_as::<usize>(&parser.params) else {
+                                warn!("Invalid ech command");
+                                output.push(TerminalOutput::Invalid);
+                                self.inner = AnsiParserInner::Empty;
+                                continue;
+                            };
+
+                            output.push(TerminalOutput::EraseChars(param.unwrap_or(1)));
+
+                            self.inner = AnsiParserInner::Empty;
+                        }
+                        CsiParserState::Finished(b'm') => {
+                            let Ok(sgrs) = parse_sgr_params(&parser.params) else {
+                                warn!("Invalid SGR sequence");
+                                output.push(TerminalOutput::Invalid);
+                                self.inner = AnsiParserInner::Empty;
+                                continue;
+                            };
+
+                            output.extend(sgrs.into_iter().map(TerminalOutput::Sgr));
+
+                            self.inner = AnsiParserInner::Empty;
+                        }
+                        CsiParserState::Finished(b'h') => {
+                            output.extend(
+                                modes_from_params(&parser.params)
+                                    .into_iter()
+                                    .map(TerminalOutput::SetMode),
+                            );
+                            self.inner = AnsiParserInner::Empty;
+                        }
+                        CsiParserState::Finished(b'l') => {
+                            output.extend(
+                                modes_from_params(&parser.params)
+                                    .into_iter()
+                                    .map(TerminalOutput::ResetMode),
+                            );
+                            self.inner = AnsiParserInner::Empty;
+                        }
+                        CsiParserState::Finished(b'@') => {
+                            let Ok(param) = parse_param_as::<usize>(&parser.params) else {
+                                warn!("Invalid ich command");
+                                output.push(TerminalOutput::Invalid);
+                                self.inner = AnsiParserInner::Empty;
+                                continue;
+                            };
+
+                            // ecma-48 8.3.64
+                            output.push(TerminalOutput::InsertSpaces(param.unwrap_or(1)));
+                            self.inner = AnsiParserInner::Empty;
+                        }
+                        CsiParserState::Finished(esc) => {
+                            warn!(
+                                "Unhandled csi code: {:?} {esc:x} {}/{}",
+                                std::char::from_u32(esc as u32),
+                                esc >> 4,
+                                esc & 0xf,
+                            );
+                            output.push(TerminalOutput::Invalid);
+                            self.inner = AnsiParserInner::Empty;
+                        }
+                        CsiParserState::Invalid => {
+                            warn!("Invalid CSI sequence");
+                            output.push(TerminalOutput::Invalid);
+                            self.inner = AnsiParserInner::Empty;
+                        }
+                        _ => {}
+                    }
+                }
+                AnsiParserInner::Osc(parser) => {
+                    if parser.state == OscParserState::Escape && *b != b'\\' {
+                        // Like xterm, an ESC that is not a string terminator ends the OSC and
+                        // starts the next sequence
+                        parser.finish(&mut output, false);
+                        self.push_escape(*b, &mut output);
+                        continue;
+                    }
+
+                    parser.push(*b);
+                    match parser.state {
+                        OscParserState::Finished => {
+                            parser.finish(&mut output, *b == 0x07);
+                            self.inner = AnsiParserInner::Empty;
+                        }
+                        OscParserState::Invalid => {
+                            warn!("Invalid OSC sequence");
+                            output.push(TerminalOutput::Invalid);
+                            self.inner = AnsiParserInner::Empty;
+                        }
+                        OscParserState::Data | OscParserState::Escape => {}
+                    }
+                }
+            }
+        }
+
+        if self.inner == AnsiParserInner::Empty {
+            self.partial_utf8 = split_incomplete_utf8(&mut data_output);
+        }
+        push_data_if_non_empty(&mut data_output, &mut output);
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::terminal_emulator::CursorShape;
+
+    #[test]
+    fn test_set_cursor_position() {
         let mut output_buffer = AnsiParser::new();
         let parsed = output_buffer.push(b"\x1b[32;15H");
         assert_eq!(parsed.len(), 1);
@@ -782,10 +1655,86 @@ mod test {
         assert_eq!(parsed.len(), 1);
         assert!(matches!(parsed[0], TerminalOutput::ClearForwards,));
 
+        let mut output_buffer = AnsiParser::new();
+        let parsed = output_buffer.push(b"\x1b[1J");
+        assert_eq!(parsed.len(), 1);
+        assert!(matches!(parsed[0], TerminalOutput::ClearBackwards,));
+
         let mut output_buffer = AnsiParser::new();
         let parsed = output_buffer.push(b"\x1b[2J");
         assert_eq!(parsed.len(), 1);
         assert!(matches!(parsed[0], TerminalOutput::ClearAll,));
+
+        let mut output_buffer = AnsiParser::new();
+        let parsed = output_buffer.push(b"\x1b[3J");
+        assert_eq!(parsed.len(), 1);
+        assert!(matches!(parsed[0], TerminalOutput::ClearScrollback,));
+    }
+
+    #[test]
+    fn test_erase_in_line() {
+        let mut output_buffer = AnsiParser::new();
+        let parsed = output_buffer.push(b"\x1b[K\x1b[1K\x1b[2K\x1b[3K");
+        assert_eq!(
+            parsed,
+            [
+                TerminalOutput::ClearLineForwards,
+                TerminalOutput::ClearLineBackwards,
+                TerminalOutput::ClearLine,
+                TerminalOutput::Invalid,
+            ]
+        );
+
+        let parsed = output_buffer.push(b"\x1b[X\x1b[5X");
+        assert_eq!(
+            parsed,
+            [TerminalOutput::EraseChars(1), TerminalOutput::EraseChars(5)]
+        );
+    }
+
+    #[test]
+    fn test_charset_parsing() {
+        let mut output_buffer = AnsiParser::new();
+        let parsed = output_buffer.push(b"\x1b(0lq\x1b(Ba\x0eb\x0fc\x1b)0\x1b(A");
+        assert_eq!(
+            parsed,
+            [
+                TerminalOutput::DesignateCharset(CharsetSlot::G0, Charset::DecSpecialGraphics),
+                TerminalOutput::Data(b"lq".to_vec()),
+                TerminalOutput::DesignateCharset(CharsetSlot::G0, Charset::Ascii),
+                TerminalOutput::Data(b"a".to_vec()),
+                TerminalOutput::ShiftOut,
+                TerminalOutput::Data(b"b".to_vec()),
+                TerminalOutput::ShiftIn,
+                TerminalOutput::Data(b"c".to_vec()),
+                TerminalOutput::DesignateCharset(CharsetSlot::G1, Charset::DecSpecialGraphics),
+                TerminalOutput::Invalid,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cursor_style_parsing() {
+        let mut output_buffer = AnsiParser::new();
+        let parsed = output_buffer.push(b"\x1b[ q\x1b[4 q\x1b[6 q\x1b[7 q");
+        assert_eq!(
+            parsed,
+            [
+                TerminalOutput::SetCursorStyle(CursorStyle {
+                    shape: CursorShape::Block,
+                    blinking: true,
+                }),
+                TerminalOutput::SetCursorStyle(CursorStyle {
+                    shape: CursorShape::Underline,
+                    blinking: false,
+                }),
+                TerminalOutput::SetCursorStyle(CursorStyle {
+                    shape: CursorShape::Bar,
+                    blinking: false,
+                }),
+                TerminalOutput::Invalid,
+            ]
+        );
     }
 
     #[test]
@@ -821,15 +1770,15 @@ mod test {
         let mut parser = CsiParser::new();
         parser.push(0x40);
 
-        assert_eq!(parser.params, &[]);
-        assert_eq!(parser.intermediates, &[]);
+        assert!(parser.params.is_empty());
+        assert!(parser.intermediates.is_empty());
         assert!(matches!(parser.state, CsiParserState::Finished(0x40)));
 
         let mut parser = CsiParser::new();
         parser.push(0x7e);
 
-        assert_eq!(parser.params, &[]);
-        assert_eq!(parser.intermediates, &[]);
+        assert!(parser.params.is_empty());
+        assert!(parser.intermediates.is_empty());
         assert!(matches!(parser.state, CsiParserState::Finished(0x7e)));
     }
 
@@ -918,6 +1867,97 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_background_color_parsing() {
+        let mut output_buffer = AnsiParser::new();
+        let output = output_buffer.push(b"\x1b[41;107;49m");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::Sgr(SelectGraphicRendition::BackgroundRed),
+                TerminalOutput::Sgr(SelectGraphicRendition::BackgroundBrightWhite),
+                TerminalOutput::Sgr(SelectGraphicRendition::DefaultBackground),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extended_color_parsing() {
+        let mut output_buffer = AnsiParser::new();
+        let output = output_buffer.push(b"\x1b[38;5;208;48;2;10;20;30;1m");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::Sgr(SelectGraphicRendition::ForegroundIndexed(208)),
+                TerminalOutput::Sgr(SelectGraphicRendition::BackgroundRgb(10, 20, 30)),
+                TerminalOutput::Sgr(SelectGraphicRendition::Bold),
+            ]
+        );
+
+        // Colon separated sub parameters, with and without a color space id
+        let output = output_buffer.push(b"\x1b[38:2::1:2:3;48:2:4:5:6;38:5:12m");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::Sgr(SelectGraphicRendition::ForegroundRgb(1, 2, 3)),
+                TerminalOutput::Sgr(SelectGraphicRendition::BackgroundRgb(4, 5, 6)),
+                TerminalOutput::Sgr(SelectGraphicRendition::ForegroundIndexed(12)),
+            ]
+        );
+
+        // Out of range and truncated colors are skipped, the rest of the SGR still applies
+        let output = output_buffer.push(b"\x1b[38;5;256;3m");
+        assert_eq!(
+            output,
+            &[TerminalOutput::Sgr(SelectGraphicRendition::Italic)]
+        );
+        let output = output_buffer.push(b"\x1b[48;2;1;2m");
+        assert_eq!(output, &[]);
+        let output = output_buffer.push(b"\x1b[1;4;38;5m");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::Sgr(SelectGraphicRendition::Bold),
+                TerminalOutput::Sgr(SelectGraphicRendition::Underline),
+            ]
+        );
+        let output = output_buffer.push(b"\x1b[38:5:300;1m");
+        assert_eq!(output, &[TerminalOutput::Sgr(SelectGraphicRendition::Bold)]);
+    }
+
+    #[test]
+    fn test_text_attribute_parsing() {
+        let mut output_buffer = AnsiParser::new();
+        let output = output_buffer.push(b"\x1b[2;3;4;7;9;22;23;24;27;29;4:3;4:0m");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::Sgr(SelectGraphicRendition::Dim),
+                TerminalOutput::Sgr(SelectGraphicRendition::Italic),
+                TerminalOutput::Sgr(SelectGraphicRendition::Underline),
+                TerminalOutput::Sgr(SelectGraphicRendition::Reverse),
+                TerminalOutput::Sgr(SelectGraphicRendition::Strikethrough),
+                TerminalOutput::Sgr(SelectGraphicRendition::NormalIntensity),
+                TerminalOutput::Sgr(SelectGraphicRendition::NotItalic),
+                TerminalOutput::Sgr(SelectGraphicRendition::NotUnderlined),
+                TerminalOutput::Sgr(SelectGraphicRendition::NotReversed),
+                TerminalOutput::Sgr(SelectGraphicRendition::NotStrikethrough),
+                TerminalOutput::Sgr(SelectGraphicRendition::Underline),
+                TerminalOutput::Sgr(SelectGraphicRendition::NotUnderlined),
+            ]
+        );
+
+        // Omitted parameters are resets
+        let output = output_buffer.push(b"\x1b[;1m");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::Sgr(SelectGraphicRendition::Reset),
+                TerminalOutput::Sgr(SelectGraphicRendition::Bold),
+            ]
+        );
+    }
+
     #[test]
     fn test_mode_parsing() {
         let mut output_buffer = AnsiParser::new();
@@ -942,6 +1982,162 @@ mod test {
         let output = output_buffer.push(b"\x1b[?1h");
         assert_eq!(output.len(), 1);
         assert_eq!(output[0], TerminalOutput::SetMode(Mode::Decckm));
+
+        let output = output_buffer.push(b"\x1b[?1049h\x1b[?1047l\x1b[?47h");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::SetMode(Mode::AlternateScreenSaveCursor),
+                TerminalOutput::ResetMode(Mode::AlternateScreen),
+                TerminalOutput::SetMode(Mode::AlternateScreen),
+            ]
+        );
+
+        // Several modes in one sequence
+        let output = output_buffer.push(b"\x1b[?1000;1006h\x1b[?25;1049l\x1b[4;20h");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::SetMode(Mode::MouseClickTracking),
+                TerminalOutput::SetMode(Mode::SgrMouse),
+                TerminalOutput::ResetMode(Mode::Dectcem),
+                TerminalOutput::ResetMode(Mode::AlternateScreenSaveCursor),
+                TerminalOutput::SetMode(Mode::Unknown(b"4".to_vec())),
+                TerminalOutput::SetMode(Mode::Unknown(b"20".to_vec())),
+            ]
+        );
+
+        let output = output_buffer.push(b"\x1b[?6h\x1b[?7l\x1b[?25l");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::SetMode(Mode::Decom),
+                TerminalOutput::ResetMode(Mode::Decawm),
+                TerminalOutput::ResetMode(Mode::Dectcem),
+            ]
+        );
+
+        let output = output_buffer.push(b"\x1b[?2004h\x1b[?2004l");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::SetMode(Mode::BracketedPaste),
+                TerminalOutput::ResetMode(Mode::BracketedPaste),
+            ]
+        );
+
+        let output = output_buffer.push(b"\x1b[?1000h\x1b[?1002h\x1b[?1003l\x1b[?1006h");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::SetMode(Mode::MouseClickTracking),
+                TerminalOutput::SetMode(Mode::MouseDragTracking),
+                TerminalOutput::ResetMode(Mode::MouseMotionTracking),
+                TerminalOutput::SetMode(Mode::SgrMouse),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scroll_parsing() {
+        let mut output_buffer = AnsiParser::new();
+        let output = output_buffer.push(b"\x1b[3;10r\x1b[r\x1b[M\x1b[2M\x1b[S\x1b[4T");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::SetScrollRegion {
+                    top: Some(3),
+                    bottom: Some(10)
+                },
+                TerminalOutput::SetScrollRegion {
+                    top: None,
+                    bottom: None
+                },
+                TerminalOutput::DeleteLines(1),
+                TerminalOutput::DeleteLines(2),
+                TerminalOutput::ScrollUp(1),
+                TerminalOutput::ScrollDown(4),
+            ]
+        );
+
+        let output = output_buffer.push(b"a\x1bDb\x1bEc\x1bM");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::Data(b"a".to_vec()),
+                TerminalOutput::Index,
+                TerminalOutput::Data(b"b".to_vec()),
+                TerminalOutput::NextLine,
+                TerminalOutput::Data(b"c".to_vec()),
+                TerminalOutput::ReverseIndex,
+            ]
+        );
+
+        let output = output_buffer.push(b"\x1b=\x1b>");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::ApplicationKeypad,
+                TerminalOutput::NormalKeypad,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_save_cursor_parsing() {
+        let mut output_buffer = AnsiParser::new();
+        let output = output_buffer.push(b"\x1b7\x1b8\x1b[s\x1b[u\x1b[1;5s");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::SaveCursor,
+                TerminalOutput::RestoreCursor,
+                TerminalOutput::SaveCursor,
+                TerminalOutput::RestoreCursor,
+                TerminalOutput::Invalid,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tab_parsing() {
+        let mut output_buffer = AnsiParser::new();
+        let output =
+            output_buffer.push(b"a\tb\x1bH\x1b[I\x1b[3I\x1b[2Z\x1b[g\x1b[0g\x1b[3g\x1b[2g");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::Data(b"a".to_vec()),
+                TerminalOutput::ForwardTab(1),
+                TerminalOutput::Data(b"b".to_vec()),
+                TerminalOutput::SetTabStop,
+                TerminalOutput::ForwardTab(1),
+                TerminalOutput::ForwardTab(3),
+                TerminalOutput::BackwardTab(2),
+                TerminalOutput::ClearTabStop,
+                TerminalOutput::ClearTabStop,
+                TerminalOutput::ClearAllTabStops,
+                TerminalOutput::Invalid,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_device_request_parsing() {
+        let mut output_buffer = AnsiParser::new();
+        let output = output_buffer.push(b"\x1b[5n\x1b[6n\x1b[c\x1b[0c\x1b[>c\x1b[>0q\x1b[7n");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::RequestStatusReport,
+                TerminalOutput::RequestCursorPosition,
+                TerminalOutput::RequestPrimaryDeviceAttributes,
+                TerminalOutput::RequestPrimaryDeviceAttributes,
+                TerminalOutput::RequestSecondaryDeviceAttributes,
+                TerminalOutput::RequestTerminalVersion,
+                TerminalOutput::Invalid,
+            ]
+        );
     }
 
     #[test]
@@ -1102,22 +2298,189 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_osc_title_parsing() {
+        let mut output_buffer = AnsiParser::new();
+        let output = output_buffer.push(b"a\x1b]0;hello world\x07b");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::Data(b"a".into()),
+                TerminalOutput::SetIconName("hello world".into()),
+                TerminalOutput::SetWindowTitle("hello world".into()),
+                TerminalOutput::Data(b"b".into()),
+            ]
+        );
+
+        let output = output_buffer.push(b"\x1b]2;title\x1b\\");
+        assert_eq!(output, &[TerminalOutput::SetWindowTitle("title".into())]);
+
+        let output = output_buffer.push(b"\x1b]1;icon\x07");
+        assert_eq!(output, &[TerminalOutput::SetIconName("icon".into())]);
+
+        // Title split across reads
+        let output = output_buffer.push(b"\x1b]2;split ");
+        assert_eq!(output, &[]);
+        let output = output_buffer.push(b"title\x1b");
+        assert_eq!(output, &[]);
+        let output = output_buffer.push(b"\\");
+        assert_eq!(
+            output,
+            &[TerminalOutput::SetWindowTitle("split title".into())]
+        );
+    }
+
+    #[test]
+    fn test_osc_ended_by_escape() {
+        let mut output_buffer = AnsiParser::new();
+        let output = output_buffer.push(b"\x1b]0;title\x1b[31m");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::SetIconName("title".into()),
+                TerminalOutput::SetWindowTitle("title".into()),
+                TerminalOutput::Sgr(SelectGraphicRendition::ForegroundRed),
+            ]
+        );
+
+        // The ESC may arrive in a separate read from what follows it
+        let output = output_buffer.push(b"\x1b]2;split\x1b");
+        assert_eq!(output, &[]);
+        let output = output_buffer.push(b"7x");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::SetWindowTitle("split".into()),
+                TerminalOutput::SaveCursor,
+                TerminalOutput::Data(b"x".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_osc_palette_parsing() {
+        let mut output_buffer = AnsiParser::new();
+        let output = output_buffer.push(b"\x1b]4;1;rgb:ff/00/00;232;?\x07");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::SetPaletteEntry(PaletteEntry::Indexed(1), Rgb::new(255, 0, 0)),
+                TerminalOutput::QueryPaletteEntry {
+                    entry: PaletteEntry::Indexed(232),
+                    bel_terminated: true
+                },
+            ]
+        );
+
+        let output = output_buffer.push(b"\x1b]11;?;#102030\x1b\\");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::QueryPaletteEntry {
+                    entry: PaletteEntry::Background,
+                    bel_terminated: false
+                },
+                TerminalOutput::SetPaletteEntry(PaletteEntry::Cursor, Rgb::new(16, 32, 48)),
+            ]
+        );
+
+        let output = output_buffer.push(b"\x1b]104\x07\x1b]104;3;4\x07\x1b]110\x07");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::ResetPaletteEntry(None),
+                TerminalOutput::ResetPaletteEntry(Some(PaletteEntry::Indexed(3))),
+                TerminalOutput::ResetPaletteEntry(Some(PaletteEntry::Indexed(4))),
+                TerminalOutput::ResetPaletteEntry(Some(PaletteEntry::Foreground)),
+            ]
+        );
+
+        let output = output_buffer.push(b"\x1b]4;300;#fff\x07\x1b]10;red\x07");
+        assert_eq!(output, &[TerminalOutput::Invalid, TerminalOutput::Invalid]);
+    }
+
+    #[test]
+    fn test_invalid_osc() {
+        let mut output_buffer = AnsiParser::new();
+        let output = output_buffer.push(b"\x1b]asdf\x07");
+        assert_eq!(output, &[TerminalOutput::Invalid]);
+
+        let output = output_buffer.push(b"\x1b]\xff;x\x07");
+        assert_eq!(output, &[TerminalOutput::Invalid]);
+
+        // Unknown commands are consumed without writing any data
+        let output = output_buffer.push(b"\x1b]777;notify;hi\x07");
+        assert_eq!(output, &[TerminalOutput::Invalid]);
+    }
+
+    #[test]
+    fn test_osc_parser_snapshot() {
+        let mut parser = OscParser {
+            state: OscParserState::Data,
+            data: b"0;asdf".to_vec(),
+        };
+
+        for state in [
+            OscParserState::Data,
+            OscParserState::Escape,
+            OscParserState::Finished,
+            OscParserState::Invalid,
+        ] {
+            parser.state = state;
+            let loaded =
+                OscParser::from_snapshot(parser.snapshot()).expect("failed to load snapshot");
+            assert_eq!(loaded, parser);
+        }
+    }
+
+    #[test]
+    fn test_utf8_split_across_pushes() {
+        let mut output_buffer = AnsiParser::new();
+        let euro = "€".as_bytes();
+        let output = output_buffer.push(&[b'a', euro[0]]);
+        assert_eq!(output, &[TerminalOutput::Data(b"a".to_vec())]);
+        let output = output_buffer.push(&euro[1..2]);
+        assert_eq!(output, &[]);
+        let output = output_buffer.push(&[euro[2], b'b']);
+        assert_eq!(output, &[TerminalOutput::Data("€b".as_bytes().to_vec())]);
+
+        // Sequence interrupted by a control character
+        let output = output_buffer.push(&[euro[0], b'\r', b'c', 0xff]);
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::Data("\u{fffd}".as_bytes().to_vec()),
+                TerminalOutput::CarriageReturn,
+                TerminalOutput::Data("c\u{fffd}".as_bytes().to_vec()),
+            ]
+        );
+    }
+
     #[test]
     fn test_ansi_parser_snapshot() {
         for inner in [
             AnsiParserInner::Empty,
             AnsiParserInner::Escape,
+            AnsiParserInner::Designate(CharsetSlot::G1),
             // NOTE: CSI parser tested separately so we only have to test one case here
             AnsiParserInner::Csi(CsiParser {
                 state: CsiParserState::Invalid,
                 params: vec![2, 3, 4],
                 intermediates: vec![5, 6, 7],
             }),
+            AnsiParserInner::Osc(OscParser {
+                state: OscParserState::Escape,
+                data: b"2;title".to_vec(),
+            }),
         ] {
-            let parser = AnsiParser { inner };
+            let parser = AnsiParser {
+                inner,
+                partial_utf8: vec![0xe2, 0x82],
+            };
             let loaded =
                 AnsiParser::from_snapshot(parser.snapshot()).expect("failed to load snapshot");
             assert_eq!(loaded.inner, parser.inner);
+            assert_eq!(loaded.partial_utf8, parser.partial_utf8);
         }
     }
 }
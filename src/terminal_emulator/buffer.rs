@@ -1,9 +1,23 @@
 use std::alloc::{self, Layout};
+use std::collections::{BTreeMap, VecDeque};
 use std::ops::Range;
 use thiserror::Error;
+use unicode_width::UnicodeWidthChar;
 
+use super::cell_format::{
+    CellFormat, LineFormat, LoadLineFormatSnapshotError, SnapshotLineFormatError,
+};
 use super::TerminalData2;
-use super::{recording::SnapshotItem, CursorPos};
+use super::{recording::SnapshotItem, CursorPos, LoadCursorPosError, SnapshotCursorPosErrorPriv};
+
+/// Occupies the cell to the right of a wide char. Also fills the last cell of a line when a wide
+/// char did not fit and wrapped, so that the padding is not mistaken for text
+const WIDE_CHAR_SPACER: char = '\0';
+
+/// Number of cells a char takes up. Zero width chars combine with the cell before them
+pub fn char_width(c: char) -> usize {
+    c.width().unwrap_or(1)
+}
 
 fn align_to_size(val: usize, alignment: usize) -> usize {
     let mask = alignment - 1;
@@ -18,6 +32,18 @@ fn bool_aligned_offset(val: usize) -> usize {
     align_to_size(val, std::mem::align_of::<bool>())
 }
 
+/// Allocates a zeroed buffer suitably aligned for the char/usize/bool layout of VisibleBuffer
+fn alloc_visible_buffer_storage(total_size: usize) -> Box<[u8]> {
+    let layout = Layout::from_size_align(total_size, std::mem::align_of::<usize>())
+        .expect("invalid alloc layout");
+    unsafe {
+        // NOTE: zeroed so that every cell holds a valid char
+        let ptr: *mut u8 = alloc::alloc_zeroed(layout);
+        let slice: &mut [u8] = std::slice::from_raw_parts_mut(ptr, total_size);
+        Box::from_raw(slice as *mut [u8])
+    }
+}
+
 #[allow(unused)]
 pub struct TerminalBufferInsertResponse {
     /// Range of written data after insertion of padding
@@ -29,26 +55,26 @@ pub struct TerminalBufferInsertResponse {
     pub new_cursor_pos: CursorPos,
 }
 
+/// Lines that were shifted within a scroll region without their line ids changing
 #[allow(unused)]
-#[derive(Debug)]
-pub struct TerminalBufferInsertLineResponse {
-    /// Range of deleted data **before insertion**
-    pub deleted_range: Range<usize>,
-    /// Range of inserted data
-    pub inserted_range: Range<usize>,
+#[derive(Debug, Eq, PartialEq)]
+pub struct RegionScroll {
+    /// Line ids of the scroll region
+    pub lines: Range<usize>,
+    /// How far content moved, negative is up. Lines that were vacated are blank
+    pub distance: isize,
 }
 
 #[allow(unused)]
 pub struct TerminalBufferSetWinSizeResponse {
     pub changed: bool,
-    pub insertion_range: Range<usize>,
     pub new_cursor_pos: CursorPos,
+    /// How the active screen was rewrapped, if it was
+    pub reflow: Option<Reflow>,
+    /// How the primary screen was rewrapped while hidden behind the alternate screen
+    pub primary_reflow: Option<Reflow>,
 }
 
-mod buf_pos_keys {
-    pub const LINE_ID: &str = "line_id";
-    pub const X_POS: &str = "x_pos";
-}
 // Cursor pos <-- visible location
 // FIXME: Should this be copy?
 #[derive(Debug, Eq, PartialEq, PartialOrd, Ord, Clone, Copy)]
@@ -71,49 +97,19 @@ impl BufPos {
     pub fn new(x_pos: usize, line_id: usize) -> BufPos {
         BufPos { x_pos, line_id }
     }
-
-    pub fn from_snapshot(root: SnapshotItem) -> BufPos {
-        use buf_pos_keys::*;
-        let mut root = root.into_map().unwrap();
-
-        let load_usize_from_i64_w_neg_1 = |v: SnapshotItem| {
-            let v = v.into_i64().unwrap();
-            if v == -1 {
-                return usize::MAX;
-            }
-            v.try_into().unwrap()
-        };
-        let line_id = load_usize_from_i64_w_neg_1(root.remove(LINE_ID).unwrap());
-        let x_pos = load_usize_from_i64_w_neg_1(root.remove(X_POS).unwrap());
-
-        BufPos { line_id, x_pos }
-    }
-
-    pub fn snapshot(&self) -> SnapshotItem {
-        use buf_pos_keys::*;
-        let usize_to_i64_w_max = |v: usize| {
-            if v == usize::MAX {
-                return SnapshotItem::Int(-1);
-            }
-            v.try_into().unwrap()
-        };
-        SnapshotItem::Map(
-            [
-                (LINE_ID.to_string(), usize_to_i64_w_max(self.line_id)),
-                (X_POS.to_string(), usize_to_i64_w_max(self.x_pos)),
-            ]
-            .into(),
-        )
-    }
 }
 
 /// All indexes are assumed to be y * width + x
 // FIXME: Put an example
+#[allow(unused)]
 pub struct TerminalBufferModification {
     // Where in the buffer we wrote after range adjustment. What are the indexes _right now_
     pub written_range: Range<BufPos>,
     // Where is the cursor after this modification
     pub new_cursor_pos: CursorPos,
+    // Set if the write scrolled a partial scroll region. written_range has already been adjusted
+    // to account for this
+    pub region_scroll: Option<RegionScroll>,
 }
 
 #[derive(Debug, Error)]
@@ -128,6 +124,20 @@ enum CreateSnapshotErrorKind {
     HeightNotI64(#[source] std::num::TryFromIntError),
     #[error("first line idx does not fit in i64")]
     FirstLineIdxNotI64(#[source] std::num::TryFromIntError),
+    #[error("combining char position does not fit in i64")]
+    CombiningPosNotI64(#[source] std::num::TryFromIntError),
+    #[error("scroll region does not fit in i64")]
+    ScrollRegionNotI64(#[source] std::num::TryFromIntError),
+    #[error("discarded line count does not fit in i64")]
+    DiscardedLinesNotI64(#[source] std::num::TryFromIntError),
+    #[error("scrollback line id does not fit in i64")]
+    ScrollbackLineIdNotI64(#[source] std::num::TryFromIntError),
+    #[error("scrollback limit does not fit in i64")]
+    ScrollbackLimitNotI64(#[source] std::num::TryFromIntError),
+    #[error("failed to snapshot saved cursor")]
+    SavedCursor(#[source] SnapshotCursorPosErrorPriv),
+    #[error("failed to snapshot line format")]
+    SnapshotLineFormat(#[from] SnapshotLineFormatError),
 }
 
 #[derive(Debug, Error)]
@@ -152,10 +162,28 @@ enum LoadSnapshotErrorKind {
     BufNotArray,
     #[error("buf element is not u8")]
     BufElemNotu8,
+    #[error("buf contains an invalid char")]
+    BufInvalidChar,
+    #[error("buf size does not match dimensions")]
+    BufInvalidSize,
+    #[error("combining chars are malformed")]
+    InvalidCombining,
     #[error("{0} is not present")]
     ElemNotPresent(&'static str),
     #[error("{0} is not a usize")]
     ElemNotUsize(&'static str),
+    #[error("{0} is not a bool")]
+    ElemNotBool(&'static str),
+    #[error("failed to load saved cursor")]
+    SavedCursor(#[source] LoadCursorPosError),
+    #[error("scroll region does not fit in visible buffer")]
+    InvalidScrollRegion,
+    #[error("{0} is not an array")]
+    ElemNotArray(&'static str),
+    #[error("{0} does not have a format for each line")]
+    FormatCountMismatch(&'static str),
+    #[error("failed to load line format")]
+    LoadLineFormat(#[from] LoadLineFormatSnapshotError),
 }
 
 #[derive(Debug, Error)]
@@ -163,17 +191,22 @@ enum LoadSnapshotErrorKind {
 pub struct LoadSnapshotError(#[from] LoadSnapshotErrorKind);
 
 struct LineInsertionResponse {
-    /// How many bytes of input we ate
+    /// How many chars of input we ate
     consumed: usize,
     /// Where is the cursor after the insertion
     new_x_pos: usize,
+    /// End of the cells that were written to
+    written_end: usize,
 }
 
 #[derive(Debug)]
 struct Line<'a> {
-    buf: &'a mut [u8],
+    buf: &'a mut [char],
     len: &'a mut usize,
     newline: &'a mut bool,
+    /// Zero width chars, keyed by the column of the cell they combine with
+    combining: &'a mut BTreeMap<usize, String>,
+    format: &'a mut LineFormat,
 }
 
 impl Line<'_> {
@@ -181,15 +214,66 @@ impl Line<'_> {
         self.buf.copy_from_slice(other.buf);
         *self.len = *other.len;
         *self.newline = *other.newline;
+        self.combining.clone_from(other.combining);
+        self.format.clone_from(other.format);
     }
 
-    fn clear(&mut self) {
+    /// Empties the line, blank cells take on fill
+    fn clear(&mut self, fill: &CellFormat) {
         *self.len = 0;
         *self.newline = false;
+        self.combining.clear();
+        *self.format = LineFormat::new(*fill);
+    }
+
+    fn is_wide_char_spacer(&self, pos: usize) -> bool {
+        pos > 0
+            && pos < *self.len
+            && self.buf[pos] == WIDE_CHAR_SPACER
+            && self.buf[pos - 1] != WIDE_CHAR_SPACER
+            && char_width(self.buf[pos - 1]) == 2
+    }
+
+    /// If pos is either half of a wide char, replaces both halves with spaces. Used before a
+    /// cell is overwritten or moved so that we never leave half of a wide char behind. Wrap
+    /// padding at pos becomes a plain space
+    fn break_wide_char(&mut self, pos: usize) {
+        if pos >= *self.len {
+            return;
+        }
+
+        let start = if self.is_wide_char_spacer(pos) {
+            pos - 1
+        } else if self.is_wide_char_spacer(pos + 1) {
+            pos
+        } else {
+            if self.buf[pos] == WIDE_CHAR_SPACER {
+                self.buf[pos] = ' ';
+            }
+            return;
+        };
+
+        self.buf[start..start + 2].fill(' ');
+        self.combining.remove(&start);
+    }
+
+    /// Moves combining chars at or after pos by distance, dropping any that end up before pos or
+    /// past the end of the line
+    fn shift_combining(&mut self, pos: usize, distance: isize) {
+        let moved = self.combining.split_off(&pos);
+        for (x, chars) in moved {
+            match x.checked_add_signed(distance) {
+                Some(x) if x >= pos && x < *self.len => {
+                    self.combining.insert(x, chars);
+                }
+                _ => (),
+            }
+        }
     }
 
-    fn insert_spaces(&mut self, pos: usize, num_spaces: usize) {
+    fn insert_spaces(&mut self, pos: usize, num_spaces: usize, fill: &CellFormat) {
         let num_spaces = num_spaces.min(self.buf.len() - pos);
+        self.format.insert(pos, num_spaces, fill, self.buf.len());
         let dest_start = pos + num_spaces;
         let dest_end = (num_spaces + *self.len).min(self.buf.len());
         if dest_start > dest_end {
@@ -197,61 +281,228 @@ impl Line<'_> {
         }
         let copy_len = dest_end - dest_start;
 
+        if self.is_wide_char_spacer(pos) {
+            self.break_wide_char(pos);
+        }
+
         self.buf.copy_within(pos..pos + copy_len, dest_start);
-        self.buf[pos..pos + num_spaces].fill(b' ');
+        self.buf[pos..pos + num_spaces].fill(' ');
         *self.len = dest_end;
+        self.shift_combining(pos, num_spaces as isize);
+
+        // A wide char pushed into the last column lost its spacer off the end of the line
+        if let Some(last) = self.buf[..*self.len].last_mut() {
+            if *last != WIDE_CHAR_SPACER && char_width(*last) == 2 {
+                *last = ' ';
+                self.combining.remove(&(*self.len - 1));
+            }
+        }
+    }
+
+    /// Deletes chars at pos, cells vacated at the end of the line take on fill
+    fn delete_chars(&mut self, pos: usize, num_chars: usize, fill: &CellFormat) {
+        if pos > *self.len {
+            return;
+        }
+        let num_chars = num_chars.min(*self.len - pos);
+        self.format.remove(pos..pos + num_chars);
+        self.format.set(*self.len - num_chars..usize::MAX, fill);
+
+        self.break_wide_char(pos);
+        if self.is_wide_char_spacer(pos + num_chars) {
+            self.break_wide_char(pos + num_chars);
+        }
+        // Wrap padding is only meaningful in the last column
+        if let Some(last) = (*self.len).checked_sub(1) {
+            if !self.is_wide_char_spacer(last) {
+                self.break_wide_char(last);
+            }
+        }
+
+        self.buf.copy_within(pos + num_chars..*self.len, pos);
+        *self.len -= num_chars;
+        self.shift_combining(pos, -(num_chars as isize));
+    }
+
+    /// Erases everything from pos on, erased cells take on fill
+    fn truncate(&mut self, pos: usize, fill: &CellFormat) {
+        self.format.set(pos..usize::MAX, fill);
+        if pos >= *self.len {
+            return;
+        }
+
+        self.break_wide_char(pos);
+        *self.len = pos;
+        self.combining.split_off(&pos);
+    }
+
+    /// Blanks the cells in columns without moving anything else, erased cells take on fill
+    fn erase(&mut self, columns: Range<usize>, fill: &CellFormat) {
+        let start = columns.start;
+        let end = columns.end.min(self.buf.len());
+        if start >= end {
+            return;
+        }
+        self.format.set(start..end, fill);
+        if start >= *self.len {
+            return;
+        }
+
+        // Wide chars straddling either edge are erased entirely
+        self.break_wide_char(start);
+        self.break_wide_char(end - 1);
+        if end >= *self.len {
+            *self.len = start;
+        } else {
+            self.buf[start..end].fill(' ');
+        }
+        let after = self.combining.split_off(&start);
+        self.combining
+            .extend(after.into_iter().filter(|(x, _)| *x >= end));
+    }
+
+    /// Marks the rest of the line as padding left behind by a wide char that wrapped
+    fn pad_wrapped(&mut self) {
+        let width = self.buf.len();
+        self.buf[*self.len..width].fill(WIDE_CHAR_SPACER);
+        *self.len = width;
+    }
+
+    fn pad_to(&mut self, pos: usize) {
+        if *self.len < pos {
+            self.buf[*self.len..pos].fill(' ');
+            *self.len = pos;
+        }
+    }
+
+    /// Writes a char occupying char_width cells at pos. The caller ensures that it fits
+    fn write_char(&mut self, pos: usize, c: char, char_width: usize) {
+        self.pad_to(pos);
+
+        for x in pos..pos + char_width {
+            self.break_wide_char(x);
+            self.combining.remove(&x);
+        }
+
+        self.buf[pos] = c;
+        if char_width == 2 {
+            self.buf[pos + 1] = WIDE_CHAR_SPACER;
+        }
+        *self.len = (*self.len).max(pos + char_width);
+    }
+
+    /// Attaches a zero width char to the cell preceding pos, if there is one
+    fn combine_with_previous_cell(&mut self, pos: usize, c: char) {
+        if pos == 0 || pos > *self.len {
+            return;
+        }
+
+        let mut x = pos - 1;
+        if self.is_wide_char_spacer(x) {
+            x -= 1;
+        }
+        self.combining.entry(x).or_default().push(c);
+    }
+
+    fn insert_data(
+        &mut self,
+        data: &[char],
+        pos: usize,
+        format: &CellFormat,
+    ) -> LineInsertionResponse {
+        let response = self.insert_chars(data, pos);
+        self.format.set(pos..response.written_end, format);
+        response
     }
 
-    fn insert_data(&mut self, data: &[u8], pos: usize) -> LineInsertionResponse {
-        if pos >= self.buf.len() {
+    fn insert_chars(&mut self, data: &[char], pos: usize) -> LineInsertionResponse {
+        let width = self.buf.len();
+        if pos >= width {
             return LineInsertionResponse {
                 consumed: 0,
                 new_x_pos: pos,
+                written_end: pos,
             };
         }
 
-        let mut copy_len = self.buf.len() - pos;
-        copy_len = copy_len.min(data.len());
+        let mut x = pos;
+        let mut consumed = 0;
+        for c in data {
+            if *c == '\n' {
+                self.pad_to(x);
+                *self.newline = true;
+                return LineInsertionResponse {
+                    consumed: consumed + 1,
+                    new_x_pos: width,
+                    written_end: x,
+                };
+            }
 
-        let newline_search_length = (copy_len + 1).min(data.len());
-        let newline_pos = data[..newline_search_length]
-            .iter()
-            .position(|b| *b == b'\n');
+            if x >= width {
+                break;
+            }
 
-        if let Some(pos) = newline_pos {
-            copy_len = copy_len.min(pos);
-            *self.newline = true;
-        }
+            let char_width = char_width(*c);
+            if char_width == 0 {
+                self.combine_with_previous_cell(x, *c);
+                consumed += 1;
+                continue;
+            }
 
-        if *self.len < pos {
-            self.buf[*self.len..pos].fill(b' ');
+            if x + char_width > width {
+                // Wide chars do not get split across lines. Pad out the last cell and let the
+                // caller wrap
+                self.write_char(x, WIDE_CHAR_SPACER, 1);
+                if x == 0 {
+                    // Wider than the whole line, wrapping would never make it fit so it is
+                    // dropped
+                    consumed += 1;
+                }
+                x = width;
+                break;
+            }
+
+            self.write_char(x, *c, char_width);
+            x += char_width;
+            consumed += 1;
         }
 
-        self.buf[pos..pos + copy_len].copy_from_slice(&data[..copy_len]);
-        *self.len = (*self.len).max(pos + copy_len);
+        LineInsertionResponse {
+            consumed,
+            new_x_pos: x,
+            written_end: x,
+        }
+    }
 
-        if let Some(newline_pos) = newline_pos {
-            LineInsertionResponse {
-                consumed: newline_pos + 1,
-                new_x_pos: self.buf.len(),
+    /// Chars in the given columns. Spacers are skipped and combining chars follow the cell they
+    /// belong to
+    fn text(&self, columns: Range<usize>) -> Vec<char> {
+        let end = columns.end.min(*self.len);
+        let mut ret = Vec::with_capacity(end.saturating_sub(columns.start));
+        for x in columns.start.min(end)..end {
+            let c = self.buf[x];
+            if c == WIDE_CHAR_SPACER {
+                continue;
             }
-        } else {
-            LineInsertionResponse {
-                consumed: copy_len,
-                new_x_pos: pos + copy_len,
+            ret.push(c);
+            if let Some(combining) = self.combining.get(&x) {
+                ret.extend(combining.chars());
             }
         }
+        ret
     }
 
-    fn serialize(&self) -> &[u8] {
-        &self.buf[..*self.len]
+    fn serialize(&self) -> String {
+        self.text(0..*self.len).into_iter().collect()
     }
 }
 
 struct VisibleBufferSerializeResponse {
     data: Vec<u8>,
-    /// Line id -> range in data
+    /// Line id -> byte offset in data
     line_mappings: Vec<usize>,
+    /// Line id -> formatting of the line
+    formats: Vec<LineFormat>,
 }
 
 mod visible_buffer_keys {
@@ -261,8 +512,13 @@ mod visible_buffer_keys {
     pub const WIDTH: &str = "width";
     pub const HEIGHT: &str = "height";
     pub const FIRST_LINE_IDX: &str = "first_line_idx";
+    pub const COMBINING: &str = "combining";
+    pub const FORMATS: &str = "formats";
 }
 
+/// Layout of buf is width * height chars, followed by height line lengths, followed by height
+/// newline flags. Combining chars are rare enough to live outside of buf, and formatting varies
+/// in size so it does too
 #[derive(PartialEq, Debug)]
 struct VisibleBuffer {
     buf: Box<[u8]>,
@@ -271,50 +527,51 @@ struct VisibleBuffer {
     width: usize,
     height: usize,
     first_line_idx: usize,
+    /// Combining chars for each line, indexed the same way as buf
+    combining: Vec<BTreeMap<usize, String>>,
+    /// Formatting for each line, indexed the same way as buf
+    formats: Vec<LineFormat>,
 }
 
 impl VisibleBuffer {
     fn new(width: usize, height: usize) -> VisibleBuffer {
-        let data_size = width * height;
+        let data_size = width * height * std::mem::size_of::<char>();
         let length_offset = usize_aligned_offset(data_size);
         let newline_offset =
             bool_aligned_offset(length_offset + std::mem::size_of::<usize>() * height);
 
         let usize_alignment = std::mem::align_of::<usize>();
         let bool_alignment = std::mem::align_of::<bool>();
-        let u8_alignment = std::mem::align_of::<u8>();
+        let char_alignment = std::mem::align_of::<char>();
 
         assert_eq!(usize_alignment % bool_alignment, 0);
-        assert_eq!(usize_alignment % u8_alignment, 0);
+        assert_eq!(usize_alignment % char_alignment, 0);
 
         let total_size = newline_offset + std::mem::size_of::<bool>() * height;
 
-        let layout =
-            Layout::from_size_align(total_size, usize_alignment).expect("invalid alloc layout");
-        unsafe {
-            let ptr: *mut u8 = alloc::alloc(layout);
-            let slice: &mut [u8] = std::slice::from_raw_parts_mut(ptr, total_size);
-            let buf: Box<[u8]> = Box::from_raw(slice as *mut [u8]);
-
-            let mut ret = VisibleBuffer {
-                buf,
-                length_offset,
-                newline_offset,
-                width,
-                height,
-                first_line_idx: 0,
-            };
+        let buf = alloc_visible_buffer_storage(total_size);
 
-            for y in 0..height {
-                ret.get_line(y).clear();
-            }
-            ret
+        let mut ret = VisibleBuffer {
+            buf,
+            length_offset,
+            newline_offset,
+            width,
+            height,
+            first_line_idx: 0,
+            combining: vec![BTreeMap::new(); height],
+            formats: vec![LineFormat::default(); height],
+        };
+
+        for y in 0..height {
+            ret.get_line(y).clear(&CellFormat::default());
         }
+        ret
     }
 
     fn serialize(&mut self) -> VisibleBufferSerializeResponse {
         let mut data = Vec::new();
         let width = self.width;
+        let height = self.height;
         let lines = self.get_all_lines();
         let mut line_mappings = Vec::new();
         let last_line_with_content = lines
@@ -332,7 +589,7 @@ impl VisibleBuffer {
 
             let next_line_is_empty_line = || lines.get(y + 1).map(|x| *x.len == 0).unwrap_or(false);
 
-            data.extend(line.serialize());
+            data.extend(line.serialize().as_bytes());
             if *line.newline || *line.len < width || next_line_is_empty_line() {
                 data.push(b'\n');
             }
@@ -341,10 +598,10 @@ impl VisibleBuffer {
             line_start = data.len();
         }
 
-        data.extend(lines[last_line_with_content].serialize());
+        data.extend(lines[last_line_with_content].serialize().as_bytes());
         line_mappings.push(line_start);
 
-        for _ in last_line_with_content + 1..self.height {
+        for _ in last_line_with_content + 1..height {
             line_mappings.push(data.len());
         }
 
@@ -353,9 +610,12 @@ impl VisibleBuffer {
             data.push(b'\n');
         }
 
+        let formats = lines.iter().map(|line| line.format.clone()).collect();
+
         VisibleBufferSerializeResponse {
             data,
             line_mappings,
+            formats,
         }
     }
 
@@ -369,6 +629,9 @@ impl VisibleBuffer {
             let (data, rest) = self.buf.split_at_mut(self.length_offset);
             let (lengths, newlines) = rest.split_at_mut(self.newline_offset - self.length_offset);
 
+            let data_start = data.as_mut_ptr() as *mut char;
+            let data = std::slice::from_raw_parts_mut(data_start, self.width * self.height);
+
             let lengths_start = lengths.as_mut_ptr() as *mut usize;
             let lengths = std::slice::from_raw_parts_mut(lengths_start, self.height);
 
@@ -379,6 +642,8 @@ impl VisibleBuffer {
                 buf: &mut data[idx * self.width..idx * self.width + self.width],
                 len: &mut lengths[idx],
                 newline: &mut newlines[idx],
+                combining: &mut self.combining[idx],
+                format: &mut self.formats[idx],
             }
         }
     }
@@ -386,16 +651,19 @@ impl VisibleBuffer {
     fn get_all_lines(&mut self) -> Vec<Line<'_>> {
         let mut ret = Vec::new();
         unsafe {
-            let (mut data, rest) = self.buf.split_at_mut(self.length_offset);
+            let (data, rest) = self.buf.split_at_mut(self.length_offset);
             let (lengths, newlines) = rest.split_at_mut(self.newline_offset - self.length_offset);
 
+            let data_start = data.as_mut_ptr() as *mut char;
+            let mut data = std::slice::from_raw_parts_mut(data_start, self.width * self.height);
+
             let lengths_start = lengths.as_mut_ptr() as *mut usize;
             let mut lengths = std::slice::from_raw_parts_mut(lengths_start, self.height);
 
             let newlines_start = newlines.as_mut_ptr() as *mut bool;
             let mut newlines = std::slice::from_raw_parts_mut(newlines_start, self.height);
 
-            for _ in 0..self.height {
+            for (combining, format) in self.combining.iter_mut().zip(&mut self.formats) {
                 let (buf, rest) = data.split_at_mut(self.width);
                 data = rest;
                 let (len, rest) = lengths.split_at_mut(1);
@@ -406,6 +674,8 @@ impl VisibleBuffer {
                     buf,
                     len: &mut len[0],
                     newline: &mut newline[0],
+                    combining,
+                    format,
                 });
             }
         }
@@ -414,13 +684,40 @@ impl VisibleBuffer {
         ret
     }
 
-    fn push_line(&mut self) -> Line<'_> {
+    fn push_line(&mut self, fill: &CellFormat) -> Line<'_> {
         self.first_line_idx = (self.first_line_idx + 1) % self.height;
         let mut line = self.get_line(self.height - 1);
-        line.clear();
+        line.clear(fill);
         line
     }
 
+    /// Moves lines within region by distance (negative is up). Lines moved outside of the region
+    /// are dropped, and lines left behind are cleared with fill
+    fn shift_lines(&mut self, region: Range<usize>, distance: isize, fill: &CellFormat) {
+        let mut lines = self.get_all_lines();
+        let lines = &mut lines[region];
+        let num_lines = lines.len();
+        let shift = distance.unsigned_abs().min(num_lines);
+
+        if distance > 0 {
+            for dest_idx in (shift..num_lines).rev() {
+                let (a, b) = lines.split_at_mut(dest_idx);
+                b[0].copy_from_other(&a[dest_idx - shift]);
+            }
+            for line in &mut lines[..shift] {
+                line.clear(fill);
+            }
+        } else {
+            for dest_idx in 0..num_lines - shift {
+                let (a, b) = lines.split_at_mut(dest_idx + 1);
+                a[dest_idx].copy_from_other(&b[shift - 1]);
+            }
+            for line in &mut lines[num_lines - shift..] {
+                line.clear(fill);
+            }
+        }
+    }
+
     fn from_snapshot(snapshot: SnapshotItem) -> Result<VisibleBuffer, LoadSnapshotError> {
         use visible_buffer_keys::*;
         use LoadSnapshotErrorKind::*;
@@ -429,7 +726,10 @@ impl VisibleBuffer {
         let buf = root.remove(BUF).ok_or(BufNotPresent)?;
         let buf = buf.into_vec().map_err(|_| BufNotArray)?;
         let buf: Result<Vec<_>, _> = buf.into_iter().map(|item| item.into_num::<u8>()).collect();
-        let buf: Box<[u8]> = buf.map_err(|_| BufElemNotu8)?.into();
+        let buf = buf.map_err(|_| BufElemNotu8)?;
+
+        let combining = root.remove(COMBINING);
+        let formats = root.remove(FORMATS);
 
         let mut as_usize = move |key| -> Result<usize, LoadSnapshotErrorKind> {
             root.remove(key)
@@ -444,6 +744,37 @@ impl VisibleBuffer {
         let height = as_usize(HEIGHT)?;
         let first_line_idx = as_usize(FIRST_LINE_IDX)?;
 
+        let expected_length_offset =
+            usize_aligned_offset(width * height * std::mem::size_of::<char>());
+        if length_offset != expected_length_offset || buf.len() < length_offset {
+            Err(BufInvalidSize)?;
+        }
+
+        let all_chars_valid = buf[..length_offset]
+            .chunks_exact(std::mem::size_of::<char>())
+            .all(|c| char::from_u32(u32::from_ne_bytes(c.try_into().unwrap())).is_some());
+        if !all_chars_valid {
+            Err(BufInvalidChar)?;
+        }
+
+        // Copy into storage with the alignment we need to reinterpret it
+        let mut aligned_buf = alloc_visible_buffer_storage(buf.len());
+        aligned_buf.copy_from_slice(&buf);
+        let buf = aligned_buf;
+
+        let combining = match combining {
+            Some(combining) => load_combining_chars(combining, width, height)?,
+            None => vec![BTreeMap::new(); height],
+        };
+
+        let formats = match formats {
+            Some(formats) => load_line_formats(formats, FORMATS)?,
+            None => vec![LineFormat::default(); height],
+        };
+        if formats.len() != height {
+            Err(FormatCountMismatch(FORMATS))?;
+        }
+
         Ok(VisibleBuffer {
             buf,
             length_offset,
@@ -451,6 +782,8 @@ impl VisibleBuffer {
             width,
             height,
             first_line_idx,
+            combining,
+            formats,
         })
     }
 
@@ -462,166 +795,1018 @@ impl VisibleBuffer {
         let width: i64 = self.width.try_into().map_err(WidthNotI64)?;
         let height: i64 = self.height.try_into().map_err(HeightNotI64)?;
         let first_line_idx: i64 = self.first_line_idx.try_into().map_err(FirstLineIdxNotI64)?;
-        Ok(SnapshotItem::Map(
-            [
-                (BUF.to_string(), self.buf.iter().collect()),
-                (LENGTH_OFFSET.to_string(), length_offset.into()),
-                (NEWLINE_OFFSET.to_string(), newline_offset.into()),
-                (WIDTH.to_string(), width.into()),
-                (HEIGHT.to_string(), height.into()),
-                (FIRST_LINE_IDX.to_string(), first_line_idx.into()),
-            ]
-            .into(),
-        ))
+        let mut ret: std::collections::HashMap<_, _> = [
+            (BUF.to_string(), self.buf.iter().collect()),
+            (LENGTH_OFFSET.to_string(), length_offset.into()),
+            (NEWLINE_OFFSET.to_string(), newline_offset.into()),
+            (WIDTH.to_string(), width.into()),
+            (HEIGHT.to_string(), height.into()),
+            (FIRST_LINE_IDX.to_string(), first_line_idx.into()),
+            (FORMATS.to_string(), snapshot_line_formats(&self.formats)?),
+        ]
+        .into();
+
+        // Stored as [line idx, column, chars] triples, and only when there is something to store
+        let mut combining = Vec::new();
+        for (idx, line_combining) in self.combining.iter().enumerate() {
+            for (x, chars) in line_combining {
+                let idx: i64 = idx.try_into().map_err(CombiningPosNotI64)?;
+                let x: i64 = (*x).try_into().map_err(CombiningPosNotI64)?;
+                combining.push(SnapshotItem::Array(vec![
+                    idx.into(),
+                    x.into(),
+                    SnapshotItem::String(chars.clone()),
+                ]));
+            }
+        }
+        if !combining.is_empty() {
+            ret.insert(COMBINING.to_string(), SnapshotItem::Array(combining));
+        }
+
+        Ok(SnapshotItem::Map(ret))
     }
 }
 
-mod terminal_buffer_keys {
-    pub const VISIBLE_BUF: &str = "visible_buf";
-    pub const SCROLLBACK_LINE_POS: &str = "scrollback_line_pos";
-    pub const SCROLLBACK: &str = "scrollback";
+fn load_combining_chars(
+    snapshot: SnapshotItem,
+    width: usize,
+    height: usize,
+) -> Result<Vec<BTreeMap<usize, String>>, LoadSnapshotErrorKind> {
+    use LoadSnapshotErrorKind::*;
+    let mut ret = vec![BTreeMap::new(); height];
+    for item in snapshot.into_vec().map_err(|_| InvalidCombining)? {
+        let Ok([idx, x, chars]) =
+            <[SnapshotItem; 3]>::try_from(item.into_vec().map_err(|_| InvalidCombining)?)
+        else {
+            Err(InvalidCombining)?
+        };
+        let idx = idx.into_num::<usize>().map_err(|_| InvalidCombining)?;
+        let x = x.into_num::<usize>().map_err(|_| InvalidCombining)?;
+        let chars = chars.into_string().map_err(|_| InvalidCombining)?;
+        if idx >= height || x >= width {
+            Err(InvalidCombining)?;
+        }
+        ret[idx].insert(x, chars);
+    }
+    Ok(ret)
 }
 
-// scrollback positions
-// Vec<usize> line id -> buf pos
-//
-// visible positions
-// current_start_id + line offset
+fn load_line_formats(
+    snapshot: SnapshotItem,
+    key: &'static str,
+) -> Result<Vec<LineFormat>, LoadSnapshotErrorKind> {
+    use LoadSnapshotErrorKind::*;
+    snapshot
+        .into_vec()
+        .map_err(|_| ElemNotArray(key))?
+        .into_iter()
+        .map(|item| Ok(LineFormat::from_snapshot(item)?))
+        .collect()
+}
 
-#[derive(PartialEq, Debug)]
-pub struct TerminalBuffer2 {
-    visible_buf: VisibleBuffer,
-    // Mapping of line id to buffer pos. E.g. line id 4 -> buf pos by scrollback_line_positions[4]
-    scrollback_line_positions: Vec<usize>,
-    scrollback: Vec<u8>,
+fn snapshot_line_formats<'a>(
+    formats: impl IntoIterator<Item = &'a LineFormat>,
+) -> Result<SnapshotItem, CreateSnapshotErrorKind> {
+    let formats = formats
+        .into_iter()
+        .map(LineFormat::snapshot)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(SnapshotItem::Array(formats))
 }
 
-impl TerminalBuffer2 {
-    pub fn new(width: usize, height: usize) -> TerminalBuffer2 {
-        let visible_buf = VisibleBuffer::new(width, height);
-        TerminalBuffer2 {
-            visible_buf,
-            scrollback_line_positions: Vec::new(),
-            scrollback: Vec::new(),
+/// Finds the char in text that starts at column. If column is past the end of text, the number
+/// of columns past the end is returned as well
+fn column_to_char_offset(text: &[char], column: usize) -> (usize, usize) {
+    let mut current_column = 0;
+    for (i, c) in text.iter().enumerate() {
+        let width = char_width(*c);
+        if width != 0 && current_column >= column {
+            return (i, 0);
         }
+        current_column += width;
     }
+    (text.len(), column.saturating_sub(current_column))
+}
 
-    pub fn from_snapshot(snapshot: SnapshotItem) -> Result<TerminalBuffer2, LoadSnapshotError> {
-        use terminal_buffer_keys::*;
-        use LoadSnapshotErrorKind::*;
+/// How many cells c takes up on a line of the given width. Chars wider than the line are
+/// replaced with a single space when written
+fn char_width_on_line(c: char, width: usize) -> usize {
+    match char_width(c) {
+        w if w > width => 1,
+        w => w,
+    }
+}
 
-        let mut root = snapshot.into_map().map_err(|_| RootNotMap)?;
-        let visible_buf = root.remove(VISIBLE_BUF).ok_or(VisibleBufNotPresent)?;
-        let visible_buf = VisibleBuffer::from_snapshot(visible_buf)?;
+/// Splits text into ranges of chars that fit in rows of width cells, following the same rules
+/// as writing text into the buffer
+fn wrap_text(text: &[char], width: usize) -> Vec<Range<usize>> {
+    let mut rows = Vec::new();
+    let mut row_start = 0;
+    let mut column = 0;
+    for (i, c) in text.iter().enumerate() {
+        let char_width = char_width_on_line(*c, width);
+        if char_width == 0 {
+            // Combining chars stay with the cell before them
+            continue;
+        }
 
-        let scrollback = root.remove(SCROLLBACK).ok_or(ScrollbackNotPresent)?;
-        let scrollback = scrollback.into_vec().map_err(|_| ScrollbackNotArray)?;
-        let scrollback: Result<Vec<_>, _> = scrollback
-            .into_iter()
-            .map(|item| item.into_num::<u8>())
-            .collect();
-        let scrollback = scrollback.map_err(|_| ScrollbackElemNotu8)?;
+        if column + char_width > width {
+            rows.push(row_start..i);
+            row_start = i;
+            column = 0;
+        }
+        column += char_width;
+    }
+    rows.push(row_start..text.len());
+    rows
+}
 
-        let scrollback_line_positions = root.remove(SCROLLBACK_LINE_POS).unwrap();
-        let scrollback_line_positions = scrollback_line_positions.into_vec().unwrap();
-        let scrollback_line_positions = scrollback_line_positions
-            .into_iter()
-            .map(|x| x.into_num().unwrap())
-            .collect();
+#[derive(Debug, Eq, PartialEq)]
+enum CharClass {
+    Whitespace,
+    Word,
+    Other,
+}
 
-        Ok(TerminalBuffer2 {
-            scrollback,
-            scrollback_line_positions,
-            visible_buf,
-        })
+/// Double clicking selects runs of the same class. Punctuation common in paths and urls counts
+/// as part of a word
+fn char_class(c: char) -> CharClass {
+    if c.is_whitespace() {
+        CharClass::Whitespace
+    } else if c.is_alphanumeric() || "_-./~:@%+#?=&".contains(c) {
+        CharClass::Word
+    } else {
+        CharClass::Other
     }
+}
 
-    pub fn snapshot(&self) -> Result<SnapshotItem, CreateSnapshotError> {
-        pub use terminal_buffer_keys::*;
-        let scrollback_line_positions_i64: Vec<SnapshotItem> = self
-            .scrollback_line_positions
-            .iter()
-            .map(|x| {
-                let x: i64 = (*x).try_into().unwrap();
-                SnapshotItem::Int(x)
-            })
-            .collect();
-        let ret = SnapshotItem::Map(
-            [
-                (
-                    SCROLLBACK.to_string(),
-                    self.scrollback.clone().into_iter().collect(),
-                ),
-                (
-                    SCROLLBACK_LINE_POS.to_string(),
-                    SnapshotItem::Array(scrollback_line_positions_i64),
-                ),
-                (VISIBLE_BUF.to_string(), self.visible_buf.snapshot()?),
-            ]
-            .into(),
-        );
-        Ok(ret)
-    }
+/// A line as the application wrote it, spread over one or more rows
+struct LogicalLine {
+    /// Line ids of the rows
+    rows: Range<usize>,
+    /// Every char that starts a cell, along with that cell's position
+    cells: Vec<(char, BufPos)>,
+}
 
-    fn push_line_to_scrollback(&mut self) -> Line<'_> {
-        let line_to_evict = self.visible_buf.get_line(0);
-        self.scrollback_line_positions.push(self.scrollback.len());
-        self.scrollback.extend(line_to_evict.serialize());
-        if *line_to_evict.newline {
-            debug!("setting newline");
-            self.scrollback.push(b'\n');
+/// A row of the buffer, as a range of chars in the logical line it belongs to
+#[derive(Debug)]
+struct ReflowRow {
+    logical_line: usize,
+    chars: Range<usize>,
+}
+
+/// Lines as the application wrote them, before being wrapped to the window width
+#[derive(Default)]
+struct LogicalLines {
+    lines: Vec<Vec<char>>,
+    newlines: Vec<bool>,
+    /// Formatting of each logical line, by column
+    formats: Vec<LineFormat>,
+    rows: Vec<ReflowRow>,
+    last_row_wrapped: bool,
+}
+
+impl LogicalLines {
+    fn push_row(&mut self, text: &[char], format: &LineFormat, wrapped: bool, newline: bool) {
+        if self.lines.is_empty() || !self.last_row_wrapped {
+            self.lines.push(Vec::new());
+            self.newlines.push(false);
+            self.formats.push(LineFormat::default());
         }
-        self.visible_buf.push_line()
+
+        let logical_line = self.lines.len() - 1;
+        let line = &mut self.lines[logical_line];
+        let start_column = line.iter().map(|c| char_width(*c)).sum();
+        self.formats[logical_line].replace_from(start_column, format);
+        let start = line.len();
+        line.extend_from_slice(text);
+        self.rows.push(ReflowRow {
+            logical_line,
+            chars: start..line.len(),
+        });
+        self.newlines[logical_line] = newline;
+        self.last_row_wrapped = wrapped;
     }
+}
 
-    fn cursor_to_buf_pos(&self, cursor_pos: &CursorPos) -> BufPos {
-        let line_id = self.scrollback_line_positions.len() + cursor_pos.y;
-        let x_pos = cursor_pos.x;
+/// Describes how rows were rewrapped on resize, so that anything holding on to a [`BufPos`] can
+/// find the same text afterwards
+#[derive(Debug)]
+pub struct Reflow {
+    /// Line id of the first row that took part in the reflow, before and after
+    first_line_id: usize,
+    logical_lines: Vec<Vec<char>>,
+    logical_line_formats: Vec<LineFormat>,
+    old_rows: Vec<ReflowRow>,
+    new_rows: Vec<ReflowRow>,
+    /// Index into new_rows of the first row of each logical line
+    logical_line_new_rows: Vec<usize>,
+}
 
-        BufPos { line_id, x_pos }
-    }
+impl Reflow {
+    fn new(width: usize, logical_lines: LogicalLines, first_line_id: usize) -> Reflow {
+        let mut new_rows = Vec::new();
+        let mut logical_line_new_rows = Vec::new();
+        for (logical_line, text) in logical_lines.lines.iter().enumerate() {
+            logical_line_new_rows.push(new_rows.len());
+            new_rows.extend(wrap_text(text, width).into_iter().map(|chars| ReflowRow {
+                logical_line,
+                chars,
+            }));
+        }
 
-    pub fn insert_data(
-        &mut self,
-        cursor_pos: &CursorPos,
-        mut data: &[u8],
-    ) -> TerminalBufferModification {
-        let mut x = cursor_pos.x;
-        let mut y = cursor_pos.y;
-        let max_y_idx = self.visible_buf.height - 1;
-        debug!("{:?}", std::str::from_utf8(data));
-        assert!(y <= max_y_idx);
+        Reflow {
+            first_line_id,
+            logical_lines: logical_lines.lines,
+            logical_line_formats: logical_lines.formats,
+            old_rows: logical_lines.rows,
+            new_rows,
+            logical_line_new_rows,
+        }
+    }
 
-        let write_start = self.cursor_to_buf_pos(cursor_pos);
+    fn new_rows_of(&self, logical_line: usize) -> Range<usize> {
+        let start = self.logical_line_new_rows[logical_line];
+        let end = self
+            .logical_line_new_rows
+            .get(logical_line + 1)
+            .copied()
+            .unwrap_or(self.new_rows.len());
+        start..end
+    }
 
-        loop {
-            if data.is_empty() {
-                break;
-            }
+    fn is_last_row_of_line(&self, row_idx: usize) -> bool {
+        let logical_line = self.new_rows[row_idx].logical_line;
+        self.new_rows_of(logical_line).end == row_idx + 1
+    }
 
-            let mut line = self.visible_buf.get_line(y);
+    fn row_text(&self, row: &ReflowRow) -> &[char] {
+        &self.logical_lines[row.logical_line][row.chars.clone()]
+    }
 
-            let response = line.insert_data(data, x);
+    fn row_format(&self, row: &ReflowRow) -> LineFormat {
+        let start_column = self.logical_lines[row.logical_line][..row.chars.start]
+            .iter()
+            .map(|c| char_width(*c))
+            .sum();
+        self.logical_line_formats[row.logical_line].split_off(start_column)
+    }
 
-            x = response.new_x_pos;
-            if x >= self.visible_buf.width {
-                x = 0;
-                y += 1;
-            }
+    /// Index of the row after the last one with any text in it
+    fn content_end(&self) -> usize {
+        self.new_rows
+            .iter()
+            .rposition(|row| !row.chars.is_empty())
+            .map(|idx| idx + 1)
+            .unwrap_or(0)
+    }
 
-            if y > max_y_idx {
-                self.push_line_to_scrollback();
-                y = max_y_idx;
-            }
-            data = &data[response.consumed..];
+    /// Where the text at pos before the reflow ended up
+    pub fn map(&self, pos: &BufPos) -> BufPos {
+        if *pos == BufPos::MAX {
+            return *pos;
         }
 
-        let new_cursor_pos = CursorPos { x, y };
-        let write_end = self.cursor_to_buf_pos(&new_cursor_pos);
+        let Some(old_idx) = pos.line_id.checked_sub(self.first_line_id) else {
+            // Rows before the reflowed ones are untouched
+            return *pos;
+        };
+
+        let Some(old_row) = self.old_rows.get(old_idx) else {
+            let line_id = self.first_line_id + self.new_rows.len() + old_idx - self.old_rows.len();
+            return BufPos::new(pos.x_pos, line_id);
+        };
+
+        let (offset, extra_columns) = column_to_char_offset(self.row_text(old_row), pos.x_pos);
+        let char_idx = old_row.chars.start + offset;
+
+        let new_rows = self.new_rows_of(old_row.logical_line);
+        let row_idx = new_rows
+            .clone()
+            .find(|idx| char_idx < self.new_rows[*idx].chars.end)
+            .unwrap_or(new_rows.end - 1);
+        let row = &self.new_rows[row_idx];
+
+        let x_pos = self.logical_lines[row.logical_line][row.chars.start..char_idx]
+            .iter()
+            .map(|c| char_width(*c))
+            .sum::<usize>()
+            + extra_columns;
+        BufPos::new(x_pos, self.first_line_id + row_idx)
+    }
+}
+
+mod primary_screen_keys {
+    pub const VISIBLE_BUF: &str = "visible_buf";
+    pub const SAVED_CURSOR: &str = "saved_cursor";
+}
+
+/// Primary screen contents stashed away while the alternate screen is active
+#[derive(PartialEq, Debug)]
+struct PrimaryScreen {
+    visible_buf: VisibleBuffer,
+    /// Cursor position to restore when leaving the alternate screen, if requested on entry
+    saved_cursor: Option<CursorPos>,
+}
+
+impl PrimaryScreen {
+    fn from_snapshot(snapshot: SnapshotItem) -> Result<PrimaryScreen, LoadSnapshotError> {
+        use primary_screen_keys::*;
+        use LoadSnapshotErrorKind::*;
+
+        let mut root = snapshot.into_map().map_err(|_| RootNotMap)?;
+        let visible_buf = root.remove(VISIBLE_BUF).ok_or(VisibleBufNotPresent)?;
+        let visible_buf = VisibleBuffer::from_snapshot(visible_buf)?;
+        let saved_cursor = root
+            .remove(SAVED_CURSOR)
+            .map(CursorPos::from_snapshot)
+            .transpose()
+            .map_err(SavedCursor)?;
+
+        Ok(PrimaryScreen {
+            visible_buf,
+            saved_cursor,
+        })
+    }
+
+    fn snapshot(&self) -> Result<SnapshotItem, CreateSnapshotError> {
+        use primary_screen_keys::*;
+        use CreateSnapshotErrorKind::*;
+
+        let mut ret: std::collections::HashMap<_, _> =
+            [(VISIBLE_BUF.to_string(), self.visible_buf.snapshot()?)].into();
+        if let Some(saved_cursor) = &self.saved_cursor {
+            ret.insert(
+                SAVED_CURSOR.to_string(),
+                saved_cursor.snapshot().map_err(SavedCursor)?,
+            );
+        }
+        Ok(SnapshotItem::Map(ret))
+    }
+}
+
+/// How much scrollback to keep. Once either limit is exceeded the oldest lines are evicted
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ScrollbackLimit {
+    pub max_lines: Option<usize>,
+    pub max_bytes: Option<usize>,
+}
+
+impl Default for ScrollbackLimit {
+    fn default() -> ScrollbackLimit {
+        ScrollbackLimit {
+            max_lines: Some(10_000),
+            max_bytes: None,
+        }
+    }
+}
+
+/// Lines that scrolled off the top of the primary screen, oldest first. Soft-wrapped lines do not
+/// end in a newline
+#[derive(Debug)]
+struct Scrollback {
+    data: VecDeque<u8>,
+    /// Start of each line in data, offset by evicted_bytes so that eviction does not have to
+    /// touch every position
+    line_positions: VecDeque<usize>,
+    /// Formatting of each line
+    formats: VecDeque<LineFormat>,
+    evicted_bytes: usize,
+    /// Line id of the oldest line still around
+    first_line_id: usize,
+    limit: ScrollbackLimit,
+}
+
+impl Scrollback {
+    fn new(first_line_id: usize, limit: ScrollbackLimit) -> Scrollback {
+        Scrollback {
+            data: VecDeque::new(),
+            line_positions: VecDeque::new(),
+            formats: VecDeque::new(),
+            evicted_bytes: 0,
+            first_line_id,
+            limit,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.line_positions.len()
+    }
+
+    /// Line id that the next line pushed will get
+    fn end_line_id(&self) -> usize {
+        self.first_line_id + self.len()
+    }
+
+    fn line_range(&self, idx: usize) -> Range<usize> {
+        let start = self.line_positions[idx] - self.evicted_bytes;
+        let end = self
+            .line_positions
+            .get(idx + 1)
+            .map(|pos| pos - self.evicted_bytes)
+            .unwrap_or(self.data.len());
+        start..end
+    }
+
+    fn line(&self, idx: usize) -> Vec<u8> {
+        self.data.range(self.line_range(idx)).copied().collect()
+    }
+
+    fn push_line(&mut self, line: &[u8], format: LineFormat) {
+        self.line_positions
+            .push_back(self.evicted_bytes + self.data.len());
+        self.data.extend(line);
+        self.formats.push_back(format);
+        self.enforce_limit();
+    }
+
+    fn evict_oldest_line(&mut self) {
+        let line_len = self.line_range(0).len();
+        self.data.drain(..line_len);
+        self.line_positions.pop_front();
+        self.formats.pop_front();
+        self.evicted_bytes += line_len;
+        self.first_line_id += 1;
+    }
+
+    fn enforce_limit(&mut self) {
+        let over_limit = |scrollback: &Scrollback| {
+            let limit = &scrollback.limit;
+            limit.max_lines.is_some_and(|max| scrollback.len() > max)
+                || limit
+                    .max_bytes
+                    .is_some_and(|max| scrollback.data.len() > max)
+        };
+
+        while !self.line_positions.is_empty() && over_limit(self) {
+            self.evict_oldest_line();
+        }
+    }
+
+    fn set_limit(&mut self, limit: ScrollbackLimit) {
+        self.limit = limit;
+        self.enforce_limit();
+    }
+
+    /// Drops every line. Line ids keep counting from where they were
+    fn clear(&mut self) {
+        *self = Scrollback::new(self.end_line_id(), self.limit);
+    }
+
+    /// Contiguous copy of the data, along with where each line starts in it and its formatting
+    fn serialize(&mut self) -> (Vec<u8>, Vec<usize>, Vec<LineFormat>) {
+        let line_positions = self
+            .line_positions
+            .iter()
+            .map(|pos| pos - self.evicted_bytes)
+            .collect();
+        (
+            self.data.make_contiguous().to_vec(),
+            line_positions,
+            self.formats.iter().cloned().collect(),
+        )
+    }
+}
+
+impl PartialEq for Scrollback {
+    fn eq(&self, other: &Scrollback) -> bool {
+        let relative_positions = |scrollback: &Scrollback| {
+            scrollback
+                .line_positions
+                .iter()
+                .map(|pos| pos - scrollback.evicted_bytes)
+                .collect::<Vec<_>>()
+        };
+
+        self.data == other.data
+            && self.formats == other.formats
+            && self.first_line_id == other.first_line_id
+            && self.limit == other.limit
+            && relative_positions(self) == relative_positions(other)
+    }
+}
+
+mod terminal_buffer_keys {
+    pub const VISIBLE_BUF: &str = "visible_buf";
+    pub const SCROLLBACK_LINE_POS: &str = "scrollback_line_pos";
+    pub const SCROLLBACK: &str = "scrollback";
+    pub const SCROLLBACK_FIRST_LINE_ID: &str = "scrollback_first_line_id";
+    pub const SCROLLBACK_FORMATS: &str = "scrollback_formats";
+    pub const SCROLLBACK_MAX_LINES: &str = "scrollback_max_lines";
+    pub const SCROLLBACK_MAX_BYTES: &str = "scrollback_max_bytes";
+    pub const PRIMARY_SCREEN: &str = "primary_screen";
+    pub const ALTERNATE_DISCARDED_LINES: &str = "alternate_discarded_lines";
+    pub const SCROLL_REGION_TOP: &str = "scroll_region_top";
+    pub const SCROLL_REGION_BOTTOM: &str = "scroll_region_bottom";
+    pub const AUTOWRAP: &str = "autowrap";
+}
+
+// scrollback positions
+// line id - scrollback first line id -> buf pos
+//
+// visible positions
+// current_start_id + line offset
+
+#[derive(PartialEq, Debug)]
+pub struct TerminalBuffer2 {
+    /// Currently displayed screen, this is the alternate screen if primary_screen is set
+    visible_buf: VisibleBuffer,
+    scrollback: Scrollback,
+    primary_screen: Option<PrimaryScreen>,
+    // The alternate screen has no scrollback, but line ids still need to advance as lines scroll
+    // off the top so that they stay stable for the lifetime of a line
+    alternate_discarded_lines: usize,
+    // Rows between the top and bottom margins (DECSTBM), exclusive of the end
+    scroll_region: Range<usize>,
+    // Whether writing past the last column continues on the next line (DECAWM)
+    autowrap: bool,
+}
+
+impl TerminalBuffer2 {
+    pub fn new(width: usize, height: usize) -> TerminalBuffer2 {
+        let visible_buf = VisibleBuffer::new(width, height);
+        TerminalBuffer2 {
+            visible_buf,
+            scrollback: Scrollback::new(0, ScrollbackLimit::default()),
+            primary_screen: None,
+            alternate_discarded_lines: 0,
+            scroll_region: 0..height,
+            autowrap: true,
+        }
+    }
+
+    pub fn from_snapshot(snapshot: SnapshotItem) -> Result<TerminalBuffer2, LoadSnapshotError> {
+        use terminal_buffer_keys::*;
+        use LoadSnapshotErrorKind::*;
+
+        let mut root = snapshot.into_map().map_err(|_| RootNotMap)?;
+        let visible_buf = root.remove(VISIBLE_BUF).ok_or(VisibleBufNotPresent)?;
+        let visible_buf = VisibleBuffer::from_snapshot(visible_buf)?;
+
+        let scrollback = root.remove(SCROLLBACK).ok_or(ScrollbackNotPresent)?;
+        let scrollback = scrollback.into_vec().map_err(|_| ScrollbackNotArray)?;
+        let scrollback: Result<Vec<_>, _> = scrollback
+            .into_iter()
+            .map(|item| item.into_num::<u8>())
+            .collect();
+        let scrollback = scrollback.map_err(|_| ScrollbackElemNotu8)?;
+
+        let scrollback_line_positions = root.remove(SCROLLBACK_LINE_POS).unwrap();
+        let scrollback_line_positions = scrollback_line_positions.into_vec().unwrap();
+        let scrollback_line_positions: VecDeque<usize> = scrollback_line_positions
+            .into_iter()
+            .map(|x| x.into_num().unwrap())
+            .collect();
+
+        // Recordings from before eviction existed never dropped a line
+        let scrollback_first_line_id = root
+            .remove(SCROLLBACK_FIRST_LINE_ID)
+            .map(|item| item.into_num::<usize>())
+            .transpose()
+            .map_err(|_| ElemNotUsize(SCROLLBACK_FIRST_LINE_ID))?
+            .unwrap_or(0);
+
+        // Missing limits mean unlimited
+        let mut load_limit = |key| -> Result<Option<usize>, LoadSnapshotErrorKind> {
+            root.remove(key)
+                .map(|item| item.into_num::<usize>().map_err(|_| ElemNotUsize(key)))
+                .transpose()
+        };
+        let scrollback_limit = ScrollbackLimit {
+            max_lines: load_limit(SCROLLBACK_MAX_LINES)?,
+            max_bytes: load_limit(SCROLLBACK_MAX_BYTES)?,
+        };
+
+        let scrollback_formats = match root.remove(SCROLLBACK_FORMATS) {
+            Some(formats) => load_line_formats(formats, SCROLLBACK_FORMATS)?,
+            None => vec![LineFormat::default(); scrollback_line_positions.len()],
+        };
+        if scrollback_formats.len() != scrollback_line_positions.len() {
+            Err(FormatCountMismatch(SCROLLBACK_FORMATS))?;
+        }
+
+        let scrollback = Scrollback {
+            data: scrollback.into(),
+            line_positions: scrollback_line_positions,
+            formats: scrollback_formats.into(),
+            evicted_bytes: 0,
+            first_line_id: scrollback_first_line_id,
+            limit: scrollback_limit,
+        };
+
+        let primary_screen = root
+            .remove(PRIMARY_SCREEN)
+            .map(PrimaryScreen::from_snapshot)
+            .transpose()?;
+
+        let alternate_discarded_lines = root
+            .remove(ALTERNATE_DISCARDED_LINES)
+            .ok_or(ElemNotPresent(ALTERNATE_DISCARDED_LINES))?
+            .into_num::<usize>()
+            .map_err(|_| ElemNotUsize(ALTERNATE_DISCARDED_LINES))?;
+
+        let mut load_usize = |key| -> Result<usize, LoadSnapshotErrorKind> {
+            root.remove(key)
+                .ok_or(ElemNotPresent(key))?
+                .into_num::<usize>()
+                .map_err(|_| ElemNotUsize(key))
+        };
+        let scroll_region = load_usize(SCROLL_REGION_TOP)?..load_usize(SCROLL_REGION_BOTTOM)?;
+        if scroll_region.start >= scroll_region.end || scroll_region.end > visible_buf.height {
+            Err(InvalidScrollRegion)?;
+        }
+
+        let autowrap = root
+            .remove(AUTOWRAP)
+            .ok_or(ElemNotPresent(AUTOWRAP))?
+            .into_bool()
+            .map_err(|_| ElemNotBool(AUTOWRAP))?;
+
+        Ok(TerminalBuffer2 {
+            scrollback,
+            visible_buf,
+            primary_screen,
+            alternate_discarded_lines,
+            scroll_region,
+            autowrap,
+        })
+    }
+
+    pub fn snapshot(&self) -> Result<SnapshotItem, CreateSnapshotError> {
+        pub use terminal_buffer_keys::*;
+        use CreateSnapshotErrorKind::*;
+        let scrollback_line_positions_i64: Vec<SnapshotItem> = self
+            .scrollback
+            .line_positions
+            .iter()
+            .map(|x| {
+                let x: i64 = (x - self.scrollback.evicted_bytes).try_into().unwrap();
+                SnapshotItem::Int(x)
+            })
+            .collect();
+        let scrollback_first_line_id: i64 = self
+            .scrollback
+            .first_line_id
+            .try_into()
+            .map_err(ScrollbackLineIdNotI64)?;
+        let alternate_discarded_lines: i64 = self
+            .alternate_discarded_lines
+            .try_into()
+            .map_err(DiscardedLinesNotI64)?;
+        let scroll_region_top: i64 = self
+            .scroll_region
+            .start
+            .try_into()
+            .map_err(ScrollRegionNotI64)?;
+        let scroll_region_bottom: i64 = self
+            .scroll_region
+            .end
+            .try_into()
+            .map_err(ScrollRegionNotI64)?;
+        let mut ret: std::collections::HashMap<_, _> = [
+            (
+                SCROLLBACK.to_string(),
+                self.scrollback.data.iter().collect(),
+            ),
+            (
+                SCROLLBACK_LINE_POS.to_string(),
+                SnapshotItem::Array(scrollback_line_positions_i64),
+            ),
+            (
+                SCROLLBACK_FIRST_LINE_ID.to_string(),
+                scrollback_first_line_id.into(),
+            ),
+            (
+                SCROLLBACK_FORMATS.to_string(),
+                snapshot_line_formats(&self.scrollback.formats)?,
+            ),
+            (VISIBLE_BUF.to_string(), self.visible_buf.snapshot()?),
+            (
+                ALTERNATE_DISCARDED_LINES.to_string(),
+                alternate_discarded_lines.into(),
+            ),
+            (SCROLL_REGION_TOP.to_string(), scroll_region_top.into()),
+            (
+                SCROLL_REGION_BOTTOM.to_string(),
+                scroll_region_bottom.into(),
+            ),
+            (AUTOWRAP.to_string(), self.autowrap.into()),
+        ]
+        .into();
+        if let Some(primary_screen) = &self.primary_screen {
+            ret.insert(PRIMARY_SCREEN.to_string(), primary_screen.snapshot()?);
+        }
+        let limits = [
+            (SCROLLBACK_MAX_LINES, self.scrollback.limit.max_lines),
+            (SCROLLBACK_MAX_BYTES, self.scrollback.limit.max_bytes),
+        ];
+        for (key, limit) in limits {
+            if let Some(limit) = limit {
+                let limit: i64 = limit.try_into().map_err(ScrollbackLimitNotI64)?;
+                ret.insert(key.to_string(), limit.into());
+            }
+        }
+        Ok(SnapshotItem::Map(ret))
+    }
+
+    pub fn is_alternate_screen_active(&self) -> bool {
+        self.primary_screen.is_some()
+    }
+
+    /// Switch to a blank alternate screen. If saved_cursor is provided it will be handed back
+    /// on exit_alternate_screen()
+    pub fn enter_alternate_screen(&mut self, saved_cursor: Option<CursorPos>) {
+        if self.is_alternate_screen_active() {
+            return;
+        }
+
+        let alternate_buf = VisibleBuffer::new(self.visible_buf.width, self.visible_buf.height);
+        let visible_buf = std::mem::replace(&mut self.visible_buf, alternate_buf);
+        self.primary_screen = Some(PrimaryScreen {
+            visible_buf,
+            saved_cursor,
+        });
+        self.alternate_discarded_lines = 0;
+    }
+
+    /// Switch back to the primary screen, discarding the alternate screen contents. Returns the
+    /// cursor position saved on entry
+    pub fn exit_alternate_screen(&mut self) -> Option<CursorPos> {
+        let primary_screen = self.primary_screen.take()?;
+        self.visible_buf = primary_screen.visible_buf;
+        self.alternate_discarded_lines = 0;
+        primary_screen.saved_cursor
+    }
+
+    /// Line id of the first line in the visible buffer
+    pub fn first_visible_line_id(&self) -> usize {
+        if self.is_alternate_screen_active() {
+            self.alternate_discarded_lines
+        } else {
+            self.scrollback.end_line_id()
+        }
+    }
+
+    /// Line id of the oldest line still held for the active screen. Anything before it has been
+    /// evicted or scrolled away
+    pub fn first_line_id(&self) -> usize {
+        if self.is_alternate_screen_active() {
+            self.alternate_discarded_lines
+        } else {
+            self.scrollback.first_line_id
+        }
+    }
+
+    pub fn set_scrollback_limit(&mut self, limit: ScrollbackLimit) {
+        self.scrollback.set_limit(limit);
+    }
+
+    pub fn clear_scrollback(&mut self) {
+        self.scrollback.clear();
+    }
+
+    /// Sets the top and bottom margins (DECSTBM). top is inclusive, bottom exclusive. Invalid
+    /// regions are ignored, returns whether the region was applied
+    pub fn set_scroll_region(&mut self, top: usize, bottom: usize) -> bool {
+        let bottom = bottom.min(self.visible_buf.height);
+        // A scroll region must be at least 2 lines
+        if top + 1 >= bottom {
+            return false;
+        }
+        self.scroll_region = top..bottom;
+        true
+    }
+
+    pub fn scroll_region(&self) -> Range<usize> {
+        self.scroll_region.clone()
+    }
+
+    pub fn set_autowrap(&mut self, autowrap: bool) {
+        self.autowrap = autowrap;
+    }
+
+    fn is_full_screen_scroll_region(&self) -> bool {
+        self.scroll_region == (0..self.visible_buf.height)
+    }
+
+    fn region_scroll(&self, region: Range<usize>, distance: isize) -> RegionScroll {
+        let first_visible_line_id = self.first_visible_line_id();
+        RegionScroll {
+            lines: first_visible_line_id + region.start..first_visible_line_id + region.end,
+            distance,
+        }
+    }
+
+    /// Scrolls the scroll region up by num_lines. If the region covers the whole screen lines
+    /// leave through push_line() and keep their ids, in which case None is returned. New lines
+    /// are blank with the fill format, as with all other operations that make room
+    pub fn scroll_up(&mut self, num_lines: usize, fill: &CellFormat) -> Option<RegionScroll> {
+        if self.is_full_screen_scroll_region() {
+            for _ in 0..num_lines.min(self.visible_buf.height) {
+                self.push_line(fill);
+            }
+            return None;
+        }
+
+        let distance = -(num_lines.min(self.scroll_region.len()) as isize);
+        self.visible_buf
+            .shift_lines(self.scroll_region.clone(), distance, fill);
+        Some(self.region_scroll(self.scroll_region.clone(), distance))
+    }
+
+    /// Scrolls the scroll region down by num_lines, lines pushed past the bottom margin are lost
+    pub fn scroll_down(&mut self, num_lines: usize, fill: &CellFormat) -> RegionScroll {
+        let distance = num_lines.min(self.scroll_region.len()) as isize;
+        self.visible_buf
+            .shift_lines(self.scroll_region.clone(), distance, fill);
+        self.region_scroll(self.scroll_region.clone(), distance)
+    }
+
+    /// Moves the cursor down a line without changing the x position, scrolling if we are on the
+    /// bottom margin
+    pub fn index(
+        &mut self,
+        cursor_pos: &CursorPos,
+        fill: &CellFormat,
+    ) -> TerminalBufferModification {
+        let mut new_cursor_pos = cursor_pos.clone();
+        let mut region_scroll = None;
+        if cursor_pos.y + 1 == self.scroll_region.end {
+            region_scroll = self.scroll_up(1, fill);
+        } else if cursor_pos.y + 1 < self.visible_buf.height {
+            new_cursor_pos.y += 1;
+        }
+
+        let pos = self.cursor_to_buf_pos(&new_cursor_pos);
+        TerminalBufferModification {
+            written_range: pos..pos,
+            new_cursor_pos,
+            region_scroll,
+        }
+    }
+
+    /// Moves the cursor up a line without changing the x position, scrolling if we are on the
+    /// top margin
+    pub fn reverse_index(
+        &mut self,
+        cursor_pos: &CursorPos,
+        fill: &CellFormat,
+    ) -> TerminalBufferModification {
+        let mut new_cursor_pos = cursor_pos.clone();
+        let mut region_scroll = None;
+        if cursor_pos.y == self.scroll_region.start {
+            region_scroll = Some(self.scroll_down(1, fill));
+        } else if cursor_pos.y > 0 {
+            new_cursor_pos.y -= 1;
+        }
+
+        let pos = self.cursor_to_buf_pos(&new_cursor_pos);
+        TerminalBufferModification {
+            written_range: pos..pos,
+            new_cursor_pos,
+            region_scroll,
+        }
+    }
+
+    /// Scrolls the visible buffer by a line. The top line goes to scrollback on the primary
+    /// screen and is dropped on the alternate screen
+    fn push_line(&mut self, fill: &CellFormat) -> Line<'_> {
+        if self.is_alternate_screen_active() {
+            self.alternate_discarded_lines += 1;
+            self.visible_buf.push_line(fill)
+        } else {
+            self.push_line_to_scrollback(fill)
+        }
+    }
+
+    fn push_line_to_scrollback(&mut self, fill: &CellFormat) -> Line<'_> {
+        let width = self.visible_buf.width;
+        let line_to_evict = self.visible_buf.get_line(0);
+        let mut line_data = line_to_evict.serialize().into_bytes();
+        // Only full lines can have wrapped into the next one
+        if *line_to_evict.newline || *line_to_evict.len < width {
+            line_data.push(b'\n');
+        }
+        let format = std::mem::take(line_to_evict.format);
+        self.scrollback.push_line(&line_data, format);
+        self.visible_buf.push_line(fill)
+    }
+
+    fn cursor_to_buf_pos(&self, cursor_pos: &CursorPos) -> BufPos {
+        let line_id = self.first_visible_line_id() + cursor_pos.y;
+        let x_pos = cursor_pos.x;
+
+        BufPos { line_id, x_pos }
+    }
+
+    /// Writes utf8 data at the cursor with the given format. Positions in the buffer count cells,
+    /// so wide chars take up two columns and combining chars none
+    pub fn insert_data(
+        &mut self,
+        cursor_pos: &CursorPos,
+        data: &[u8],
+        format: &CellFormat,
+    ) -> TerminalBufferModification {
+        let data = String::from_utf8_lossy(data)
+            .chars()
+            // NUL is used to mark wide char spacers, and is ignored by terminals anyways
+            .filter(|c| *c != WIDE_CHAR_SPACER)
+            .collect::<Vec<_>>();
+        self.insert_chars(cursor_pos, &data, format)
+    }
+
+    fn insert_chars(
+        &mut self,
+        cursor_pos: &CursorPos,
+        mut data: &[char],
+        format: &CellFormat,
+    ) -> TerminalBufferModification {
+        let mut x = cursor_pos.x;
+        let mut y = cursor_pos.y;
+        let width = self.visible_buf.width;
+        let max_y_idx = self.visible_buf.height - 1;
+        debug!("{:?}", data.iter().collect::<String>());
+        assert!(y <= max_y_idx);
+
+        let mut write_start = self.cursor_to_buf_pos(cursor_pos);
+        let mut write_end = None;
+        let mut region_scroll: Option<RegionScroll> = None;
+
+        loop {
+            if data.is_empty() {
+                break;
+            }
+
+            if x == 0 && y > 0 && char_width(data[0]) == 0 {
+                // A combining char right after a wrap belongs to the end of the previous line
+                let mut prev_line = self.visible_buf.get_line(y - 1);
+                let prev_len = *prev_line.len;
+                if !*prev_line.newline && prev_len == prev_line.buf.len() {
+                    prev_line.combine_with_previous_cell(prev_len, data[0]);
+                }
+                data = &data[1..];
+                continue;
+            }
+
+            let mut line = self.visible_buf.get_line(y);
+
+            let response = line.insert_data(data, x, format);
+            let consumed = response.consumed;
+
+            x = response.new_x_pos;
+            if x >= width && !self.autowrap {
+                // Without autowrap everything past the edge overwrites the last column, so only
+                // the last char there is left
+                let rest = &data[consumed..];
+                if let Some(last) = rest.iter().rposition(|c| char_width(*c) > 0) {
+                    let last_x = width.saturating_sub(char_width(rest[last]));
+                    line.insert_data(&rest[last..], last_x, format);
+                }
+                x = width - 1;
+                write_end = Some(self.cursor_to_buf_pos(&CursorPos { x: width, y }));
+                break;
+            }
+            if x >= self.visible_buf.width {
+                x = 0;
+                y += 1;
+            }
+
+            if y == self.scroll_region.end {
+                y -= 1;
+                if let Some(scroll) = self.scroll_up(1, &format.blank()) {
+                    match &mut region_scroll {
+                        Some(region_scroll) => region_scroll.distance += scroll.distance,
+                        None => region_scroll = Some(scroll),
+                    }
+                }
+            } else if y > max_y_idx {
+                // Below the scroll region, the bottom line just gets overwritten
+                y = max_y_idx;
+            }
+            data = &data[consumed..];
+        }
+
+        if let Some(region_scroll) = &region_scroll {
+            // Anything written inside the region moved up with it
+            if region_scroll.lines.contains(&write_start.line_id) {
+                let moved_line_id = write_start
+                    .line_id
+                    .saturating_add_signed(region_scroll.distance);
+                if moved_line_id < region_scroll.lines.start {
+                    write_start = BufPos::new(0, region_scroll.lines.start);
+                } else {
+                    write_start.line_id = moved_line_id;
+                }
+            }
+        }
+
+        let new_cursor_pos = CursorPos { x, y };
+        let write_end = write_end.unwrap_or_else(|| self.cursor_to_buf_pos(&new_cursor_pos));
 
         TerminalBufferModification {
             written_range: write_start..write_end,
             new_cursor_pos: CursorPos { x, y },
+            region_scroll,
         }
     }
 
@@ -630,6 +1815,7 @@ impl TerminalBuffer2 {
         &mut self,
         cursor_pos: &CursorPos,
         num_spaces: usize,
+        fill: &CellFormat,
     ) -> TerminalBufferInsertResponse {
         if cursor_pos.y >= self.visible_buf.height {
             return TerminalBufferInsertResponse {
@@ -639,9 +1825,8 @@ impl TerminalBuffer2 {
             };
         }
         let mut line = self.visible_buf.get_line(cursor_pos.y);
-        line.insert_spaces(cursor_pos.x, num_spaces);
+        line.insert_spaces(cursor_pos.x, num_spaces, fill);
 
-        // FIXME: color tracking broken
         TerminalBufferInsertResponse {
             written_range: 0..0,
             insertion_range: 0..0,
@@ -655,89 +1840,130 @@ impl TerminalBuffer2 {
     //
     // What happens? Does the terminal emulator behave as if there are newlines there?
 
+    /// Inserts blank lines at the cursor, pushing lines below down to the bottom margin. Does
+    /// nothing if the cursor is outside the scroll region
     pub fn insert_lines(
         &mut self,
         cursor_pos: &CursorPos,
         num_lines: usize,
-    ) -> TerminalBufferInsertLineResponse {
-        let mut lines = self.visible_buf.get_all_lines();
-        debug!("{:?}", lines);
-        for source_idx in (cursor_pos.y..lines.len()).rev() {
-            let (a, b) = lines.split_at_mut(source_idx + 1);
-            let source = a
-                .last_mut()
-                .expect("source_idx shoul be guaranteed to be a valid element");
-            if let Some(dest) = b.get_mut(num_lines - 1) {
-                dest.copy_from_other(source);
-            }
-
-            source.clear();
+        fill: &CellFormat,
+    ) -> Option<RegionScroll> {
+        if !self.scroll_region.contains(&cursor_pos.y) {
+            return None;
         }
 
-        // FIXME: Formatting completely broken
-        TerminalBufferInsertLineResponse {
-            deleted_range: 0..0,
-            inserted_range: 0..0,
+        let region = cursor_pos.y..self.scroll_region.end;
+        let distance = num_lines.min(region.len()) as isize;
+        self.visible_buf.shift_lines(region.clone(), distance, fill);
+        Some(self.region_scroll(region, distance))
+    }
+
+    /// Deletes lines at the cursor, pulling lines below up from the bottom margin. Does nothing
+    /// if the cursor is outside the scroll region
+    pub fn delete_lines(
+        &mut self,
+        cursor_pos: &CursorPos,
+        num_lines: usize,
+        fill: &CellFormat,
+    ) -> Option<RegionScroll> {
+        if !self.scroll_region.contains(&cursor_pos.y) {
+            return None;
         }
+
+        let region = cursor_pos.y..self.scroll_region.end;
+        let distance = -(num_lines.min(region.len()) as isize);
+        self.visible_buf.shift_lines(region.clone(), distance, fill);
+        Some(self.region_scroll(region, distance))
     }
 
-    pub fn clear_forwards(&mut self, cursor_pos: &CursorPos) -> Option<usize> {
-        self.clear_line_forwards(cursor_pos);
+    pub fn clear_forwards(&mut self, cursor_pos: &CursorPos, fill: &CellFormat) {
+        self.clear_line_forwards(cursor_pos, fill);
         for y in cursor_pos.y + 1..self.visible_buf.height {
             let mut line = self.visible_buf.get_line(y);
-            line.clear();
+            line.clear(fill);
         }
-        // FIXME: color tracking
-        None
     }
 
-    pub fn clear_line_forwards(&mut self, cursor_pos: &CursorPos) -> Option<Range<usize>> {
-        let line = self.visible_buf.get_line(cursor_pos.y);
-        *line.len = usize::min(cursor_pos.x, *line.len);
+    pub fn clear_line_forwards(&mut self, cursor_pos: &CursorPos, fill: &CellFormat) {
+        let mut line = self.visible_buf.get_line(cursor_pos.y);
+        line.truncate(cursor_pos.x, fill);
         // FIXME: not sure
         *line.newline = false;
-        // FIXME: Color tracking is completely broken
-        None
     }
 
-    pub fn clear_all(&mut self) {
-        for y in 0..self.visible_buf.height {
+    /// Erases from the top of the screen up to and including the cursor (ED 1)
+    pub fn clear_backwards(&mut self, cursor_pos: &CursorPos, fill: &CellFormat) {
+        for y in 0..cursor_pos.y {
             let mut line = self.visible_buf.get_line(y);
-            line.clear();
+            line.clear(fill);
         }
-        self.scrollback.clear();
+        self.clear_line_backwards(cursor_pos, fill);
     }
 
-    pub fn delete_forwards(
-        &mut self,
-        cursor_pos: &CursorPos,
-        num_chars: usize,
-    ) -> Option<Range<usize>> {
-        let line = self.visible_buf.get_line(cursor_pos.y);
-        if cursor_pos.x > *line.len {
-            return None;
+    /// Erases from the start of the line up to and including the cursor (EL 1)
+    pub fn clear_line_backwards(&mut self, cursor_pos: &CursorPos, fill: &CellFormat) {
+        let mut line = self.visible_buf.get_line(cursor_pos.y);
+        line.erase(0..cursor_pos.x + 1, fill);
+    }
+
+    /// Erases the cursor's line (EL 2)
+    pub fn clear_line(&mut self, cursor_pos: &CursorPos, fill: &CellFormat) {
+        self.clear_line_forwards(
+            &CursorPos {
+                x: 0,
+                y: cursor_pos.y,
+            },
+            fill,
+        );
+    }
+
+    /// Blanks num_chars cells from the cursor on without moving the rest of the line (ECH)
+    pub fn erase_chars(&mut self, cursor_pos: &CursorPos, num_chars: usize, fill: &CellFormat) {
+        let mut line = self.visible_buf.get_line(cursor_pos.y);
+        line.erase(cursor_pos.x..cursor_pos.x.saturating_add(num_chars), fill);
+    }
+
+    pub fn clear_all(&mut self, fill: &CellFormat) {
+        for y in 0..self.visible_buf.height {
+            let mut line = self.visible_buf.get_line(y);
+            line.clear(fill);
         }
-        let num_chars = num_chars.min(*line.len - cursor_pos.x);
-        let new_end = *line.len - num_chars;
-        line.buf
-            .copy_within(cursor_pos.x + num_chars..*line.len, cursor_pos.x);
-        *line.len = new_end;
+    }
+
+    pub fn delete_forwards(&mut self, cursor_pos: &CursorPos, num_chars: usize, fill: &CellFormat) {
+        let mut line = self.visible_buf.get_line(cursor_pos.y);
+        line.delete_chars(cursor_pos.x, num_chars, fill);
         // FIXME: Should newline ever be cleared here?
-        // FIXME: color tracking broken
-        None
     }
 
     // FIXME: no mut
     pub fn data(&mut self) -> TerminalData2 {
         let visible_response = self.visible_buf.serialize();
-        let scrollback = self.scrollback.clone();
-        let scrollback_line_mappings = self.scrollback_line_positions.clone();
+        let first_visible_line_id = self.first_visible_line_id();
+        // Scrollback belongs to the primary screen and is hidden while the alternate screen is up
+        let (scrollback, scrollback_line_mappings, scrollback_formats, first_scrollback_line_id) =
+            match self.is_alternate_screen_active() {
+                true => (Vec::new(), Vec::new(), Vec::new(), 0),
+                false => {
+                    let (scrollback, line_mappings, formats) = self.scrollback.serialize();
+                    (
+                        scrollback,
+                        line_mappings,
+                        formats,
+                        self.scrollback.first_line_id,
+                    )
+                }
+            };
         //println!("scrollback: {:?}", scrollback);
         TerminalData2 {
             scrollback,
             visible: visible_response.data,
             visible_line_mappings: visible_response.line_mappings,
             scrollback_line_mappings,
+            scrollback_formats,
+            visible_formats: visible_response.formats,
+            first_scrollback_line_id,
+            first_visible_line_id,
         }
     }
 
@@ -745,12 +1971,141 @@ impl TerminalBuffer2 {
         (self.visible_buf.width, self.visible_buf.height)
     }
 
-    pub fn get_visible_range(&self) -> Range<BufPos> {
-        let first_visible_line_id = self.scrollback_line_positions.len();
-        let end_x = self.visible_buf.width;
-        let end_y = self.visible_buf.height + first_visible_line_id;
+    /// Text of the row with the given line id, and whether it soft-wraps into the next row. None
+    /// if the row has been evicted, belongs to hidden scrollback or does not exist yet
+    fn row(&mut self, line_id: usize) -> Option<(Vec<char>, bool)> {
+        if let Some(y) = line_id.checked_sub(self.first_visible_line_id()) {
+            if y >= self.visible_buf.height {
+                return None;
+            }
+            let width = self.visible_buf.width;
+            let line = self.visible_buf.get_line(y);
+            let wrapped = !*line.newline && *line.len == width;
+            return Some((line.text(0..*line.len), wrapped));
+        }
+
+        if self.is_alternate_screen_active() {
+            return None;
+        }
+
+        let idx = line_id.checked_sub(self.scrollback.first_line_id)?;
+        let row = self.scrollback.line(idx);
+        let row = String::from_utf8_lossy(&row);
+        Some(match row.strip_suffix('\n') {
+            Some(row) => (row.chars().collect(), false),
+            None => (row.chars().collect(), true),
+        })
+    }
+
+    /// Cells of the line containing line_id, following soft wraps in both directions
+    fn logical_line(&mut self, line_id: usize) -> LogicalLine {
+        let mut first_row = line_id;
+        while first_row > self.first_line_id() {
+            match self.row(first_row - 1) {
+                Some((_, true)) => first_row -= 1,
+                _ => break,
+            }
+        }
+
+        let mut cells = Vec::new();
+        let mut row_id = first_row;
+        while let Some((text, wrapped)) = self.row(row_id) {
+            let mut column = 0;
+            for c in text {
+                let width = char_width(c);
+                // Combining chars belong to the cell before them
+                if width != 0 {
+                    cells.push((c, BufPos::new(column, row_id)));
+                }
+                column += width;
+            }
+
+            row_id += 1;
+            if !wrapped {
+                break;
+            }
+        }
+
+        LogicalLine {
+            rows: first_row..row_id.max(first_row + 1),
+            cells,
+        }
+    }
+
+    /// The word, run of whitespace or single other char at pos. Empty if pos is past the end of
+    /// the line
+    pub fn word_range(&mut self, pos: BufPos) -> Range<BufPos> {
+        let cells = self.logical_line(pos.line_id).cells;
+        let cell_end = |(c, cell_pos): &(char, BufPos)| {
+            BufPos::new(cell_pos.x_pos + char_width(*c), cell_pos.line_id)
+        };
+
+        let Some(idx) = cells.iter().position(|cell| pos < cell_end(cell)) else {
+            return pos..pos;
+        };
+        if cells[idx].1 > pos {
+            // Blank cell before a wide char that wrapped
+            return pos..pos;
+        }
+
+        let class = char_class(cells[idx].0);
+        if class == CharClass::Other {
+            return cells[idx].1..cell_end(&cells[idx]);
+        }
+
+        let same_class = |cell: &&(char, BufPos)| char_class(cell.0) == class;
+        let start = cells[..idx]
+            .iter()
+            .rev()
+            .take_while(same_class)
+            .last()
+            .unwrap_or(&cells[idx]);
+        let end = cells[idx..]
+            .iter()
+            .take_while(same_class)
+            .last()
+            .unwrap_or(&cells[idx]);
+        start.1..cell_end(end)
+    }
+
+    /// The whole line at pos, including the rows it soft-wraps onto
+    pub fn line_range(&mut self, pos: BufPos) -> Range<BufPos> {
+        let rows = self.logical_line(pos.line_id).rows;
+        BufPos::new(0, rows.start)..BufPos::new(usize::MAX, rows.end - 1)
+    }
+
+    /// Text between two positions. Soft-wrapped rows are joined, other rows end in a newline
+    /// with trailing whitespace removed
+    pub fn text(&mut self, range: Range<BufPos>) -> String {
+        let mut ret = String::new();
+        let first_line_id = range.start.line_id.max(self.first_line_id());
+        for line_id in first_line_id..=range.end.line_id {
+            let Some((text, wrapped)) = self.row(line_id) else {
+                break;
+            };
 
-        BufPos::new(0, first_visible_line_id)..BufPos::new(end_x, end_y)
+            let is_first = line_id == range.start.line_id;
+            let is_last = line_id == range.end.line_id;
+            let start_column = if is_first { range.start.x_pos } else { 0 };
+            let end_column = if is_last { range.end.x_pos } else { usize::MAX };
+            let start = column_to_char_offset(&text, start_column).0;
+            let end = column_to_char_offset(&text, end_column).0.max(start);
+
+            let row = text[start..end].iter().collect::<String>();
+            if wrapped || end < text.len() {
+                ret.push_str(&row);
+            } else {
+                ret.push_str(row.trim_end());
+            }
+
+            if is_last {
+                break;
+            }
+            if !wrapped {
+                ret.push('\n');
+            }
+        }
+        ret
     }
 
     pub fn set_win_size(
@@ -762,54 +2117,163 @@ impl TerminalBuffer2 {
         if self.visible_buf.width == width && self.visible_buf.height == height {
             return TerminalBufferSetWinSizeResponse {
                 changed: false,
-                insertion_range: 0..0,
                 new_cursor_pos: cursor_pos.clone(),
+                reflow: None,
+                primary_reflow: None,
             };
         }
 
-        let mut old_visible_buf =
-            std::mem::replace(&mut self.visible_buf, VisibleBuffer::new(width, height));
-        let old_lines = old_visible_buf.get_all_lines();
-
-        let mut pos = CursorPos { x: 0, y: 0 };
-        let mut new_cursor_pos = pos.clone();
-        for (i, line) in old_lines.into_iter().enumerate() {
-            // FIXME: pos, cursor_pos naming is confusing
-            if i == cursor_pos.y {
-                let serialized = line.serialize();
-                // FIXME: out of bounds handling
-                new_cursor_pos = self
-                    .insert_data(&pos, &serialized[..cursor_pos.x])
-                    .new_cursor_pos;
-                pos = self
-                    .insert_data(&pos, &serialized[cursor_pos.x..])
-                    .new_cursor_pos;
-            } else {
-                pos = self.insert_data(&pos, line.serialize()).new_cursor_pos;
+        // Margins do not survive a resize
+        self.scroll_region = 0..height;
+
+        let mut primary_reflow = None;
+        if let Some(mut primary_screen) = self.primary_screen.take() {
+            // Reflow the hidden primary screen as well so that it matches the window size when we
+            // switch back to it
+            std::mem::swap(&mut self.visible_buf, &mut primary_screen.visible_buf);
+            let (reflow, new_cursor_pos) =
+                self.reflow(width, height, primary_screen.saved_cursor.as_ref());
+            primary_screen.saved_cursor = new_cursor_pos;
+            std::mem::swap(&mut self.visible_buf, &mut primary_screen.visible_buf);
+            self.primary_screen = Some(primary_screen);
+            primary_reflow = Some(reflow);
+        }
+
+        let (reflow, new_cursor_pos) = self.reflow(width, height, Some(cursor_pos));
+
+        TerminalBufferSetWinSizeResponse {
+            changed: true,
+            new_cursor_pos: new_cursor_pos.expect("cursor position should be mapped"),
+            reflow: Some(reflow),
+            primary_reflow,
+        }
+    }
+
+    /// Rewraps the screen, and scrollback if it has any, to a new size. Lines that were
+    /// soft-wrapped are joined back together before being wrapped at the new width. The top of
+    /// the screen stays where it was unless more room is needed to keep the cursor and the text
+    /// above it on screen
+    fn reflow(
+        &mut self,
+        width: usize,
+        height: usize,
+        cursor_pos: Option<&CursorPos>,
+    ) -> (Reflow, Option<CursorPos>) {
+        let is_alternate_screen = self.is_alternate_screen_active();
+        let first_line_id = self.first_line_id();
+        let old_first_visible_line_id = self.first_visible_line_id();
+
+        let mut logical_lines = LogicalLines::default();
+        if !is_alternate_screen {
+            for i in 0..self.scrollback.len() {
+                let row = self.scrollback.line(i);
+                let row = String::from_utf8_lossy(&row);
+                let (row, newline) = match row.strip_suffix('\n') {
+                    Some(row) => (row, true),
+                    None => (&*row, false),
+                };
+                logical_lines.push_row(
+                    &row.chars().collect::<Vec<_>>(),
+                    &self.scrollback.formats[i],
+                    !newline,
+                    newline,
+                );
+            }
+        }
+
+        let old_width = self.visible_buf.width;
+        for line in self.visible_buf.get_all_lines() {
+            let wrapped = !*line.newline && *line.len == old_width;
+            logical_lines.push_row(
+                &line.text(0..*line.len),
+                line.format,
+                wrapped,
+                *line.newline,
+            );
+        }
+        let newlines = std::mem::take(&mut logical_lines.newlines);
+
+        let reflow = Reflow::new(width, logical_lines, first_line_id);
+
+        let row_of = |pos: BufPos| reflow.map(&pos).line_id - first_line_id;
+        let top = row_of(BufPos::new(0, old_first_visible_line_id));
+        let mut first_visible_row = top.max(reflow.content_end().saturating_sub(height));
+
+        let new_cursor_pos = cursor_pos.map(|cursor_pos| {
+            let mut pos = reflow.map(&BufPos::new(
+                cursor_pos.x,
+                old_first_visible_line_id + cursor_pos.y,
+            ));
+            // Writing up to the end of a line puts the cursor at the start of the next one
+            if pos.x_pos == width {
+                pos = BufPos::new(0, pos.line_id + 1);
             }
+            pos
+        });
+        if let Some(cursor_pos) = &new_cursor_pos {
+            let cursor_row = cursor_pos.line_id - first_line_id;
+            first_visible_row = first_visible_row
+                .max((cursor_row + 1).saturating_sub(height))
+                .min(cursor_row);
+        }
 
-            if *line.newline {
-                pos = self.insert_data(&pos, b"\n").new_cursor_pos;
+        self.visible_buf = VisibleBuffer::new(width, height);
+        let visible_rows = reflow
+            .new_rows
+            .iter()
+            .enumerate()
+            .skip(first_visible_row)
+            .take(height);
+        for (y, (row_idx, row)) in visible_rows.enumerate() {
+            let mut line = self.visible_buf.get_line(y);
+            line.insert_chars(reflow.row_text(row), 0);
+            *line.format = reflow.row_format(row);
+            if reflow.is_last_row_of_line(row_idx) {
+                *line.newline = newlines[row.logical_line];
+            } else {
+                line.pad_wrapped();
             }
         }
 
-        TerminalBufferSetWinSizeResponse {
-            changed: true,
-            insertion_range: 0..0,
-            new_cursor_pos,
+        if is_alternate_screen {
+            self.alternate_discarded_lines += first_visible_row;
+        } else {
+            // Rebuilt lines keep the ids the reflow assigned them. Rows that do not fit the limit
+            // any more get evicted as they are pushed
+            self.scrollback = Scrollback::new(first_line_id, self.scrollback.limit);
+            for (row_idx, row) in reflow.new_rows[..first_visible_row].iter().enumerate() {
+                let mut line_data = reflow.row_text(row).iter().collect::<String>().into_bytes();
+                if reflow.is_last_row_of_line(row_idx) {
+                    line_data.push(b'\n');
+                }
+                self.scrollback
+                    .push_line(&line_data, reflow.row_format(row));
+            }
         }
+
+        let new_cursor_pos = new_cursor_pos.map(|pos| CursorPos {
+            x: pos.x_pos.min(width.saturating_sub(1)),
+            y: pos.line_id - first_line_id - first_visible_row,
+        });
+
+        (reflow, new_cursor_pos)
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::terminal_emulator::TerminalColor;
 
     #[test]
     fn test_canvas_clear_forwards() {
         let mut buffer = TerminalBuffer2::new(5, 5);
         // Push enough data to get some in scrollback
-        buffer.insert_data(&CursorPos { x: 0, y: 0 }, b"012343456789\n0123456789\n1234");
+        buffer.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            b"012343456789\n0123456789\n1234",
+            &CellFormat::default(),
+        );
 
         assert_eq!(
             buffer.data().visible,
@@ -820,7 +2284,7 @@ mod test {
                    56789\n\
                    1234\n"
         );
-        buffer.clear_forwards(&CursorPos { x: 1, y: 1 });
+        buffer.clear_forwards(&CursorPos { x: 1, y: 1 }, &CellFormat::default());
         // Same amount of lines should be present before and after clear
         assert_eq!(
             buffer.data().visible,
@@ -832,8 +2296,12 @@ mod test {
         // A few special cases.
         // 1. Truncating on beginning of line and previous char was not a newline
         let mut buffer = TerminalBuffer2::new(5, 5);
-        buffer.insert_data(&CursorPos { x: 0, y: 0 }, b"012340123401234012340123401234");
-        buffer.clear_forwards(&CursorPos { x: 0, y: 1 });
+        buffer.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            b"012340123401234012340123401234",
+            &CellFormat::default(),
+        );
+        buffer.clear_forwards(&CursorPos { x: 0, y: 1 }, &CellFormat::default());
         assert_eq!(buffer.data().visible, b"01234\n");
 
         // 2. Truncating on beginning of line and previous char was a newline
@@ -841,46 +2309,63 @@ mod test {
         buffer.insert_data(
             &CursorPos { x: 0, y: 0 },
             b"01234\n0123401234012340123401234",
+            &CellFormat::default(),
         );
-        buffer.clear_forwards(&CursorPos { x: 0, y: 1 });
+        buffer.clear_forwards(&CursorPos { x: 0, y: 1 }, &CellFormat::default());
         assert_eq!(buffer.data().visible, b"01234\n");
 
         // 3. Truncating on a newline
         let mut buffer = TerminalBuffer2::new(5, 5);
-        buffer.insert_data(&CursorPos { x: 0, y: 0 }, b"\n\n\n\n\n\n");
-        buffer.clear_forwards(&CursorPos { x: 0, y: 1 });
+        buffer.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            b"\n\n\n\n\n\n",
+            &CellFormat::default(),
+        );
+        buffer.clear_forwards(&CursorPos { x: 0, y: 1 }, &CellFormat::default());
         assert_eq!(buffer.data().visible, b"");
     }
 
     #[test]
     fn test_canvas_clear() {
         let mut buffer = TerminalBuffer2::new(5, 5);
-        buffer.insert_data(&CursorPos { x: 0, y: 0 }, b"0123456789");
-        buffer.clear_all();
-        assert_eq!(buffer.data().visible, &[]);
+        buffer.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            b"0123456789",
+            &CellFormat::default(),
+        );
+        buffer.clear_all(&CellFormat::default());
+        assert!(buffer.data().visible.is_empty());
     }
 
     #[test]
     fn test_terminal_buffer_overwrite_early_newline() {
         let mut buffer = TerminalBuffer2::new(5, 5);
-        buffer.insert_data(&CursorPos { x: 0, y: 0 }, b"012\n3456789");
+        buffer.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            b"012\n3456789",
+            &CellFormat::default(),
+        );
         assert_eq!(buffer.data().visible, b"012\n3456789\n");
 
         // Cursor pos should be calculated based off wrapping at column 5, but should not result in
         // an extra newline
-        buffer.insert_data(&CursorPos { x: 2, y: 1 }, b"test");
+        buffer.insert_data(&CursorPos { x: 2, y: 1 }, b"test", &CellFormat::default());
         assert_eq!(buffer.data().visible, b"012\n34test9\n");
     }
 
     #[test]
     fn test_terminal_buffer_overwrite_no_newline() {
         let mut buffer = TerminalBuffer2::new(5, 5);
-        buffer.insert_data(&CursorPos { x: 0, y: 0 }, b"0123456789");
+        buffer.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            b"0123456789",
+            &CellFormat::default(),
+        );
         assert_eq!(buffer.data().visible, b"0123456789\n");
 
         // Cursor pos should be calculated based off wrapping at column 5, but should not result in
         // an extra newline
-        buffer.insert_data(&CursorPos { x: 2, y: 1 }, b"test");
+        buffer.insert_data(&CursorPos { x: 2, y: 1 }, b"test", &CellFormat::default());
         assert_eq!(buffer.data().visible, b"0123456test\n");
     }
 
@@ -889,20 +2374,32 @@ mod test {
         // This should behave exactly as test_terminal_buffer_overwrite_no_newline(), except with a
         // neline between lines 1 and 2
         let mut buffer = TerminalBuffer2::new(5, 5);
-        buffer.insert_data(&CursorPos { x: 0, y: 0 }, b"01234\n56789");
+        buffer.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            b"01234\n56789",
+            &CellFormat::default(),
+        );
         assert_eq!(buffer.data().visible, b"01234\n56789\n");
 
-        buffer.insert_data(&CursorPos { x: 2, y: 1 }, b"test");
+        buffer.insert_data(&CursorPos { x: 2, y: 1 }, b"test", &CellFormat::default());
         assert_eq!(buffer.data().visible, b"01234\n56test\n");
     }
 
     #[test]
     fn test_terminal_buffer_insert_unallocated_data() {
         let mut buffer = TerminalBuffer2::new(10, 10);
-        buffer.insert_data(&CursorPos { x: 4, y: 5 }, b"hello world");
+        buffer.insert_data(
+            &CursorPos { x: 4, y: 5 },
+            b"hello world",
+            &CellFormat::default(),
+        );
         assert_eq!(buffer.data().visible, b"\n\n\n\n\n    hello world\n");
 
-        buffer.insert_data(&CursorPos { x: 3, y: 2 }, b"hello world");
+        buffer.insert_data(
+            &CursorPos { x: 3, y: 2 },
+            b"hello world",
+            &CellFormat::default(),
+        );
         assert_eq!(
             buffer.data().visible,
             b"\n\n   hello world\n\n    hello world\n"
@@ -916,17 +2413,22 @@ mod test {
 
         fn crlf(pos: &mut CursorPos, canvas: &mut TerminalBuffer2) {
             pos.x = 0;
-            *pos = canvas.insert_data(pos, b"\n").new_cursor_pos;
+            *pos = canvas
+                .insert_data(pos, b"\n", &CellFormat::default())
+                .new_cursor_pos;
         }
 
         // Simulate real terminal usage where newlines are injected with cursor moves
-        let mut response = canvas.insert_data(&initial_cursor_pos, b"asdf");
+        let mut response = canvas.insert_data(&initial_cursor_pos, b"asdf", &CellFormat::default());
         crlf(&mut response.new_cursor_pos, &mut canvas);
-        let mut response = canvas.insert_data(&response.new_cursor_pos, b"xyzw");
+        let mut response =
+            canvas.insert_data(&response.new_cursor_pos, b"xyzw", &CellFormat::default());
         crlf(&mut response.new_cursor_pos, &mut canvas);
-        let mut response = canvas.insert_data(&response.new_cursor_pos, b"1234");
+        let mut response =
+            canvas.insert_data(&response.new_cursor_pos, b"1234", &CellFormat::default());
         crlf(&mut response.new_cursor_pos, &mut canvas);
-        let mut response = canvas.insert_data(&response.new_cursor_pos, b"5678");
+        let _response =
+            canvas.insert_data(&response.new_cursor_pos, b"5678", &CellFormat::default());
         //crlf(&mut response.new_cursor_pos, &mut canvas);
 
         assert_eq!(canvas.data().scrollback, b"asdf\n");
@@ -936,51 +2438,56 @@ mod test {
     #[test]
     fn test_canvas_delete_forwards() {
         let mut canvas = TerminalBuffer2::new(10, 5);
-        canvas.insert_data(&CursorPos { x: 0, y: 0 }, b"asdf\n123456789012345");
+        canvas.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            b"asdf\n123456789012345",
+            &CellFormat::default(),
+        );
 
         // Test normal deletion
-        let deleted_range = canvas.delete_forwards(&CursorPos { x: 1, y: 0 }, 1);
-
-        //assert_eq!(deleted_range, Some(1..2));
+        canvas.delete_forwards(&CursorPos { x: 1, y: 0 }, 1, &CellFormat::default());
         assert_eq!(canvas.data().visible, b"adf\n123456789012345\n");
 
         // Test deletion clamped on newline
-        let deleted_range = canvas.delete_forwards(&CursorPos { x: 1, y: 0 }, 10);
-        //assert_eq!(deleted_range, Some(1..3));
+        canvas.delete_forwards(&CursorPos { x: 1, y: 0 }, 10, &CellFormat::default());
         assert_eq!(canvas.data().visible, b"a\n123456789012345\n");
 
         // Test deletion clamped on wrap
-        let deleted_range = canvas.delete_forwards(&CursorPos { x: 7, y: 1 }, 10);
-        //assert_eq!(deleted_range, Some(9..12));
+        canvas.delete_forwards(&CursorPos { x: 7, y: 1 }, 10, &CellFormat::default());
         assert_eq!(canvas.data().visible, b"a\n1234567\n12345\n");
 
         // Test deletion in case where nothing is deleted
-        let deleted_range = canvas.delete_forwards(&CursorPos { x: 5, y: 5 }, 10);
-        //assert_eq!(deleted_range, None);
+        canvas.delete_forwards(&CursorPos { x: 5, y: 5 }, 10, &CellFormat::default());
         assert_eq!(canvas.data().visible, b"a\n1234567\n12345\n");
     }
 
     #[test]
     fn test_canvas_insert_spaces() {
         let mut canvas = TerminalBuffer2::new(10, 5);
-        canvas.insert_data(&CursorPos { x: 0, y: 0 }, b"asdf\n123456789012345");
+        canvas.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            b"asdf\n123456789012345",
+            &CellFormat::default(),
+        );
 
         // Happy path
-        let response = canvas.insert_spaces(&CursorPos { x: 2, y: 0 }, 2);
+        let response = canvas.insert_spaces(&CursorPos { x: 2, y: 0 }, 2, &CellFormat::default());
         //assert_eq!(response.written_range, 2..4);
         //assert_eq!(response.insertion_range, 2..4);
         assert_eq!(response.new_cursor_pos, CursorPos { x: 2, y: 0 });
         assert_eq!(canvas.data().visible, b"as  df\n123456789012345\n");
 
         // Truncation at newline
-        let response = canvas.insert_spaces(&CursorPos { x: 2, y: 0 }, 1000);
+        let response =
+            canvas.insert_spaces(&CursorPos { x: 2, y: 0 }, 1000, &CellFormat::default());
         //assert_eq!(response.written_range, 2..10);
         //assert_eq!(response.insertion_range, 2..6);
         assert_eq!(response.new_cursor_pos, CursorPos { x: 2, y: 0 });
         assert_eq!(canvas.data().visible, b"as        \n123456789012345\n");
 
         // Truncation at line wrap
-        let response = canvas.insert_spaces(&CursorPos { x: 4, y: 1 }, 1000);
+        let response =
+            canvas.insert_spaces(&CursorPos { x: 4, y: 1 }, 1000, &CellFormat::default());
         //assert_eq!(response.written_range, 15..21);
         //assert_eq!(
         //    response.insertion_range.start - response.insertion_range.end,
@@ -990,78 +2497,186 @@ mod test {
         assert_eq!(canvas.data().visible, b"as        \n1234      12345\n");
 
         // Insertion at non-existant buffer pos
-        let response = canvas.insert_spaces(&CursorPos { x: 2, y: 4 }, 3);
+        let response = canvas.insert_spaces(&CursorPos { x: 2, y: 4 }, 3, &CellFormat::default());
         //assert_eq!(response.written_range, 30..33);
         //assert_eq!(response.insertion_range, 27..34);
         assert_eq!(response.new_cursor_pos, CursorPos { x: 2, y: 4 });
         assert_eq!(canvas.data().visible, b"as        \n1234      12345\n");
     }
 
+    #[test]
+    fn test_erase() {
+        let red = colored(TerminalColor::Red);
+        let blue = colored(TerminalColor::Blue);
+
+        let mut canvas = TerminalBuffer2::new(10, 3);
+        canvas.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            "0123456789ab中de\nxyz".as_bytes(),
+            &CellFormat::default(),
+        );
+
+        // Erasing characters leaves the rest of the line in place
+        canvas.erase_chars(&CursorPos { x: 2, y: 0 }, 3, &red);
+        assert_eq!(canvas.data().visible, "01   56789ab中de\nxyz\n".as_bytes());
+        assert_eq!(
+            run_colors(&canvas.data().visible_formats[0]),
+            [
+                (0..2, TerminalColor::Default),
+                (2..5, TerminalColor::Red),
+                (5..usize::MAX, TerminalColor::Default),
+            ]
+        );
+
+        // Half of a wide char takes the other half with it
+        canvas.clear_line_backwards(&CursorPos { x: 2, y: 1 }, &blue);
+        assert_eq!(canvas.data().visible, "01   56789    de\nxyz\n".as_bytes());
+
+        canvas.clear_line(&CursorPos { x: 5, y: 2 }, &blue);
+        assert_eq!(canvas.data().visible, "01   56789    de\n".as_bytes());
+        assert_eq!(
+            run_colors(&canvas.data().visible_formats[2]),
+            [(0..usize::MAX, TerminalColor::Blue)]
+        );
+
+        canvas.insert_data(&CursorPos { x: 0, y: 2 }, b"last", &CellFormat::default());
+        canvas.clear_backwards(&CursorPos { x: 1, y: 2 }, &red);
+        assert_eq!(canvas.data().visible, b"\n\n  st\n");
+        assert_eq!(
+            run_colors(&canvas.data().visible_formats[0]),
+            [(0..usize::MAX, TerminalColor::Red)]
+        );
+    }
+
     #[test]
     fn test_clear_line_forwards() {
         let mut canvas = TerminalBuffer2::new(10, 5);
-        canvas.insert_data(&CursorPos { x: 0, y: 0 }, b"asdf\n123456789012345");
+        canvas.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            b"asdf\n123456789012345",
+            &CellFormat::default(),
+        );
 
         // Nothing do delete
-        let response = canvas.clear_line_forwards(&CursorPos { x: 5, y: 5 });
-        assert_eq!(response, None);
+        canvas.clear_line_forwards(&CursorPos { x: 5, y: 5 }, &CellFormat::default());
         assert_eq!(canvas.data().visible, b"asdf\n123456789012345\n");
 
         // Hit a newline
-        let response = canvas.clear_line_forwards(&CursorPos { x: 2, y: 0 });
-        //assert_eq!(response, Some(2..4));
+        canvas.clear_line_forwards(&CursorPos { x: 2, y: 0 }, &CellFormat::default());
         assert_eq!(canvas.data().visible, b"as\n123456789012345\n");
 
         // Hit a wrap
-        let response = canvas.clear_line_forwards(&CursorPos { x: 2, y: 1 });
-        //assert_eq!(response, Some(5..13));
+        canvas.clear_line_forwards(&CursorPos { x: 2, y: 1 }, &CellFormat::default());
         assert_eq!(canvas.data().visible, b"as\n12\n12345\n");
 
         // End of screen, beginning of line, previous line has no newline
         let mut canvas = TerminalBuffer2::new(5, 5);
         // 6 lines of 012345
-        canvas.insert_data(&CursorPos { x: 0, y: 0 }, b"01234012340123401234abcde0123");
+        canvas.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            b"01234012340123401234abcde0123",
+            &CellFormat::default(),
+        );
         assert_eq!(canvas.data().visible, b"012340123401234abcde0123\n");
-        let response = canvas.clear_line_forwards(&CursorPos { x: 0, y: 4 });
-        //assert_eq!(response, Some(25..30));
+        canvas.clear_line_forwards(&CursorPos { x: 0, y: 4 }, &CellFormat::default());
         assert_eq!(canvas.data().visible, b"012340123401234abcde\n");
     }
     //
-    //    #[test]
-    //    fn test_resize_expand() {
-    //        // Ensure that on window size increase, text stays in same spot relative to cursor position
-    //        // This was problematic with our initial implementation. It's less of a problem after some
-    //        // later improvements, but we can keep the test to make sure it still seems sane
-    //        let mut canvas = TerminalBuffer2::new(10, 6);
-    //
-    //        let cursor_pos = CursorPos { x: 0, y: 0 };
-    //
-    //        fn simulate_resize(
-    //            canvas: &mut TerminalBuffer,
-    //            width: usize,
-    //            height: usize,
-    //            cursor_pos: &CursorPos,
-    //        ) -> TerminalBufferInsertResponse {
-    //            let mut response = canvas.set_win_size(width, height, cursor_pos);
-    //            response.new_cursor_pos.x = 0;
-    //            let mut response = canvas.insert_data(&response.new_cursor_pos, &vec![b' '; width]);
-    //            response.new_cursor_pos.x = 0;
-    //
-    //            canvas.insert_data(&response.new_cursor_pos, b"$ ")
-    //        }
-    //        let response = simulate_resize(&mut canvas, 10, 5, &cursor_pos);
-    //        let response = simulate_resize(&mut canvas, 10, 4, &response.new_cursor_pos);
-    //        let response = simulate_resize(&mut canvas, 10, 3, &response.new_cursor_pos);
-    //        simulate_resize(&mut canvas, 10, 5, &response.new_cursor_pos);
-    //        assert_eq!(canvas.data().visible, b"$         \n");
-    //    }
+    #[test]
+    fn test_resize_expand() {
+        // Ensure that on window size increase, text stays in same spot relative to cursor position
+        // This was problematic with our initial implementation. It's less of a problem after some
+        // later improvements, but we can keep the test to make sure it still seems sane
+        let mut canvas = TerminalBuffer2::new(10, 6);
+
+        let cursor_pos = CursorPos { x: 0, y: 0 };
+
+        fn simulate_resize(
+            canvas: &mut TerminalBuffer2,
+            width: usize,
+            height: usize,
+            cursor_pos: &CursorPos,
+        ) -> TerminalBufferModification {
+            let mut response = canvas.set_win_size(width, height, cursor_pos);
+            response.new_cursor_pos.x = 0;
+            let mut response = canvas.insert_data(
+                &response.new_cursor_pos,
+                &vec![b' '; width],
+                &CellFormat::default(),
+            );
+            response.new_cursor_pos.x = 0;
+
+            canvas.insert_data(&response.new_cursor_pos, b"$ ", &CellFormat::default())
+        }
+        let response = simulate_resize(&mut canvas, 10, 5, &cursor_pos);
+        let response = simulate_resize(&mut canvas, 10, 4, &response.new_cursor_pos);
+        let response = simulate_resize(&mut canvas, 10, 3, &response.new_cursor_pos);
+        let response = canvas.set_win_size(10, 5, &response.new_cursor_pos);
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 2, y: 2 });
+        assert_eq!(
+            canvas.data().visible,
+            format!("{}$ \n", " ".repeat(20)).as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_resize_reflow() {
+        let mut buffer = TerminalBuffer2::new(10, 4);
+        let response = buffer.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            b"0123456789abcde\n$ ",
+            &CellFormat::default(),
+        );
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 2, y: 2 });
+
+        // Soft-wrapped lines rewrap, hard newlines are kept
+        let response = buffer.set_win_size(5, 4, &response.new_cursor_pos);
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 2, y: 3 });
+        assert_eq!(buffer.data().visible, b"0123456789abcde\n$ \n");
+        let reflow = response.reflow.expect("no reflow");
+        assert_eq!(reflow.map(&BufPos::new(2, 1)), BufPos::new(2, 2));
+        assert_eq!(reflow.map(&BufPos::new(1, 2)), BufPos::new(1, 3));
+
+        // Rows that no longer fit go to scrollback
+        let response = buffer.set_win_size(5, 2, &response.new_cursor_pos);
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 2, y: 1 });
+        let data = buffer.data();
+        assert_eq!(data.scrollback, b"0123456789");
+        assert_eq!(data.scrollback_line_mappings, [0, 5]);
+        assert_eq!(data.visible, b"abcde\n$ \n");
+
+        // Scrollback rewraps too, but the top of the screen stays put
+        let response = buffer.set_win_size(10, 4, &response.new_cursor_pos);
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 2, y: 1 });
+        let data = buffer.data();
+        assert_eq!(data.scrollback, b"0123456789");
+        assert_eq!(data.visible, b"abcde\n$ \n");
+    }
+
+    #[test]
+    fn test_resize_reflow_wide_chars() {
+        let mut buffer = TerminalBuffer2::new(4, 2);
+        let response = buffer.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            "a中文".as_bytes(),
+            &CellFormat::default(),
+        );
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 2, y: 1 });
+
+        // Wide chars are never split across rows
+        let response = buffer.set_win_size(3, 2, &response.new_cursor_pos);
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 2, y: 1 });
+        assert_eq!(buffer.data().visible, "a中文\n".as_bytes());
+        let reflow = response.reflow.expect("no reflow");
+        assert_eq!(reflow.map(&BufPos::new(3, 0)), BufPos::new(0, 1));
+    }
     //
     #[test]
     fn test_insert_lines() {
         let mut canvas = TerminalBuffer2::new(5, 5);
 
         // Test empty canvas
-        let response = canvas.insert_lines(&CursorPos { x: 0, y: 0 }, 3);
+        let _response = canvas.insert_lines(&CursorPos { x: 0, y: 0 }, 3, &CellFormat::default());
         // Clear doesn't have to do anything as there's nothing in the canvas to push aside
         //assert_eq!(response.deleted_range.start - response.deleted_range.end, 0);
         //assert_eq!(
@@ -1071,26 +2686,439 @@ mod test {
         assert_eq!(canvas.data().visible, b"");
 
         // Test edge wrapped
-        canvas.insert_data(&CursorPos { x: 0, y: 0 }, b"0123456789asdf\nxyzw");
+        canvas.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            b"0123456789asdf\nxyzw",
+            &CellFormat::default(),
+        );
         assert_eq!(canvas.data().visible, b"0123456789asdf\nxyzw\n");
-        let response = canvas.insert_lines(&CursorPos { x: 3, y: 2 }, 1);
+        let _response = canvas.insert_lines(&CursorPos { x: 3, y: 2 }, 1, &CellFormat::default());
         assert_eq!(canvas.data().visible, b"0123456789\n\nasdf\nxyzw\n");
         //assert_eq!(response.deleted_range.start - response.deleted_range.end, 0);
         //assert_eq!(response.inserted_range, 10..12);
 
         // Test newline wrapped + lines pushed off the edge
-        let response = canvas.insert_lines(&CursorPos { x: 3, y: 2 }, 1);
+        let _response = canvas.insert_lines(&CursorPos { x: 3, y: 2 }, 1, &CellFormat::default());
         assert_eq!(canvas.data().visible, b"0123456789\n\n\nasdf\n");
         //assert_eq!(response.deleted_range, 17..22);
         //assert_eq!(response.inserted_range, 11..12);
     }
 
+    #[test]
+    fn test_scroll_region() {
+        let mut canvas = TerminalBuffer2::new(5, 5);
+        canvas.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            b"0\n1\n2\n3\n4",
+            &CellFormat::default(),
+        );
+        assert!(canvas.set_scroll_region(1, 4));
+        assert!(!canvas.set_scroll_region(3, 4));
+
+        // Writing past the bottom margin scrolls only the region, nothing goes to scrollback
+        let response =
+            canvas.insert_data(&CursorPos { x: 0, y: 3 }, b"a\nb", &CellFormat::default());
+        assert_eq!(canvas.data().visible, b"0\n2\na\nb\n4\n");
+        assert_eq!(canvas.data().scrollback, b"");
+        assert_eq!(
+            response.region_scroll,
+            Some(RegionScroll {
+                lines: 1..4,
+                distance: -1
+            })
+        );
+        assert_eq!(response.written_range, BufPos::new(0, 2)..BufPos::new(1, 3));
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 1, y: 3 });
+
+        let response = canvas.reverse_index(&CursorPos { x: 2, y: 1 }, &CellFormat::default());
+        assert_eq!(canvas.data().visible, b"0\n\n2\na\n4\n");
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 2, y: 1 });
+
+        // Index below the region does not scroll
+        let response = canvas.index(&CursorPos { x: 2, y: 4 }, &CellFormat::default());
+        assert_eq!(response.region_scroll, None);
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 2, y: 4 });
+
+        canvas.delete_lines(&CursorPos { x: 0, y: 2 }, 1, &CellFormat::default());
+        assert_eq!(canvas.data().visible, b"0\n\na\n\n4\n");
+
+        canvas.scroll_up(5, &CellFormat::default());
+        assert_eq!(canvas.data().visible, b"0\n\n\n\n4\n");
+
+        // Cursor outside the region
+        assert_eq!(
+            canvas.insert_lines(&CursorPos { x: 0, y: 4 }, 1, &CellFormat::default()),
+            None
+        );
+
+        assert_eq!(
+            canvas.scroll_down(1, &CellFormat::default()),
+            RegionScroll {
+                lines: 1..4,
+                distance: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_autowrap() {
+        let mut canvas = TerminalBuffer2::new(5, 3);
+        canvas.set_autowrap(false);
+
+        // Everything past the edge lands in the last column
+        let response =
+            canvas.insert_data(&CursorPos { x: 2, y: 0 }, b"abcdef", &CellFormat::default());
+        assert_eq!(canvas.data().visible, b"  abf\n");
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 4, y: 0 });
+        assert_eq!(response.written_range, BufPos::new(2, 0)..BufPos::new(5, 0));
+
+        let response = canvas.insert_data(
+            &response.new_cursor_pos,
+            "g中".as_bytes(),
+            &CellFormat::default(),
+        );
+        assert_eq!(canvas.data().visible, "  a中\n".as_bytes());
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 4, y: 0 });
+
+        canvas.set_autowrap(true);
+        let response =
+            canvas.insert_data(&CursorPos { x: 3, y: 1 }, b"xyz", &CellFormat::default());
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 1, y: 2 });
+    }
+
+    #[test]
+    fn test_wide_char_wider_than_line() {
+        // A wide char never fits on a single column line, it is dropped instead of wrapping
+        // forever
+        let mut canvas = TerminalBuffer2::new(1, 3);
+        let response = canvas.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            "中a".as_bytes(),
+            &CellFormat::default(),
+        );
+        assert_eq!(canvas.data().visible, b"a\n");
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 0, y: 2 });
+
+        // Without autowrap it is dropped too, rows 1 and 2 are soft wrapped so show as one
+        canvas.set_autowrap(false);
+        let response = canvas.insert_data(
+            &CursorPos { x: 0, y: 2 },
+            "中b".as_bytes(),
+            &CellFormat::default(),
+        );
+        assert_eq!(canvas.data().visible, b"ab\n");
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 0, y: 2 });
+    }
+
     #[test]
     fn test_buffer_snapshot() {
         let mut terminal_buffer = TerminalBuffer2::new(5, 3);
         terminal_buffer.insert_data(
             &CursorPos { x: 2, y: 1 },
             b"hello world\n asdf asdf\n wrap and stuff",
+            &CellFormat::default(),
+        );
+        terminal_buffer.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            b"red",
+            &colored(TerminalColor::Red),
+        );
+        terminal_buffer.set_scroll_region(1, 3);
+        terminal_buffer.set_autowrap(false);
+
+        let snapshot = terminal_buffer.snapshot().expect("failed to snapshot");
+        let loaded = TerminalBuffer2::from_snapshot(snapshot).expect("failed to load snapshot");
+        assert_eq!(terminal_buffer, loaded);
+    }
+
+    fn colored(color: TerminalColor) -> CellFormat {
+        CellFormat {
+            color,
+            ..Default::default()
+        }
+    }
+
+    fn run_colors(format: &LineFormat) -> Vec<(Range<usize>, TerminalColor)> {
+        format
+            .runs()
+            .map(|(columns, format)| (columns, format.color))
+            .collect()
+    }
+
+    #[test]
+    fn test_formats_move_with_text() {
+        let mut buffer = TerminalBuffer2::new(10, 3);
+        let red = colored(TerminalColor::Red);
+        let blue = colored(TerminalColor::Blue);
+        let response = buffer.insert_data(&CursorPos { x: 0, y: 0 }, b"ab", &CellFormat::default());
+        let response = buffer.insert_data(&response.new_cursor_pos, b"cd", &red);
+        buffer.insert_data(&response.new_cursor_pos, b"ef", &CellFormat::default());
+        assert_eq!(
+            run_colors(&buffer.data().visible_formats[0]),
+            [
+                (0..2, TerminalColor::Default),
+                (2..4, TerminalColor::Red),
+                (4..usize::MAX, TerminalColor::Default),
+            ]
+        );
+
+        buffer.delete_forwards(&CursorPos { x: 0, y: 0 }, 1, &CellFormat::default());
+        buffer.insert_spaces(&CursorPos { x: 0, y: 0 }, 2, &blue);
+        assert_eq!(buffer.data().visible, b"  bcdef\n");
+        assert_eq!(
+            run_colors(&buffer.data().visible_formats[0]),
+            [
+                (0..2, TerminalColor::Blue),
+                (2..3, TerminalColor::Default),
+                (3..5, TerminalColor::Red),
+                (5..usize::MAX, TerminalColor::Default),
+            ]
+        );
+
+        // Inserted lines push the formatted line down, and it keeps its formatting on the way to
+        // scrollback
+        buffer.insert_lines(&CursorPos { x: 0, y: 0 }, 1, &blue);
+        assert_eq!(
+            run_colors(&buffer.data().visible_formats[0]),
+            [(0..usize::MAX, TerminalColor::Blue)]
+        );
+        assert_eq!(
+            run_colors(&buffer.data().visible_formats[1])[2],
+            (3..5, TerminalColor::Red)
+        );
+        buffer.scroll_up(2, &CellFormat::default());
+        let data = buffer.data();
+        assert_eq!(data.scrollback, b"\n  bcdef\n");
+        assert_eq!(
+            run_colors(&data.scrollback_formats[1])[2],
+            (3..5, TerminalColor::Red)
+        );
+
+        buffer.clear_all(&CellFormat::default());
+        assert_eq!(
+            run_colors(&buffer.data().visible_formats[0]),
+            [(0..usize::MAX, TerminalColor::Default)]
+        );
+    }
+
+    #[test]
+    fn test_formats_reflow() {
+        let mut buffer = TerminalBuffer2::new(5, 4);
+        let response = buffer.insert_data(&CursorPos { x: 0, y: 0 }, b"ab", &CellFormat::default());
+        buffer.insert_data(
+            &response.new_cursor_pos,
+            b"cdefg",
+            &colored(TerminalColor::Red),
+        );
+        assert_eq!(
+            run_colors(&buffer.data().visible_formats[1]),
+            [
+                (0..2, TerminalColor::Red),
+                (2..usize::MAX, TerminalColor::Default),
+            ]
+        );
+
+        let response = buffer.set_win_size(10, 4, &CursorPos { x: 2, y: 1 });
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 7, y: 0 });
+        assert_eq!(buffer.data().visible, b"abcdefg\n");
+        assert_eq!(
+            run_colors(&buffer.data().visible_formats[0]),
+            [
+                (0..2, TerminalColor::Default),
+                (2..7, TerminalColor::Red),
+                (7..usize::MAX, TerminalColor::Default),
+            ]
+        );
+
+        buffer.set_win_size(3, 4, &response.new_cursor_pos);
+        let data = buffer.data();
+        assert_eq!(data.visible, b"abcdefg\n");
+        assert_eq!(
+            run_colors(&data.visible_formats[1]),
+            [
+                (0..4, TerminalColor::Red),
+                (4..usize::MAX, TerminalColor::Default),
+            ]
+        );
+        assert_eq!(
+            run_colors(&data.visible_formats[2]),
+            [
+                (0..1, TerminalColor::Red),
+                (1..usize::MAX, TerminalColor::Default),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scrollback_line_limit() {
+        let mut buffer = TerminalBuffer2::new(5, 2);
+        buffer.set_scrollback_limit(ScrollbackLimit {
+            max_lines: Some(2),
+            max_bytes: None,
+        });
+        buffer.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            b"a\nb\nc\nd\ne\nf",
+            &CellFormat::default(),
+        );
+        assert_eq!(buffer.data().scrollback, b"c\nd\n");
+        assert_eq!(buffer.data().visible, b"e\nf\n");
+
+        // Surviving lines keep their ids
+        assert_eq!(buffer.first_line_id(), 2);
+        assert_eq!(buffer.data().first_scrollback_line_id, 2);
+        assert_eq!(buffer.data().first_visible_line_id, 4);
+
+        buffer.set_scrollback_limit(ScrollbackLimit {
+            max_lines: Some(1),
+            max_bytes: None,
+        });
+        assert_eq!(buffer.data().scrollback, b"d\n");
+        assert_eq!(buffer.first_line_id(), 3);
+        assert_eq!(buffer.data().first_visible_line_id, 4);
+    }
+
+    #[test]
+    fn test_scrollback_byte_limit() {
+        let mut buffer = TerminalBuffer2::new(5, 1);
+        buffer.set_scrollback_limit(ScrollbackLimit {
+            max_lines: None,
+            max_bytes: Some(7),
+        });
+        buffer.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            b"aaa\nbb\ncc\nd",
+            &CellFormat::default(),
+        );
+        assert_eq!(buffer.data().scrollback, b"bb\ncc\n");
+        assert_eq!(buffer.data().scrollback_line_mappings, [0, 3]);
+        assert_eq!(buffer.first_line_id(), 1);
+
+        // A single line over the budget is dropped entirely
+        buffer.set_scrollback_limit(ScrollbackLimit {
+            max_lines: None,
+            max_bytes: Some(2),
+        });
+        assert_eq!(buffer.data().scrollback, b"");
+        assert_eq!(buffer.first_line_id(), 3);
+        assert_eq!(buffer.data().visible, b"d\n");
+    }
+
+    #[test]
+    fn test_clear_scrollback() {
+        let mut buffer = TerminalBuffer2::new(5, 2);
+        buffer.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            b"a\nb\nc\nd",
+            &CellFormat::default(),
+        );
+
+        // Clearing the screen leaves scrollback alone
+        buffer.clear_all(&CellFormat::default());
+        assert_eq!(buffer.data().scrollback, b"a\nb\n");
+
+        buffer.clear_scrollback();
+        assert_eq!(buffer.data().scrollback, b"");
+        assert_eq!(buffer.first_line_id(), 2);
+        assert_eq!(buffer.data().first_visible_line_id, 2);
+
+        buffer.insert_data(&CursorPos { x: 0, y: 1 }, b"e\nf", &CellFormat::default());
+        assert_eq!(buffer.data().scrollback, b"\n");
+        assert_eq!(buffer.data().first_scrollback_line_id, 2);
+    }
+
+    #[test]
+    fn test_scrollback_snapshot() {
+        let mut terminal_buffer = TerminalBuffer2::new(5, 2);
+        terminal_buffer.set_scrollback_limit(ScrollbackLimit {
+            max_lines: Some(3),
+            max_bytes: Some(100),
+        });
+        terminal_buffer.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            b"a\nb\nc\nd\ne\nf\ng",
+            &CellFormat::default(),
+        );
+
+        let snapshot = terminal_buffer.snapshot().expect("failed to snapshot");
+        let loaded = TerminalBuffer2::from_snapshot(snapshot).expect("failed to load snapshot");
+        assert_eq!(terminal_buffer, loaded);
+        assert_eq!(loaded.first_line_id(), 2);
+    }
+
+    #[test]
+    fn test_alternate_screen() {
+        let mut buffer = TerminalBuffer2::new(5, 3);
+        buffer.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            b"0123456789abcde",
+            &CellFormat::default(),
+        );
+        assert_eq!(buffer.data().scrollback, b"01234");
+
+        buffer.enter_alternate_screen(Some(CursorPos { x: 1, y: 2 }));
+        assert!(buffer.is_alternate_screen_active());
+        assert_eq!(buffer.data().visible, b"");
+        assert_eq!(buffer.data().scrollback, b"");
+
+        // Scrolling the alternate screen should not add to scrollback, but line ids still advance
+        let response = buffer.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            b"alt\n\n\n\nend",
+            &CellFormat::default(),
+        );
+        assert_eq!(response.written_range, BufPos::new(0, 0)..BufPos::new(3, 4));
+        assert_eq!(buffer.data().visible, b"\n\nend\n");
+        assert_eq!(buffer.data().first_visible_line_id, 2);
+
+        // Nested entry is a no-op
+        buffer.enter_alternate_screen(None);
+        assert_eq!(buffer.data().visible, b"\n\nend\n");
+
+        assert_eq!(
+            buffer.exit_alternate_screen(),
+            Some(CursorPos { x: 1, y: 2 })
+        );
+        assert!(!buffer.is_alternate_screen_active());
+        assert_eq!(buffer.data().scrollback, b"01234");
+        assert_eq!(buffer.data().visible, b"56789abcde\n");
+        assert_eq!(buffer.exit_alternate_screen(), None);
+    }
+
+    #[test]
+    fn test_alternate_screen_resize() {
+        let mut buffer = TerminalBuffer2::new(5, 3);
+        buffer.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            b"0123456789",
+            &CellFormat::default(),
+        );
+        buffer.enter_alternate_screen(Some(CursorPos { x: 0, y: 2 }));
+        buffer.insert_data(&CursorPos { x: 0, y: 0 }, b"alt", &CellFormat::default());
+
+        buffer.set_win_size(10, 3, &CursorPos { x: 3, y: 0 });
+        assert_eq!(buffer.data().visible, b"alt\n");
+
+        assert_eq!(
+            buffer.exit_alternate_screen(),
+            Some(CursorPos { x: 0, y: 1 })
+        );
+        assert_eq!(buffer.data().visible, b"0123456789\n");
+    }
+
+    #[test]
+    fn test_alternate_screen_snapshot() {
+        let mut terminal_buffer = TerminalBuffer2::new(5, 3);
+        terminal_buffer.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            b"hello world",
+            &CellFormat::default(),
+        );
+        terminal_buffer.enter_alternate_screen(Some(CursorPos { x: 1, y: 2 }));
+        terminal_buffer.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            b"a\nb\nc\nd",
+            &CellFormat::default(),
         );
 
         let snapshot = terminal_buffer.snapshot().expect("failed to snapshot");
@@ -1098,15 +3126,124 @@ mod test {
         assert_eq!(terminal_buffer, loaded);
     }
 
+    #[test]
+    fn test_multibyte_chars() {
+        let mut buffer = TerminalBuffer2::new(5, 5);
+        let response = buffer.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            "héllo wörld".as_bytes(),
+            &CellFormat::default(),
+        );
+        // Wrapping and cursor position count chars, not bytes
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 1, y: 2 });
+        assert_eq!(buffer.data().visible, "héllo wörld\n".as_bytes());
+
+        buffer.insert_data(
+            &CursorPos { x: 1, y: 1 },
+            "ü".as_bytes(),
+            &CellFormat::default(),
+        );
+        assert_eq!(buffer.data().visible, "héllo üörld\n".as_bytes());
+
+        buffer.delete_forwards(&CursorPos { x: 0, y: 1 }, 2, &CellFormat::default());
+        assert_eq!(buffer.data().visible, "hélloörl\nd\n".as_bytes());
+    }
+
+    #[test]
+    fn test_wide_chars() {
+        let mut buffer = TerminalBuffer2::new(5, 3);
+        let response = buffer.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            "a中b".as_bytes(),
+            &CellFormat::default(),
+        );
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 4, y: 0 });
+        assert_eq!(response.written_range, BufPos::new(0, 0)..BufPos::new(4, 0));
+        assert_eq!(buffer.data().visible, "a中b\n".as_bytes());
+
+        // Overwriting either half of a wide char blanks the other half
+        buffer.insert_data(&CursorPos { x: 2, y: 0 }, b"x", &CellFormat::default());
+        assert_eq!(buffer.data().visible, "a xb\n".as_bytes());
+        buffer.insert_data(
+            &CursorPos { x: 1, y: 0 },
+            "中".as_bytes(),
+            &CellFormat::default(),
+        );
+        buffer.insert_data(&CursorPos { x: 1, y: 0 }, b"y", &CellFormat::default());
+        assert_eq!(buffer.data().visible, "ay b\n".as_bytes());
+
+        // Wide chars that do not fit in the last column wrap
+        buffer.clear_all(&CellFormat::default());
+        let response = buffer.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            "abcd中".as_bytes(),
+            &CellFormat::default(),
+        );
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 2, y: 1 });
+        assert_eq!(buffer.data().visible, "abcd中\n".as_bytes());
+
+        // Deleting or shifting half of a wide char takes the rest of it with it
+        buffer.clear_all(&CellFormat::default());
+        buffer.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            "中文".as_bytes(),
+            &CellFormat::default(),
+        );
+        buffer.delete_forwards(&CursorPos { x: 1, y: 0 }, 1, &CellFormat::default());
+        assert_eq!(buffer.data().visible, " 文\n".as_bytes());
+        buffer.insert_spaces(&CursorPos { x: 0, y: 0 }, 3, &CellFormat::default());
+        assert_eq!(buffer.data().visible, "     \n".as_bytes());
+
+        buffer.clear_all(&CellFormat::default());
+        buffer.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            "中文".as_bytes(),
+            &CellFormat::default(),
+        );
+        buffer.clear_line_forwards(&CursorPos { x: 3, y: 0 }, &CellFormat::default());
+        assert_eq!(buffer.data().visible, "中 \n".as_bytes());
+    }
+
+    #[test]
+    fn test_combining_chars() {
+        let mut buffer = TerminalBuffer2::new(3, 3);
+        let response = buffer.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            "e\u{301}中\u{301}".as_bytes(),
+            &CellFormat::default(),
+        );
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 0, y: 1 });
+        assert_eq!(buffer.data().visible, "e\u{301}中\u{301}\n".as_bytes());
+
+        // Combining chars are dropped along with the cell they belong to
+        buffer.insert_data(&CursorPos { x: 0, y: 0 }, b"a", &CellFormat::default());
+        assert_eq!(buffer.data().visible, "a中\u{301}\n".as_bytes());
+
+        // A combining char after a wrap still belongs to the last cell
+        buffer.clear_all(&CellFormat::default());
+        buffer.insert_data(
+            &CursorPos { x: 0, y: 0 },
+            "abc\u{308}d".as_bytes(),
+            &CellFormat::default(),
+        );
+        assert_eq!(buffer.data().visible, "abc\u{308}d\n".as_bytes());
+
+        let snapshot = buffer.snapshot().expect("failed to snapshot");
+        let loaded = TerminalBuffer2::from_snapshot(snapshot).expect("failed to load snapshot");
+        assert_eq!(buffer, loaded);
+    }
+
     #[test]
     fn test_insertion_response() {
         let mut terminal_buffer = TerminalBuffer2::new(5, 5);
-        let response = terminal_buffer.insert_data(&CursorPos { x: 0, y: 0 }, b"asdf");
+        let response =
+            terminal_buffer.insert_data(&CursorPos { x: 0, y: 0 }, b"asdf", &CellFormat::default());
         assert_eq!(response.written_range, BufPos::new(0, 0)..BufPos::new(4, 0));
         assert_eq!(response.new_cursor_pos, CursorPos { x: 4, y: 0 });
 
         // insertion at x 3, y 2, NOTE: no eviction
-        let response = terminal_buffer.insert_data(&CursorPos { x: 3, y: 2 }, b"asdf");
+        let response =
+            terminal_buffer.insert_data(&CursorPos { x: 3, y: 2 }, b"asdf", &CellFormat::default());
         assert_eq!(response.written_range, BufPos::new(3, 2)..BufPos::new(2, 3));
         assert_eq!(response.new_cursor_pos, CursorPos { x: 2, y: 3 });
     }
@@ -1117,6 +3254,7 @@ mod test {
         let response = terminal_buffer.insert_data(
             &CursorPos { x: 0, y: 0 },
             b"0123401234012340123401234abcdeabc",
+            &CellFormat::default(),
         );
 
         assert_eq!(response.written_range, BufPos::new(0, 0)..BufPos::new(3, 6));
@@ -1126,6 +3264,7 @@ mod test {
         let response = terminal_buffer.insert_data(
             &CursorPos { x: 0, y: 0 },
             b"01234\n01234\n01234\n01234\n01234\nabcde\nabc",
+            &CellFormat::default(),
         );
         assert_eq!(response.written_range, BufPos::new(0, 0)..BufPos::new(3, 6));
         assert_eq!(response.new_cursor_pos, CursorPos { x: 3, y: 4 });
@@ -1134,10 +3273,12 @@ mod test {
     #[test]
     fn test_insertion_response_some_evicted() {
         let mut terminal_buffer = TerminalBuffer2::new(5, 5);
-        let response = terminal_buffer.insert_data(&CursorPos { x: 0, y: 0 }, b"as\n");
+        let response =
+            terminal_buffer.insert_data(&CursorPos { x: 0, y: 0 }, b"as\n", &CellFormat::default());
         let response = terminal_buffer.insert_data(
             &response.new_cursor_pos,
             b"01234\n01234\n01234\n01234\n0123",
+            &CellFormat::default(),
         );
         assert_eq!(
             response.written_range,
@@ -0,0 +1,552 @@
+use std::{num::TryFromIntError, ops::Range};
+
+use super::{recording::NotIntOfType, CursorState, TerminalColor};
+use crate::terminal_emulator::recording::SnapshotItem;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+enum LoadCellFormatSnapshotError {
+    #[error("root element is not a map")]
+    RootNotMap,
+    #[error("{0} element missing")]
+    FlagMissing(&'static str),
+    #[error("{0} element not bool")]
+    FlagNotBool(&'static str),
+    #[error("{0} element is missing")]
+    ColorMissing(&'static str),
+    #[error("{0} not a string")]
+    ColorNotString(&'static str),
+    #[error("failed to parse {0} from string")]
+    ParseColor(&'static str),
+}
+
+#[derive(Debug, Error)]
+enum LoadLineFormatSnapshotErrorKind {
+    #[error("root element is not an array")]
+    NotArray,
+    #[error("run start element missing")]
+    StartMissing,
+    #[error("run start is not a usize")]
+    StartNotUsize(#[source] NotIntOfType),
+    #[error("runs do not start at 0 in increasing order")]
+    InvalidRunOrder,
+    #[error("failed to load run format")]
+    LoadFormat(#[from] LoadCellFormatSnapshotError),
+}
+
+#[derive(Debug, Error)]
+#[error(transparent)]
+pub struct LoadLineFormatSnapshotError(#[from] LoadLineFormatSnapshotErrorKind);
+
+#[derive(Debug, Error)]
+enum SnapshotLineFormatErrorKind {
+    #[error("run start cannot be serialized as i64")]
+    StartNotI64(#[source] TryFromIntError),
+}
+
+#[derive(Debug, Error)]
+#[error(transparent)]
+pub struct SnapshotLineFormatError(#[from] SnapshotLineFormatErrorKind);
+
+mod cell_format_keys {
+    pub const START: &str = "start";
+    pub const COLOR: &str = "color";
+    pub const BACKGROUND_COLOR: &str = "background_color";
+    pub const BOLD: &str = "bold";
+    pub const DIM: &str = "dim";
+    pub const ITALIC: &str = "italic";
+    pub const UNDERLINE: &str = "underline";
+    pub const REVERSE: &str = "reverse";
+    pub const STRIKETHROUGH: &str = "strikethrough";
+}
+
+// CellFormat <-- attributes of a single cell in the terminal buffer
+// LineFormat <-- runs of cells in a line that share a CellFormat
+// FormatTagSerialized <-- byte range in scrollback/visible data -> attributes
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FormatTagSerialized {
+    pub start: usize,
+    pub end: usize,
+    pub color: TerminalColor,
+    pub background_color: TerminalColor,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub reverse: bool,
+    pub strikethrough: bool,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CellFormat {
+    pub color: TerminalColor,
+    pub background_color: TerminalColor,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub reverse: bool,
+    pub strikethrough: bool,
+}
+
+impl Default for CellFormat {
+    fn default() -> CellFormat {
+        CellFormat::from_cursor(&CursorState::new())
+    }
+}
+
+impl CellFormat {
+    pub(super) fn from_cursor(cursor: &CursorState) -> CellFormat {
+        CellFormat {
+            color: cursor.color,
+            background_color: cursor.background_color,
+            bold: cursor.bold,
+            dim: cursor.dim,
+            italic: cursor.italic,
+            underline: cursor.underline,
+            reverse: cursor.reverse,
+            strikethrough: cursor.strikethrough,
+        }
+    }
+
+    /// Format of cells that are erased or made room for while this format is active. Like xterm,
+    /// only the background color carries over
+    pub fn blank(&self) -> CellFormat {
+        CellFormat {
+            background_color: self.background_color,
+            ..Default::default()
+        }
+    }
+
+    pub fn to_serialized(self, start: usize, end: usize) -> FormatTagSerialized {
+        FormatTagSerialized {
+            start,
+            end,
+            color: self.color,
+            background_color: self.background_color,
+            bold: self.bold,
+            dim: self.dim,
+            italic: self.italic,
+            underline: self.underline,
+            reverse: self.reverse,
+            strikethrough: self.strikethrough,
+        }
+    }
+
+    fn from_snapshot(
+        root: &mut std::collections::HashMap<String, SnapshotItem>,
+    ) -> Result<CellFormat, LoadCellFormatSnapshotError> {
+        use LoadCellFormatSnapshotError::*;
+
+        let mut load_flag = |key: &'static str| -> Result<bool, LoadCellFormatSnapshotError> {
+            root.remove(key)
+                .ok_or(FlagMissing(key))?
+                .into_bool()
+                .map_err(|_| FlagNotBool(key))
+        };
+
+        let bold = load_flag(cell_format_keys::BOLD)?;
+        let dim = load_flag(cell_format_keys::DIM)?;
+        let italic = load_flag(cell_format_keys::ITALIC)?;
+        let underline = load_flag(cell_format_keys::UNDERLINE)?;
+        let reverse = load_flag(cell_format_keys::REVERSE)?;
+        let strikethrough = load_flag(cell_format_keys::STRIKETHROUGH)?;
+
+        let mut load_color =
+            |key: &'static str| -> Result<TerminalColor, LoadCellFormatSnapshotError> {
+                root.remove(key)
+                    .ok_or(ColorMissing(key))?
+                    .into_string()
+                    .map_err(|_| ColorNotString(key))?
+                    .parse()
+                    .map_err(|_| ParseColor(key))
+            };
+
+        let color = load_color(cell_format_keys::COLOR)?;
+        let background_color = load_color(cell_format_keys::BACKGROUND_COLOR)?;
+
+        Ok(CellFormat {
+            color,
+            background_color,
+            bold,
+            dim,
+            italic,
+            underline,
+            reverse,
+            strikethrough,
+        })
+    }
+
+    fn snapshot(&self) -> std::collections::HashMap<String, SnapshotItem> {
+        [
+            (
+                cell_format_keys::COLOR.to_string(),
+                self.color.to_string().into(),
+            ),
+            (
+                cell_format_keys::BACKGROUND_COLOR.to_string(),
+                self.background_color.to_string().into(),
+            ),
+            (cell_format_keys::BOLD.to_string(), self.bold.into()),
+            (cell_format_keys::DIM.to_string(), self.dim.into()),
+            (cell_format_keys::ITALIC.to_string(), self.italic.into()),
+            (
+                cell_format_keys::UNDERLINE.to_string(),
+                self.underline.into(),
+            ),
+            (cell_format_keys::REVERSE.to_string(), self.reverse.into()),
+            (
+                cell_format_keys::STRIKETHROUGH.to_string(),
+                self.strikethrough.into(),
+            ),
+        ]
+        .into()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct FormatRun {
+    start: usize,
+    format: CellFormat,
+}
+
+/// Formatting of a line as runs of cells that share a format. A run covers everything up to the
+/// start of the next one, and the last run covers the rest of the line. Most lines only need a
+/// handful of runs
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LineFormat {
+    /// Sorted by start, the first run starts at 0 and neighbours never share a format
+    runs: Vec<FormatRun>,
+}
+
+impl Default for LineFormat {
+    fn default() -> LineFormat {
+        LineFormat::new(CellFormat::default())
+    }
+}
+
+impl LineFormat {
+    pub fn new(format: CellFormat) -> LineFormat {
+        LineFormat {
+            runs: vec![FormatRun { start: 0, format }],
+        }
+    }
+
+    fn run_idx(&self, column: usize) -> usize {
+        self.runs.partition_point(|run| run.start <= column) - 1
+    }
+
+    pub fn format_at(&self, column: usize) -> &CellFormat {
+        &self.runs[self.run_idx(column)].format
+    }
+
+    /// Makes sure a run starts at column, returning its index
+    fn split_at(&mut self, column: usize) -> usize {
+        let idx = self.run_idx(column);
+        if self.runs[idx].start == column {
+            return idx;
+        }
+
+        let format = self.runs[idx].format;
+        self.runs.insert(
+            idx + 1,
+            FormatRun {
+                start: column,
+                format,
+            },
+        );
+        idx + 1
+    }
+
+    fn merge_runs(&mut self) {
+        self.runs.dedup_by(|run, prev| run.format == prev.format);
+    }
+
+    /// Applies format to the given columns, use usize::MAX as the end for the rest of the line
+    pub fn set(&mut self, columns: Range<usize>, format: &CellFormat) {
+        if columns.is_empty() {
+            return;
+        }
+
+        let start = self.split_at(columns.start);
+        let end = match columns.end {
+            usize::MAX => self.runs.len(),
+            end => self.split_at(end),
+        };
+        self.runs.splice(
+            start..end,
+            [FormatRun {
+                start: columns.start,
+                format: *format,
+            }],
+        );
+        self.merge_runs();
+    }
+
+    /// Shifts everything at or after column right by num_cells, filling the gap with format. Runs
+    /// pushed past width are dropped
+    pub fn insert(&mut self, column: usize, num_cells: usize, format: &CellFormat, width: usize) {
+        if num_cells == 0 {
+            return;
+        }
+
+        let idx = self.split_at(column);
+        for run in &mut self.runs[idx..] {
+            run.start += num_cells;
+        }
+        self.runs.insert(
+            idx,
+            FormatRun {
+                start: column,
+                format: *format,
+            },
+        );
+        self.runs.retain(|run| run.start < width || run.start == 0);
+        self.merge_runs();
+    }
+
+    /// Removes the given columns, shifting everything after them left
+    pub fn remove(&mut self, columns: Range<usize>) {
+        if columns.is_empty() {
+            return;
+        }
+
+        let start = self.split_at(columns.start);
+        let end = self.split_at(columns.end);
+        self.runs.drain(start..end);
+        for run in &mut self.runs[start..] {
+            run.start -= columns.len();
+        }
+        self.merge_runs();
+    }
+
+    /// Formatting from column onwards, shifted to start at 0
+    pub fn split_off(&self, column: usize) -> LineFormat {
+        let idx = self.run_idx(column);
+        let runs = self.runs[idx..]
+            .iter()
+            .map(|run| FormatRun {
+                start: run.start.saturating_sub(column),
+                format: run.format,
+            })
+            .collect();
+        LineFormat { runs }
+    }
+
+    /// Replaces formatting from column onwards with other, shifted to start at column
+    pub fn replace_from(&mut self, column: usize, other: &LineFormat) {
+        let idx = self.split_at(column);
+        self.runs.truncate(idx);
+        self.runs.extend(other.runs.iter().map(|run| FormatRun {
+            start: run.start + column,
+            format: run.format,
+        }));
+        self.merge_runs();
+    }
+
+    /// Columns covered by each run, the last one ending at usize::MAX
+    pub fn runs(&self) -> impl Iterator<Item = (Range<usize>, &CellFormat)> {
+        self.runs.iter().enumerate().map(|(i, run)| {
+            let end = self
+                .runs
+                .get(i + 1)
+                .map(|next| next.start)
+                .unwrap_or(usize::MAX);
+            (run.start..end, &run.format)
+        })
+    }
+
+    pub fn from_snapshot(
+        snapshot: SnapshotItem,
+    ) -> Result<LineFormat, LoadLineFormatSnapshotError> {
+        use LoadLineFormatSnapshotErrorKind::*;
+        let arr = snapshot.into_vec().map_err(|_| NotArray)?;
+
+        let mut runs: Vec<FormatRun> = Vec::with_capacity(arr.len());
+        for item in arr {
+            let mut root = item
+                .into_map()
+                .map_err(|_| LoadFormat(LoadCellFormatSnapshotError::RootNotMap))?;
+            let start = root
+                .remove(cell_format_keys::START)
+                .ok_or(StartMissing)?
+                .into_num::<usize>()
+                .map_err(StartNotUsize)?;
+            let format = CellFormat::from_snapshot(&mut root).map_err(LoadFormat)?;
+
+            let expected_order = match runs.last() {
+                Some(prev) => prev.start < start,
+                None => start == 0,
+            };
+            if !expected_order {
+                Err(InvalidRunOrder)?;
+            }
+            runs.push(FormatRun { start, format });
+        }
+
+        if runs.is_empty() {
+            Err(InvalidRunOrder)?;
+        }
+
+        Ok(LineFormat { runs })
+    }
+
+    pub fn snapshot(&self) -> Result<SnapshotItem, SnapshotLineFormatError> {
+        use SnapshotLineFormatErrorKind::*;
+        let runs = self
+            .runs
+            .iter()
+            .map(|run| {
+                let start: i64 = run.start.try_into().map_err(StartNotI64)?;
+                let mut ret = run.format.snapshot();
+                ret.insert(cell_format_keys::START.to_string(), start.into());
+                Ok(SnapshotItem::Map(ret))
+            })
+            .collect::<Result<Vec<_>, SnapshotLineFormatErrorKind>>()?;
+        Ok(SnapshotItem::Array(runs))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn colored(color: TerminalColor) -> CellFormat {
+        CellFormat {
+            color,
+            ..Default::default()
+        }
+    }
+
+    fn run_colors(format: &LineFormat) -> Vec<(Range<usize>, TerminalColor)> {
+        format
+            .runs()
+            .map(|(columns, format)| (columns, format.color))
+            .collect()
+    }
+
+    #[test]
+    fn test_set() {
+        let mut format = LineFormat::default();
+        format.set(2..5, &colored(TerminalColor::Red));
+        assert_eq!(
+            run_colors(&format),
+            [
+                (0..2, TerminalColor::Default),
+                (2..5, TerminalColor::Red),
+                (5..usize::MAX, TerminalColor::Default),
+            ]
+        );
+
+        // Adjacent runs of the same format merge
+        format.set(5..7, &colored(TerminalColor::Red));
+        format.set(3..4, &colored(TerminalColor::Blue));
+        assert_eq!(
+            run_colors(&format),
+            [
+                (0..2, TerminalColor::Default),
+                (2..3, TerminalColor::Red),
+                (3..4, TerminalColor::Blue),
+                (4..7, TerminalColor::Red),
+                (7..usize::MAX, TerminalColor::Default),
+            ]
+        );
+
+        format.set(1..usize::MAX, &colored(TerminalColor::Green));
+        assert_eq!(
+            run_colors(&format),
+            [
+                (0..1, TerminalColor::Default),
+                (1..usize::MAX, TerminalColor::Green),
+            ]
+        );
+        assert_eq!(format.format_at(0).color, TerminalColor::Default);
+        assert_eq!(format.format_at(100).color, TerminalColor::Green);
+    }
+
+    #[test]
+    fn test_insert_remove() {
+        let mut format = LineFormat::default();
+        format.set(2..4, &colored(TerminalColor::Red));
+
+        format.insert(3, 2, &colored(TerminalColor::Blue), 10);
+        assert_eq!(
+            run_colors(&format),
+            [
+                (0..2, TerminalColor::Default),
+                (2..3, TerminalColor::Red),
+                (3..5, TerminalColor::Blue),
+                (5..6, TerminalColor::Red),
+                (6..usize::MAX, TerminalColor::Default),
+            ]
+        );
+
+        // Runs pushed off the end of the line are dropped
+        format.insert(0, 5, &CellFormat::default(), 8);
+        assert_eq!(
+            run_colors(&format),
+            [
+                (0..7, TerminalColor::Default),
+                (7..usize::MAX, TerminalColor::Red),
+            ]
+        );
+
+        format.remove(1..7);
+        assert_eq!(
+            run_colors(&format),
+            [
+                (0..1, TerminalColor::Default),
+                (1..usize::MAX, TerminalColor::Red),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_off_replace_from() {
+        let mut format = LineFormat::default();
+        format.set(2..4, &colored(TerminalColor::Red));
+
+        let tail = format.split_off(3);
+        assert_eq!(
+            run_colors(&tail),
+            [
+                (0..1, TerminalColor::Red),
+                (1..usize::MAX, TerminalColor::Default),
+            ]
+        );
+
+        let mut joined = LineFormat::new(colored(TerminalColor::Blue));
+        joined.replace_from(2, &tail);
+        assert_eq!(
+            run_colors(&joined),
+            [
+                (0..2, TerminalColor::Blue),
+                (2..3, TerminalColor::Red),
+                (3..usize::MAX, TerminalColor::Default),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_line_format_snapshot() {
+        let mut format = LineFormat::default();
+        format.set(
+            2..4,
+            &CellFormat {
+                color: TerminalColor::Red,
+                background_color: TerminalColor::Blue,
+                bold: true,
+                underline: true,
+                ..Default::default()
+            },
+        );
+
+        let snapshot = format.snapshot().expect("failed to snapshot");
+        let loaded = LineFormat::from_snapshot(snapshot).expect("failed to load snapshot");
+        assert_eq!(format, loaded);
+
+        let out_of_order = SnapshotItem::Array(vec![]);
+        assert!(LineFormat::from_snapshot(out_of_order).is_err());
+    }
+}
@@ -0,0 +1,242 @@
+// https://vt100.net/docs/vt220-rm/chapter4.html
+
+use std::borrow::Cow;
+
+use thiserror::Error;
+
+use super::recording::SnapshotItem;
+
+#[derive(Debug, Error)]
+enum LoadSnapshotErrorKind {
+    #[error("root element is not a map")]
+    RootNotMap,
+    #[error("{0} field is not present")]
+    FieldNotPresent(&'static str),
+    #[error("{0} field is not a string")]
+    FieldNotString(&'static str),
+    #[error("{0} field is not a bool")]
+    FieldNotBool(&'static str),
+    #[error("unknown charset {0}")]
+    UnknownCharset(String),
+}
+
+#[derive(Debug, Error)]
+#[error(transparent)]
+pub struct LoadSnapshotError(#[from] LoadSnapshotErrorKind);
+
+mod charset_state_keys {
+    pub const G0: &str = "g0";
+    pub const G1: &str = "g1";
+    pub const SHIFTED: &str = "shifted";
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Charset {
+    // ESC ( B
+    #[default]
+    Ascii,
+    // ESC ( 0, line drawing characters in place of lowercase letters
+    DecSpecialGraphics,
+}
+
+impl Charset {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Charset::Ascii => "ascii",
+            Charset::DecSpecialGraphics => "dec_special_graphics",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<Charset> {
+        match s {
+            "ascii" => Some(Charset::Ascii),
+            "dec_special_graphics" => Some(Charset::DecSpecialGraphics),
+            _ => None,
+        }
+    }
+}
+
+/// Which designation an SCS sequence sets
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CharsetSlot {
+    // ESC (
+    G0,
+    // ESC )
+    G1,
+}
+
+impl CharsetSlot {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CharsetSlot::G0 => charset_state_keys::G0,
+            CharsetSlot::G1 => charset_state_keys::G1,
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<CharsetSlot> {
+        match s {
+            charset_state_keys::G0 => Some(CharsetSlot::G0),
+            charset_state_keys::G1 => Some(CharsetSlot::G1),
+            _ => None,
+        }
+    }
+}
+
+/// Designated charsets and which one is shifted in
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CharsetState {
+    g0: Charset,
+    g1: Charset,
+    // SO selects G1, SI goes back to G0
+    shifted: bool,
+}
+
+impl CharsetState {
+    pub fn designate(&mut self, slot: CharsetSlot, charset: Charset) {
+        match slot {
+            CharsetSlot::G0 => self.g0 = charset,
+            CharsetSlot::G1 => self.g1 = charset,
+        }
+    }
+
+    pub fn shift_out(&mut self) {
+        self.shifted = true;
+    }
+
+    pub fn shift_in(&mut self) {
+        self.shifted = false;
+    }
+
+    fn active(&self) -> Charset {
+        match self.shifted {
+            true => self.g1,
+            false => self.g0,
+        }
+    }
+
+    /// Maps data from the child through the active charset
+    pub fn translate<'a>(&self, data: &'a [u8]) -> Cow<'a, [u8]> {
+        if self.active() == Charset::Ascii {
+            return Cow::Borrowed(data);
+        }
+
+        let mut ret = Vec::with_capacity(data.len());
+        for b in data {
+            // Multi byte utf8 sequences never contain ascii bytes, so they pass through untouched
+            match dec_special_graphic(*b) {
+                Some(c) => ret.extend(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                None => ret.push(*b),
+            }
+        }
+        Cow::Owned(ret)
+    }
+
+    pub fn snapshot(&self) -> SnapshotItem {
+        SnapshotItem::Map(
+            [
+                (charset_state_keys::G0.to_string(), self.g0.as_str().into()),
+                (charset_state_keys::G1.to_string(), self.g1.as_str().into()),
+                (charset_state_keys::SHIFTED.to_string(), self.shifted.into()),
+            ]
+            .into(),
+        )
+    }
+
+    pub fn from_snapshot(snapshot: SnapshotItem) -> Result<CharsetState, LoadSnapshotError> {
+        use LoadSnapshotErrorKind::*;
+        let mut map = snapshot.into_map().map_err(|_| RootNotMap)?;
+
+        let mut load_charset = |key: &'static str| -> Result<Charset, LoadSnapshotErrorKind> {
+            let name = map
+                .remove(key)
+                .ok_or(FieldNotPresent(key))?
+                .into_string()
+                .map_err(|_| FieldNotString(key))?;
+            Charset::from_str(&name).ok_or(UnknownCharset(name))
+        };
+
+        let g0 = load_charset(charset_state_keys::G0)?;
+        let g1 = load_charset(charset_state_keys::G1)?;
+        let shifted = map
+            .remove(charset_state_keys::SHIFTED)
+            .ok_or(FieldNotPresent(charset_state_keys::SHIFTED))?
+            .into_bool()
+            .map_err(|_| FieldNotBool(charset_state_keys::SHIFTED))?;
+
+        Ok(CharsetState { g0, g1, shifted })
+    }
+}
+
+fn dec_special_graphic(b: u8) -> Option<char> {
+    let c = match b {
+        b'_' => ' ',
+        b'`' => '◆',
+        b'a' => '▒',
+        b'b' => '␉',
+        b'c' => '␌',
+        b'd' => '␍',
+        b'e' => '␊',
+        b'f' => '°',
+        b'g' => '±',
+        b'h' => '␤',
+        b'i' => '␋',
+        b'j' => '┘',
+        b'k' => '┐',
+        b'l' => '┌',
+        b'm' => '└',
+        b'n' => '┼',
+        b'o' => '⎺',
+        b'p' => '⎻',
+        b'q' => '─',
+        b'r' => '⎼',
+        b's' => '⎽',
+        b't' => '├',
+        b'u' => '┤',
+        b'v' => '┴',
+        b'w' => '┬',
+        b'x' => '│',
+        b'y' => '≤',
+        b'z' => '≥',
+        b'{' => 'π',
+        b'|' => '≠',
+        b'}' => '£',
+        b'~' => '·',
+        _ => return None,
+    };
+    Some(c)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_translate() {
+        let mut state = CharsetState::default();
+        assert!(matches!(state.translate(b"lqk"), Cow::Borrowed(b"lqk")));
+
+        state.designate(CharsetSlot::G1, Charset::DecSpecialGraphics);
+        assert_eq!(state.translate(b"lqk").as_ref(), b"lqk");
+
+        state.shift_out();
+        assert_eq!(
+            state.translate("lqqk x€A".as_bytes()).as_ref(),
+            "┌──┐ │€A".as_bytes()
+        );
+
+        state.shift_in();
+        assert_eq!(state.translate(b"x").as_ref(), b"x");
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut state = CharsetState::default();
+        state.designate(CharsetSlot::G0, Charset::DecSpecialGraphics);
+        state.shift_out();
+        let loaded =
+            CharsetState::from_snapshot(state.snapshot()).expect("failed to load snapshot");
+        assert_eq!(loaded, state);
+
+        assert!(CharsetState::from_snapshot(SnapshotItem::Bool(true)).is_err());
+    }
+}
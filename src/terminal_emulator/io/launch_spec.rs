@@ -0,0 +1,129 @@
+use std::{
+    collections::BTreeMap,
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+};
+
+const FALLBACK_SHELL: &str = "/bin/sh";
+
+/// What to run in the terminal and how
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LaunchSpec {
+    /// Looked up in PATH if it has no slashes
+    pub program: OsString,
+    /// Arguments after argv[0]
+    pub args: Vec<OsString>,
+    /// Set on top of the inherited environment
+    pub env: Vec<(OsString, OsString)>,
+    /// Removed from the inherited environment
+    pub env_remove: Vec<OsString>,
+    /// Working directory, inherited if None
+    pub cwd: Option<PathBuf>,
+    /// Start the program as a login shell, i.e. with argv[0] prefixed with "-"
+    pub login: bool,
+}
+
+impl LaunchSpec {
+    /// The user's shell from $SHELL
+    pub fn shell() -> LaunchSpec {
+        let program = std::env::var_os("SHELL")
+            .filter(|shell| !shell.is_empty())
+            .unwrap_or_else(|| FALLBACK_SHELL.into());
+        LaunchSpec::command(program, Vec::new())
+    }
+
+    pub fn command(program: impl Into<OsString>, args: Vec<OsString>) -> LaunchSpec {
+        LaunchSpec {
+            program: program.into(),
+            args,
+            env: Vec::new(),
+            env_remove: Vec::new(),
+            cwd: None,
+            login: false,
+        }
+    }
+
+    pub(super) fn argv0(&self) -> OsString {
+        if !self.login {
+            return self.program.clone();
+        }
+
+        let name = Path::new(&self.program)
+            .file_name()
+            .unwrap_or(&self.program);
+        let mut ret = OsString::from("-");
+        ret.push(name);
+        ret
+    }
+
+    /// Environment of the child. Variables the terminal needs come after removals, but can still
+    /// be overridden by the spec
+    pub(super) fn child_env<'a>(
+        &self,
+        inherited: impl IntoIterator<Item = (OsString, OsString)>,
+        terminal_vars: impl IntoIterator<Item = (&'a str, &'a OsStr)>,
+    ) -> BTreeMap<OsString, OsString> {
+        let mut ret: BTreeMap<_, _> = inherited.into_iter().collect();
+        for key in &self.env_remove {
+            ret.remove(key);
+        }
+        for (key, val) in terminal_vars {
+            ret.insert(key.into(), val.into());
+        }
+        for (key, val) in &self.env {
+            ret.insert(key.clone(), val.clone());
+        }
+        ret
+    }
+}
+
+impl Default for LaunchSpec {
+    fn default() -> LaunchSpec {
+        LaunchSpec::shell()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_argv0() {
+        let mut spec = LaunchSpec::command("/usr/bin/zsh", vec!["-i".into()]);
+        assert_eq!(spec.argv0(), "/usr/bin/zsh");
+        spec.login = true;
+        assert_eq!(spec.argv0(), "-zsh");
+        spec.program = "fish".into();
+        assert_eq!(spec.argv0(), "-fish");
+    }
+
+    #[test]
+    fn test_child_env() {
+        let mut spec = LaunchSpec::command("sh", Vec::new());
+        spec.env_remove = vec!["PROMPT_COMMAND".into(), "TERM".into()];
+        spec.env = vec![
+            ("EDITOR".into(), "vi".into()),
+            ("TERMINFO".into(), "/ti".into()),
+        ];
+
+        let inherited = [
+            ("PROMPT_COMMAND".into(), "history -a".into()),
+            ("HOME".into(), "/home/test".into()),
+            ("EDITOR".into(), "nano".into()),
+        ];
+        let terminal_vars = [
+            ("TERM", OsStr::new("termie")),
+            ("TERMINFO", OsStr::new("/tmp/terminfo")),
+        ];
+        let env = spec.child_env(inherited, terminal_vars);
+        assert_eq!(
+            env.into_iter().collect::<Vec<_>>(),
+            [
+                ("EDITOR".into(), "vi".into()),
+                ("HOME".into(), "/home/test".into()),
+                ("TERM".into(), "termie".into()),
+                ("TERMINFO".into(), "/ti".into()),
+            ]
+        );
+    }
+}
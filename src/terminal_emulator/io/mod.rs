@@ -1,15 +1,52 @@
+mod launch_spec;
 mod pty;
-pub use pty::{CreatePtyIoError, PtyIo};
+pub use launch_spec::LaunchSpec;
+pub use pty::{CreatePtyIoError, PtyIo, PtyReader};
+
+use std::fmt;
 
 pub type TermIoErr = Box<dyn std::error::Error>;
 
-pub enum ReadResponse {
-    Success(usize),
-    Empty,
+/// How the child process ended
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExitStatus {
+    Code(i32),
+    Signal(i32),
+}
+
+impl ExitStatus {
+    pub fn success(&self) -> bool {
+        *self == ExitStatus::Code(0)
+    }
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExitStatus::Code(code) => write!(f, "code {code}"),
+            ExitStatus::Signal(signal) => write!(f, "signal {signal}"),
+        }
+    }
 }
 
 pub trait TermIo {
-    fn read(&mut self, buf: &mut [u8]) -> Result<ReadResponse, TermIoErr>;
     fn write(&mut self, buf: &[u8]) -> Result<usize, TermIoErr>;
     fn set_win_size(&mut self, width: usize, height: usize) -> Result<(), TermIoErr>;
 }
+
+pub enum ReaderEvent {
+    /// Number of bytes of output read into the buffer
+    Data(usize),
+    /// Output after this point was produced at a new window size
+    Resize { width: usize, height: usize },
+    /// No more output will arrive
+    Closed,
+    /// The child process exited, no more output will arrive
+    Exited(ExitStatus),
+}
+
+/// Read side of a [`TermIo`] that can block, so that output can be waited for on a dedicated
+/// thread instead of being polled for
+pub trait TermReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<ReaderEvent, TermIoErr>;
+}
@@ -1,15 +1,26 @@
-use nix::{errno::Errno, ioctl_write_ptr_bad, pty::ForkptyResult};
+use nix::{
+    errno::Errno,
+    ioctl_write_ptr_bad,
+    poll::{poll, PollFd, PollFlags},
+    pty::ForkptyResult,
+    sys::wait::{waitpid, WaitPidFlag, WaitStatus},
+    unistd::Pid,
+};
 
 use tempfile::TempDir;
 use thiserror::Error;
 
 use std::{
-    ffi::CStr,
-    os::fd::{AsRawFd, OwnedFd},
+    ffi::{CString, NulError, OsStr},
+    os::{
+        fd::{AsFd, AsRawFd, OwnedFd},
+        unix::ffi::OsStrExt,
+    },
     path::Path,
+    time::Duration,
 };
 
-use super::{ReadResponse, TermIo, TermIoErr};
+use super::{ExitStatus, LaunchSpec, ReaderEvent, TermIo, TermIoErr, TermReader};
 
 ioctl_write_ptr_bad!(
     set_window_size_ioctl,
@@ -53,48 +64,93 @@ fn extract_terminfo() -> Result<TempDir, ExtractTerminfoError> {
 
 #[derive(Error, Debug)]
 enum SpawnShellErrorKind {
+    #[error("launch spec contains a nul byte")]
+    NulByte(#[from] NulError),
     #[error("failed to fork")]
     Fork(#[source] Errno),
-    #[error("failed to exec")]
-    Exec(#[source] Errno),
 }
 
 #[derive(Error, Debug)]
 #[error(transparent)]
 struct SpawnShellError(#[from] SpawnShellErrorKind);
 
-/// Spawn a shell in a child process and return the file descriptor used for I/O
-fn spawn_shell(terminfo_dir: &Path) -> Result<OwnedFd, SpawnShellError> {
+fn os_to_cstring(s: &OsStr) -> Result<CString, NulError> {
+    CString::new(s.as_bytes())
+}
+
+/// Write to stderr of the child, which is the terminal itself
+fn child_report(parts: &[&[u8]]) {
+    let stderr = std::io::stderr();
+    for part in parts {
+        let _ = nix::unistd::write(&stderr, part);
+    }
+}
+
+/// Spawn the program described by spec in a child process and return the file descriptor used for
+/// I/O along with its pid
+fn spawn_shell(spec: &LaunchSpec, terminfo_dir: &Path) -> Result<(OwnedFd, Pid), SpawnShellError> {
+    // Everything is prepared before forking, the child of a multi threaded process should not
+    // allocate
+    let program = os_to_cstring(&spec.program).map_err(SpawnShellErrorKind::NulByte)?;
+    let argv = std::iter::once(spec.argv0())
+        .chain(spec.args.iter().cloned())
+        .map(|arg| os_to_cstring(&arg))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(SpawnShellErrorKind::NulByte)?;
+    let terminal_vars = [
+        ("TERMINFO", terminfo_dir.as_os_str()),
+        ("TERM", OsStr::new("termie")),
+    ];
+    let env = spec
+        .child_env(std::env::vars_os(), terminal_vars)
+        .into_iter()
+        .map(|(key, val)| {
+            let mut var = key;
+            var.push("=");
+            var.push(val);
+            os_to_cstring(&var)
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(SpawnShellErrorKind::NulByte)?;
+    let cwd = spec
+        .cwd
+        .as_ref()
+        .map(|cwd| os_to_cstring(cwd.as_os_str()))
+        .transpose()
+        .map_err(SpawnShellErrorKind::NulByte)?;
+
     unsafe {
         let res = nix::pty::forkpty(None, None).map_err(SpawnShellErrorKind::Fork)?;
-        let _master: OwnedFd;
         match res {
-            ForkptyResult::Parent { master, .. } => {
-                _master = master;
-            }
+            ForkptyResult::Parent { master, child } => Ok((master, child)),
             ForkptyResult::Child => {
-                let shell_name = CStr::from_bytes_with_nul(b"bash\0")
-                    .expect("Should always have null terminator");
-                let args: &[&[u8]] = &[b"bash\0", b"--noprofile\0", b"--norc\0"];
-
-                let args: Vec<&'static CStr> = args
-                    .iter()
-                    .map(|v| {
-                        CStr::from_bytes_with_nul(v).expect("Should always have null terminator")
-                    })
-                    .collect::<Vec<_>>();
-
-                // Temporary workaround to avoid rendering issues
-                std::env::remove_var("PROMPT_COMMAND");
-                std::env::set_var("TERMINFO", terminfo_dir);
-                std::env::set_var("TERM", "termie");
-                std::env::set_var("PS1", "$ ");
-                nix::unistd::execvp(shell_name, &args).map_err(SpawnShellErrorKind::Exec)?;
-                // Should never run
-                std::process::exit(1);
+                if let Some(cwd) = &cwd {
+                    if let Err(e) = nix::unistd::chdir(cwd.as_c_str()) {
+                        child_report(&[
+                            b"termie: failed to change directory to ",
+                            cwd.as_bytes(),
+                            b": ",
+                            e.desc().as_bytes(),
+                            b"\n",
+                        ]);
+                    }
+                }
+
+                let e = match nix::unistd::execvpe(&program, &argv, &env) {
+                    Ok(infallible) => match infallible {},
+                    Err(e) => e,
+                };
+                child_report(&[
+                    b"termie: failed to execute ",
+                    program.as_bytes(),
+                    b": ",
+                    e.desc().as_bytes(),
+                    b"\n",
+                ]);
+                // Skip atexit handlers, they belong to the parent
+                nix::libc::_exit(127);
             }
         }
-        Ok(_master)
     }
 }
 
@@ -137,6 +193,10 @@ enum PtyIoErrKind {
     Read(#[source] Errno),
     #[error("failed to write to file descriptor")]
     Write(#[source] Errno),
+    #[error("failed to poll file descriptor")]
+    Poll(#[source] Errno),
+    #[error("failed to wait for child process")]
+    Wait(#[source] Errno),
 }
 
 #[derive(Debug, Error)]
@@ -145,33 +205,96 @@ pub struct PtyIoErr(#[from] PtyIoErrKind);
 
 pub struct PtyIo {
     fd: OwnedFd,
+    child: Pid,
     _terminfo_dir: TempDir,
 }
 
 impl PtyIo {
-    pub fn new() -> Result<PtyIo, CreatePtyIoError> {
+    pub fn new(launch_spec: &LaunchSpec) -> Result<PtyIo, CreatePtyIoError> {
         let terminfo_dir = extract_terminfo().map_err(CreatePtyIoErrorKind::ExtractTerminfo)?;
-        let fd = spawn_shell(terminfo_dir.path()).map_err(CreatePtyIoErrorKind::SpawnShell)?;
+        let (fd, child) = spawn_shell(launch_spec, terminfo_dir.path())
+            .map_err(CreatePtyIoErrorKind::SpawnShell)?;
         set_nonblock(&fd).map_err(CreatePtyIoErrorKind::SetNonblock)?;
         Ok(PtyIo {
             fd,
+            child,
             _terminfo_dir: terminfo_dir,
         })
     }
+
+    /// Reader sharing the pty with self, for use on another thread. The reader is responsible
+    /// for reaping the child
+    pub fn reader(&self) -> Result<PtyReader, std::io::Error> {
+        Ok(PtyReader {
+            fd: self.fd.try_clone()?,
+            child: self.child,
+        })
+    }
 }
 
-impl TermIo for PtyIo {
-    fn read(&mut self, buf: &mut [u8]) -> Result<ReadResponse, TermIoErr> {
-        let res = nix::unistd::read(self.fd.as_raw_fd(), buf);
-        match res {
-            Ok(v) => Ok(ReadResponse::Success(v)),
-            Err(Errno::EAGAIN) => Ok(ReadResponse::Empty),
-            Err(e) => Err(Box::new(PtyIoErrKind::Read(e))),
+/// How often to check whether the child exited while something else holds the pty open
+const CHILD_POLL_INTERVAL_MS: u16 = 1000;
+
+/// None if the child has not exited yet
+fn wait_child(child: Pid, flags: Option<WaitPidFlag>) -> Result<Option<ExitStatus>, PtyIoErr> {
+    loop {
+        match waitpid(child, flags) {
+            Ok(WaitStatus::Exited(_, code)) => return Ok(Some(ExitStatus::Code(code))),
+            Ok(WaitStatus::Signaled(_, signal, _)) => {
+                return Ok(Some(ExitStatus::Signal(signal as i32)))
+            }
+            Ok(_) => return Ok(None),
+            Err(Errno::EINTR) => (),
+            Err(e) => return Err(PtyIoErrKind::Wait(e).into()),
+        }
+    }
+}
+
+pub struct PtyReader {
+    fd: OwnedFd,
+    child: Pid,
+}
+
+impl TermReader for PtyReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<ReaderEvent, TermIoErr> {
+        loop {
+            // The fd is non-blocking as the emulator shares it, so wait for data ourselves
+            let mut poll_fds = [PollFd::new(self.fd.as_fd(), PollFlags::POLLIN)];
+            match poll(&mut poll_fds, CHILD_POLL_INTERVAL_MS) {
+                Ok(0) => {
+                    // Background jobs can keep the pty open after the shell is gone
+                    match wait_child(self.child, Some(WaitPidFlag::WNOHANG))? {
+                        Some(status) => return Ok(ReaderEvent::Exited(status)),
+                        None => continue,
+                    }
+                }
+                Ok(_) | Err(Errno::EINTR) => (),
+                Err(e) => return Err(Box::new(PtyIoErr::from(PtyIoErrKind::Poll(e)))),
+            }
+
+            match nix::unistd::read(self.fd.as_raw_fd(), buf) {
+                Ok(0) | Err(Errno::EIO) => {
+                    // Linux reports EIO once every process has closed its end, which happens
+                    // when the child exits. The child may close it without exiting though, so
+                    // never block on it
+                    loop {
+                        if let Some(status) = wait_child(self.child, Some(WaitPidFlag::WNOHANG))? {
+                            return Ok(ReaderEvent::Exited(status));
+                        }
+                        std::thread::sleep(Duration::from_millis(CHILD_POLL_INTERVAL_MS.into()));
+                    }
+                }
+                Ok(v) => return Ok(ReaderEvent::Data(v)),
+                Err(Errno::EAGAIN) | Err(Errno::EINTR) => continue,
+                Err(e) => return Err(Box::new(PtyIoErr::from(PtyIoErrKind::Read(e)))),
+            }
         }
     }
+}
 
+impl TermIo for PtyIo {
     fn write(&mut self, buf: &[u8]) -> Result<usize, TermIoErr> {
-        Ok(nix::unistd::write(self.fd.try_clone().unwrap(), buf).map_err(PtyIoErrKind::Write)?)
+        Ok(nix::unistd::write(&self.fd, buf).map_err(PtyIoErrKind::Write)?)
     }
 
     fn set_win_size(&mut self, width: usize, height: usize) -> Result<(), TermIoErr> {
@@ -197,3 +320,25 @@ impl TermIo for PtyIo {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reader_reports_exit_code() {
+        let launch_spec = LaunchSpec::command("sh", vec!["-c".into(), "exit 3".into()]);
+        let io = PtyIo::new(&launch_spec).expect("failed to spawn child");
+        let mut reader = io.reader().expect("failed to create reader");
+
+        let mut buf = [0u8; 4096];
+        let status = loop {
+            match reader.read(&mut buf).expect("failed to read") {
+                ReaderEvent::Exited(status) => break status,
+                ReaderEvent::Data(_) => (),
+                ReaderEvent::Resize { .. } | ReaderEvent::Closed => panic!("unexpected event"),
+            }
+        };
+        assert_eq!(status, ExitStatus::Code(3));
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+};
+
+use super::{ReaderEvent, TermIo, TermReader, TerminalEmulator};
+use crate::error::backtraced_err;
+
+/// Feeds output from reader into terminal_emulator on a dedicated thread, calling on_update
+/// whenever the screen may have changed. The thread exits once the reader is closed
+pub fn spawn_io_thread<Io, Reader, OnUpdate>(
+    terminal_emulator: Arc<Mutex<TerminalEmulator<Io>>>,
+    mut reader: Reader,
+    on_update: OnUpdate,
+) -> std::io::Result<JoinHandle<()>>
+where
+    Io: TermIo + Send + 'static,
+    Reader: TermReader + Send + 'static,
+    OnUpdate: Fn() + Send + 'static,
+{
+    std::thread::Builder::new()
+        .name("termie-io".to_string())
+        .spawn(move || {
+            let mut buf = vec![0u8; 4096];
+            loop {
+                let event = match reader.read(&mut buf) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        error!("Failed to read terminal output: {}", backtraced_err(&*e));
+                        break;
+                    }
+                };
+
+                // The lock is only held per read so that a flood of output cannot starve the GUI
+                let mut terminal_emulator = terminal_emulator
+                    .lock()
+                    .expect("terminal emulator lock poisoned");
+                match event {
+                    ReaderEvent::Data(read_size) => {
+                        terminal_emulator.process_output(&buf[..read_size]);
+                    }
+                    ReaderEvent::Resize { width, height } => {
+                        if let Err(e) = terminal_emulator.set_win_size(width, height) {
+                            error!("failed to set window size: {}", backtraced_err(&*e));
+                        }
+                    }
+                    ReaderEvent::Closed => break,
+                    ReaderEvent::Exited(status) => {
+                        info!("Child process exited with {status}");
+                        terminal_emulator.child_exited(status);
+                        break;
+                    }
+                }
+                drop(terminal_emulator);
+
+                on_update();
+            }
+
+            on_update();
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::VecDeque,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+    use crate::terminal_emulator::{ExitStatus, TermIoErr};
+
+    struct NullIo;
+
+    impl TermIo for NullIo {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, TermIoErr> {
+            Ok(buf.len())
+        }
+
+        fn set_win_size(&mut self, _width: usize, _height: usize) -> Result<(), TermIoErr> {
+            Ok(())
+        }
+    }
+
+    enum TestEvent {
+        Data(&'static [u8]),
+        Resize(usize, usize),
+        Exit(ExitStatus),
+    }
+
+    struct TestReader {
+        events: VecDeque<TestEvent>,
+    }
+
+    impl TermReader for TestReader {
+        fn read(&mut self, buf: &mut [u8]) -> Result<ReaderEvent, TermIoErr> {
+            let event = match self.events.pop_front() {
+                Some(TestEvent::Data(data)) => {
+                    buf[..data.len()].copy_from_slice(data);
+                    ReaderEvent::Data(data.len())
+                }
+                Some(TestEvent::Resize(width, height)) => ReaderEvent::Resize { width, height },
+                Some(TestEvent::Exit(status)) => ReaderEvent::Exited(status),
+                None => ReaderEvent::Closed,
+            };
+            Ok(event)
+        }
+    }
+
+    #[test]
+    fn test_io_thread() {
+        let terminal_emulator = Arc::new(Mutex::new(TerminalEmulator::new_with_io(
+            NullIo,
+            "recordings".into(),
+        )));
+        let reader = TestReader {
+            events: [
+                TestEvent::Data(b"0123456789"),
+                TestEvent::Resize(5, 4),
+                TestEvent::Data(b"abc"),
+                TestEvent::Exit(ExitStatus::Code(3)),
+                TestEvent::Data(b"never read"),
+            ]
+            .into(),
+        };
+        let num_updates = Arc::new(AtomicUsize::new(0));
+        let on_update = {
+            let num_updates = Arc::clone(&num_updates);
+            move || {
+                num_updates.fetch_add(1, Ordering::Relaxed);
+            }
+        };
+
+        spawn_io_thread(Arc::clone(&terminal_emulator), reader, on_update)
+            .expect("failed to spawn io thread")
+            .join()
+            .expect("io thread panicked");
+
+        // Output before the resize wraps at the old width, output after at the new one
+        let mut terminal_emulator = terminal_emulator.lock().unwrap();
+        assert_eq!(terminal_emulator.get_win_size(), (5, 4));
+        assert_eq!(terminal_emulator.data().visible, b"0123456789abc\n");
+        assert_eq!(terminal_emulator.cursor_pos().y, 2);
+        assert_eq!(terminal_emulator.exit_status(), Some(ExitStatus::Code(3)));
+        assert_eq!(num_updates.load(Ordering::Relaxed), 4);
+    }
+}
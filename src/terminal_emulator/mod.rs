@@ -1,35 +1,69 @@
-use std::{fmt, num::TryFromIntError, path::PathBuf};
+use std::{borrow::Cow, fmt, num::TryFromIntError, ops::Range, path::PathBuf};
 
 use ansi::{AnsiParser, SelectGraphicRendition, TerminalOutput};
-use buffer::{BufPos, TerminalBuffer2};
-use format_tracker::FormatTracker;
+use buffer::TerminalBuffer2;
+use cell_format::{CellFormat, LineFormat};
+use charset::CharsetState;
 use recording::{NotIntOfType, Recorder};
-
-pub use format_tracker::FormatTagSerialized;
-pub use io::{PtyIo, TermIo};
-pub use recording::{LoadRecordingError, Recording, RecordingHandle, SnapshotItem};
-pub use replay::{ControlAction, RecordingAction, ReplayControl, ReplayIo};
-
-use crate::{error::backtraced_err, terminal_emulator::io::ReadResponse};
+use selection::Selection;
+use tab_stops::TabStops;
+
+pub use buffer::{char_width, BufPos, ScrollbackLimit};
+pub use cell_format::FormatTagSerialized;
+pub use io::{
+    CreatePtyIoError, ExitStatus, LaunchSpec, PtyIo, PtyReader, ReaderEvent, TermIo, TermIoErr,
+    TermReader,
+};
+pub use io_thread::spawn_io_thread;
+pub use mouse::{MouseButton, MouseEvent, MouseEventKind, MouseTracking};
+pub use palette::{theme_names, Palette, PaletteEntry, Rgb};
+pub use recording::{LoadRecordingError, Recording, RecordingHandle, RecordingItem, SnapshotItem};
+pub use replay::{RecordingAction, ReplayControl, ReplayIo, ReplayReader};
+pub use selection::SelectionMode;
+
+use crate::error::backtraced_err;
 use thiserror::Error;
 
-use self::{
-    io::CreatePtyIoError,
-    recording::{RecordingItem, StartRecordingResponse},
-};
+use self::recording::StartRecordingResponse;
 
 mod ansi;
 mod buffer;
-mod format_tracker;
+mod cell_format;
+mod charset;
 mod io;
+mod io_thread;
+mod mouse;
+mod palette;
 mod recording;
 mod replay;
+mod selection;
+mod tab_stops;
 
 #[derive(Eq, PartialEq)]
 enum Mode {
     // Cursor keys mode
     // https://vt100.net/docs/vt100-ug/chapter3.html
     Decckm,
+    // Origin mode, cursor positions are relative to the scroll region (?6)
+    Decom,
+    // Autowrap mode (?7)
+    Decawm,
+    // Text cursor enable mode, whether the cursor is shown (?25)
+    Dectcem,
+    // Alternate screen buffer (?47, ?1047)
+    AlternateScreen,
+    // Alternate screen buffer, saving the cursor on entry and restoring it on exit (?1049)
+    AlternateScreenSaveCursor,
+    // Report mouse presses and releases (?1000)
+    MouseClickTracking,
+    // Also report motion while a button is held (?1002)
+    MouseDragTracking,
+    // Also report motion with no button held (?1003)
+    MouseMotionTracking,
+    // Report the mouse as ESC [ < ... M instead of ESC [ M ... (?1006)
+    SgrMouse,
+    // Pastes are wrapped in ESC [ 200 ~ and ESC [ 201 ~ (?2004)
+    BracketedPaste,
     Unknown(Vec<u8>),
 }
 
@@ -37,6 +71,16 @@ impl fmt::Debug for Mode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Mode::Decckm => f.write_str("Decckm"),
+            Mode::Decom => f.write_str("Decom"),
+            Mode::Decawm => f.write_str("Decawm"),
+            Mode::Dectcem => f.write_str("Dectcem"),
+            Mode::AlternateScreen => f.write_str("AlternateScreen"),
+            Mode::AlternateScreenSaveCursor => f.write_str("AlternateScreenSaveCursor"),
+            Mode::MouseClickTracking => f.write_str("MouseClickTracking"),
+            Mode::MouseDragTracking => f.write_str("MouseDragTracking"),
+            Mode::MouseMotionTracking => f.write_str("MouseMotionTracking"),
+            Mode::SgrMouse => f.write_str("SgrMouse"),
+            Mode::BracketedPaste => f.write_str("BracketedPaste"),
             Mode::Unknown(params) => {
                 let params_s = std::str::from_utf8(params)
                     .expect("parameter parsing should not allow non-utf8 characters here");
@@ -46,16 +90,138 @@ impl fmt::Debug for Mode {
     }
 }
 
+/// Finds the byte offset of the cell at column in a serialized line. Wide chars span two columns,
+/// and combining chars stay with the cell before them
+fn column_to_byte_offset(data: &[u8], column: usize) -> usize {
+    let Ok(data_str) = std::str::from_utf8(data) else {
+        warn!("serialized line is not utf8");
+        return data.len();
+    };
+
+    let mut current_column = 0;
+    for (i, c) in data_str.char_indices() {
+        let width = char_width(c);
+        if current_column >= column && width != 0 {
+            return i;
+        }
+        current_column += width;
+    }
+    data.len()
+}
+
+/// Byte offset of pos in serialized lines. Positions before the first line map to the start and
+/// positions after the last line to the end
+fn buf_pos_to_byte_offset(
+    data: &[u8],
+    line_mappings: &[usize],
+    first_line_id: usize,
+    pos: &BufPos,
+) -> usize {
+    let Some(idx) = pos.line_id.checked_sub(first_line_id) else {
+        return 0;
+    };
+    let Some(line_start) = line_mappings.get(idx) else {
+        return data.len();
+    };
+
+    let line_end = line_mappings.get(idx + 1).copied().unwrap_or(data.len());
+    line_start + column_to_byte_offset(&data[*line_start..line_end], pos.x_pos)
+}
+
+/// Converts per line column runs into byte ranges covering all of data. The last run of a line
+/// extends to the start of the next line so that the ranges never have gaps
+fn serialize_line_formats(
+    data: &[u8],
+    line_mappings: &[usize],
+    formats: &[LineFormat],
+) -> Vec<FormatTagSerialized> {
+    let mut tags: Vec<FormatTagSerialized> = Vec::new();
+    let mut last_format = None;
+    for (i, (line_start, format)) in line_mappings.iter().zip(formats).enumerate() {
+        let line_end = line_mappings.get(i + 1).copied().unwrap_or(data.len());
+        let line = &data[*line_start..line_end];
+        for (range, cell_format) in format.runs() {
+            let start = if range.start == 0 {
+                *line_start
+            } else {
+                line_start + column_to_byte_offset(line, range.start)
+            };
+            let end = if range.end == usize::MAX {
+                line_end
+            } else {
+                line_start + column_to_byte_offset(line, range.end)
+            };
+            if start >= end {
+                continue;
+            }
+
+            match (tags.last_mut(), last_format) {
+                (Some(last), Some(last_format)) if last_format == *cell_format => {
+                    last.end = end;
+                }
+                _ => tags.push(cell_format.to_serialized(start, end)),
+            }
+            last_format = Some(*cell_format);
+        }
+    }
+
+    tags
+}
+
 fn char_to_ctrl_code(c: u8) -> u8 {
     // https://catern.com/posts/terminal_quirks.html
     // man ascii
-    c & 0b0001_1111
+    match c {
+        // The one control code that is not in the first 32
+        b'?' => 0x7f,
+        _ => c & 0b0001_1111,
+    }
+}
+
+/// Modes set by the child that change what input is sent as
+#[derive(Clone, Copy, Debug, Default)]
+struct InputModes {
+    decckm: bool,
+    bracketed_paste: bool,
+}
+
+/// Modifier keys held with a key or mouse event
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct InputModifiers {
+    pub shift: bool,
+    pub alt: bool,
+    pub ctrl: bool,
+}
+
+impl InputModifiers {
+    /// xterm's modifier parameter, None if nothing is held
+    /// https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h3-PC-Style-Function-Keys
+    fn param(&self) -> Option<u8> {
+        let param = 1 + self.shift as u8 + 2 * self.alt as u8 + 4 * self.ctrl as u8;
+        (param > 1).then_some(param)
+    }
 }
 
 #[derive(Eq, PartialEq, Debug)]
-enum TerminalInputPayload {
+enum TerminalInputPayload<'a> {
     Single(u8),
-    Many(&'static [u8]),
+    Many(Cow<'a, [u8]>),
+}
+
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+/// Pasted text as it is sent to the application. In bracketed paste mode the text is wrapped in
+/// markers, and any end marker inside of it is removed so that the paste cannot end early
+fn paste_payload(text: &str, bracketed_paste_mode: bool) -> Vec<u8> {
+    if !bracketed_paste_mode {
+        return text.as_bytes().to_vec();
+    }
+
+    let mut ret = PASTE_START.to_vec();
+    ret.extend_from_slice(text.replace("\x1b[201~", "").as_bytes());
+    ret.extend_from_slice(PASTE_END);
+    ret
 }
 
 #[derive(Clone)]
@@ -76,10 +242,50 @@ pub enum TerminalInput {
     Insert,
     PageUp,
     PageDown,
+    Tab,
+    Escape,
+    // F1 through F12
+    Function(u8),
+    // Key with modifiers held. Keys sent as CSI sequences get the modifiers as a parameter, Alt
+    // prefixes anything else with ESC
+    Modified(Box<TerminalInput>, InputModifiers),
+    // Text from the clipboard
+    Paste(String),
+}
+
+/// Number and final byte of keys that are sent as CSI sequences when modified
+fn csi_key(input: &TerminalInput) -> Option<(u8, u8)> {
+    let key = match input {
+        TerminalInput::ArrowUp => (1, b'A'),
+        TerminalInput::ArrowDown => (1, b'B'),
+        TerminalInput::ArrowRight => (1, b'C'),
+        TerminalInput::ArrowLeft => (1, b'D'),
+        TerminalInput::Home => (1, b'H'),
+        TerminalInput::End => (1, b'F'),
+        TerminalInput::Insert => (2, b'~'),
+        TerminalInput::Delete => (3, b'~'),
+        TerminalInput::PageUp => (5, b'~'),
+        TerminalInput::PageDown => (6, b'~'),
+        TerminalInput::Function(n @ 1..=4) => (1, b'P' + n - 1),
+        TerminalInput::Function(5) => (15, b'~'),
+        // 16 and 22 are skipped, a leftover from the vt220 keyboard layout
+        TerminalInput::Function(n @ 6..=10) => (n + 11, b'~'),
+        TerminalInput::Function(n @ 11..=12) => (n + 12, b'~'),
+        _ => return None,
+    };
+    Some(key)
 }
 
 impl TerminalInput {
-    fn to_payload(&self, decckm_mode: bool) -> TerminalInputPayload {
+    /// Wraps self in [`TerminalInput::Modified`] if any modifiers are held
+    pub fn with_modifiers(self, modifiers: InputModifiers) -> TerminalInput {
+        match modifiers == InputModifiers::default() {
+            true => self,
+            false => TerminalInput::Modified(Box::new(self), modifiers),
+        }
+    }
+
+    fn to_payload(&self, modes: &InputModes) -> TerminalInputPayload<'_> {
         match self {
             TerminalInput::Ascii(c) => TerminalInputPayload::Single(*c),
             TerminalInput::Ctrl(c) => TerminalInputPayload::Single(char_to_ctrl_code(*c)),
@@ -88,42 +294,88 @@ impl TerminalInput {
             TerminalInput::Backspace => TerminalInputPayload::Single(0x7f),
             // https://vt100.net/docs/vt100-ug/chapter3.html
             // Table 3-6
-            TerminalInput::ArrowRight => match decckm_mode {
-                true => TerminalInputPayload::Many(b"\x1bOC"),
-                false => TerminalInputPayload::Many(b"\x1b[C"),
+            TerminalInput::ArrowRight => match modes.decckm {
+                true => TerminalInputPayload::Many(Cow::Borrowed(b"\x1bOC")),
+                false => TerminalInputPayload::Many(Cow::Borrowed(b"\x1b[C")),
             },
-            TerminalInput::ArrowLeft => match decckm_mode {
-                true => TerminalInputPayload::Many(b"\x1bOD"),
-                false => TerminalInputPayload::Many(b"\x1b[D"),
+            TerminalInput::ArrowLeft => match modes.decckm {
+                true => TerminalInputPayload::Many(Cow::Borrowed(b"\x1bOD")),
+                false => TerminalInputPayload::Many(Cow::Borrowed(b"\x1b[D")),
             },
-            TerminalInput::ArrowUp => match decckm_mode {
-                true => TerminalInputPayload::Many(b"\x1bOA"),
-                false => TerminalInputPayload::Many(b"\x1b[A"),
+            TerminalInput::ArrowUp => match modes.decckm {
+                true => TerminalInputPayload::Many(Cow::Borrowed(b"\x1bOA")),
+                false => TerminalInputPayload::Many(Cow::Borrowed(b"\x1b[A")),
             },
-            TerminalInput::ArrowDown => match decckm_mode {
-                true => TerminalInputPayload::Many(b"\x1bOB"),
-                false => TerminalInputPayload::Many(b"\x1b[B"),
+            TerminalInput::ArrowDown => match modes.decckm {
+                true => TerminalInputPayload::Many(Cow::Borrowed(b"\x1bOB")),
+                false => TerminalInputPayload::Many(Cow::Borrowed(b"\x1b[B")),
             },
-            TerminalInput::Home => match decckm_mode {
-                true => TerminalInputPayload::Many(b"\x1bOH"),
-                false => TerminalInputPayload::Many(b"\x1b[H"),
+            TerminalInput::Home => match modes.decckm {
+                true => TerminalInputPayload::Many(Cow::Borrowed(b"\x1bOH")),
+                false => TerminalInputPayload::Many(Cow::Borrowed(b"\x1b[H")),
             },
-            TerminalInput::End => match decckm_mode {
-                true => TerminalInputPayload::Many(b"\x1bOF"),
-                false => TerminalInputPayload::Many(b"\x1b[F"),
+            TerminalInput::End => match modes.decckm {
+                true => TerminalInputPayload::Many(Cow::Borrowed(b"\x1bOF")),
+                false => TerminalInputPayload::Many(Cow::Borrowed(b"\x1b[F")),
             },
             // Why \e[3~? It seems like we are emulating the vt510. Other terminals do it, so we
             // can too
             // https://web.archive.org/web/20160304024035/http://www.vt100.net/docs/vt510-rm/chapter8
             // https://en.wikipedia.org/wiki/Delete_character
-            TerminalInput::Delete => TerminalInputPayload::Many(b"\x1b[3~"),
-            TerminalInput::Insert => TerminalInputPayload::Many(b"\x1b[2~"),
-            TerminalInput::PageUp => TerminalInputPayload::Many(b"\x1b[5~"),
-            TerminalInput::PageDown => TerminalInputPayload::Many(b"\x1b[6~"),
+            TerminalInput::Delete => TerminalInputPayload::Many(Cow::Borrowed(b"\x1b[3~")),
+            TerminalInput::Insert => TerminalInputPayload::Many(Cow::Borrowed(b"\x1b[2~")),
+            TerminalInput::PageUp => TerminalInputPayload::Many(Cow::Borrowed(b"\x1b[5~")),
+            TerminalInput::PageDown => TerminalInputPayload::Many(Cow::Borrowed(b"\x1b[6~")),
+            TerminalInput::Tab => TerminalInputPayload::Single(b'\t'),
+            TerminalInput::Escape => TerminalInputPayload::Single(0x1b),
+            // https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h3-PC-Style-Function-Keys
+            TerminalInput::Function(n @ 1..=4) => {
+                TerminalInputPayload::Many(vec![0x1b, b'O', b'P' + n - 1].into())
+            }
+            TerminalInput::Function(_) => match csi_key(self) {
+                Some((number, _)) => {
+                    TerminalInputPayload::Many(format!("\x1b[{number}~").into_bytes().into())
+                }
+                None => TerminalInputPayload::Many(Cow::Borrowed(b"")),
+            },
+            TerminalInput::Modified(input, modifiers) => modified_payload(input, modifiers, modes),
+            TerminalInput::Paste(text) => {
+                TerminalInputPayload::Many(Cow::Owned(paste_payload(text, modes.bracketed_paste)))
+            }
         }
     }
 }
 
+fn modified_payload<'a>(
+    input: &'a TerminalInput,
+    modifiers: &InputModifiers,
+    modes: &InputModes,
+) -> TerminalInputPayload<'a> {
+    if let (Some((number, final_byte)), Some(param)) = (csi_key(input), modifiers.param()) {
+        let final_byte = final_byte as char;
+        return TerminalInputPayload::Many(
+            format!("\x1b[{number};{param}{final_byte}")
+                .into_bytes()
+                .into(),
+        );
+    }
+
+    let payload = match (input, modifiers.shift) {
+        (TerminalInput::Tab, true) => TerminalInputPayload::Many(Cow::Borrowed(b"\x1b[Z")),
+        _ => input.to_payload(modes),
+    };
+    if !modifiers.alt {
+        return payload;
+    }
+
+    let mut ret = vec![0x1b];
+    match payload {
+        TerminalInputPayload::Single(c) => ret.push(c),
+        TerminalInputPayload::Many(bytes) => ret.extend_from_slice(&bytes),
+    }
+    TerminalInputPayload::Many(ret.into())
+}
+
 #[derive(Debug, Error)]
 enum SnapshotCursorPosErrorPriv {
     #[error("x pos cannot be cast to i64")]
@@ -186,30 +438,94 @@ impl CursorPos {
     }
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CursorShape {
+    Block,
+    Underline,
+    Bar,
+}
+
+/// How the cursor is drawn, as set by DECSCUSR
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CursorStyle {
+    pub shape: CursorShape,
+    pub blinking: bool,
+}
+
+impl Default for CursorStyle {
+    fn default() -> CursorStyle {
+        CursorStyle {
+            shape: CursorShape::Block,
+            blinking: true,
+        }
+    }
+}
+
+impl CursorStyle {
+    /// Style for DECSCUSR parameter param, where 0 is the default
+    fn from_decscusr(param: usize) -> Option<CursorStyle> {
+        let (shape, blinking) = match param {
+            0 => return Some(CursorStyle::default()),
+            1 => (CursorShape::Block, true),
+            2 => (CursorShape::Block, false),
+            3 => (CursorShape::Underline, true),
+            4 => (CursorShape::Underline, false),
+            5 => (CursorShape::Bar, true),
+            6 => (CursorShape::Bar, false),
+            _ => return None,
+        };
+        Some(CursorStyle { shape, blinking })
+    }
+
+    fn decscusr(&self) -> u8 {
+        let shape = match self.shape {
+            CursorShape::Block => 1,
+            CursorShape::Underline => 3,
+            CursorShape::Bar => 5,
+        };
+        match self.blinking {
+            true => shape,
+            false => shape + 1,
+        }
+    }
+}
+
 mod cursor_state_keys {
     pub const POS: &str = "pos";
     pub const BOLD: &str = "bold";
+    pub const DIM: &str = "dim";
+    pub const ITALIC: &str = "italic";
+    pub const UNDERLINE: &str = "underline";
+    pub const REVERSE: &str = "reverse";
+    pub const STRIKETHROUGH: &str = "strikethrough";
     pub const COLOR: &str = "color";
+    pub const BACKGROUND_COLOR: &str = "background_color";
+    pub const ORIGIN_MODE: &str = "origin_mode";
+    pub const CHARSET: &str = "charset";
 }
 
 #[derive(Debug, Error)]
 enum LoadCursorStateErrorPriv {
     #[error("root element is not a map")]
     RootNotMap,
-    #[error("bold field is not present")]
-    BoldNotPresent,
-    #[error("bold field is not a bool")]
-    BoldNotBool,
-    #[error("color field is not present")]
-    ColorNotPresent,
-    #[error("color field is not a bool")]
-    ColorNotString,
-    #[error("color failed to parse")]
-    ColorInvalid(()),
+    #[error("{0} field is not present")]
+    FlagNotPresent(&'static str),
+    #[error("{0} field is not a bool")]
+    FlagNotBool(&'static str),
+    #[error("{0} field is not present")]
+    ColorNotPresent(&'static str),
+    #[error("{0} field is not a string")]
+    ColorNotString(&'static str),
+    #[error("{0} failed to parse")]
+    ColorInvalid(&'static str),
     #[error("pos field not present")]
     PosNotPresent,
     #[error("failed to parse position")]
     FailParsePos(#[source] LoadCursorPosError),
+    #[error("charset field not present")]
+    CharsetNotPresent,
+    #[error("failed to parse charset")]
+    FailParseCharset(#[source] charset::LoadSnapshotError),
 }
 
 #[derive(Error, Debug)]
@@ -220,31 +536,100 @@ pub struct LoadCursorStateError(#[from] LoadCursorStateErrorPriv);
 struct CursorState {
     pos: CursorPos,
     bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+    reverse: bool,
+    strikethrough: bool,
     color: TerminalColor,
+    background_color: TerminalColor,
+    // DECOM
+    origin_mode: bool,
+    charset: CharsetState,
 }
 
 impl CursorState {
+    fn new() -> CursorState {
+        CursorState {
+            pos: CursorPos { x: 0, y: 0 },
+            bold: false,
+            dim: false,
+            italic: false,
+            underline: false,
+            reverse: false,
+            strikethrough: false,
+            color: TerminalColor::Default,
+            background_color: TerminalColor::Default,
+            origin_mode: false,
+            charset: CharsetState::default(),
+        }
+    }
+
+    /// Resets all graphic renditions (SGR 0), position is left untouched
+    fn reset_sgr(&mut self) {
+        self.bold = false;
+        self.dim = false;
+        self.italic = false;
+        self.underline = false;
+        self.reverse = false;
+        self.strikethrough = false;
+        self.color = TerminalColor::Default;
+        self.background_color = TerminalColor::Default;
+    }
+
     fn from_snapshot(snapshot: SnapshotItem) -> Result<CursorState, LoadCursorStateError> {
         use LoadCursorStateErrorPriv::*;
         let mut map = snapshot.into_map().map_err(|_| RootNotMap)?;
 
-        let bold = map.remove(cursor_state_keys::BOLD).ok_or(BoldNotPresent)?;
-        let SnapshotItem::Bool(bold) = bold else {
-            Err(BoldNotBool)?
+        let mut load_flag = |key: &'static str| -> Result<bool, LoadCursorStateErrorPriv> {
+            map.remove(key)
+                .ok_or(FlagNotPresent(key))?
+                .into_bool()
+                .map_err(|_| FlagNotBool(key))
         };
 
-        let color = map
-            .remove(cursor_state_keys::COLOR)
-            .ok_or(ColorNotPresent)?;
-        let SnapshotItem::String(color) = color else {
-            Err(ColorNotString)?
-        };
-        let color = color.parse().map_err(ColorInvalid)?;
+        let bold = load_flag(cursor_state_keys::BOLD)?;
+        let dim = load_flag(cursor_state_keys::DIM)?;
+        let italic = load_flag(cursor_state_keys::ITALIC)?;
+        let underline = load_flag(cursor_state_keys::UNDERLINE)?;
+        let reverse = load_flag(cursor_state_keys::REVERSE)?;
+        let strikethrough = load_flag(cursor_state_keys::STRIKETHROUGH)?;
+        let origin_mode = load_flag(cursor_state_keys::ORIGIN_MODE)?;
+
+        let mut load_color =
+            |key: &'static str| -> Result<TerminalColor, LoadCursorStateErrorPriv> {
+                map.remove(key)
+                    .ok_or(ColorNotPresent(key))?
+                    .into_string()
+                    .map_err(|_| ColorNotString(key))?
+                    .parse()
+                    .map_err(|_| ColorInvalid(key))
+            };
+
+        let color = load_color(cursor_state_keys::COLOR)?;
+        let background_color = load_color(cursor_state_keys::BACKGROUND_COLOR)?;
 
         let pos = map.remove(cursor_state_keys::POS).ok_or(PosNotPresent)?;
         let pos = CursorPos::from_snapshot(pos).map_err(FailParsePos)?;
 
-        Ok(CursorState { bold, color, pos })
+        let charset = map
+            .remove(cursor_state_keys::CHARSET)
+            .ok_or(CharsetNotPresent)?;
+        let charset = CharsetState::from_snapshot(charset).map_err(FailParseCharset)?;
+
+        Ok(CursorState {
+            pos,
+            bold,
+            dim,
+            italic,
+            underline,
+            reverse,
+            strikethrough,
+            color,
+            background_color,
+            origin_mode,
+            charset,
+        })
     }
 
     fn snapshot(&self) -> Result<SnapshotItem, SnapshotCursorPosError> {
@@ -252,10 +637,33 @@ impl CursorState {
             [
                 (cursor_state_keys::POS.to_string(), self.pos.snapshot()?),
                 (cursor_state_keys::BOLD.to_string(), self.bold.into()),
+                (cursor_state_keys::DIM.to_string(), self.dim.into()),
+                (cursor_state_keys::ITALIC.to_string(), self.italic.into()),
+                (
+                    cursor_state_keys::UNDERLINE.to_string(),
+                    self.underline.into(),
+                ),
+                (cursor_state_keys::REVERSE.to_string(), self.reverse.into()),
+                (
+                    cursor_state_keys::STRIKETHROUGH.to_string(),
+                    self.strikethrough.into(),
+                ),
                 (
                     cursor_state_keys::COLOR.to_string(),
                     self.color.to_string().into(),
                 ),
+                (
+                    cursor_state_keys::BACKGROUND_COLOR.to_string(),
+                    self.background_color.to_string().into(),
+                ),
+                (
+                    cursor_state_keys::ORIGIN_MODE.to_string(),
+                    self.origin_mode.into(),
+                ),
+                (
+                    cursor_state_keys::CHARSET.to_string(),
+                    self.charset.snapshot(),
+                ),
             ]
             .into(),
         );
@@ -274,6 +682,17 @@ pub enum TerminalColor {
     Magenta,
     Cyan,
     White,
+    BrightBlack,
+    BrightRed,
+    BrightGreen,
+    BrightYellow,
+    BrightBlue,
+    BrightMagenta,
+    BrightCyan,
+    BrightWhite,
+    /// Entry in the 256 color palette
+    Indexed(u8),
+    Rgb(u8, u8, u8),
 }
 
 impl fmt::Display for TerminalColor {
@@ -288,6 +707,16 @@ impl fmt::Display for TerminalColor {
             TerminalColor::Magenta => "magenta",
             TerminalColor::Cyan => "cyan",
             TerminalColor::White => "white",
+            TerminalColor::BrightBlack => "bright_black",
+            TerminalColor::BrightRed => "bright_red",
+            TerminalColor::BrightGreen => "bright_green",
+            TerminalColor::BrightYellow => "bright_yellow",
+            TerminalColor::BrightBlue => "bright_blue",
+            TerminalColor::BrightMagenta => "bright_magenta",
+            TerminalColor::BrightCyan => "bright_cyan",
+            TerminalColor::BrightWhite => "bright_white",
+            TerminalColor::Indexed(idx) => return write!(f, "indexed:{idx}"),
+            TerminalColor::Rgb(r, g, b) => return write!(f, "#{r:02x}{g:02x}{b:02x}"),
         };
 
         f.write_str(s)
@@ -298,6 +727,22 @@ impl std::str::FromStr for TerminalColor {
     type Err = ();
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(idx) = s.strip_prefix("indexed:") {
+            return Ok(TerminalColor::Indexed(idx.parse().map_err(|_| ())?));
+        }
+
+        if let Some(hex) = s.strip_prefix('#') {
+            if hex.len() != 6 || !hex.is_ascii() {
+                return Err(());
+            }
+            let component = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| ());
+            return Ok(TerminalColor::Rgb(
+                component(0)?,
+                component(2)?,
+                component(4)?,
+            ));
+        }
+
         let ret = match s {
             "default" => TerminalColor::Default,
             "black" => TerminalColor::Black,
@@ -308,6 +753,14 @@ impl std::str::FromStr for TerminalColor {
             "magenta" => TerminalColor::Magenta,
             "cyan" => TerminalColor::Cyan,
             "white" => TerminalColor::White,
+            "bright_black" => TerminalColor::BrightBlack,
+            "bright_red" => TerminalColor::BrightRed,
+            "bright_green" => TerminalColor::BrightGreen,
+            "bright_yellow" => TerminalColor::BrightYellow,
+            "bright_blue" => TerminalColor::BrightBlue,
+            "bright_magenta" => TerminalColor::BrightMagenta,
+            "bright_cyan" => TerminalColor::BrightCyan,
+            "bright_white" => TerminalColor::BrightWhite,
             _ => return Err(()),
         };
         Ok(ret)
@@ -325,6 +778,44 @@ impl TerminalColor {
             SelectGraphicRendition::ForegroundMagenta => TerminalColor::Magenta,
             SelectGraphicRendition::ForegroundCyan => TerminalColor::Cyan,
             SelectGraphicRendition::ForegroundWhite => TerminalColor::White,
+            SelectGraphicRendition::ForegroundBrightBlack => TerminalColor::BrightBlack,
+            SelectGraphicRendition::ForegroundBrightRed => TerminalColor::BrightRed,
+            SelectGraphicRendition::ForegroundBrightGreen => TerminalColor::BrightGreen,
+            SelectGraphicRendition::ForegroundBrightYellow => TerminalColor::BrightYellow,
+            SelectGraphicRendition::ForegroundBrightBlue => TerminalColor::BrightBlue,
+            SelectGraphicRendition::ForegroundBrightMagenta => TerminalColor::BrightMagenta,
+            SelectGraphicRendition::ForegroundBrightCyan => TerminalColor::BrightCyan,
+            SelectGraphicRendition::ForegroundBrightWhite => TerminalColor::BrightWhite,
+            SelectGraphicRendition::ForegroundIndexed(idx) => TerminalColor::Indexed(idx),
+            SelectGraphicRendition::ForegroundRgb(r, g, b) => TerminalColor::Rgb(r, g, b),
+            SelectGraphicRendition::DefaultForeground => TerminalColor::Default,
+            _ => return None,
+        };
+
+        Some(ret)
+    }
+
+    fn background_from_sgr(sgr: SelectGraphicRendition) -> Option<TerminalColor> {
+        let ret = match sgr {
+            SelectGraphicRendition::BackgroundBlack => TerminalColor::Black,
+            SelectGraphicRendition::BackgroundRed => TerminalColor::Red,
+            SelectGraphicRendition::BackgroundGreen => TerminalColor::Green,
+            SelectGraphicRendition::BackgroundYellow => TerminalColor::Yellow,
+            SelectGraphicRendition::BackgroundBlue => TerminalColor::Blue,
+            SelectGraphicRendition::BackgroundMagenta => TerminalColor::Magenta,
+            SelectGraphicRendition::BackgroundCyan => TerminalColor::Cyan,
+            SelectGraphicRendition::BackgroundWhite => TerminalColor::White,
+            SelectGraphicRendition::BackgroundBrightBlack => TerminalColor::BrightBlack,
+            SelectGraphicRendition::BackgroundBrightRed => TerminalColor::BrightRed,
+            SelectGraphicRendition::BackgroundBrightGreen => TerminalColor::BrightGreen,
+            SelectGraphicRendition::BackgroundBrightYellow => TerminalColor::BrightYellow,
+            SelectGraphicRendition::BackgroundBrightBlue => TerminalColor::BrightBlue,
+            SelectGraphicRendition::BackgroundBrightMagenta => TerminalColor::BrightMagenta,
+            SelectGraphicRendition::BackgroundBrightCyan => TerminalColor::BrightCyan,
+            SelectGraphicRendition::BackgroundBrightWhite => TerminalColor::BrightWhite,
+            SelectGraphicRendition::BackgroundIndexed(idx) => TerminalColor::Indexed(idx),
+            SelectGraphicRendition::BackgroundRgb(r, g, b) => TerminalColor::Rgb(r, g, b),
+            SelectGraphicRendition::DefaultBackground => TerminalColor::Default,
             _ => return None,
         };
 
@@ -337,10 +828,15 @@ pub struct TerminalData2 {
     // FIXME: slice?
     pub scrollback: Vec<u8>,
     pub visible: Vec<u8>,
-    // Line id -> buf pos
+    // line id - first_scrollback_line_id -> buf pos
     pub scrollback_line_mappings: Vec<usize>,
-    // line id - scrollback_line_mappings.len()
+    // line id - first_visible_line_id
     pub visible_line_mappings: Vec<usize>,
+    // Formatting of each line, indexed the same way as the mappings
+    pub scrollback_formats: Vec<LineFormat>,
+    pub visible_formats: Vec<LineFormat>,
+    pub first_scrollback_line_id: usize,
+    pub first_visible_line_id: usize,
 }
 
 #[derive(Debug)]
@@ -349,14 +845,20 @@ pub struct TerminalData<T: std::fmt::Debug> {
     pub visible: T,
 }
 
+#[derive(Debug)]
+pub struct RenderData {
+    pub text: TerminalData<Vec<u8>>,
+    pub formats: TerminalData<Vec<FormatTagSerialized>>,
+    // Byte ranges of the selection in text
+    pub selection: TerminalData<Option<Range<usize>>>,
+}
+
 #[derive(Debug, Error)]
 enum StartRecordingErrorPriv {
     #[error("failed to start recording")]
     Start(#[from] std::io::Error),
     #[error("failed to snapshot terminal buffer")]
     SnapshotBuffer(#[from] buffer::CreateSnapshotError),
-    #[error("failed to snapshot format tracker")]
-    SnapshotFormatTracker(#[from] format_tracker::SnapshotFormatTagError),
     #[error("failed to snapshot cursor")]
     SnapshotCursor(#[from] SnapshotCursorPosError),
 }
@@ -377,30 +879,105 @@ enum LoadSnapshotErrorPriv {
     BufferNotPresent,
     #[error("failed to load buffer")]
     LoadBuffer(#[from] buffer::LoadSnapshotError),
-    #[error("format tracker not present")]
-    FormatTrackerNotPresent,
-    #[error("failed to load format tracker")]
-    LoadFormatTracker(#[from] format_tracker::LoadFormatTrackerSnapshotError),
     #[error("decckm field not present")]
     DecckmNotPresent,
     #[error("decckm field not bool")]
     DecckmNotBool,
+    #[error("bracketed paste field not present")]
+    BracketedPasteNotPresent,
+    #[error("bracketed paste field not bool")]
+    BracketedPasteNotBool,
+    #[error("cursor_visible field not present")]
+    CursorVisibleNotPresent,
+    #[error("cursor_visible field not bool")]
+    CursorVisibleNotBool,
+    #[error("cursor_style field not present")]
+    CursorStyleNotPresent,
+    #[error("cursor_style field is not a valid DECSCUSR parameter")]
+    CursorStyleInvalid,
     #[error("cursor_state not present")]
     CursorStateNotPresent,
     #[error("failed to load cursor state")]
     LoadCursorState(#[from] LoadCursorStateError),
+    #[error("failed to load saved cursor state")]
+    LoadSavedCursor(#[source] LoadCursorStateError),
+    #[error("tab_stops not present")]
+    TabStopsNotPresent,
+    #[error("failed to load tab stops")]
+    LoadTabStops(#[from] tab_stops::LoadSnapshotError),
 }
 
 #[derive(Debug, Error)]
 #[error(transparent)]
 pub struct LoadSnapshotError(#[from] LoadSnapshotErrorPriv);
 
+/// Replies to terminal queries. These are queued while parsing output from the child and
+/// written back to it once the read finishes
+///
+/// * DSR 5 (`CSI 5 n`): `CSI 0 n`, terminal is ok
+/// * DSR 6 (`CSI 6 n`): `CSI row ; col R`, 1 indexed cursor position
+/// * DA1 (`CSI c`): `CSI ? 62 ; 22 c`, VT220 with ANSI color
+/// * DA2 (`CSI > c`): `CSI > 1 ; version ; 0 c`, version is major * 10000 + minor * 100 + patch
+/// * XTVERSION (`CSI > q`): `DCS > | termie version ST`
+/// * OSC 4/10/11/12 queries (`OSC 4 ; idx ; ? ST`): `OSC 4 ; idx ; rgb:rrrr/gggg/bbbb ST`, with the
+///   terminator of the query
+mod replies {
+    use super::{PaletteEntry, Rgb};
+
+    pub const STATUS_OK: &[u8] = b"\x1b[0n";
+    pub const PRIMARY_DEVICE_ATTRIBUTES: &[u8] = b"\x1b[?62;22c";
+
+    pub fn cursor_position(x: usize, y: usize) -> Vec<u8> {
+        format!("\x1b[{};{}R", y + 1, x + 1).into_bytes()
+    }
+
+    pub fn secondary_device_attributes() -> Vec<u8> {
+        let version_component = |s: &str| s.parse::<u32>().unwrap_or(0);
+        let version = version_component(env!("CARGO_PKG_VERSION_MAJOR")) * 10000
+            + version_component(env!("CARGO_PKG_VERSION_MINOR")) * 100
+            + version_component(env!("CARGO_PKG_VERSION_PATCH"));
+        format!("\x1b[>1;{version};0c").into_bytes()
+    }
+
+    pub fn terminal_version() -> Vec<u8> {
+        format!("\x1bP>|termie {}\x1b\\", env!("CARGO_PKG_VERSION")).into_bytes()
+    }
+
+    pub fn palette_entry(entry: PaletteEntry, color: Rgb, bel_terminated: bool) -> Vec<u8> {
+        let terminator = if bel_terminated { "\x07" } else { "\x1b\\" };
+        let color = color.to_xcolor();
+        let ret = match entry {
+            PaletteEntry::Indexed(idx) => format!("\x1b]4;{idx};{color}{terminator}"),
+            PaletteEntry::Foreground => format!("\x1b]10;{color}{terminator}"),
+            PaletteEntry::Background => format!("\x1b]11;{color}{terminator}"),
+            PaletteEntry::Cursor => format!("\x1b]12;{color}{terminator}"),
+        };
+        ret.into_bytes()
+    }
+}
+
 pub struct TerminalEmulator<Io: TermIo> {
     parser: AnsiParser,
     terminal_buffer: TerminalBuffer2,
-    format_tracker: FormatTracker,
     cursor_state: CursorState,
+    // DECSC
+    saved_cursor: Option<CursorState>,
+    tab_stops: TabStops,
     decckm_mode: bool,
+    bracketed_paste_mode: bool,
+    mouse_tracking: MouseTracking,
+    sgr_mouse_mode: bool,
+    // DECTCEM
+    cursor_visible: bool,
+    cursor_style: CursorStyle,
+    window_title: Option<String>,
+    palette: Palette,
+    // What OSC 104/110/111/112 reset to
+    default_palette: Palette,
+    selection: Option<Selection>,
+    // Replies to queries that have not been written to io yet
+    pending_replies: Vec<u8>,
+    exit_status: Option<ExitStatus>,
     recorder: Recorder,
     io: Io,
 }
@@ -409,27 +986,22 @@ pub const TERMINAL_WIDTH: usize = 50;
 pub const TERMINAL_HEIGHT: usize = 16;
 
 impl TerminalEmulator<PtyIo> {
-    pub fn new(recording_path: PathBuf) -> Result<TerminalEmulator<PtyIo>, CreatePtyIoError> {
-        let mut io = PtyIo::new()?;
+    pub fn new(
+        launch_spec: &LaunchSpec,
+        recording_path: PathBuf,
+    ) -> Result<TerminalEmulator<PtyIo>, CreatePtyIoError> {
+        let mut io = PtyIo::new(launch_spec)?;
 
         if let Err(e) = io.set_win_size(TERMINAL_WIDTH, TERMINAL_HEIGHT) {
             error!("Failed to set initial window size: {}", backtraced_err(&*e));
         }
 
-        let ret = TerminalEmulator {
-            parser: AnsiParser::new(),
-            terminal_buffer: TerminalBuffer2::new(TERMINAL_WIDTH, TERMINAL_HEIGHT),
-            format_tracker: FormatTracker::new(),
-            decckm_mode: false,
-            cursor_state: CursorState {
-                pos: CursorPos { x: 0, y: 0 },
-                bold: false,
-                color: TerminalColor::Default,
-            },
-            recorder: Recorder::new(recording_path),
-            io,
-        };
-        Ok(ret)
+        Ok(TerminalEmulator::new_with_io(io, recording_path))
+    }
+
+    /// Blocking reader of the child's output, see [`spawn_io_thread`]
+    pub fn reader(&self) -> Result<PtyReader, std::io::Error> {
+        self.io.reader()
     }
 }
 
@@ -446,25 +1018,59 @@ impl TerminalEmulator<ReplayIo> {
         let terminal_buffer =
             TerminalBuffer2::from_snapshot(root.remove("terminal_buffer").ok_or(BufferNotPresent)?)
                 .map_err(LoadBuffer)?;
-        let format_tracker = FormatTracker::from_snapshot(
-            root.remove("format_tracker")
-                .ok_or(FormatTrackerNotPresent)?,
-        )
-        .map_err(LoadFormatTracker)?;
         let SnapshotItem::Bool(decckm_mode) = root.remove("decckm_mode").ok_or(DecckmNotPresent)?
         else {
             Err(DecckmNotBool)?
         };
+        let SnapshotItem::Bool(bracketed_paste_mode) = root
+            .remove("bracketed_paste_mode")
+            .ok_or(BracketedPasteNotPresent)?
+        else {
+            Err(BracketedPasteNotBool)?
+        };
+        let SnapshotItem::Bool(cursor_visible) = root
+            .remove("cursor_visible")
+            .ok_or(CursorVisibleNotPresent)?
+        else {
+            Err(CursorVisibleNotBool)?
+        };
+        let cursor_style = root
+            .remove("cursor_style")
+            .ok_or(CursorStyleNotPresent)?
+            .into_num::<usize>()
+            .ok()
+            .and_then(CursorStyle::from_decscusr)
+            .ok_or(CursorStyleInvalid)?;
         let cursor_state =
             CursorState::from_snapshot(root.remove("cursor_state").ok_or(CursorStateNotPresent)?)
                 .map_err(LoadCursorState)?;
+        let saved_cursor = root
+            .remove("saved_cursor")
+            .map(CursorState::from_snapshot)
+            .transpose()
+            .map_err(LoadSavedCursor)?;
+        let tab_stops =
+            TabStops::from_snapshot(root.remove("tab_stops").ok_or(TabStopsNotPresent)?)
+                .map_err(LoadTabStops)?;
 
         Ok(TerminalEmulator {
             parser,
             terminal_buffer,
-            format_tracker,
             decckm_mode,
+            bracketed_paste_mode,
+            mouse_tracking: MouseTracking::Off,
+            sgr_mouse_mode: false,
+            cursor_visible,
+            cursor_style,
             cursor_state,
+            saved_cursor,
+            tab_stops,
+            window_title: None,
+            palette: Palette::default(),
+            default_palette: Palette::default(),
+            selection: None,
+            pending_replies: Vec::new(),
+            exit_status: None,
             recorder: Recorder::new("recordings".into()),
             io: io_handle,
         })
@@ -472,6 +1078,31 @@ impl TerminalEmulator<ReplayIo> {
 }
 
 impl<Io: TermIo> TerminalEmulator<Io> {
+    /// Emulator driven by something other than a child process, e.g. benchmarks
+    pub fn new_with_io(io: Io, recording_path: PathBuf) -> TerminalEmulator<Io> {
+        TerminalEmulator {
+            parser: AnsiParser::new(),
+            terminal_buffer: TerminalBuffer2::new(TERMINAL_WIDTH, TERMINAL_HEIGHT),
+            decckm_mode: false,
+            bracketed_paste_mode: false,
+            mouse_tracking: MouseTracking::Off,
+            sgr_mouse_mode: false,
+            cursor_visible: true,
+            cursor_style: CursorStyle::default(),
+            cursor_state: CursorState::new(),
+            saved_cursor: None,
+            tab_stops: TabStops::new(TERMINAL_WIDTH),
+            window_title: None,
+            palette: Palette::default(),
+            default_palette: Palette::default(),
+            selection: None,
+            pending_replies: Vec::new(),
+            exit_status: None,
+            recorder: Recorder::new(recording_path),
+            io,
+        }
+    }
+
     pub fn get_win_size(&self) -> (usize, usize) {
         self.terminal_buffer.get_win_size()
     }
@@ -481,67 +1112,180 @@ impl<Io: TermIo> TerminalEmulator<Io> {
         width_chars: usize,
         height_chars: usize,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let _visible_range = self.terminal_buffer.get_visible_range();
         let response =
             self.terminal_buffer
                 .set_win_size(width_chars, height_chars, &self.cursor_state.pos);
 
-        // Get old format
-        // Clear visible format data
-        // Re-insert new format data with resized window
         self.cursor_state.pos = response.new_cursor_pos;
+        if self.tab_stops.width() != width_chars {
+            self.tab_stops = TabStops::new(width_chars);
+        }
+        if let (Some(selection), Some(reflow)) = (&mut self.selection, &response.reflow) {
+            selection.apply_reflow(reflow);
+        }
 
         if response.changed {
             self.io.set_win_size(width_chars, height_chars)?;
             self.recorder.set_win_size(width_chars, height_chars);
-            // FIXME: Preserve coloring info
-            self.format_tracker
-                .push_range(&self.cursor_state, BufPos::new(0, 0)..BufPos::MAX);
         }
 
         Ok(())
     }
 
     pub fn write(&mut self, to_write: TerminalInput) -> Result<(), Box<dyn std::error::Error>> {
-        match to_write.to_payload(self.decckm_mode) {
+        let modes = InputModes {
+            decckm: self.decckm_mode,
+            bracketed_paste: self.bracketed_paste_mode,
+        };
+        match to_write.to_payload(&modes) {
             TerminalInputPayload::Single(c) => {
                 let mut written = 0;
                 while written == 0 {
                     written = self.io.write(&[c])?;
                 }
             }
-            TerminalInputPayload::Many(mut to_write) => {
-                while !to_write.is_empty() {
-                    let written = self.io.write(to_write)?;
-                    to_write = &to_write[written..];
+            TerminalInputPayload::Many(to_write) => self.write_all(&to_write)?,
+        };
+        Ok(())
+    }
+
+    /// Tells the child about a mouse event if it asked for mouse tracking
+    pub fn report_mouse(&mut self, event: &MouseEvent) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(report) = mouse::encode(event, self.mouse_tracking, self.sgr_mouse_mode) {
+            self.write_all(&report)?;
+        }
+        Ok(())
+    }
+
+    fn write_all(&mut self, mut to_write: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        while !to_write.is_empty() {
+            let written = self.io.write(to_write)?;
+            to_write = &to_write[written..];
+        }
+        Ok(())
+    }
+
+    fn enter_alternate_screen(&mut self, save_cursor: bool) {
+        if self.terminal_buffer.is_alternate_screen_active() {
+            return;
+        }
+
+        let saved_cursor = save_cursor.then(|| self.cursor_state.pos.clone());
+        self.terminal_buffer.enter_alternate_screen(saved_cursor);
+        self.selection = None;
+    }
+
+    fn exit_alternate_screen(&mut self) {
+        if self.terminal_buffer.is_alternate_screen_active() {
+            self.selection = None;
+        }
+        if let Some(saved_cursor) = self.terminal_buffer.exit_alternate_screen() {
+            self.cursor_state.pos = saved_cursor;
+        }
+    }
+
+    /// Where the cursor goes when homed, the top of the scroll region in origin mode
+    fn home(&self) -> CursorPos {
+        let y = match self.cursor_state.origin_mode {
+            true => self.terminal_buffer.scroll_region().start,
+            false => 0,
+        };
+        CursorPos { x: 0, y }
+    }
+
+    /// Goes back to the state from DECSC. With nothing saved the cursor is homed and attributes
+    /// are reset
+    fn restore_cursor(&mut self) {
+        self.cursor_state = self.saved_cursor.clone().unwrap_or_else(CursorState::new);
+        // The screen may have shrunk since
+        self.clamp_cursor();
+    }
+
+    /// Moves the cursor back onto the screen, or into the scroll region in origin mode
+    fn clamp_cursor(&mut self) {
+        let (width, height) = self.get_win_size();
+        let rows = match self.cursor_state.origin_mode {
+            true => self.terminal_buffer.scroll_region(),
+            false => 0..height,
+        };
+        let pos = &mut self.cursor_state.pos;
+        pos.x = pos.x.min(width.saturating_sub(1));
+        pos.y = pos.y.max(rows.start).min(rows.end.saturating_sub(1));
+    }
+
+    fn queue_reply(&mut self, reply: &[u8]) {
+        self.recorder.reply(reply);
+        self.pending_replies.extend_from_slice(reply);
+    }
+
+    fn flush_replies(&mut self) {
+        while !self.pending_replies.is_empty() {
+            match self.io.write(&self.pending_replies) {
+                // Try again on the next read
+                Ok(0) => break,
+                Ok(written) => {
+                    self.pending_replies.drain(..written);
+                }
+                Err(e) => {
+                    error!("Failed to write reply to child process: {e}");
+                    break;
                 }
             }
+        }
+    }
+
+    /// Drops the selection if the text in range was replaced
+    fn text_overwritten(&mut self, range: Range<BufPos>) {
+        let Some(selection) = &self.selection else {
+            return;
         };
-        Ok(())
+
+        let overlaps = selection
+            .range(&mut self.terminal_buffer)
+            .is_some_and(|selected| selected.start < range.end && range.start < selected.end);
+        if overlaps {
+            self.selection = None;
+        }
+    }
+
+    /// Lines moved within a scroll region keep their ids, so a selection on them would end up
+    /// on different text
+    fn region_scrolled(&mut self, region_scroll: Option<buffer::RegionScroll>) {
+        if let Some(region_scroll) = region_scroll {
+            let lines = region_scroll.lines;
+            self.text_overwritten(BufPos::new(0, lines.start)..BufPos::new(0, lines.end));
+        }
+    }
+
+    /// Erase and insert operations do not report what they touched, so assume the whole screen
+    fn screen_overwritten(&mut self) {
+        let first_visible_line_id = self.terminal_buffer.first_visible_line_id();
+        self.text_overwritten(BufPos::new(0, first_visible_line_id)..BufPos::MAX);
     }
 
     fn handle_incoming_data(&mut self, incoming: &[u8]) {
         let parsed = self.parser.push(incoming);
         for segment in parsed {
+            let format = CellFormat::from_cursor(&self.cursor_state);
+            let fill = format.blank();
             match segment {
                 TerminalOutput::Data(data) => {
-                    let response = self
-                        .terminal_buffer
-                        .insert_data(&self.cursor_state.pos, &data);
-                    // FIXME: Not complete
-                    //self.format_tracker
-                    //    .delete_range(response.visible_to_scrollback.0);
-                    self.format_tracker
-                        .push_range(&self.cursor_state, response.written_range);
+                    let data = self.cursor_state.charset.translate(&data);
+                    let response =
+                        self.terminal_buffer
+                            .insert_data(&self.cursor_state.pos, &data, &format);
                     self.cursor_state.pos = response.new_cursor_pos;
+                    self.text_overwritten(response.written_range);
+                    self.region_scrolled(response.region_scroll);
                 }
                 TerminalOutput::SetCursorPos { x, y } => {
                     if let Some(x) = x {
-                        self.cursor_state.pos.x = x - 1;
+                        self.cursor_state.pos.x = x.saturating_sub(1);
                     }
                     if let Some(y) = y {
-                        self.cursor_state.pos.y = y - 1;
+                        self.cursor_state.pos.y = self.home().y.saturating_add(y.saturating_sub(1));
                     }
+                    self.clamp_cursor();
                 }
                 TerminalOutput::SetCursorPosRel { x, y } => {
                     if let Some(x) = x {
@@ -564,36 +1308,72 @@ impl<Io: TermIo> TerminalEmulator<Io> {
                             .expect("y position larger than i64 can handle");
                         self.cursor_state.pos.y = (current_y + y).max(0) as usize;
                     }
+                    self.clamp_cursor();
                 }
                 TerminalOutput::ClearForwards => {
-                    if let Some(_buf_pos) =
-                        self.terminal_buffer.clear_forwards(&self.cursor_state.pos)
-                    {
-                        //self.format_tracker
-                        //    .push_range(&self.cursor_state, _buf_pos..usize::MAX);
-                    }
+                    self.terminal_buffer
+                        .clear_forwards(&self.cursor_state.pos, &fill);
+                    self.screen_overwritten();
+                }
+                TerminalOutput::ClearBackwards => {
+                    self.terminal_buffer
+                        .clear_backwards(&self.cursor_state.pos, &fill);
+                    self.screen_overwritten();
                 }
                 TerminalOutput::ClearAll => {
-                    self.format_tracker
-                        .push_range(&self.cursor_state, BufPos::new(0, 0)..BufPos::MAX);
-                    self.terminal_buffer.clear_all();
+                    self.terminal_buffer.clear_all(&fill);
+                    self.screen_overwritten();
+                }
+                TerminalOutput::ClearScrollback => {
+                    self.terminal_buffer.clear_scrollback();
                 }
                 TerminalOutput::ClearLineForwards => {
-                    if let Some(_range) = self
-                        .terminal_buffer
-                        .clear_line_forwards(&self.cursor_state.pos)
-                    {
-                        //self.format_tracker.delete_range(_range);
-                    }
+                    self.terminal_buffer
+                        .clear_line_forwards(&self.cursor_state.pos, &fill);
+                    self.screen_overwritten();
+                }
+                TerminalOutput::ClearLineBackwards => {
+                    self.terminal_buffer
+                        .clear_line_backwards(&self.cursor_state.pos, &fill);
+                    self.screen_overwritten();
+                }
+                TerminalOutput::ClearLine => {
+                    self.terminal_buffer
+                        .clear_line(&self.cursor_state.pos, &fill);
+                    self.screen_overwritten();
+                }
+                TerminalOutput::EraseChars(num_chars) => {
+                    self.terminal_buffer
+                        .erase_chars(&self.cursor_state.pos, num_chars, &fill);
+                    self.screen_overwritten();
                 }
                 TerminalOutput::CarriageReturn => {
                     self.cursor_state.pos.x = 0;
                 }
                 TerminalOutput::Newline => {
-                    self.cursor_state.pos = self
+                    let response =
+                        self.terminal_buffer
+                            .insert_data(&self.cursor_state.pos, b"\n", &format);
+                    self.cursor_state.pos = response.new_cursor_pos;
+                    self.region_scrolled(response.region_scroll);
+                }
+                TerminalOutput::Index => {
+                    let response = self.terminal_buffer.index(&self.cursor_state.pos, &fill);
+                    self.cursor_state.pos = response.new_cursor_pos;
+                    self.region_scrolled(response.region_scroll);
+                }
+                TerminalOutput::NextLine => {
+                    let response = self.terminal_buffer.index(&self.cursor_state.pos, &fill);
+                    self.cursor_state.pos = response.new_cursor_pos;
+                    self.cursor_state.pos.x = 0;
+                    self.region_scrolled(response.region_scroll);
+                }
+                TerminalOutput::ReverseIndex => {
+                    let response = self
                         .terminal_buffer
-                        .insert_data(&self.cursor_state.pos, b"\n")
-                        .new_cursor_pos;
+                        .reverse_index(&self.cursor_state.pos, &fill);
+                    self.cursor_state.pos = response.new_cursor_pos;
+                    self.region_scrolled(response.region_scroll);
                 }
                 TerminalOutput::Backspace => {
                     if self.cursor_state.pos.x >= 1 {
@@ -601,79 +1381,231 @@ impl<Io: TermIo> TerminalEmulator<Io> {
                     }
                 }
                 TerminalOutput::InsertLines(num_lines) => {
-                    let _response = self
-                        .terminal_buffer
-                        .insert_lines(&self.cursor_state.pos, num_lines);
-                    //self.format_tracker.delete_range(_response.deleted_range);
-                    //self.format_tracker
-                    //    .push_range_adjustment(_response.inserted_range);
+                    let region_scroll =
+                        self.terminal_buffer
+                            .insert_lines(&self.cursor_state.pos, num_lines, &fill);
+                    self.region_scrolled(region_scroll);
+                }
+                TerminalOutput::DeleteLines(num_lines) => {
+                    let region_scroll =
+                        self.terminal_buffer
+                            .delete_lines(&self.cursor_state.pos, num_lines, &fill);
+                    self.region_scrolled(region_scroll);
+                }
+                TerminalOutput::ScrollUp(num_lines) => {
+                    let region_scroll = self.terminal_buffer.scroll_up(num_lines, &fill);
+                    self.region_scrolled(region_scroll);
+                }
+                TerminalOutput::ScrollDown(num_lines) => {
+                    let region_scroll = self.terminal_buffer.scroll_down(num_lines, &fill);
+                    self.region_scrolled(Some(region_scroll));
+                }
+                TerminalOutput::SetScrollRegion { top, bottom } => {
+                    let (_, height) = self.get_win_size();
+                    let top = top.unwrap_or(1).max(1) - 1;
+                    let bottom = bottom.unwrap_or(height);
+                    if self.terminal_buffer.set_scroll_region(top, bottom) {
+                        // DECSTBM homes the cursor
+                        self.cursor_state.pos = self.home();
+                    } else {
+                        warn!("Invalid scroll region {top}..{bottom}");
+                    }
                 }
                 TerminalOutput::Delete(num_chars) => {
-                    let _deleted_buf_range = self
-                        .terminal_buffer
-                        .delete_forwards(&self.cursor_state.pos, num_chars);
-                    //if let Some(range) = _deleted_buf_range {
-                    //    self.format_tracker.delete_range(range);
-                    //}
+                    self.terminal_buffer
+                        .delete_forwards(&self.cursor_state.pos, num_chars, &fill);
+                    self.screen_overwritten();
                 }
                 TerminalOutput::Sgr(sgr) => {
-                    // Should this be one big match ???????
                     if let Some(color) = TerminalColor::from_sgr(sgr) {
                         self.cursor_state.color = color;
-                    } else if sgr == SelectGraphicRendition::Reset {
-                        self.cursor_state.color = TerminalColor::Default;
-                        self.cursor_state.bold = false;
-                    } else if sgr == SelectGraphicRendition::Bold {
-                        self.cursor_state.bold = true;
-                    } else {
-                        warn!("Unhandled sgr: {:?}", sgr);
+                        continue;
+                    }
+
+                    if let Some(color) = TerminalColor::background_from_sgr(sgr) {
+                        self.cursor_state.background_color = color;
+                        continue;
+                    }
+
+                    match sgr {
+                        SelectGraphicRendition::Reset => self.cursor_state.reset_sgr(),
+                        SelectGraphicRendition::Bold => self.cursor_state.bold = true,
+                        SelectGraphicRendition::Dim => self.cursor_state.dim = true,
+                        SelectGraphicRendition::Italic => self.cursor_state.italic = true,
+                        SelectGraphicRendition::Underline => self.cursor_state.underline = true,
+                        SelectGraphicRendition::Reverse => self.cursor_state.reverse = true,
+                        SelectGraphicRendition::Strikethrough => {
+                            self.cursor_state.strikethrough = true
+                        }
+                        // SGR 22 turns off both bold and faint
+                        SelectGraphicRendition::NormalIntensity => {
+                            self.cursor_state.bold = false;
+                            self.cursor_state.dim = false;
+                        }
+                        SelectGraphicRendition::NotItalic => self.cursor_state.italic = false,
+                        SelectGraphicRendition::NotUnderlined => {
+                            self.cursor_state.underline = false
+                        }
+                        SelectGraphicRendition::NotReversed => self.cursor_state.reverse = false,
+                        SelectGraphicRendition::NotStrikethrough => {
+                            self.cursor_state.strikethrough = false
+                        }
+                        _ => {
+                            warn!("Unhandled sgr: {:?}", sgr);
+                        }
                     }
                 }
                 TerminalOutput::SetMode(mode) => match mode {
                     Mode::Decckm => {
                         self.decckm_mode = true;
                     }
+                    Mode::AlternateScreen => self.enter_alternate_screen(false),
+                    Mode::AlternateScreenSaveCursor => self.enter_alternate_screen(true),
+                    Mode::Decom => {
+                        self.cursor_state.origin_mode = true;
+                        self.cursor_state.pos = self.home();
+                    }
+                    Mode::Decawm => self.terminal_buffer.set_autowrap(true),
+                    Mode::Dectcem => self.cursor_visible = true,
+                    Mode::MouseClickTracking => self.mouse_tracking = MouseTracking::Click,
+                    Mode::MouseDragTracking => self.mouse_tracking = MouseTracking::Drag,
+                    Mode::MouseMotionTracking => self.mouse_tracking = MouseTracking::Motion,
+                    Mode::SgrMouse => self.sgr_mouse_mode = true,
+                    Mode::BracketedPaste => self.bracketed_paste_mode = true,
                     _ => {
                         warn!("unhandled set mode: {mode:?}");
                     }
                 },
                 TerminalOutput::InsertSpaces(num_spaces) => {
-                    let _response = self
-                        .terminal_buffer
-                        .insert_spaces(&self.cursor_state.pos, num_spaces);
-                    //self.format_tracker
-                    //    .push_range_adjustment(_response.insertion_range);
+                    self.terminal_buffer
+                        .insert_spaces(&self.cursor_state.pos, num_spaces, &fill);
+                    self.screen_overwritten();
                 }
                 TerminalOutput::ResetMode(mode) => match mode {
                     Mode::Decckm => {
                         self.decckm_mode = false;
                     }
+                    Mode::AlternateScreen | Mode::AlternateScreenSaveCursor => {
+                        self.exit_alternate_screen()
+                    }
+                    Mode::MouseClickTracking
+                    | Mode::MouseDragTracking
+                    | Mode::MouseMotionTracking => self.mouse_tracking = MouseTracking::Off,
+                    Mode::SgrMouse => self.sgr_mouse_mode = false,
+                    Mode::Decom => {
+                        self.cursor_state.origin_mode = false;
+                        self.cursor_state.pos = self.home();
+                    }
+                    Mode::Decawm => self.terminal_buffer.set_autowrap(false),
+                    Mode::Dectcem => self.cursor_visible = false,
+                    Mode::BracketedPaste => self.bracketed_paste_mode = false,
                     _ => {
                         warn!("unhandled set mode: {mode:?}");
                     }
                 },
+                TerminalOutput::ForwardTab(num_stops) => {
+                    self.cursor_state.pos.x =
+                        self.tab_stops.next(self.cursor_state.pos.x, num_stops);
+                }
+                TerminalOutput::BackwardTab(num_stops) => {
+                    self.cursor_state.pos.x =
+                        self.tab_stops.prev(self.cursor_state.pos.x, num_stops);
+                }
+                TerminalOutput::SaveCursor => self.saved_cursor = Some(self.cursor_state.clone()),
+                TerminalOutput::RestoreCursor => self.restore_cursor(),
+                TerminalOutput::SetTabStop => self.tab_stops.set(self.cursor_state.pos.x),
+                TerminalOutput::ClearTabStop => self.tab_stops.clear(self.cursor_state.pos.x),
+                TerminalOutput::ClearAllTabStops => self.tab_stops.clear_all(),
+                TerminalOutput::DesignateCharset(slot, charset) => {
+                    self.cursor_state.charset.designate(slot, charset)
+                }
+                TerminalOutput::ShiftOut => self.cursor_state.charset.shift_out(),
+                TerminalOutput::ShiftIn => self.cursor_state.charset.shift_in(),
+                TerminalOutput::SetCursorStyle(style) => self.cursor_style = style,
+                // The GUI cannot tell keypad keys from the rest of the keyboard, so they always
+                // send what the main keys do
+                TerminalOutput::ApplicationKeypad | TerminalOutput::NormalKeypad => {}
+                TerminalOutput::RequestStatusReport => {
+                    self.queue_reply(replies::STATUS_OK);
+                }
+                TerminalOutput::RequestCursorPosition => {
+                    let pos = &self.cursor_state.pos;
+                    let y = pos.y - self.home().y.min(pos.y);
+                    self.queue_reply(&replies::cursor_position(pos.x, y));
+                }
+                TerminalOutput::RequestPrimaryDeviceAttributes => {
+                    self.queue_reply(replies::PRIMARY_DEVICE_ATTRIBUTES);
+                }
+                TerminalOutput::RequestSecondaryDeviceAttributes => {
+                    self.queue_reply(&replies::secondary_device_attributes());
+                }
+                TerminalOutput::RequestTerminalVersion => {
+                    self.queue_reply(&replies::terminal_version());
+                }
+                TerminalOutput::SetWindowTitle(title) => {
+                    self.window_title = Some(title);
+                }
+                // There is no icon to show it on, the window title is used instead
+                TerminalOutput::SetIconName(_) => {}
+                TerminalOutput::SetPaletteEntry(entry, color) => {
+                    *self.palette.entry_mut(entry) = color;
+                }
+                TerminalOutput::QueryPaletteEntry {
+                    entry,
+                    bel_terminated,
+                } => {
+                    let color = self.palette.entry(entry);
+                    self.queue_reply(&replies::palette_entry(entry, color, bel_terminated));
+                }
+                TerminalOutput::ResetPaletteEntry(Some(entry)) => {
+                    *self.palette.entry_mut(entry) = self.default_palette.entry(entry);
+                }
+                TerminalOutput::ResetPaletteEntry(None) => {
+                    self.palette.indexed = self.default_palette.indexed;
+                }
                 TerminalOutput::Invalid => {}
             }
         }
     }
 
-    pub fn read(&mut self) {
-        let mut buf = vec![0u8; 4096];
-        loop {
-            let read_size = match self.io.read(&mut buf) {
-                Ok(ReadResponse::Empty) => break,
-                Ok(ReadResponse::Success(v)) => v,
-                Err(e) => {
-                    error!("Failed to read from child process: {e}");
-                    break;
-                }
-            };
+    /// Replaces the palette, including any colors changed by the child
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.default_palette = palette.clone();
+        self.palette = palette;
+    }
 
-            let incoming = &buf[0..read_size];
-            debug!("Incoming data: {:?}", std::str::from_utf8(incoming));
-            self.recorder.write(incoming);
-            self.handle_incoming_data(incoming);
-        }
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    pub fn mouse_tracking(&self) -> MouseTracking {
+        self.mouse_tracking
+    }
+
+    /// Whether the cursor should be drawn, the child hides it with DECRST 25
+    pub fn cursor_visible(&self) -> bool {
+        self.cursor_visible
+    }
+
+    pub fn cursor_style(&self) -> CursorStyle {
+        self.cursor_style
+    }
+
+    /// Whether the child has asked for pastes to be wrapped in markers (DECSET 2004)
+    pub fn bracketed_paste_mode(&self) -> bool {
+        self.bracketed_paste_mode
+    }
+
+    pub fn set_scrollback_limit(&mut self, limit: ScrollbackLimit) {
+        self.terminal_buffer.set_scrollback_limit(limit);
+    }
+
+    /// Handles output that was read from a [`TermReader`]
+    pub fn process_output(&mut self, incoming: &[u8]) {
+        debug!("Incoming data: {:?}", std::str::from_utf8(incoming));
+        self.recorder.write(incoming);
+        self.handle_incoming_data(incoming);
+        self.flush_replies();
     }
 
     // FIXME: no mut
@@ -686,107 +1618,58 @@ impl<Io: TermIo> TerminalEmulator<Io> {
     }
 
     // FIXME: no mut
-    #[allow(unused)]
     pub fn format_data(&mut self) -> TerminalData<Vec<FormatTagSerialized>> {
-        let (width, height) = self.get_win_size();
-        let mut output_tags = Vec::new();
-        let mut scrollback_tags = Vec::new();
-        // FIXME: serializing twice just to get format data
+        self.render_data().formats
+    }
+
+    // FIXME: no mut
+    /// Text, formats and selection of the buffer, all taken from a single serialization
+    pub fn render_data(&mut self) -> RenderData {
+        let range = self
+            .selection
+            .as_ref()
+            .and_then(|selection| selection.range(&mut self.terminal_buffer));
         let data = self.terminal_buffer.data();
 
-        #[derive(Eq, PartialEq, Ord, PartialOrd)]
-        enum SerializedPos {
-            Scrollback(usize),
-            Visible(usize),
-        }
-
-        let map_input_to_output = |idx: BufPos| -> SerializedPos {
-            let num_scrollback_lines = data.scrollback_line_mappings.len();
-            let num_visible_lines = data.visible_line_mappings.len();
-            if idx.line_id < num_scrollback_lines {
-                let ret = data.scrollback_line_mappings[idx.line_id] + idx.x_pos;
-                let max = data
-                    .scrollback_line_mappings
-                    .get(idx.line_id + 1)
-                    .cloned()
-                    .unwrap_or(data.scrollback.len());
-                SerializedPos::Scrollback(ret.min(max))
-            } else if idx.line_id < num_scrollback_lines + num_visible_lines {
-                let ret =
-                    data.visible_line_mappings[idx.line_id - num_scrollback_lines] + idx.x_pos;
-                let max = data
-                    .visible_line_mappings
-                    .get(idx.line_id - num_scrollback_lines + 1)
-                    .cloned()
-                    .unwrap_or(data.visible.len());
-                SerializedPos::Visible(ret.min(max))
-            } else if idx == BufPos::MAX {
-                //
-                SerializedPos::Visible(usize::MAX)
-            } else {
-                // FIXME: is this right?
-                SerializedPos::Visible(0)
-            }
+        let formats = TerminalData {
+            scrollback: serialize_line_formats(
+                &data.scrollback,
+                &data.scrollback_line_mappings,
+                &data.scrollback_formats,
+            ),
+            visible: serialize_line_formats(
+                &data.visible,
+                &data.visible_line_mappings,
+                &data.visible_formats,
+            ),
         };
 
-        let input_tags = self.format_tracker.tags();
-        debug!("input_tags: {:?}", input_tags);
-        for input_tag in input_tags {
-            let start = map_input_to_output(input_tag.start);
-            let end = map_input_to_output(input_tag.end);
-
-            assert!(start <= end);
-            match (start, end) {
-                (SerializedPos::Scrollback(start), SerializedPos::Scrollback(end)) => {
-                    let output_tag = FormatTagSerialized {
-                        start,
-                        end,
-                        bold: input_tag.bold,
-                        color: input_tag.color,
-                    };
-
-                    assert!(start <= end);
-                    scrollback_tags.push(output_tag);
-                }
-                (SerializedPos::Visible(start), SerializedPos::Visible(end)) => {
-                    let output_tag = FormatTagSerialized {
-                        start,
-                        end,
-                        bold: input_tag.bold,
-                        color: input_tag.color,
-                    };
-                    assert!(start <= end);
-
-                    output_tags.push(output_tag);
-                }
-                (SerializedPos::Scrollback(start), SerializedPos::Visible(end)) => {
-                    let scrollback_tag = FormatTagSerialized {
-                        start,
-                        end: data.scrollback.len(),
-                        bold: input_tag.bold,
-                        color: input_tag.color,
-                    };
-
-                    scrollback_tags.push(scrollback_tag);
-
-                    let visible_tag = FormatTagSerialized {
-                        start: 0,
-                        end,
-                        bold: input_tag.bold,
-                        color: input_tag.color,
-                    };
-                    output_tags.push(visible_tag);
-                }
-                (SerializedPos::Visible(_), SerializedPos::Scrollback(_)) => {
-                    panic!("Backwards range");
-                }
-            }
-        }
+        let byte_range = |data: &[u8], line_mappings: &[usize], first_line_id: usize| {
+            let range = range.as_ref()?;
+            let start = buf_pos_to_byte_offset(data, line_mappings, first_line_id, &range.start);
+            let end = buf_pos_to_byte_offset(data, line_mappings, first_line_id, &range.end);
+            (start < end).then_some(start..end)
+        };
+        let selection = TerminalData {
+            scrollback: byte_range(
+                &data.scrollback,
+                &data.scrollback_line_mappings,
+                data.first_scrollback_line_id,
+            ),
+            visible: byte_range(
+                &data.visible,
+                &data.visible_line_mappings,
+                data.first_visible_line_id,
+            ),
+        };
 
-        debug!("output_tags: {:?}", output_tags);
-        TerminalData {
-            scrollback: scrollback_tags,
-            visible: output_tags,
+        RenderData {
+            text: TerminalData {
+                scrollback: data.scrollback,
+                visible: data.visible,
+            },
+            formats,
+            selection,
         }
     }
 
@@ -794,6 +1677,65 @@ impl<Io: TermIo> TerminalEmulator<Io> {
         self.cursor_state.pos.clone()
     }
 
+    /// Position of the cell at pos on screen
+    pub fn buf_pos_on_screen(&self, pos: &CursorPos) -> BufPos {
+        let (_, height) = self.get_win_size();
+        let y = pos.y.min(height.saturating_sub(1));
+        BufPos::new(pos.x, self.terminal_buffer.first_visible_line_id() + y)
+    }
+
+    /// Position of the cell at column x of a scrollback row, rows counting from the oldest line.
+    /// Clamped to the scrollback that is shown
+    pub fn buf_pos_in_scrollback(&self, x: usize, row: usize) -> BufPos {
+        let first_line_id = self.terminal_buffer.first_line_id();
+        let last_line_id = self
+            .terminal_buffer
+            .first_visible_line_id()
+            .saturating_sub(1)
+            .max(first_line_id);
+        BufPos::new(x, (first_line_id + row).min(last_line_id))
+    }
+
+    pub fn start_selection(&mut self, pos: BufPos, mode: SelectionMode) {
+        self.selection = Some(Selection::new(pos, mode));
+    }
+
+    pub fn extend_selection(&mut self, pos: BufPos) {
+        if let Some(selection) = &mut self.selection {
+            selection.extend(pos);
+        }
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.selection = None;
+    }
+
+    /// Selected text, with soft-wrapped lines joined
+    pub fn selected_text(&mut self) -> Option<String> {
+        let range = self.selection.as_ref()?.range(&mut self.terminal_buffer)?;
+        Some(self.terminal_buffer.text(range))
+    }
+
+    // FIXME: no mut
+    /// Byte ranges of the selection in the output of [`Self::data`]
+    pub fn selection_data(&mut self) -> TerminalData<Option<Range<usize>>> {
+        self.render_data().selection
+    }
+
+    /// Window title as last set by the application via OSC 0 or OSC 2
+    pub fn title(&self) -> Option<&str> {
+        self.window_title.as_deref()
+    }
+
+    /// How the child process exited, None while it is still running
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        self.exit_status
+    }
+
+    fn child_exited(&mut self, status: ExitStatus) {
+        self.exit_status = Some(status);
+    }
+
     pub fn start_recording(&mut self) -> Result<RecordingHandle, StartRecordingError> {
         use StartRecordingErrorPriv::*;
 
@@ -805,17 +1747,27 @@ impl<Io: TermIo> TerminalEmulator<Io> {
                     "terminal_buffer".to_string(),
                     self.terminal_buffer.snapshot().map_err(SnapshotBuffer)?,
                 );
+                initializer.snapshot_item("decckm_mode".to_string(), self.decckm_mode.into());
                 initializer.snapshot_item(
-                    "format_tracker".to_string(),
-                    self.format_tracker
-                        .snapshot()
-                        .map_err(SnapshotFormatTracker)?,
+                    "bracketed_paste_mode".to_string(),
+                    self.bracketed_paste_mode.into(),
+                );
+                initializer.snapshot_item("cursor_visible".to_string(), self.cursor_visible.into());
+                initializer.snapshot_item(
+                    "cursor_style".to_string(),
+                    self.cursor_style.decscusr().into(),
                 );
-                initializer.snapshot_item("decckm_mode".to_string(), self.decckm_mode.into());
                 initializer.snapshot_item(
                     "cursor_state".to_string(),
                     self.cursor_state.snapshot().map_err(SnapshotCursor)?,
                 );
+                if let Some(saved_cursor) = &self.saved_cursor {
+                    initializer.snapshot_item(
+                        "saved_cursor".to_string(),
+                        saved_cursor.snapshot().map_err(SnapshotCursor)?,
+                    );
+                }
+                initializer.snapshot_item("tab_stops".to_string(), self.tab_stops.snapshot());
                 Ok(initializer.into_handle())
             }
             StartRecordingResponse::Existing(handle) => Ok(handle),
@@ -827,16 +1779,480 @@ impl<Io: TermIo> TerminalEmulator<Io> {
 mod test {
     use super::*;
 
+    struct TestIo {
+        written: Vec<u8>,
+    }
+
+    impl TermIo for TestIo {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, io::TermIoErr> {
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn set_win_size(&mut self, _width: usize, _height: usize) -> Result<(), io::TermIoErr> {
+            Ok(())
+        }
+    }
+
+    fn test_emulator(to_read: &[u8]) -> TerminalEmulator<TestIo> {
+        let io = TestIo {
+            written: Vec::new(),
+        };
+        let mut emulator = TerminalEmulator::new_with_io(io, "recordings".into());
+        emulator.process_output(to_read);
+        emulator
+    }
+
+    #[test]
+    fn test_multibyte_format_data() {
+        let mut emulator = test_emulator("é\x1b[31mü\x1b[0mx".as_bytes());
+        assert_eq!(emulator.cursor_pos(), CursorPos { x: 3, y: 0 });
+
+        let data = emulator.data();
+        let format_data = emulator.format_data();
+        let red_tag = format_data
+            .visible
+            .iter()
+            .find(|tag| tag.color == TerminalColor::Red)
+            .expect("no red tag");
+        assert_eq!(&data.visible[red_tag.start..red_tag.end], "ü".as_bytes());
+    }
+
+    #[test]
+    fn test_wide_char_format_data() {
+        let mut emulator = test_emulator("中\x1b[31mü\x1b[0m\x1b[1;5Hx".as_bytes());
+        assert_eq!(emulator.cursor_pos(), CursorPos { x: 5, y: 0 });
+
+        let data = emulator.data();
+        assert_eq!(data.visible, "中ü x\n".as_bytes());
+        let format_data = emulator.format_data();
+        let red_tag = format_data
+            .visible
+            .iter()
+            .find(|tag| tag.color == TerminalColor::Red)
+            .expect("no red tag");
+        assert_eq!(&data.visible[red_tag.start..red_tag.end], "ü".as_bytes());
+    }
+
+    #[test]
+    fn test_scrollback_eviction_prunes_formatting() {
+        let mut emulator =
+            test_emulator(b"\x1b[31ma\r\n\x1b[32mb\r\n\x1b[33mc\r\n\x1b[34md\r\n\x1b[0me");
+        emulator
+            .set_win_size(10, 2)
+            .expect("failed to set win size");
+        emulator.set_scrollback_limit(ScrollbackLimit {
+            max_lines: Some(1),
+            max_bytes: None,
+        });
+
+        let data = emulator.data();
+        assert_eq!(data.scrollback, b"c\n");
+        assert_eq!(data.visible, b"d\ne\n");
+
+        // Formatting of the evicted red and green lines goes with them
+        let format_data = emulator.format_data();
+        assert!(!format_data
+            .scrollback
+            .iter()
+            .chain(&format_data.visible)
+            .any(|tag| matches!(tag.color, TerminalColor::Red | TerminalColor::Green)));
+        let yellow_tag = format_data
+            .scrollback
+            .iter()
+            .find(|tag| tag.color == TerminalColor::Yellow)
+            .expect("no yellow tag");
+        assert_eq!(&data.scrollback[yellow_tag.start..yellow_tag.end], b"c");
+
+        // ED 3 drops the scrollback but leaves the screen alone
+        emulator.handle_incoming_data(b"\x1b[3J");
+        let data = emulator.data();
+        assert_eq!(data.scrollback, b"");
+        assert_eq!(data.visible, b"d\ne\n");
+        let format_data = emulator.format_data();
+        assert!(format_data.scrollback.is_empty());
+        assert!(!format_data
+            .visible
+            .iter()
+            .any(|tag| tag.color == TerminalColor::Yellow));
+    }
+
+    #[test]
+    fn test_resize_keeps_formatting() {
+        let mut emulator = test_emulator(b"\x1b[31m0123456789abcde\x1b[0m\r\n$ ");
+        emulator
+            .set_win_size(10, 4)
+            .expect("failed to set win size");
+        assert_eq!(emulator.cursor_pos(), CursorPos { x: 2, y: 2 });
+
+        emulator.set_win_size(5, 4).expect("failed to set win size");
+        assert_eq!(emulator.cursor_pos(), CursorPos { x: 2, y: 3 });
+
+        let data = emulator.data();
+        assert_eq!(data.visible, b"0123456789abcde\n$ \n");
+        let format_data = emulator.format_data();
+        let red_tag = format_data
+            .visible
+            .iter()
+            .find(|tag| tag.color == TerminalColor::Red)
+            .expect("no red tag");
+        assert_eq!(
+            &data.visible[red_tag.start..red_tag.end],
+            b"0123456789abcde"
+        );
+    }
+
+    #[test]
+    fn test_device_replies() {
+        let emulator = test_emulator(b"ab\x1b[6n\x1b[5n\x1b[c\x1b[>c");
+        assert_eq!(
+            emulator.io.written,
+            b"\x1b[1;3R\x1b[0n\x1b[?62;22c\x1b[>1;100;0c"
+        );
+        assert!(emulator.pending_replies.is_empty());
+
+        let emulator = test_emulator(b"\x1b[>q");
+        assert_eq!(
+            emulator.io.written,
+            format!("\x1bP>|termie {}\x1b\\", env!("CARGO_PKG_VERSION")).as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_selection() {
+        let mut emulator = test_emulator(b"");
+        emulator
+            .set_win_size(10, 2)
+            .expect("failed to set win size");
+        emulator.process_output(b"one\r\ntwo");
+        emulator.start_selection(
+            emulator.buf_pos_on_screen(&CursorPos { x: 0, y: 1 }),
+            SelectionMode::Line,
+        );
+        assert_eq!(emulator.selected_text().as_deref(), Some("two"));
+
+        // Stays on its text as it scrolls into scrollback
+        emulator.process_output(b"\r\nthree\r\nfour");
+        assert_eq!(emulator.selected_text().as_deref(), Some("two"));
+        let selection_data = emulator.selection_data();
+        assert_eq!(selection_data.scrollback, Some(4..8));
+        assert_eq!(selection_data.visible, None);
+        assert_eq!(emulator.buf_pos_in_scrollback(0, 1), BufPos::new(0, 1));
+
+        emulator.start_selection(
+            emulator.buf_pos_on_screen(&CursorPos { x: 1, y: 1 }),
+            SelectionMode::Cell,
+        );
+        emulator.extend_selection(emulator.buf_pos_on_screen(&CursorPos { x: 4, y: 1 }));
+        assert_eq!(emulator.selection_data().visible, Some(7..10));
+        assert_eq!(emulator.selected_text().as_deref(), Some("our"));
+
+        // Replacing the selected text drops the selection
+        emulator.process_output(b"\rFOUR");
+        assert_eq!(emulator.selected_text(), None);
+    }
+
+    #[test]
+    fn test_key_encoding() {
+        let encode = |input: TerminalInput, modes: &InputModes| match input.to_payload(modes) {
+            TerminalInputPayload::Single(c) => vec![c],
+            TerminalInputPayload::Many(bytes) => bytes.into_owned(),
+        };
+        let normal = InputModes::default();
+        let application = InputModes {
+            decckm: true,
+            bracketed_paste: false,
+        };
+        let ctrl = InputModifiers {
+            ctrl: true,
+            ..Default::default()
+        };
+        let shift = InputModifiers {
+            shift: true,
+            ..Default::default()
+        };
+        let alt = InputModifiers {
+            alt: true,
+            ..Default::default()
+        };
+
+        assert_eq!(encode(TerminalInput::Tab, &normal), b"\t");
+        assert_eq!(encode(TerminalInput::Escape, &normal), b"\x1b");
+        assert_eq!(encode(TerminalInput::Ctrl(b' '), &normal), b"\0");
+        assert_eq!(encode(TerminalInput::Ctrl(b'?'), &normal), b"\x7f");
+        assert_eq!(encode(TerminalInput::Function(1), &normal), b"\x1bOP");
+        assert_eq!(encode(TerminalInput::Function(5), &normal), b"\x1b[15~");
+        assert_eq!(encode(TerminalInput::Function(6), &normal), b"\x1b[17~");
+        assert_eq!(encode(TerminalInput::Function(12), &normal), b"\x1b[24~");
+
+        // Modified keys ignore DECCKM
+        let ctrl_up = TerminalInput::ArrowUp.with_modifiers(ctrl);
+        assert_eq!(encode(ctrl_up, &application), b"\x1b[1;5A");
+        let shift_f1 = TerminalInput::Function(1).with_modifiers(shift);
+        assert_eq!(encode(shift_f1, &normal), b"\x1b[1;2P");
+        let alt_delete = TerminalInput::Delete.with_modifiers(alt);
+        assert_eq!(encode(alt_delete, &normal), b"\x1b[3;3~");
+        assert_eq!(
+            encode(TerminalInput::Tab.with_modifiers(shift), &normal),
+            b"\x1b[Z"
+        );
+        assert_eq!(
+            encode(TerminalInput::Ascii(b'b').with_modifiers(alt), &normal),
+            b"\x1bb"
+        );
+        assert_eq!(
+            encode(TerminalInput::Ctrl(b'a').with_modifiers(alt), &normal),
+            b"\x1b\x01"
+        );
+        assert!(matches!(
+            TerminalInput::Enter.with_modifiers(InputModifiers::default()),
+            TerminalInput::Enter
+        ));
+    }
+
+    #[test]
+    fn test_paste() {
+        let mut emulator = test_emulator(b"");
+        emulator
+            .write(TerminalInput::Paste("ls\nrm".to_string()))
+            .expect("failed to write");
+        assert_eq!(emulator.io.written, b"ls\nrm");
+
+        // End markers in the pasted text cannot end the paste early
+        emulator.io.written.clear();
+        emulator.handle_incoming_data(b"\x1b[?2004h");
+        assert!(emulator.bracketed_paste_mode());
+        emulator
+            .write(TerminalInput::Paste("a\x1b[201~b".to_string()))
+            .expect("failed to write");
+        assert_eq!(emulator.io.written, b"\x1b[200~ab\x1b[201~");
+
+        emulator.handle_incoming_data(b"\x1b[?2004l");
+        assert!(!emulator.bracketed_paste_mode());
+    }
+
+    #[test]
+    fn test_save_restore_cursor() {
+        let mut emulator = test_emulator(b"");
+        emulator.process_output(b"\x1b[3;5H\x1b[1;31m\x1b7\x1b[0m\x1b[H\x1b8x");
+        assert_eq!(emulator.cursor_pos(), CursorPos { x: 5, y: 2 });
+        assert!(emulator.cursor_state.bold);
+        assert_eq!(emulator.cursor_state.color, TerminalColor::Red);
+
+        // Restoring a position that is now off screen stays on screen
+        emulator.process_output(b"\x1b[10;40H\x1b[s");
+        emulator
+            .set_win_size(20, 5)
+            .expect("failed to set win size");
+        emulator.process_output(b"\x1b[u");
+        assert_eq!(emulator.cursor_pos(), CursorPos { x: 19, y: 4 });
+    }
+
+    #[test]
+    fn test_origin_mode() {
+        let mut emulator = test_emulator(b"\x1b[3;6r\x1b[?6h\x1b[2;4H\x1b[6n\x1b[9;1H");
+        assert_eq!(emulator.io.written, b"\x1b[2;4R");
+        // Clamped to the bottom of the scroll region
+        assert_eq!(emulator.cursor_pos(), CursorPos { x: 0, y: 5 });
+
+        emulator.process_output(b"\x1b7\x1b[?6l\x1b8\x1b[H");
+        assert_eq!(emulator.cursor_pos(), CursorPos { x: 0, y: 2 });
+        emulator.process_output(b"\x1b[?6l");
+        assert_eq!(emulator.cursor_pos(), CursorPos { x: 0, y: 0 });
+    }
+
+    #[test]
+    fn test_autowrap_mode() {
+        let mut emulator = test_emulator(b"");
+        emulator.set_win_size(5, 3).expect("failed to set win size");
+        emulator.process_output(b"\x1b[?7labcdefg\x1b[?7h\r\nhijklm");
+        assert_eq!(emulator.data().visible, b"abcdg\nhijklm\n");
+    }
+
+    #[test]
+    fn test_tabs() {
+        let mut emulator = test_emulator(b"");
+        emulator.process_output(b"a\tb\x1b[2Ic\r\x1b[5C\x1bH\r\tX\x1b[ZY");
+        assert!(emulator
+            .data()
+            .visible
+            .starts_with(b"a    Y  b               c\n"));
+
+        // A resize puts the default stops back
+        emulator.process_output(b"\r\n\x1b[3g\t");
+        assert_eq!(emulator.cursor_pos().x, TERMINAL_WIDTH - 1);
+        emulator
+            .set_win_size(20, TERMINAL_HEIGHT)
+            .expect("failed to set win size");
+        emulator.process_output(b"\r\ty");
+        assert_eq!(emulator.cursor_pos().x, 9);
+    }
+
+    #[test]
+    fn test_erase() {
+        let mut emulator = test_emulator(b"");
+        emulator.process_output(b"abcdef\x1b[3D\x1b[1K\r\n123456\x1b[4D\x1b[2X\r\nxyz\x1b[2K");
+        assert_eq!(emulator.data().visible, b"    ef\n12  56\n");
+        assert_eq!(emulator.cursor_pos(), CursorPos { x: 3, y: 2 });
+
+        emulator.process_output(b"\x1b[A\x1b[1J");
+        assert_eq!(emulator.data().visible, b"\n    56\n");
+    }
+
+    #[test]
+    fn test_line_drawing_charset() {
+        let mut emulator = test_emulator(b"");
+        emulator.process_output(b"\x1b(0lqk\x1b(B lqk\r\n\x1b)0a\x0ex");
+        assert_eq!(emulator.data().visible, "┌─┐ lqk\na│\n".as_bytes());
+
+        // The shift state is saved with the cursor
+        emulator.process_output(b"\x1b7\x0fx\x1b8x");
+        assert_eq!(emulator.data().visible, "┌─┐ lqk\na││\n".as_bytes());
+    }
+
+    #[test]
+    fn test_cursor_style() {
+        let mut emulator = test_emulator(b"");
+        assert!(emulator.cursor_visible());
+        assert_eq!(emulator.cursor_style(), CursorStyle::default());
+
+        emulator.process_output(b"\x1b[?25l\x1b[6 q");
+        assert!(!emulator.cursor_visible());
+        assert_eq!(
+            emulator.cursor_style(),
+            CursorStyle {
+                shape: CursorShape::Bar,
+                blinking: false,
+            }
+        );
+
+        emulator.process_output(b"\x1b[?25h\x1b[0 q");
+        assert!(emulator.cursor_visible());
+        assert_eq!(emulator.cursor_style(), CursorStyle::default());
+
+        for param in 0..=6 {
+            let style = CursorStyle::from_decscusr(param).expect("valid param");
+            assert_eq!(
+                CursorStyle::from_decscusr(style.decscusr().into()),
+                Some(style)
+            );
+        }
+    }
+
+    #[test]
+    fn test_wide_char_in_single_column() {
+        let mut emulator = test_emulator(b"");
+        emulator.set_win_size(1, 5).expect("failed to set win size");
+        emulator.process_output("中x".as_bytes());
+        assert_eq!(emulator.data().visible, b"x\n");
+    }
+
+    #[test]
+    fn test_out_of_range_cursor_movement() {
+        let mut emulator = test_emulator(b"");
+        emulator.process_output(b"\x1b[100;200Hx");
+        assert_eq!(
+            emulator.cursor_pos(),
+            CursorPos {
+                x: 0,
+                y: TERMINAL_HEIGHT - 1
+            }
+        );
+
+        emulator.process_output(b"\x1b[H\x1b[500B\x1b[500C");
+        assert_eq!(
+            emulator.cursor_pos(),
+            CursorPos {
+                x: TERMINAL_WIDTH - 1,
+                y: TERMINAL_HEIGHT - 1
+            }
+        );
+
+        emulator.process_output(b"\x1b[1;200H\x1b[@\x1b[0;0H");
+        assert_eq!(emulator.cursor_pos(), CursorPos { x: 0, y: 0 });
+    }
+
+    #[test]
+    fn test_mouse_reporting() {
+        let press = MouseEvent {
+            kind: MouseEventKind::Press(MouseButton::Left),
+            pos: CursorPos { x: 1, y: 2 },
+            modifiers: InputModifiers::default(),
+        };
+
+        let mut emulator = test_emulator(b"");
+        emulator.report_mouse(&press).expect("failed to report");
+        assert!(emulator.io.written.is_empty());
+
+        emulator.handle_incoming_data(b"\x1b[?1002h\x1b[?1006h");
+        assert_eq!(emulator.mouse_tracking(), MouseTracking::Drag);
+        emulator.report_mouse(&press).expect("failed to report");
+        assert_eq!(emulator.io.written, b"\x1b[<0;2;3M");
+
+        emulator.handle_incoming_data(b"\x1b[?1002l");
+        assert_eq!(emulator.mouse_tracking(), MouseTracking::Off);
+    }
+
+    #[test]
+    fn test_palette_replies() {
+        let mut emulator = test_emulator(b"");
+        emulator.set_palette(Palette::theme("solarized-dark").expect("bundled theme"));
+        emulator.process_output(b"\x1b]4;1;#ff8000\x07\x1b]4;1;?\x07\x1b]11;?\x1b\\");
+        assert_eq!(
+            emulator.io.written,
+            b"\x1b]4;1;rgb:ffff/8080/0000\x07\x1b]11;rgb:0000/2b2b/3636\x1b\\"
+        );
+        assert_eq!(emulator.palette().indexed[1], Rgb::new(255, 128, 0));
+
+        emulator.handle_incoming_data(b"\x1b]10;#000000\x07\x1b]104\x07\x1b]110\x07");
+        assert_eq!(
+            emulator.palette(),
+            &Palette::theme("solarized-dark").expect("bundled theme")
+        );
+    }
+
     #[test]
     fn test_cursor_state_snapshot() {
+        let mut charset = CharsetState::default();
+        charset.designate(
+            charset::CharsetSlot::G1,
+            charset::Charset::DecSpecialGraphics,
+        );
+        charset.shift_out();
+
         let state = CursorState {
             pos: CursorPos { x: 10, y: 50 },
             bold: false,
+            dim: true,
+            italic: true,
+            underline: false,
+            reverse: true,
+            strikethrough: false,
             color: TerminalColor::Magenta,
+            background_color: TerminalColor::Rgb(1, 2, 3),
+            origin_mode: true,
+            charset,
         };
 
         let snapshot = state.snapshot().expect("failed to create snapshot");
         let loaded = CursorState::from_snapshot(snapshot).expect("failed to load snapshot");
         assert_eq!(loaded, state);
     }
+
+    #[test]
+    fn test_terminal_color_round_trip() {
+        for color in [
+            TerminalColor::Default,
+            TerminalColor::Red,
+            TerminalColor::BrightCyan,
+            TerminalColor::Indexed(0),
+            TerminalColor::Indexed(255),
+            TerminalColor::Rgb(0, 128, 255),
+        ] {
+            assert_eq!(color.to_string().parse(), Ok(color));
+        }
+
+        assert_eq!("indexed:256".parse::<TerminalColor>(), Err(()));
+        assert_eq!("#12345".parse::<TerminalColor>(), Err(()));
+    }
 }
@@ -0,0 +1,186 @@
+// https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h2-Mouse-Tracking
+
+use super::{CursorPos, InputModifiers};
+
+/// Which mouse events the child asked to be told about
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum MouseTracking {
+    #[default]
+    Off,
+    /// Presses, releases and the wheel (?1000)
+    Click,
+    /// Also motion while a button is held (?1002)
+    Drag,
+    /// Also motion with no button held (?1003)
+    Motion,
+}
+
+impl MouseTracking {
+    fn reports(&self, kind: &MouseEventKind) -> bool {
+        match (self, kind) {
+            (MouseTracking::Off, _) => false,
+            (_, MouseEventKind::Press(_) | MouseEventKind::Release(_)) => true,
+            (MouseTracking::Click, MouseEventKind::Motion(_)) => false,
+            (MouseTracking::Drag, MouseEventKind::Motion(button)) => button.is_some(),
+            (MouseTracking::Motion, MouseEventKind::Motion(_)) => true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+    WheelUp,
+    WheelDown,
+}
+
+impl MouseButton {
+    fn code(&self) -> usize {
+        match self {
+            MouseButton::Left => 0,
+            MouseButton::Middle => 1,
+            MouseButton::Right => 2,
+            MouseButton::WheelUp => 64,
+            MouseButton::WheelDown => 65,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MouseEventKind {
+    Press(MouseButton),
+    Release(MouseButton),
+    /// Pointer moved to another cell, with the button that is held if any
+    Motion(Option<MouseButton>),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MouseEvent {
+    pub kind: MouseEventKind,
+    /// Cell on the visible screen
+    pub pos: CursorPos,
+    pub modifiers: InputModifiers,
+}
+
+/// Bytes telling the child about event. None if the child did not ask for it, or if the position
+/// does not fit in the X10 encoding
+pub fn encode(event: &MouseEvent, tracking: MouseTracking, sgr: bool) -> Option<Vec<u8>> {
+    if !tracking.reports(&event.kind) {
+        return None;
+    }
+
+    let mut code = match event.kind {
+        MouseEventKind::Press(button) => button.code(),
+        // The wheel has nothing to release
+        MouseEventKind::Release(MouseButton::WheelUp | MouseButton::WheelDown) => return None,
+        MouseEventKind::Release(button) if sgr => button.code(),
+        // X10 encoding cannot say which button was released
+        MouseEventKind::Release(_) => 3,
+        MouseEventKind::Motion(Some(button)) => button.code() + 32,
+        MouseEventKind::Motion(None) => 3 + 32,
+    };
+
+    let modifiers = &event.modifiers;
+    for (held, bit) in [
+        (modifiers.shift, 4),
+        (modifiers.alt, 8),
+        (modifiers.ctrl, 16),
+    ] {
+        if held {
+            code |= bit;
+        }
+    }
+
+    let x = event.pos.x + 1;
+    let y = event.pos.y + 1;
+    if sgr {
+        let action = match event.kind {
+            MouseEventKind::Release(_) => 'm',
+            _ => 'M',
+        };
+        return Some(format!("\x1b[<{code};{x};{y}{action}").into_bytes());
+    }
+
+    // Values are offset by 32 to keep them printable, anything past 223 does not fit in a byte
+    let to_byte = |v: usize| u8::try_from(v + 32).ok();
+    let mut ret = b"\x1b[M".to_vec();
+    ret.extend([to_byte(code)?, to_byte(x)?, to_byte(y)?]);
+    Some(ret)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn event(kind: MouseEventKind, x: usize, y: usize) -> MouseEvent {
+        MouseEvent {
+            kind,
+            pos: CursorPos { x, y },
+            modifiers: InputModifiers::default(),
+        }
+    }
+
+    #[test]
+    fn test_x10_encoding() {
+        let press = event(MouseEventKind::Press(MouseButton::Left), 2, 4);
+        assert_eq!(encode(&press, MouseTracking::Off, false), None);
+        assert_eq!(
+            encode(&press, MouseTracking::Click, false).as_deref(),
+            Some(b"\x1b[M #%".as_ref())
+        );
+
+        let release = event(MouseEventKind::Release(MouseButton::Right), 0, 0);
+        assert_eq!(
+            encode(&release, MouseTracking::Click, false).as_deref(),
+            Some(b"\x1b[M#!!".as_ref())
+        );
+
+        let mut wheel = event(MouseEventKind::Press(MouseButton::WheelDown), 0, 0);
+        wheel.modifiers.ctrl = true;
+        assert_eq!(
+            encode(&wheel, MouseTracking::Click, false).as_deref(),
+            Some(b"\x1b[Mq!!".as_ref())
+        );
+
+        // Too far right to fit in a byte
+        let far = event(MouseEventKind::Press(MouseButton::Left), 223, 0);
+        assert_eq!(encode(&far, MouseTracking::Click, false), None);
+    }
+
+    #[test]
+    fn test_sgr_encoding() {
+        let press = event(MouseEventKind::Press(MouseButton::Middle), 300, 9);
+        assert_eq!(
+            encode(&press, MouseTracking::Click, true).as_deref(),
+            Some(b"\x1b[<1;301;10M".as_ref())
+        );
+
+        let release = event(MouseEventKind::Release(MouseButton::Middle), 300, 9);
+        assert_eq!(
+            encode(&release, MouseTracking::Click, true).as_deref(),
+            Some(b"\x1b[<1;301;10m".as_ref())
+        );
+
+        let wheel_release = event(MouseEventKind::Release(MouseButton::WheelUp), 0, 0);
+        assert_eq!(encode(&wheel_release, MouseTracking::Click, true), None);
+    }
+
+    #[test]
+    fn test_motion_tracking() {
+        let drag = event(MouseEventKind::Motion(Some(MouseButton::Left)), 0, 0);
+        let hover = event(MouseEventKind::Motion(None), 0, 0);
+
+        assert_eq!(encode(&drag, MouseTracking::Click, true), None);
+        assert_eq!(
+            encode(&drag, MouseTracking::Drag, true).as_deref(),
+            Some(b"\x1b[<32;1;1M".as_ref())
+        );
+        assert_eq!(encode(&hover, MouseTracking::Drag, true), None);
+        assert_eq!(
+            encode(&hover, MouseTracking::Motion, true).as_deref(),
+            Some(b"\x1b[<35;1;1M".as_ref())
+        );
+    }
+}
@@ -0,0 +1,310 @@
+use super::TerminalColor;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const fn new(r: u8, g: u8, b: u8) -> Rgb {
+        Rgb { r, g, b }
+    }
+
+    /// #rrggbb
+    pub const fn from_hex(hex: u32) -> Rgb {
+        Rgb::new((hex >> 16) as u8, (hex >> 8) as u8, hex as u8)
+    }
+
+    /// Color spec as used by XParseColor, i.e. `rgb:r/g/b` with 1-4 hex digits per channel or
+    /// `#rgb`, `#rrggbb`, `#rrrgggbbb` and `#rrrrggggbbbb`. Color names are not supported
+    pub fn parse_xcolor(spec: &str) -> Option<Rgb> {
+        fn scale(hex: &str) -> Option<u8> {
+            if hex.is_empty() || hex.len() > 4 {
+                return None;
+            }
+            let v = u32::from_str_radix(hex, 16).ok()?;
+            let max = (1u32 << (4 * hex.len())) - 1;
+            Some((v * 255 / max) as u8)
+        }
+
+        if let Some(channels) = spec.strip_prefix("rgb:") {
+            let mut it = channels.split('/');
+            let ret = Rgb::new(scale(it.next()?)?, scale(it.next()?)?, scale(it.next()?)?);
+            return it.next().is_none().then_some(ret);
+        }
+
+        let hex = spec.strip_prefix('#')?;
+        if !hex.is_ascii() || hex.is_empty() || hex.len() % 3 != 0 || hex.len() > 12 {
+            return None;
+        }
+
+        // Unlike rgb:, the # form takes the most significant bits rather than scaling
+        let n = hex.len() / 3;
+        let channel = |i: usize| -> Option<u8> {
+            let v = u16::from_str_radix(&hex[i * n..(i + 1) * n], 16).ok()?;
+            Some(match n {
+                1 => (v << 4) as u8,
+                _ => (v >> (4 * (n - 2))) as u8,
+            })
+        };
+        Some(Rgb::new(channel(0)?, channel(1)?, channel(2)?))
+    }
+
+    /// `rgb:rrrr/gggg/bbbb`, the form xterm uses to answer color queries
+    pub fn to_xcolor(self) -> String {
+        let channel = |v: u8| v as u16 * 0x101;
+        format!(
+            "rgb:{:04x}/{:04x}/{:04x}",
+            channel(self.r),
+            channel(self.g),
+            channel(self.b)
+        )
+    }
+}
+
+/// A color that can be changed through OSC 4/10/11/12
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PaletteEntry {
+    Indexed(u8),
+    Foreground,
+    Background,
+    Cursor,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Palette {
+    pub foreground: Rgb,
+    pub background: Rgb,
+    pub cursor: Rgb,
+    pub selection: Rgb,
+    /// 0-15 are the ANSI colors, 16-231 a 6x6x6 color cube and 232-255 a grayscale ramp
+    pub indexed: [Rgb; 256],
+}
+
+impl Palette {
+    /// Palette with colors 16-255 derived the same way xterm does
+    pub fn new(
+        foreground: Rgb,
+        background: Rgb,
+        cursor: Rgb,
+        selection: Rgb,
+        ansi: [Rgb; 16],
+    ) -> Palette {
+        let mut indexed = [Rgb::new(0, 0, 0); 256];
+        indexed[..16].copy_from_slice(&ansi);
+
+        let level = |v: usize| if v == 0 { 0 } else { (55 + v * 40) as u8 };
+        for (i, color) in indexed[16..232].iter_mut().enumerate() {
+            *color = Rgb::new(level(i / 36), level((i / 6) % 6), level(i % 6));
+        }
+
+        for (i, color) in indexed[232..].iter_mut().enumerate() {
+            let v = (8 + i * 10) as u8;
+            *color = Rgb::new(v, v, v);
+        }
+
+        Palette {
+            foreground,
+            background,
+            cursor,
+            selection,
+            indexed,
+        }
+    }
+
+    /// None for [`TerminalColor::Default`], which means foreground or background depending on
+    /// where the color is used
+    pub fn get(&self, color: &TerminalColor) -> Option<Rgb> {
+        let idx = match color {
+            TerminalColor::Default => return None,
+            TerminalColor::Black => 0,
+            TerminalColor::Red => 1,
+            TerminalColor::Green => 2,
+            TerminalColor::Yellow => 3,
+            TerminalColor::Blue => 4,
+            TerminalColor::Magenta => 5,
+            TerminalColor::Cyan => 6,
+            TerminalColor::White => 7,
+            TerminalColor::BrightBlack => 8,
+            TerminalColor::BrightRed => 9,
+            TerminalColor::BrightGreen => 10,
+            TerminalColor::BrightYellow => 11,
+            TerminalColor::BrightBlue => 12,
+            TerminalColor::BrightMagenta => 13,
+            TerminalColor::BrightCyan => 14,
+            TerminalColor::BrightWhite => 15,
+            TerminalColor::Indexed(idx) => *idx,
+            TerminalColor::Rgb(r, g, b) => return Some(Rgb::new(*r, *g, *b)),
+        };
+
+        Some(self.indexed[idx as usize])
+    }
+
+    pub fn entry(&self, entry: PaletteEntry) -> Rgb {
+        match entry {
+            PaletteEntry::Indexed(idx) => self.indexed[idx as usize],
+            PaletteEntry::Foreground => self.foreground,
+            PaletteEntry::Background => self.background,
+            PaletteEntry::Cursor => self.cursor,
+        }
+    }
+
+    pub fn entry_mut(&mut self, entry: PaletteEntry) -> &mut Rgb {
+        match entry {
+            PaletteEntry::Indexed(idx) => &mut self.indexed[idx as usize],
+            PaletteEntry::Foreground => &mut self.foreground,
+            PaletteEntry::Background => &mut self.background,
+            PaletteEntry::Cursor => &mut self.cursor,
+        }
+    }
+
+    pub fn theme(name: &str) -> Option<Palette> {
+        THEMES
+            .iter()
+            .find(|theme| theme.name == name)
+            .map(Theme::palette)
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        THEMES[0].palette()
+    }
+}
+
+struct Theme {
+    name: &'static str,
+    foreground: u32,
+    background: u32,
+    cursor: u32,
+    selection: u32,
+    ansi: [u32; 16],
+}
+
+impl Theme {
+    fn palette(&self) -> Palette {
+        Palette::new(
+            Rgb::from_hex(self.foreground),
+            Rgb::from_hex(self.background),
+            Rgb::from_hex(self.cursor),
+            Rgb::from_hex(self.selection),
+            self.ansi.map(Rgb::from_hex),
+        )
+    }
+}
+
+const TANGO: [u32; 16] = [
+    0x2e3436, 0xcc0000, 0x4e9a06, 0xc4a000, 0x3465a4, 0x75507b, 0x06989a, 0xd3d7cf, 0x555753,
+    0xef2929, 0x8ae234, 0xfce94f, 0x729fcf, 0xad7fa8, 0x34e2e2, 0xeeeeec,
+];
+
+const SOLARIZED: [u32; 16] = [
+    0x073642, 0xdc322f, 0x859900, 0xb58900, 0x268bd2, 0xd33682, 0x2aa198, 0xeee8d5, 0x002b36,
+    0xcb4b16, 0x586e75, 0x657b83, 0x839496, 0x6c71c4, 0x93a1a1, 0xfdf6e3,
+];
+
+/// The first theme is the default
+const THEMES: &[Theme] = &[
+    Theme {
+        name: "termie-dark",
+        foreground: 0xd3d7cf,
+        background: 0x1c1c1c,
+        cursor: 0xd3d7cf,
+        selection: 0x3a4a5c,
+        ansi: TANGO,
+    },
+    Theme {
+        name: "termie-light",
+        foreground: 0x2e3436,
+        background: 0xffffff,
+        cursor: 0x2e3436,
+        selection: 0xb5d5ff,
+        ansi: TANGO,
+    },
+    Theme {
+        name: "solarized-dark",
+        foreground: 0x839496,
+        background: 0x002b36,
+        cursor: 0x93a1a1,
+        selection: 0x073642,
+        ansi: SOLARIZED,
+    },
+    Theme {
+        name: "solarized-light",
+        foreground: 0x657b83,
+        background: 0xfdf6e3,
+        cursor: 0x586e75,
+        selection: 0xeee8d5,
+        ansi: SOLARIZED,
+    },
+    Theme {
+        name: "gruvbox-dark",
+        foreground: 0xebdbb2,
+        background: 0x282828,
+        cursor: 0xebdbb2,
+        selection: 0x504945,
+        ansi: [
+            0x282828, 0xcc241d, 0x98971a, 0xd79921, 0x458588, 0xb16286, 0x689d6a, 0xa89984,
+            0x928374, 0xfb4934, 0xb8bb26, 0xfabd2f, 0x83a598, 0xd3869b, 0x8ec07c, 0xebdbb2,
+        ],
+    },
+];
+
+pub fn theme_names() -> impl Iterator<Item = &'static str> {
+    THEMES.iter().map(|theme| theme.name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_xcolor() {
+        assert_eq!(
+            Rgb::parse_xcolor("rgb:ffff/8080/0"),
+            Some(Rgb::new(255, 128, 0))
+        );
+        assert_eq!(Rgb::parse_xcolor("rgb:f/8/0"), Some(Rgb::new(255, 136, 0)));
+        assert_eq!(Rgb::parse_xcolor("#ff8000"), Some(Rgb::new(255, 128, 0)));
+        assert_eq!(Rgb::parse_xcolor("#f80"), Some(Rgb::new(240, 128, 0)));
+        assert_eq!(
+            Rgb::parse_xcolor("#ffff80800000"),
+            Some(Rgb::new(255, 128, 0))
+        );
+        assert_eq!(Rgb::parse_xcolor("rgb:ff/80"), None);
+        assert_eq!(Rgb::parse_xcolor("rgb:ff/80/00/00"), None);
+        assert_eq!(Rgb::parse_xcolor("#ff80"), None);
+        assert_eq!(Rgb::parse_xcolor("red"), None);
+
+        assert_eq!(Rgb::new(255, 128, 0).to_xcolor(), "rgb:ffff/8080/0000");
+    }
+
+    #[test]
+    fn test_palette() {
+        let palette = Palette::default();
+        assert_eq!(palette.get(&TerminalColor::Default), None);
+        assert_eq!(
+            palette.get(&TerminalColor::Red),
+            palette.get(&TerminalColor::Indexed(1))
+        );
+        assert_eq!(
+            palette.get(&TerminalColor::Indexed(196)),
+            Some(Rgb::new(255, 0, 0))
+        );
+        assert_eq!(
+            palette.get(&TerminalColor::Indexed(232)),
+            Some(Rgb::new(8, 8, 8))
+        );
+        assert_eq!(
+            palette.get(&TerminalColor::Rgb(1, 2, 3)),
+            Some(Rgb::new(1, 2, 3))
+        );
+
+        for name in theme_names() {
+            assert!(Palette::theme(name).is_some());
+        }
+        assert_eq!(Palette::theme("termie-dark"), Some(Palette::default()));
+    }
+}
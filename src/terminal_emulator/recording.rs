@@ -375,8 +375,51 @@ pub struct RecordingHandle {
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum RecordingItem {
-    SetWinSize { width: usize, height: usize },
-    Write { data: Vec<u8> },
+    SetWinSize {
+        width: usize,
+        height: usize,
+    },
+    Write {
+        data: Vec<u8>,
+    },
+    /// Data the terminal sent back to the child in response to a query
+    Reply {
+        data: Vec<u8>,
+    },
+}
+
+fn parse_data_field(
+    map: &mut HashMap<String, JsonValue>,
+) -> Result<Vec<u8>, ParseRecordingItemErrorKind> {
+    use ParseRecordingItemErrorKind::*;
+
+    let data = map.remove("data").ok_or(DataNotPresent)?;
+    let JsonValue::Array(data) = data else {
+        Err(DataNotArray)?
+    };
+
+    data.into_iter()
+        .map(|v| -> Result<u8, ParseRecordingItemErrorKind> {
+            let v_num: f64 = *v.get().ok_or(DataElemNotNumber)?;
+            if v_num > u8::MAX as f64 || v_num < u8::MIN as f64 {
+                Err(DataElemNotU8)?
+            }
+            Ok(v_num as u8)
+        })
+        .collect()
+}
+
+fn data_to_json(typ: &str, data: &[u8]) -> JsonValue {
+    JsonValue::Object(
+        [
+            ("type".into(), JsonValue::String(typ.into())),
+            (
+                "data".into(),
+                JsonValue::Array(data.iter().map(|v| JsonValue::Number(*v as f64)).collect()),
+            ),
+        ]
+        .into(),
+    )
 }
 
 impl RecordingItem {
@@ -412,24 +455,13 @@ impl RecordingItem {
                 Ok(RecordingItem::SetWinSize { width, height })
             }
             "write" => {
-                let data = map.remove("data").ok_or(DataNotPresent)?;
-                let JsonValue::Array(data) = data else {
-                    Err(DataNotArray)?
-                };
-
-                let data: Vec<_> = data
-                    .into_iter()
-                    .map(|v| -> Result<u8, ParseRecordingItemErrorKind> {
-                        let v_num: f64 = *v.get().ok_or(DataElemNotNumber)?;
-                        if v_num > u8::MAX as f64 || v_num < u8::MIN as f64 {
-                            Err(DataElemNotU8)?
-                        }
-                        Ok(v_num as u8)
-                    })
-                    .collect::<Result<_, _>>()?;
-
+                let data = parse_data_field(&mut map)?;
                 Ok(RecordingItem::Write { data })
             }
+            "reply" => {
+                let data = parse_data_field(&mut map)?;
+                Ok(RecordingItem::Reply { data })
+            }
             _ => Err(UnexpectedField(typ))?,
         }
     }
@@ -444,18 +476,8 @@ impl RecordingItem {
                 ]
                 .into(),
             ),
-            RecordingItem::Write { data } => JsonValue::Object(
-                [
-                    ("type".into(), JsonValue::String("write".into())),
-                    (
-                        "data".into(),
-                        JsonValue::Array(
-                            data.iter().map(|v| JsonValue::Number(*v as f64)).collect(),
-                        ),
-                    ),
-                ]
-                .into(),
-            ),
+            RecordingItem::Write { data } => data_to_json("write", data),
+            RecordingItem::Reply { data } => data_to_json("reply", data),
         }
     }
 }
@@ -517,6 +539,15 @@ impl Recorder {
         }
     }
 
+    pub fn reply(&self, data: &[u8]) {
+        if let Some(inner) = self.handle.upgrade() {
+            let mut inner = inner.lock().expect("poisoned lock");
+            inner.recording.items.push(RecordingItem::Reply {
+                data: data.to_vec(),
+            });
+        }
+    }
+
     pub fn start_recording(&mut self) -> Result<StartRecordingResponse, std::io::Error> {
         std::fs::create_dir_all(&self.recording_dir)?;
 
@@ -580,6 +611,8 @@ mod test {
         recorder.write(b"1234");
         recorder.set_win_size(10, 20);
         recorder.write(b"xyzw");
+        recorder.reply(b"\x1b[0n");
+        recorder.write(b"5678");
         let saved = handle
             .inner
             .lock()
@@ -588,6 +621,28 @@ mod test {
             .clone();
         drop(handle);
 
+        assert_eq!(
+            saved.items(),
+            &[
+                RecordingItem::Write {
+                    data: b"asdf1234".to_vec()
+                },
+                RecordingItem::SetWinSize {
+                    width: 10,
+                    height: 20
+                },
+                RecordingItem::Write {
+                    data: b"xyzw".to_vec()
+                },
+                RecordingItem::Reply {
+                    data: b"\x1b[0n".to_vec()
+                },
+                RecordingItem::Write {
+                    data: b"5678".to_vec()
+                },
+            ]
+        );
+
         let loaded =
             Recording::load(&_temp_dir.path().join("0.json")).expect("failed to load recording");
 
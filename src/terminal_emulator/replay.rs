@@ -1,36 +1,67 @@
-use super::io::TermIo;
-use crate::terminal_emulator::{ReadResponse, Recording, RecordingItem, SnapshotItem};
+use super::io::{ReaderEvent, TermIo, TermIoErr, TermReader};
+use crate::terminal_emulator::{Recording, RecordingItem, SnapshotItem};
 
-use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
 
-pub struct ReplayIo {
-    rx: Receiver<u8>,
-}
+/// Replayed output is delivered through [`ReplayControl::reader`], input to the replay is dropped
+pub struct ReplayIo;
 
 impl TermIo for ReplayIo {
-    fn read(&mut self, buf: &mut [u8]) -> Result<super::io::ReadResponse, super::io::TermIoErr> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, TermIoErr> {
+        Ok(buf.len())
+    }
+
+    fn set_win_size(&mut self, _width: usize, _height: usize) -> Result<(), TermIoErr> {
+        Ok(())
+    }
+}
+
+pub struct ReplayReader {
+    rx: Receiver<RecordingAction>,
+    // Resize that ended the previous batch of writes
+    pending_resize: Option<(usize, usize)>,
+}
+
+impl TermReader for ReplayReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<ReaderEvent, TermIoErr> {
+        if let Some((width, height)) = self.pending_resize.take() {
+            return Ok(ReaderEvent::Resize { width, height });
+        }
+
+        let mut action = match self.rx.recv() {
+            Ok(v) => v,
+            // Replay control is gone, e.g. the replay was reloaded
+            Err(_) => return Ok(ReaderEvent::Closed),
+        };
+
         let mut idx = 0;
-        while let Ok(b) = self.rx.try_recv() {
+        loop {
+            match action {
+                RecordingAction::Write(b) => {
+                    buf[idx] = b;
+                    idx += 1;
+                }
+                RecordingAction::SetWinSize { width, height } if idx == 0 => {
+                    return Ok(ReaderEvent::Resize { width, height });
+                }
+                RecordingAction::SetWinSize { width, height } => {
+                    self.pending_resize = Some((width, height));
+                    break;
+                }
+                RecordingAction::None => (),
+            }
+
             if idx >= buf.len() {
                 break;
             }
 
-            buf[idx] = b;
-            idx += 1;
-        }
-        if idx == 0 {
-            Ok(ReadResponse::Empty)
-        } else {
-            Ok(ReadResponse::Success(idx))
+            action = match self.rx.try_recv() {
+                Ok(v) => v,
+                Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => break,
+            };
         }
-    }
 
-    fn write(&mut self, _buf: &[u8]) -> Result<usize, super::io::TermIoErr> {
-        Ok(_buf.len())
-    }
-
-    fn set_win_size(&mut self, _width: usize, _height: usize) -> Result<(), super::io::TermIoErr> {
-        Ok(())
+        Ok(ReaderEvent::Data(idx))
     }
 }
 
@@ -38,6 +69,8 @@ fn item_len(item: &RecordingItem) -> usize {
     match item {
         RecordingItem::Write { data } => data.len(),
         RecordingItem::SetWinSize { .. } => 1,
+        // Replies are regenerated by the emulator as the recorded writes are replayed
+        RecordingItem::Reply { .. } => 0,
     }
 }
 
@@ -80,6 +113,9 @@ impl RecordingTracker {
                     width: *width,
                     height: *height,
                 },
+                RecordingItem::Reply { .. } => {
+                    unreachable!("reply items have no length and are skipped above")
+                }
             };
 
             self.item_pos += 1;
@@ -89,18 +125,13 @@ impl RecordingTracker {
     }
 }
 
-pub enum ControlAction {
-    Resize { width: usize, height: usize },
-    None,
-}
-
 pub struct ReplayControl {
     recording: Recording,
     tracker: RecordingTracker,
     segment_lengths: Vec<usize>,
     total_len: usize,
-    tx: Sender<u8>,
-    rx: Option<Receiver<u8>>,
+    tx: Sender<RecordingAction>,
+    rx: Option<Receiver<RecordingAction>>,
 }
 
 impl ReplayControl {
@@ -127,11 +158,19 @@ impl ReplayControl {
         self.recording.initial_state()
     }
 
-    pub fn io_handle(&mut self) -> ReplayIo {
+    pub fn io_handle(&self) -> ReplayIo {
+        ReplayIo
+    }
+
+    /// Source of the replayed output, resizes are sent in order with the writes around them
+    pub fn reader(&mut self) -> ReplayReader {
         if let Some(rx) = std::mem::take(&mut self.rx) {
-            ReplayIo { rx }
+            ReplayReader {
+                rx,
+                pending_resize: None,
+            }
         } else {
-            panic!("io_handle should only be called once");
+            panic!("reader should only be called once");
         }
     }
 
@@ -149,6 +188,10 @@ impl ReplayControl {
         self.total_len
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.total_len == 0
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = RecordingAction> + '_ {
         struct Iter<'b> {
             tracker: RecordingTracker,
@@ -178,17 +221,14 @@ impl ReplayControl {
         }
     }
 
-    pub fn next(&mut self) -> ControlAction {
+    // Steps the replay, not an iterator over it
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) {
         let action = self.tracker.next(&self.recording);
-        match action {
-            RecordingAction::Write(b) => {
-                self.tx.send(b).expect("failed to send write action");
-                ControlAction::None
-            }
-            RecordingAction::SetWinSize { width, height } => {
-                ControlAction::Resize { width, height }
-            }
-            RecordingAction::None => ControlAction::None,
+        if let RecordingAction::None = action {
+            return;
         }
+        // Nobody reading the replay anymore is not a problem for stepping through it
+        let _ = self.tx.send(action);
     }
 }
@@ -0,0 +1,136 @@
+use std::ops::Range;
+
+use super::buffer::{BufPos, Reflow, TerminalBuffer2};
+
+/// What a selection grows by as it is extended
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SelectionMode {
+    Cell,
+    Word,
+    Line,
+}
+
+/// Text selected with the mouse. Ends are held as [`BufPos`] so that the selection stays on its
+/// text as it scrolls into scrollback
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Selection {
+    /// Where the selection was started
+    anchor: BufPos,
+    /// Where the selection was extended to, may be before anchor
+    head: BufPos,
+    mode: SelectionMode,
+}
+
+impl Selection {
+    pub fn new(pos: BufPos, mode: SelectionMode) -> Selection {
+        Selection {
+            anchor: pos,
+            head: pos,
+            mode,
+        }
+    }
+
+    pub fn extend(&mut self, pos: BufPos) {
+        self.head = pos;
+    }
+
+    /// Selected range after expanding to whole words or lines. None if nothing is selected, or
+    /// if everything that was has been evicted
+    pub fn range(&self, buffer: &mut TerminalBuffer2) -> Option<Range<BufPos>> {
+        let start = self.anchor.min(self.head);
+        let end = self.anchor.max(self.head);
+        let range = match self.mode {
+            SelectionMode::Cell => start..end,
+            SelectionMode::Word => buffer.word_range(start).start..buffer.word_range(end).end,
+            SelectionMode::Line => buffer.line_range(start).start..buffer.line_range(end).end,
+        };
+
+        let start = range.start.max(BufPos::new(0, buffer.first_line_id()));
+        (start < range.end).then_some(start..range.end)
+    }
+
+    pub fn apply_reflow(&mut self, reflow: &Reflow) {
+        self.anchor = reflow.map(&self.anchor);
+        self.head = reflow.map(&self.head);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::terminal_emulator::{cell_format::CellFormat, CursorPos};
+
+    fn buffer_with(width: usize, height: usize, data: &[u8]) -> TerminalBuffer2 {
+        let mut buffer = TerminalBuffer2::new(width, height);
+        buffer.insert_data(&CursorPos { x: 0, y: 0 }, data, &CellFormat::default());
+        buffer
+    }
+
+    fn selected_text(selection: &Selection, buffer: &mut TerminalBuffer2) -> String {
+        let range = selection.range(buffer).expect("nothing selected");
+        buffer.text(range)
+    }
+
+    #[test]
+    fn test_cell_selection() {
+        let mut buffer = buffer_with(5, 5, b"hello wor\nld  \nx");
+
+        let mut selection = Selection::new(BufPos::new(1, 0), SelectionMode::Cell);
+        assert_eq!(selection.range(&mut buffer), None);
+
+        // Soft-wrapped rows are joined, trailing spaces dropped at hard line ends
+        selection.extend(BufPos::new(usize::MAX, 3));
+        assert_eq!(selected_text(&selection, &mut buffer), "ello wor\nld\nx");
+
+        // Selecting backwards is the same as forwards
+        let mut selection = Selection::new(BufPos::new(3, 1), SelectionMode::Cell);
+        selection.extend(BufPos::new(4, 0));
+        assert_eq!(selected_text(&selection, &mut buffer), "o wo");
+    }
+
+    #[test]
+    fn test_word_and_line_selection() {
+        let mut buffer = buffer_with(8, 5, "ls (~/a.txt) 中文\n".as_bytes());
+
+        let selection = Selection::new(BufPos::new(5, 0), SelectionMode::Word);
+        assert_eq!(selected_text(&selection, &mut buffer), "~/a.txt");
+
+        let selection = Selection::new(BufPos::new(3, 0), SelectionMode::Word);
+        assert_eq!(selected_text(&selection, &mut buffer), "(");
+
+        // Words continue across soft wraps, and the right half of a wide char is part of it
+        let mut selection = Selection::new(BufPos::new(1, 0), SelectionMode::Word);
+        selection.extend(BufPos::new(6, 1));
+        assert_eq!(selected_text(&selection, &mut buffer), "ls (~/a.txt) 中文");
+
+        let selection = Selection::new(BufPos::new(0, 1), SelectionMode::Line);
+        assert_eq!(
+            selection.range(&mut buffer),
+            Some(BufPos::new(0, 0)..BufPos::new(usize::MAX, 2))
+        );
+        assert_eq!(selected_text(&selection, &mut buffer), "ls (~/a.txt) 中文");
+
+        // Nothing to select past the end of the line
+        let selection = Selection::new(BufPos::new(6, 2), SelectionMode::Word);
+        assert_eq!(selection.range(&mut buffer), None);
+    }
+
+    #[test]
+    fn test_selection_follows_text() {
+        let mut buffer = buffer_with(10, 2, b"abc\ndef\n");
+        let mut selection = Selection::new(BufPos::new(0, 1), SelectionMode::Cell);
+        selection.extend(BufPos::new(3, 1));
+        assert_eq!(selected_text(&selection, &mut buffer), "def");
+
+        // The selected line scrolls into scrollback
+        buffer.insert_data(&CursorPos { x: 0, y: 1 }, b"ghi\n", &CellFormat::default());
+        assert_eq!(selected_text(&selection, &mut buffer), "def");
+
+        let response = buffer.set_win_size(2, 3, &CursorPos { x: 0, y: 1 });
+        selection.apply_reflow(&response.reflow.expect("resize should reflow"));
+        assert_eq!(selected_text(&selection, &mut buffer), "def");
+
+        buffer.clear_scrollback();
+        assert_eq!(selection.range(&mut buffer), None);
+    }
+}
@@ -0,0 +1,129 @@
+use thiserror::Error;
+
+use super::recording::{NotArray, NotBool, SnapshotItem};
+
+#[derive(Debug, Error)]
+enum LoadSnapshotErrorKind {
+    #[error("root element is not an array")]
+    RootNotArray(#[source] NotArray),
+    #[error("tab stop is not a bool")]
+    StopNotBool(#[source] NotBool),
+}
+
+#[derive(Debug, Error)]
+#[error(transparent)]
+pub struct LoadSnapshotError(#[from] LoadSnapshotErrorKind);
+
+const DEFAULT_TAB_WIDTH: usize = 8;
+
+/// Columns that a horizontal tab moves the cursor to
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TabStops {
+    stops: Vec<bool>,
+}
+
+impl TabStops {
+    /// A stop every 8 columns
+    pub fn new(width: usize) -> TabStops {
+        TabStops {
+            stops: (0..width)
+                .map(|x| x != 0 && x % DEFAULT_TAB_WIDTH == 0)
+                .collect(),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.stops.len()
+    }
+
+    pub fn set(&mut self, x: usize) {
+        if let Some(stop) = self.stops.get_mut(x) {
+            *stop = true;
+        }
+    }
+
+    pub fn clear(&mut self, x: usize) {
+        if let Some(stop) = self.stops.get_mut(x) {
+            *stop = false;
+        }
+    }
+
+    pub fn clear_all(&mut self) {
+        self.stops.fill(false);
+    }
+
+    /// Column num_stops stops after x, or the last column if there are not that many
+    pub fn next(&self, x: usize, num_stops: usize) -> usize {
+        let last = self.width().saturating_sub(1);
+        (x + 1..last)
+            .filter(|x| self.stops[*x])
+            .nth(num_stops.saturating_sub(1))
+            .unwrap_or(last)
+    }
+
+    /// Column num_stops stops before x, or the first column if there are not that many
+    pub fn prev(&self, x: usize, num_stops: usize) -> usize {
+        (1..x.min(self.width()))
+            .rev()
+            .filter(|x| self.stops[*x])
+            .nth(num_stops.saturating_sub(1))
+            .unwrap_or(0)
+    }
+
+    pub fn snapshot(&self) -> SnapshotItem {
+        self.stops.iter().copied().collect()
+    }
+
+    pub fn from_snapshot(snapshot: SnapshotItem) -> Result<TabStops, LoadSnapshotError> {
+        use LoadSnapshotErrorKind::*;
+
+        let stops = snapshot
+            .into_vec()
+            .map_err(RootNotArray)?
+            .into_iter()
+            .map(|item| item.into_bool().map_err(StopNotBool))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(TabStops { stops })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tab_stops() {
+        let mut tab_stops = TabStops::new(20);
+        assert_eq!(tab_stops.next(0, 1), 8);
+        assert_eq!(tab_stops.next(8, 1), 16);
+        assert_eq!(tab_stops.next(3, 2), 16);
+        // Past the last stop the cursor stops at the edge of the screen
+        assert_eq!(tab_stops.next(16, 1), 19);
+        assert_eq!(tab_stops.next(30, 1), 19);
+
+        assert_eq!(tab_stops.prev(10, 1), 8);
+        assert_eq!(tab_stops.prev(16, 1), 8);
+        assert_eq!(tab_stops.prev(17, 2), 8);
+        assert_eq!(tab_stops.prev(5, 1), 0);
+
+        tab_stops.set(3);
+        tab_stops.clear(8);
+        assert_eq!(tab_stops.next(0, 1), 3);
+        assert_eq!(tab_stops.next(3, 1), 16);
+
+        tab_stops.clear_all();
+        assert_eq!(tab_stops.next(0, 1), 19);
+        assert_eq!(tab_stops.prev(19, 1), 0);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut tab_stops = TabStops::new(12);
+        tab_stops.set(5);
+        let loaded =
+            TabStops::from_snapshot(tab_stops.snapshot()).expect("failed to load snapshot");
+        assert_eq!(loaded, tab_stops);
+
+        assert!(TabStops::from_snapshot(SnapshotItem::Int(1)).is_err());
+    }
+}